@@ -139,10 +139,11 @@ impl<T: PageTable> DerefMut for CowExt<T> {
 /// A map contains reference count for shared frame
 ///
 /// It will lazily construct the `BTreeMap`, to avoid heap alloc when heap is unavailable.
-#[derive(Default)]
-struct FrameRcMap(Option<BTreeMap<Frame, (u16, u16)>>);
+/// Frames are removed from the map once their counts drop to zero.
+#[derive(Debug, Default)]
+pub struct FrameRcMap(Option<BTreeMap<Frame, (u16, u16)>>);
 
-type Frame = usize;
+pub type Frame = usize;
 
 impl FrameRcMap {
     /*
@@ -150,7 +151,7 @@ impl FrameRcMap {
      **  @param  frame: &Frame        the frame to get the read reference count
      **  @retval u16                  the read reference count
      */
-    fn read_count(&mut self, frame: &Frame) -> u16 {
+    pub fn read_count(&mut self, frame: &Frame) -> u16 {
         self.map().get(frame).unwrap_or(&(0, 0)).0
     }
     /*
@@ -158,7 +159,7 @@ impl FrameRcMap {
      **  @param  frame: &Frame        the frame to get the write reference count
      **  @retval u16                  the write reference count
      */
-    fn write_count(&mut self, frame: &Frame) -> u16 {
+    pub fn write_count(&mut self, frame: &Frame) -> u16 {
         self.map().get(frame).unwrap_or(&(0, 0)).1
     }
     /*
@@ -166,7 +167,7 @@ impl FrameRcMap {
      **  @param  frame: &Frame        the frame to increase the read reference count
      **  @retval none
      */
-    fn read_increase(&mut self, frame: &Frame) {
+    pub fn read_increase(&mut self, frame: &Frame) {
         let (r, w) = self.map().get(&frame).unwrap_or(&(0, 0)).clone();
         self.map().insert(frame.clone(), (r + 1, w));
     }
@@ -175,15 +176,16 @@ impl FrameRcMap {
      **  @param  frame: &Frame        the frame to decrease the read reference count
      **  @retval none
      */
-    fn read_decrease(&mut self, frame: &Frame) {
+    pub fn read_decrease(&mut self, frame: &Frame) {
         self.map().get_mut(frame).unwrap().0 -= 1;
+        self.remove_unused(frame);
     }
     /*
      **  @brief  increase the write reference count of the frame
      **  @param  frame: &Frame        the frame to increase the write reference count
      **  @retval none
      */
-    fn write_increase(&mut self, frame: &Frame) {
+    pub fn write_increase(&mut self, frame: &Frame) {
         let (r, w) = self.map().get(&frame).unwrap_or(&(0, 0)).clone();
         self.map().insert(frame.clone(), (r, w + 1));
     }
@@ -192,8 +194,19 @@ impl FrameRcMap {
      **  @param  frame: &Frame        the frame to decrease the write reference count
      **  @retval none
      */
-    fn write_decrease(&mut self, frame: &Frame) {
+    pub fn write_decrease(&mut self, frame: &Frame) {
         self.map().get_mut(frame).unwrap().1 -= 1;
+        self.remove_unused(frame);
+    }
+    /*
+     **  @brief  remove the frame from the map if it is no longer shared
+     **  @param  frame: &Frame        the frame to check
+     **  @retval none
+     */
+    fn remove_unused(&mut self, frame: &Frame) {
+        if self.map().get(frame) == Some(&(0, 0)) {
+            self.map().remove(frame);
+        }
     }
     /*
      **  @brief  get the internal btree map, lazily initialize the btree map if it is not present
@@ -0,0 +1,311 @@
+use super::*;
+use crate::cow::FrameRcMap;
+
+/// The global `FrameRcMap` of frames shared by copy-on-write, provided by the OS.
+///
+/// It is shared by all memory sets and used in page fault handlers,
+/// so the OS should protect it with an interrupt-safe lock.
+pub trait FrameRcMapLock: Debug + Clone + 'static {
+    fn with<R>(&self, f: impl FnOnce(&mut FrameRcMap) -> R) -> R;
+}
+
+/// Copy-on-write over a handler owning its frames, like `ByFrame` or `Delay`
+///
+/// On `MemorySet::fork`, present pages are shared read-only by both memory sets.
+/// The first write to a shared page copies it into a new frame,
+/// except for the last writer, which takes the frame directly.
+#[derive(Debug, Clone)]
+pub struct CopyOnWrite<H: MemoryHandler + Clone, T: FrameAllocator, R: FrameRcMapLock> {
+    inner: H,
+    allocator: T,
+    rc_map: R,
+}
+
+impl<H: MemoryHandler + Clone, T: FrameAllocator, R: FrameRcMapLock> MemoryHandler
+    for CopyOnWrite<H, T, R>
+{
+    fn box_clone(&self) -> Box<MemoryHandler> {
+        Box::new(self.clone())
+    }
+
+    fn map(&self, pt: &mut PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+        self.inner.map(pt, addr, attr);
+    }
+
+    fn map_eager(&self, pt: &mut PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+        self.inner.map_eager(pt, addr, attr);
+    }
+
+    fn unmap(&self, pt: &mut PageTable, addr: VirtAddr) {
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        if !entry.present() || !(entry.readonly_shared() || entry.writable_shared()) {
+            self.inner.unmap(pt, addr);
+            return;
+        }
+        let frame = entry.target() / PAGE_SIZE;
+        let writable = entry.writable_shared();
+        let unused = self.rc_map.with(|rc_map| {
+            match writable {
+                true => rc_map.write_decrease(&frame),
+                false => rc_map.read_decrease(&frame),
+            }
+            rc_map.read_count(&frame) == 0 && rc_map.write_count(&frame) == 0
+        });
+        if unused {
+            self.allocator.dealloc(frame * PAGE_SIZE);
+        }
+        pt.unmap(addr);
+    }
+
+    fn handle_page_fault(&self, pt: &mut PageTable, addr: VirtAddr) -> bool {
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        if !entry.present() || !entry.writable_shared() {
+            // not a copy-on-write case
+            // a readonly shared page is really readonly
+            return self.inner.handle_page_fault(pt, addr);
+        }
+        let frame = entry.target() / PAGE_SIZE;
+        let last = self.rc_map.with(|rc_map| {
+            let last = rc_map.read_count(&frame) == 0 && rc_map.write_count(&frame) == 1;
+            if last {
+                rc_map.write_decrease(&frame);
+            }
+            last
+        });
+        if !last {
+            // copy the page while holding the reference,
+            // so that nobody else can take the frame and write to it
+            let mut temp_data = [0u8; PAGE_SIZE];
+            temp_data.copy_from_slice(pt.get_page_slice_mut(addr));
+            let target = self.allocator.alloc().expect("failed to alloc frame");
+            let entry = pt.get_entry(addr).expect("failed to get entry");
+            entry.set_target(target);
+            entry.clear_shared();
+            entry.set_writable(true);
+            entry.update();
+            pt.get_page_slice_mut(addr).copy_from_slice(&temp_data);
+
+            let unused = self.rc_map.with(|rc_map| {
+                rc_map.write_decrease(&frame);
+                rc_map.read_count(&frame) == 0 && rc_map.write_count(&frame) == 0
+            });
+            if unused {
+                self.allocator.dealloc(frame * PAGE_SIZE);
+            }
+            return true;
+        }
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        entry.clear_shared();
+        entry.set_writable(true);
+        entry.update();
+        true
+    }
+
+    fn is_cow(&self) -> bool {
+        true
+    }
+
+    fn share(&self, pt: &mut PageTable, addr: VirtAddr) -> Option<PhysAddr> {
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        if !entry.present() {
+            return None;
+        }
+        if !entry.readonly_shared() && !entry.writable_shared() {
+            let writable = entry.writable();
+            entry.set_writable(false);
+            entry.set_shared(writable);
+            entry.update();
+            self.increase(entry.target(), writable);
+        }
+        Some(entry.target())
+    }
+
+    fn map_shared(
+        &self,
+        pt: &mut PageTable,
+        addr: VirtAddr,
+        target: Option<PhysAddr>,
+        attr: &MemoryAttr,
+    ) {
+        let target = match target {
+            Some(target) => target,
+            None => {
+                self.inner.map(pt, addr, attr);
+                return;
+            }
+        };
+        let entry = pt.map(addr, target);
+        attr.apply(entry);
+        let writable = entry.writable();
+        entry.set_writable(false);
+        entry.set_shared(writable);
+        entry.update();
+        self.increase(target, writable);
+    }
+
+    fn protect(&self, pt: &mut PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        if !entry.present() || !(entry.readonly_shared() || entry.writable_shared()) {
+            self.inner.protect(pt, addr, attr);
+            return;
+        }
+        // a shared page keeps readonly until it is copied on write
+        let frame = entry.target() / PAGE_SIZE;
+        let was_writable = entry.writable_shared();
+        let writable = !attr.readonly;
+        attr.apply(entry);
+        entry.set_writable(false);
+        entry.set_shared(writable);
+        entry.update();
+        if writable != was_writable {
+            self.rc_map.with(|rc_map| match writable {
+                true => {
+                    rc_map.write_increase(&frame);
+                    rc_map.read_decrease(&frame);
+                }
+                false => {
+                    rc_map.read_increase(&frame);
+                    rc_map.write_decrease(&frame);
+                }
+            });
+        }
+    }
+}
+
+impl<H: MemoryHandler + Clone, T: FrameAllocator, R: FrameRcMapLock> CopyOnWrite<H, T, R> {
+    pub fn new(inner: H, allocator: T, rc_map: R) -> Self {
+        CopyOnWrite {
+            inner,
+            allocator,
+            rc_map,
+        }
+    }
+
+    fn increase(&self, target: PhysAddr, writable: bool) {
+        let frame = target / PAGE_SIZE;
+        self.rc_map.with(|rc_map| match writable {
+            true => rc_map.write_increase(&frame),
+            false => rc_map.read_increase(&frame),
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::rc::Rc;
+    use core::cell::RefCell;
+
+    #[derive(Debug, Clone)]
+    struct MockFrameAlloc(Rc<RefCell<Vec<PhysAddr>>>);
+
+    impl FrameAllocator for MockFrameAlloc {
+        fn alloc(&self) -> Option<PhysAddr> {
+            self.0.borrow_mut().pop()
+        }
+        fn dealloc(&self, target: PhysAddr) {
+            self.0.borrow_mut().push(target);
+        }
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct MockRcMap(Rc<RefCell<FrameRcMap>>);
+
+    impl FrameRcMapLock for MockRcMap {
+        fn with<R>(&self, f: impl FnOnce(&mut FrameRcMap) -> R) -> R {
+            f(&mut self.0.borrow_mut())
+        }
+    }
+
+    #[test]
+    fn cow() {
+        let frames = MockFrameAlloc(Rc::new(RefCell::new(vec![0x3000, 0x2000, 0x1000])));
+        let rc_map = MockRcMap::default();
+        let handler =
+            CopyOnWrite::new(ByFrame::new(frames.clone()), frames.clone(), rc_map.clone());
+        let attr = MemoryAttr::default().user();
+
+        let mut pt = MockPageTable::new();
+        let h = handler.clone();
+        pt.set_handler(Box::new(move |pt, addr| {
+            assert!(h.handle_page_fault(pt, addr));
+        }));
+
+        // map 0x0 as the parent, 0x1000 as the child sharing the same frame
+        handler.map(&mut pt, 0x0, &attr);
+        pt.write(0x0, 1);
+        let target = handler.share(&mut pt, 0x0).unwrap();
+        handler.map_shared(&mut pt, 0x1000, Some(target), &attr);
+        assert_eq!(rc_map.0.borrow_mut().write_count(&(target / PAGE_SIZE)), 2);
+        assert!(!pt.get_entry(0x0).unwrap().writable());
+        assert_eq!(pt.read(0x1000), 1);
+
+        // the first writer copies the page
+        pt.write(0x0, 2);
+        assert_ne!(pt.get_entry(0x0).unwrap().target(), target);
+        assert_eq!(pt.read(0x0), 2);
+        assert_eq!(pt.read(0x1000), 1);
+        assert_eq!(rc_map.0.borrow_mut().write_count(&(target / PAGE_SIZE)), 1);
+
+        // the last writer takes the frame
+        pt.write(0x1000, 3);
+        assert_eq!(pt.get_entry(0x1000).unwrap().target(), target);
+        assert_eq!(pt.read(0x0), 2);
+        assert_eq!(pt.read(0x1000), 3);
+        assert_eq!(rc_map.0.borrow_mut().write_count(&(target / PAGE_SIZE)), 0);
+
+        handler.unmap(&mut pt, 0x0);
+        handler.unmap(&mut pt, 0x1000);
+        assert_eq!(frames.0.borrow().len(), 3);
+    }
+
+    #[test]
+    fn unmap_shared() {
+        let frames = MockFrameAlloc(Rc::new(RefCell::new(vec![0x1000])));
+        let rc_map = MockRcMap::default();
+        let handler =
+            CopyOnWrite::new(ByFrame::new(frames.clone()), frames.clone(), rc_map.clone());
+        let attr = MemoryAttr::default().user();
+
+        let mut pt = MockPageTable::new();
+        handler.map(&mut pt, 0x0, &attr);
+        let target = handler.share(&mut pt, 0x0).unwrap();
+        handler.map_shared(&mut pt, 0x1000, Some(target), &attr);
+
+        // the frame is freed after all shared pages are unmapped
+        handler.unmap(&mut pt, 0x0);
+        assert_eq!(frames.0.borrow().len(), 0);
+        handler.unmap(&mut pt, 0x1000);
+        assert_eq!(frames.0.borrow().len(), 1);
+    }
+
+    #[test]
+    fn protect_shared() {
+        let frames = MockFrameAlloc(Rc::new(RefCell::new(vec![0x2000, 0x1000])));
+        let rc_map = MockRcMap::default();
+        let handler =
+            CopyOnWrite::new(ByFrame::new(frames.clone()), frames.clone(), rc_map.clone());
+        let readonly = MemoryAttr::default().user().readonly();
+
+        let mut pt = MockPageTable::new();
+        let h = handler.clone();
+        pt.set_handler(Box::new(move |pt, addr| {
+            assert!(h.handle_page_fault(pt, addr));
+        }));
+        handler.map(&mut pt, 0x0, &readonly);
+        let target = handler.share(&mut pt, 0x0).unwrap();
+        handler.map_shared(&mut pt, 0x1000, Some(target), &readonly);
+        assert_eq!(rc_map.0.borrow_mut().read_count(&(target / PAGE_SIZE)), 2);
+
+        // the page made writable is still shared until written
+        handler.protect(&mut pt, 0x1000, &MemoryAttr::default().user());
+        assert!(!pt.get_entry(0x1000).unwrap().writable());
+        assert_eq!(rc_map.0.borrow_mut().read_count(&(target / PAGE_SIZE)), 1);
+        assert_eq!(rc_map.0.borrow_mut().write_count(&(target / PAGE_SIZE)), 1);
+
+        pt.write(0x1000, 1);
+        assert_ne!(pt.get_entry(0x1000).unwrap().target(), target);
+        assert!(!pt.get_entry(0x0).unwrap().writable());
+        assert_eq!(rc_map.0.borrow_mut().write_count(&(target / PAGE_SIZE)), 0);
+    }
+}
@@ -0,0 +1,144 @@
+use super::*;
+
+/// Read the content of a mapped file, provided by the OS
+pub trait Read: Debug + Clone + Send + 'static {
+    /// Read at `offset` of the file into `buf`, return the bytes read.
+    /// The rest of `buf` is filled with zero by the caller.
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize;
+}
+
+/// Private mapping of a file, like `Delay` but pages are read from the file on page faults
+#[derive(Debug, Clone)]
+pub struct File<F: Read, T: FrameAllocator> {
+    file: F,
+    mem_start: VirtAddr,
+    file_start: usize,
+    allocator: T,
+}
+
+impl<F: Read, T: FrameAllocator> MemoryHandler for File<F, T> {
+    fn box_clone(&self) -> Box<MemoryHandler> {
+        Box::new(self.clone())
+    }
+
+    fn map(&self, pt: &mut PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+        let entry = pt.map(addr, 0);
+        entry.set_present(false);
+        attr.apply(entry);
+    }
+
+    fn unmap(&self, pt: &mut PageTable, addr: VirtAddr) {
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        if entry.present() {
+            self.allocator.dealloc(entry.target());
+        }
+
+        // PageTable::unmap requires page to be present
+        entry.set_present(true);
+        pt.unmap(addr);
+    }
+
+    fn handle_page_fault(&self, pt: &mut PageTable, addr: VirtAddr) -> bool {
+        let addr = addr & !(PAGE_SIZE - 1);
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        if entry.present() {
+            // not a delay case
+            return false;
+        }
+        let frame = match self.allocator.alloc() {
+            Some(frame) => frame,
+            None => return false,
+        };
+        // the page may be readonly, make it writable to fill the data
+        let writable = entry.writable();
+        entry.set_target(frame);
+        entry.set_present(true);
+        entry.set_writable(true);
+        entry.update();
+
+        let offset = addr - self.mem_start + self.file_start;
+        let data = pt.get_page_slice_mut(addr);
+        let len = self.file.read_at(offset, data);
+        for x in data[len..].iter_mut() {
+            *x = 0;
+        }
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        entry.set_writable(writable);
+        entry.update();
+        true
+    }
+}
+
+impl<F: Read, T: FrameAllocator> File<F, T> {
+    /// Map `file` from `file_start` at `mem_start`
+    pub fn new(file: F, mem_start: VirtAddr, file_start: usize, allocator: T) -> Self {
+        File {
+            file,
+            mem_start,
+            file_start,
+            allocator,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::rc::Rc;
+    use core::cell::RefCell;
+
+    #[derive(Debug, Clone)]
+    struct MockFile;
+
+    impl Read for MockFile {
+        fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+            // a file of 0x1800 bytes, each byte is the page number of its offset plus one
+            let len = 0x1800usize.saturating_sub(offset).min(buf.len());
+            for (i, x) in buf[..len].iter_mut().enumerate() {
+                *x = ((offset + i) / PAGE_SIZE + 1) as u8;
+            }
+            len
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct MockFrameAlloc(Rc<RefCell<Vec<PhysAddr>>>);
+
+    impl FrameAllocator for MockFrameAlloc {
+        fn alloc(&self) -> Option<PhysAddr> {
+            self.0.borrow_mut().pop()
+        }
+        fn dealloc(&self, target: PhysAddr) {
+            self.0.borrow_mut().push(target);
+        }
+    }
+
+    #[test]
+    fn read_on_page_fault() {
+        let frames = MockFrameAlloc(Rc::new(RefCell::new(vec![0x3000, 0x2000])));
+        // map the file from offset 0x1000 at 0x4000
+        let handler = File::new(MockFile, 0x4000, 0x1000, frames.clone());
+        let attr = MemoryAttr::default().user();
+
+        let mut pt = MockPageTable::new();
+        let h = handler.clone();
+        pt.set_handler(Box::new(move |pt, addr| {
+            assert!(h.handle_page_fault(pt, addr));
+        }));
+        handler.map(&mut pt, 0x4000, &attr);
+        handler.map(&mut pt, 0x5000, &attr);
+        assert!(!pt.get_entry(0x4000).unwrap().present());
+
+        assert_eq!(pt.read(0x4000), 2);
+        assert_eq!(pt.read(0x47ff), 2);
+        // beyond the end of file
+        pt.write(0x4800, 1);
+        assert_eq!(pt.read(0x4801), 0);
+        assert_eq!(pt.read(0x5000), 0);
+        assert_eq!(frames.0.borrow().len(), 0);
+
+        handler.unmap(&mut pt, 0x4000);
+        handler.unmap(&mut pt, 0x5000);
+        assert_eq!(frames.0.borrow().len(), 2);
+    }
+}
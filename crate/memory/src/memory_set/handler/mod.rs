@@ -21,6 +21,39 @@ pub trait MemoryHandler: Debug + 'static {
     /// Handle page fault on `addr`
     /// Return true if success, false if error
     fn handle_page_fault(&self, pt: &mut PageTable, addr: VirtAddr) -> bool;
+
+    /// Whether the pages can be shared with a forked memory set by copy-on-write.
+    /// See `MemorySet::fork`.
+    fn is_cow(&self) -> bool {
+        false
+    }
+
+    /// Mark `addr` in the page table as shared, for a forked memory set.
+    /// Return the frame to share, or `None` if the page is not present.
+    fn share(&self, _pt: &mut PageTable, _addr: VirtAddr) -> Option<PhysAddr> {
+        unimplemented!("copy-on-write is not supported by this handler")
+    }
+
+    /// Map `addr` in the page table of a forked memory set,
+    /// to the frame `target` returned by `share`
+    fn map_shared(
+        &self,
+        _pt: &mut PageTable,
+        _addr: VirtAddr,
+        _target: Option<PhysAddr>,
+        _attr: &MemoryAttr,
+    ) {
+        unimplemented!("copy-on-write is not supported by this handler")
+    }
+
+    /// Write back `addr` in the page table if it is dirty, for shared file mappings
+    fn sync(&self, _pt: &mut PageTable, _addr: VirtAddr) {}
+
+    /// Apply the new attributes of the area to the mapped `addr`, e.g. on mprotect
+    fn protect(&self, pt: &mut PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        attr.apply(entry);
+    }
 }
 
 impl Clone for Box<MemoryHandler> {
@@ -35,10 +68,16 @@ pub trait FrameAllocator: Debug + Clone + 'static {
 }
 
 mod byframe;
+mod cow;
 mod delay;
+mod file;
 mod linear;
+mod shared;
 //mod swap;
 
 pub use self::byframe::ByFrame;
+pub use self::cow::{CopyOnWrite, FrameRcMapLock};
 pub use self::delay::Delay;
+pub use self::file::{File, Read};
 pub use self::linear::Linear;
+pub use self::shared::{Shared, SharedPages};
@@ -0,0 +1,194 @@
+use super::*;
+
+/// Frames shared by all mappers of a file or of shared anonymous memory,
+/// like the page cache, provided by the OS
+pub trait SharedPages: Debug + Clone + Send + 'static {
+    /// Get the frame of the page at `offset` and take a reference to it.
+    /// If the page is not cached, it is read in a new frame,
+    /// which `read_in` maps and returns the data of.
+    fn get<'a>(
+        &self,
+        offset: usize,
+        read_in: impl FnOnce(PhysAddr) -> &'a mut [u8],
+    ) -> Option<PhysAddr>;
+
+    /// Release the reference to the page at `offset` taken by `get`.
+    /// The page is to be written back later if it is `dirty`.
+    fn put(&self, offset: usize, dirty: bool);
+
+    /// Write back the page at `offset` from its `data`
+    fn sync(&self, offset: usize, data: &[u8]);
+}
+
+/// Shared mapping, whose pages are faulted in from `SharedPages`
+///
+/// Forked memory sets map the same pages, so it is not copy-on-write.
+#[derive(Debug, Clone)]
+pub struct Shared<S: SharedPages> {
+    pages: S,
+    mem_start: VirtAddr,
+    offset: usize,
+}
+
+impl<S: SharedPages> MemoryHandler for Shared<S> {
+    fn box_clone(&self) -> Box<MemoryHandler> {
+        Box::new(self.clone())
+    }
+
+    fn map(&self, pt: &mut PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+        let entry = pt.map(addr, 0);
+        entry.set_present(false);
+        attr.apply(entry);
+    }
+
+    fn unmap(&self, pt: &mut PageTable, addr: VirtAddr) {
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        if entry.present() {
+            self.pages.put(self.offset_of(addr), entry.dirty());
+        }
+
+        // PageTable::unmap requires page to be present
+        entry.set_present(true);
+        pt.unmap(addr);
+    }
+
+    fn handle_page_fault(&self, pt: &mut PageTable, addr: VirtAddr) -> bool {
+        let addr = addr & !(PAGE_SIZE - 1);
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        if entry.present() {
+            return false;
+        }
+        let writable = entry.writable();
+        let frame = self.pages.get(self.offset_of(addr), |frame| {
+            // the page may be readonly, make it writable to read in the data
+            let entry = pt.get_entry(addr).expect("failed to get entry");
+            entry.set_target(frame);
+            entry.set_present(true);
+            entry.set_writable(true);
+            entry.update();
+            pt.get_page_slice_mut(addr)
+        });
+        let frame = match frame {
+            Some(frame) => frame,
+            None => return false,
+        };
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        entry.set_target(frame);
+        entry.set_present(true);
+        entry.set_writable(writable);
+        entry.clear_dirty();
+        entry.update();
+        true
+    }
+
+    fn sync(&self, pt: &mut PageTable, addr: VirtAddr) {
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        if entry.present() && entry.dirty() {
+            entry.clear_dirty();
+            entry.update();
+            let data = pt.get_page_slice_mut(addr);
+            self.pages.sync(self.offset_of(addr), data);
+        }
+    }
+}
+
+impl<S: SharedPages> Shared<S> {
+    /// Map `pages` from `offset` at `mem_start`
+    pub fn new(pages: S, mem_start: VirtAddr, offset: usize) -> Self {
+        Shared {
+            pages,
+            mem_start,
+            offset,
+        }
+    }
+
+    fn offset_of(&self, addr: VirtAddr) -> usize {
+        (addr & !(PAGE_SIZE - 1)) - self.mem_start + self.offset
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::collections::BTreeMap;
+    use alloc::rc::Rc;
+    use core::cell::RefCell;
+
+    /// Pages at offset `n * PAGE_SIZE` are in frame `n * PAGE_SIZE`, with reference counts.
+    /// They are read in with the first byte set to 0xff.
+    #[derive(Debug, Clone, Default)]
+    struct MockPages {
+        refs: Rc<RefCell<BTreeMap<usize, usize>>>,
+        /// Offsets and the byte at 0x10 of the pages written back
+        written: Rc<RefCell<Vec<(usize, u8)>>>,
+        dirty: Rc<RefCell<Vec<usize>>>,
+    }
+
+    // SAFETY: only used in single-threaded tests
+    unsafe impl Send for MockPages {}
+
+    impl SharedPages for MockPages {
+        fn get<'a>(
+            &self,
+            offset: usize,
+            read_in: impl FnOnce(PhysAddr) -> &'a mut [u8],
+        ) -> Option<PhysAddr> {
+            let mut refs = self.refs.borrow_mut();
+            let count = refs.entry(offset).or_insert(0);
+            if *count == 0 {
+                read_in(offset)[0] = 0xff;
+            }
+            *count += 1;
+            Some(offset)
+        }
+        fn put(&self, offset: usize, dirty: bool) {
+            if dirty {
+                self.dirty.borrow_mut().push(offset);
+            }
+            *self.refs.borrow_mut().get_mut(&offset).unwrap() -= 1;
+        }
+        fn sync(&self, offset: usize, data: &[u8]) {
+            self.written.borrow_mut().push((offset, data[0x10]));
+        }
+    }
+
+    #[test]
+    fn shared() {
+        let pages = MockPages::default();
+        // two mappings of the pages from offset 0x1000
+        let handler0 = Shared::new(pages.clone(), 0x0, 0x1000);
+        let handler1 = Shared::new(pages.clone(), 0x2000, 0x1000);
+        let attr = MemoryAttr::default().user();
+
+        let mut pt = MockPageTable::new();
+        let (h0, h1) = (handler0.clone(), handler1.clone());
+        pt.set_handler(Box::new(move |pt, addr| match addr < 0x2000 {
+            true => assert!(h0.handle_page_fault(pt, addr)),
+            false => assert!(h1.handle_page_fault(pt, addr)),
+        }));
+        handler0.map(&mut pt, 0x0, &attr);
+        handler1.map(&mut pt, 0x2000, &attr);
+
+        // the same frame is mapped
+        assert_eq!(pt.read(0x0), 0xff);
+        pt.write(0x10, 1);
+        assert_eq!(pt.read(0x2010), 1);
+        assert_eq!(pt.get_entry(0x2000).unwrap().target(), 0x1000);
+        assert_eq!(pages.refs.borrow()[&0x1000], 2);
+
+        // only dirty pages are written back
+        handler0.sync(&mut pt, 0x0);
+        handler1.sync(&mut pt, 0x2000);
+        assert_eq!(*pages.written.borrow(), vec![(0x1000, 1)]);
+        handler0.sync(&mut pt, 0x0);
+        assert_eq!(pages.written.borrow().len(), 1);
+
+        // dirty pages are kept to be written back on unmap
+        pt.write(0x2010, 2);
+        handler1.unmap(&mut pt, 0x2000);
+        handler0.unmap(&mut pt, 0x0);
+        assert_eq!(*pages.dirty.borrow(), vec![0x1000]);
+        assert_eq!(pages.written.borrow().len(), 1);
+        assert_eq!(pages.refs.borrow()[&0x1000], 0);
+    }
+}
@@ -54,6 +54,26 @@ impl MemoryArea {
     pub fn contains(&self, addr: VirtAddr) -> bool {
         addr >= self.start_addr && addr < self.end_addr
     }
+    /// Get the start address of the area
+    pub fn start_addr(&self) -> VirtAddr {
+        self.start_addr
+    }
+    /// Get the end address of the area
+    pub fn end_addr(&self) -> VirtAddr {
+        self.end_addr
+    }
+    /// Get the attributes of the area
+    pub fn attr(&self) -> &MemoryAttr {
+        &self.attr
+    }
+    /// Get the name of the area
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+    /// Whether the pages of the area can be shared by copy-on-write, see `MemoryHandler::is_cow`
+    pub fn is_cow(&self) -> bool {
+        self.handler.is_cow()
+    }
     /// Check the array is within the readable memory
     fn check_read_array<S>(&self, ptr: *const S, count: usize) -> bool {
         // page align
@@ -168,6 +188,18 @@ impl MemoryAttr {
         self.mmio = value;
         self
     }
+    /// Whether it is accessible by the user
+    pub fn is_user(&self) -> bool {
+        self.user
+    }
+    /// Whether it is not writable
+    pub fn is_readonly(&self) -> bool {
+        self.readonly
+    }
+    /// Whether it is executable
+    pub fn is_execute(&self) -> bool {
+        self.execute
+    }
     /// Apply the attributes to page table entry, then update it.
     /// NOTE: You may need to set present manually.
     pub fn apply(&self, entry: &mut Entry) {
@@ -310,87 +342,147 @@ impl<T: InactivePageTable> MemorySet<T> {
      */
     pub fn pop_with_split(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) {
         assert!(start_addr <= end_addr, "invalid memory area");
+        self.split_at(start_addr);
+        self.split_at(end_addr);
         let mut i = 0;
         while i < self.areas.len() {
-            if self.areas[i].is_overlap_with(start_addr, end_addr) {
-                if self.areas[i].start_addr >= start_addr && self.areas[i].end_addr <= end_addr {
-                    // subset
-                    let area = self.areas.remove(i);
-                    self.page_table.edit(|pt| area.unmap(pt));
-                    i -= 1;
-                } else if self.areas[i].start_addr >= start_addr
-                    && self.areas[i].start_addr < end_addr
-                {
-                    // prefix
-                    let area = self.areas.remove(i);
-                    let dead_area = MemoryArea {
-                        start_addr: area.start_addr,
-                        end_addr,
-                        attr: area.attr,
-                        handler: area.handler.box_clone(),
-                        name: area.name,
-                    };
-                    self.page_table.edit(|pt| dead_area.unmap(pt));
-                    let new_area = MemoryArea {
-                        start_addr: end_addr,
-                        end_addr: area.end_addr,
-                        attr: area.attr,
-                        handler: area.handler,
-                        name: area.name,
-                    };
-                    self.areas.insert(i, new_area);
-                } else if self.areas[i].end_addr <= end_addr && self.areas[i].end_addr > start_addr
-                {
-                    // postfix
-                    let area = self.areas.remove(i);
-                    let dead_area = MemoryArea {
-                        start_addr: start_addr,
-                        end_addr: area.end_addr,
-                        attr: area.attr,
-                        handler: area.handler.box_clone(),
-                        name: area.name,
-                    };
-                    self.page_table.edit(|pt| dead_area.unmap(pt));
-                    let new_area = MemoryArea {
-                        start_addr: area.start_addr,
-                        end_addr: start_addr,
-                        attr: area.attr,
-                        handler: area.handler,
-                        name: area.name,
-                    };
-                    self.areas.insert(i, new_area);
-                } else {
-                    // superset
-                    let area = self.areas.remove(i);
-                    let dead_area = MemoryArea {
-                        start_addr: start_addr,
-                        end_addr: end_addr,
-                        attr: area.attr,
-                        handler: area.handler.box_clone(),
-                        name: area.name,
-                    };
-                    self.page_table.edit(|pt| dead_area.unmap(pt));
-                    let new_area_left = MemoryArea {
-                        start_addr: area.start_addr,
-                        end_addr: start_addr,
-                        attr: area.attr,
-                        handler: area.handler.box_clone(),
-                        name: area.name,
-                    };
-                    self.areas.insert(i, new_area_left);
-                    let new_area_right = MemoryArea {
-                        start_addr: end_addr,
-                        end_addr: area.end_addr,
-                        attr: area.attr,
-                        handler: area.handler,
-                        name: area.name,
-                    };
-                    self.areas.insert(i + 1, new_area_right);
-                    i += 1;
+            let area = &self.areas[i];
+            // empty areas are kept, like the heap before brk
+            if area.start_addr >= start_addr
+                && area.end_addr <= end_addr
+                && area.start_addr < area.end_addr
+            {
+                let area = self.areas.remove(i);
+                self.page_table.edit(|pt| area.unmap(pt));
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    /// Split the area containing `addr` into two at `addr`
+    fn split_at(&mut self, addr: VirtAddr) {
+        let i = match self
+            .areas
+            .iter()
+            .position(|area| area.start_addr < addr && addr < area.end_addr)
+        {
+            Some(i) => i,
+            None => return,
+        };
+        let area = &mut self.areas[i];
+        let right = MemoryArea {
+            start_addr: addr,
+            end_addr: area.end_addr,
+            attr: area.attr,
+            handler: area.handler.box_clone(),
+            name: area.name,
+        };
+        area.end_addr = addr;
+        self.areas.insert(i + 1, right);
+    }
+
+    /// Change the attributes of [`start_addr`, `end_addr`) to `attr`,
+    /// splitting the areas on the boundaries, and update the mapped pages.
+    /// Return false if some of the range is not mapped, then nothing is changed.
+    pub fn protect(&mut self, start_addr: VirtAddr, end_addr: VirtAddr, attr: MemoryAttr) -> bool {
+        assert!(start_addr <= end_addr, "invalid memory area");
+        let mut ranges: Vec<_> = self
+            .areas
+            .iter()
+            .filter(|area| area.start_addr < end_addr && start_addr < area.end_addr)
+            .map(|area| (area.start_addr, area.end_addr))
+            .collect();
+        ranges.sort();
+        let mut covered = start_addr;
+        for (start, end) in ranges {
+            if start > covered {
+                break;
+            }
+            covered = covered.max(end);
+        }
+        if covered < end_addr {
+            return false;
+        }
+
+        self.split_at(start_addr);
+        self.split_at(end_addr);
+        let Self {
+            ref mut page_table,
+            ref mut areas,
+        } = self;
+        page_table.edit(|pt| {
+            for area in areas.iter_mut() {
+                if area.start_addr < start_addr || area.end_addr > end_addr {
+                    continue;
+                }
+                area.attr = attr;
+                for page in Page::range_of(area.start_addr, area.end_addr) {
+                    area.handler.protect(pt, page.start_address(), &attr);
                 }
             }
-            i += 1;
+        });
+        true
+    }
+
+    /// Resize the area named `name` starting at `start_addr` to end at `end_addr`,
+    /// mapping or unmapping the pages in between. The end addresses should be page aligned.
+    /// Return false if there is no such area, or it would overlap with others.
+    ///
+    /// The name tells the area apart from an empty one mapped at the same address.
+    pub fn resize(&mut self, name: &str, start_addr: VirtAddr, end_addr: VirtAddr) -> bool {
+        assert!(start_addr <= end_addr, "invalid memory area");
+        let areas = &mut self.areas;
+        let i = match areas
+            .iter()
+            .position(|area| area.start_addr == start_addr && area.name == name)
+        {
+            Some(i) => i,
+            None => return false,
+        };
+        let old_end_addr = areas[i].end_addr;
+        if end_addr > old_end_addr {
+            let mut others = areas.iter().enumerate().filter(|&(j, _)| j != i);
+            if others.any(|(_, area)| area.is_overlap_with(old_end_addr, end_addr)) {
+                return false;
+            }
         }
+        let area = &mut areas[i];
+        let delta_area = MemoryArea {
+            start_addr: old_end_addr.min(end_addr),
+            end_addr: old_end_addr.max(end_addr),
+            attr: area.attr,
+            handler: area.handler.box_clone(),
+            name: area.name,
+        };
+        area.end_addr = end_addr;
+        if delta_area.start_addr < delta_area.end_addr {
+            self.page_table.edit(|pt| match end_addr > old_end_addr {
+                true => delta_area.map(pt),
+                false => delta_area.unmap(pt),
+            });
+        }
+        true
+    }
+
+    /// Write back the dirty pages in [`start_addr`, `end_addr`) of shared file mappings
+    pub fn sync(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) {
+        let Self {
+            ref mut page_table,
+            ref areas,
+        } = self;
+        page_table.edit(|pt| {
+            for area in areas.iter() {
+                let start = area.start_addr.max(start_addr);
+                let end = area.end_addr.min(end_addr);
+                if start >= end {
+                    continue;
+                }
+                for page in Page::range_of(start, end) {
+                    area.handler.sync(pt, page.start_address());
+                }
+            }
+        });
     }
 
     /*
@@ -426,6 +518,44 @@ impl<T: InactivePageTable> MemorySet<T> {
     pub fn token(&self) -> usize {
         self.page_table.token()
     }
+    /// Fork the memory set, sharing pages by copy-on-write.
+    ///
+    /// Pages of areas with a copy-on-write handler (`MemoryHandler::is_cow`)
+    /// are shared read-only by both memory sets, and copied on the first write.
+    /// Other areas are mapped eagerly like `clone`, but their data is not copied.
+    pub fn fork(&mut self) -> Self {
+        let Self {
+            ref mut page_table,
+            ref areas,
+        } = self;
+        let mut shared = Vec::new();
+        page_table.edit(|pt| {
+            for area in areas.iter().filter(|area| area.handler.is_cow()) {
+                for page in Page::range_of(area.start_addr, area.end_addr) {
+                    shared.push(area.handler.share(pt, page.start_address()));
+                }
+            }
+        });
+        let mut page_table = T::new();
+        page_table.edit(|pt| {
+            let mut shared = shared.into_iter();
+            for area in areas.iter() {
+                if !area.handler.is_cow() {
+                    area.map_eager(pt);
+                    continue;
+                }
+                for page in Page::range_of(area.start_addr, area.end_addr) {
+                    let target = shared.next().unwrap();
+                    area.handler
+                        .map_shared(pt, page.start_address(), target, &area.attr);
+                }
+            }
+        });
+        MemorySet {
+            areas: areas.clone(),
+            page_table,
+        }
+    }
     /*
      **  @brief  clear the memory set
      **  @retval none
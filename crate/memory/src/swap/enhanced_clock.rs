@@ -1,76 +1,84 @@
 //! Implememnt the swap manager with the enhanced clock page replacement algorithm
 
-use alloc::collections::VecDeque;
 use super::*;
-use crate::paging::Entry;
+use alloc::collections::VecDeque;
 
 #[derive(Default)]
 pub struct EnhancedClockSwapManager {
     clock_ptr: usize,
-    deque: VecDeque<VirtAddr>,
+    deque: VecDeque<Frame>,
 }
 
-// FIXME: It's unusable. But can pass a simple test.
 impl SwapManager for EnhancedClockSwapManager {
-    fn tick(&mut self) {
-    }
+    fn tick(&mut self) {}
 
-    fn push(&mut self, addr: usize) {
-        let pos = if self.clock_ptr == 0 {self.deque.len()} else {self.clock_ptr};
-        self.deque.insert(pos, addr);
+    fn push(&mut self, frame: Frame) {
+        // insert behind the clock hand, so the new page is checked last
+        self.deque.insert(self.clock_ptr, frame);
+        self.move_next();
     }
 
-    fn remove(&mut self, addr: usize) {
-        let id = self.deque.iter()
-            .position(|&x| x == addr)
+    fn remove(&mut self, token: usize, addr: VirtAddr) {
+        let id = self
+            .deque
+            .iter()
+            .position(|x| x.get_virtaddr() == addr && x.get_token() == token)
             .expect("address not found");
-        if id < self.clock_ptr {
-            self.clock_ptr -= 1;
+        self.remove_at(id);
+    }
+
+    fn remove_range(&mut self, token: usize, start: VirtAddr, end: VirtAddr) {
+        let mut id = 0;
+        while id < self.deque.len() {
+            let frame = &self.deque[id];
+            if frame.get_token() == token
+                && frame.get_virtaddr() >= start
+                && frame.get_virtaddr() < end
+            {
+                self.remove_at(id);
+            } else {
+                id += 1;
+            }
         }
-        self.deque.remove(id);
     }
 
-    fn pop<T, S>(&mut self, page_table: &mut T, _swapper: &mut S) -> Option<VirtAddr>
-        where T: PageTable, S: Swapper
+    fn pop<F>(&mut self, mut access: F) -> Option<Frame>
+    where
+        F: FnMut(&Frame) -> Option<(bool, bool)>,
     {
-        loop {
-            let addr = self.deque[self.clock_ptr];
-            // FIXME: Once define `slice`, all modifies of `entry` below will fail.
-            //        Then the loop will never stop.
-            //        The reason may be `get_page_slice_mut()` contains unsafe operation,
-            //        which lead the compiler to do a wrong optimization.
-//            let slice = page_table.get_page_slice_mut(addr);
-            let entry = page_table.get_entry(addr).unwrap();
-//            println!("{:#x} , {}, {}", addr, entry.accessed(), entry.dirty());
-
-            match (entry.accessed(), entry.dirty()) {
-                (true, _) => {
-                    entry.clear_accessed();
-                },
-                (false, true) => {
-                    // FIXME:
-//                    if let Ok(token) = swapper.swap_out(slice) {
-//                        entry.clear_dirty();
-//                    }
-                },
-                _ => {
-                    return self.remove_current();
+        // the first round clears the accessed bits,
+        // so a page neither accessed nor dirty is found in two rounds if there is any.
+        let mut dirty = None;
+        for _ in 0..self.deque.len() * 2 {
+            match access(&self.deque[self.clock_ptr]) {
+                Some((true, _)) => {}
+                Some((false, true)) => {
+                    if dirty.is_none() {
+                        dirty = Some(self.clock_ptr);
+                    }
                 }
+                _ => return self.remove_at(self.clock_ptr),
             }
             self.move_next();
         }
+        // all pages are dirty, take the first one found
+        let id = dirty.unwrap_or(self.clock_ptr);
+        self.remove_at(id)
     }
 }
 
-
 impl EnhancedClockSwapManager {
-    fn remove_current(&mut self) -> Option<VirtAddr> {
-        let addr = self.deque.remove(self.clock_ptr);
+    fn remove_at(&mut self, id: usize) -> Option<Frame> {
+        let frame = self.deque.remove(id);
+        if id < self.clock_ptr {
+            self.clock_ptr -= 1;
+        }
         if self.clock_ptr == self.deque.len() {
             self.clock_ptr = 0;
         }
-        return addr;
+        frame
     }
+
     fn move_next(&mut self) {
         self.clock_ptr += 1;
         if self.clock_ptr == self.deque.len() {
@@ -82,19 +90,35 @@ impl EnhancedClockSwapManager {
 #[cfg(test)]
 mod test {
     use super::*;
-    use swap::test::*;
 
     #[test]
     fn test() {
-        use self::MemOp::{R, W};
-        let ops = [
-            R(0x1000), R(0x2000), R(0x3000), R(0x4000),
-            R(0x3000), W(0x1000), R(0x4000), W(0x2000), R(0x5000),
-            R(0x2000), W(0x1000), R(0x2000), R(0x3000), R(0x4000)];
-        let pgfault_count = [
-            1, 2, 3, 4,
-            4, 4, 4, 4, 5,
-            5, 5, 5, 6, 7];
-        test_manager(EnhancedClockSwapManager::default(), &ops, &pgfault_count);
+        let mut manager = EnhancedClockSwapManager::default();
+        // (accessed, dirty) of pages 0x1000..0x5000
+        let mut bits = [
+            (false, false),
+            (true, false),
+            (false, true),
+            (false, false),
+            (true, true),
+        ];
+        for i in 1..5 {
+            manager.push(Frame::new(0, i * 0x1000, 0));
+        }
+        let mut pop = || {
+            manager
+                .pop(|frame| {
+                    let bits = &mut bits[frame.get_virtaddr() / 0x1000];
+                    let old = *bits;
+                    bits.0 = false;
+                    Some(old)
+                })
+                .map(|frame| frame.get_virtaddr())
+        };
+        assert_eq!(pop(), Some(0x3000));
+        assert_eq!(pop(), Some(0x1000));
+        assert_eq!(pop(), Some(0x2000));
+        assert_eq!(pop(), Some(0x4000));
+        assert_eq!(pop(), None);
     }
 }
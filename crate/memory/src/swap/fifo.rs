@@ -31,10 +31,15 @@ impl SwapManager for FifoSwapManager {
         //info!("SwapManager remove token finished: {:x?} vaddr: {:x?}", token, addr);
     }
 
-    fn pop<T, S>(&mut self, _: &mut T, _: &mut S) -> Option<Frame>
+    fn remove_range(&mut self, token: usize, start: VirtAddr, end: VirtAddr) {
+        self.deque.retain(|x| {
+            x.get_token() != token || x.get_virtaddr() < start || x.get_virtaddr() >= end
+        });
+    }
+
+    fn pop<F>(&mut self, _: F) -> Option<Frame>
     where
-        T: PageTable,
-        S: Swapper,
+        F: FnMut(&Frame) -> Option<(bool, bool)>,
     {
         self.deque.pop_front()
     }
@@ -14,8 +14,8 @@ use core::ops::{Deref, DerefMut};
 //pub use self::fifo::FifoSwapManager;
 //pub use self::enhanced_clock::EnhancedClockSwapManager;
 
+pub mod enhanced_clock;
 pub mod fifo;
-//mod enhanced_clock;
 pub mod mock_swapper;
 //#[cfg(test)]
 //mod mock_swapper;
@@ -44,17 +44,24 @@ pub trait SwapManager {
      **  @retval none
      */
     fn remove(&mut self, token: usize, addr: VirtAddr);
+    /*
+     **  @brief  update intarnal state when pages are removed from memory
+     **          Called to delete all entries in the range from the swap manager
+     **  @param  token: usize         the inactive page table token for the virtual addresses
+     **  @param  start: VirtAddr      the start virual address of the range
+     **  @param  end: VirtAddr        the end virual address of the range (exclusive)
+     **  @retval none
+     */
+    fn remove_range(&mut self, token: usize, start: VirtAddr, end: VirtAddr);
     /*
      **  @brief  select swap out victim when there is need to swap out a page
-     **          (The params is only used by `EnhancedClockSwapManager` currently)
-     **  @param  page_table: &mut T   the current page table
-     **  @param  swapper: &mut S      the swapper used
-     **  @retval Option<Frame>     the Frame of the victim page, if present
+     **  @param  access: F            get the accessed and dirty bits of a page and clear the accessed bit,
+     **                               or None if they can't be read, then the page may be selected
+     **  @retval Option<Frame>        the Frame of the victim page, if present
      */
-    fn pop<T, S>(&mut self, page_table: &mut T, swapper: &mut S) -> Option<Frame>
+    fn pop<F>(&mut self, access: F) -> Option<Frame>
     where
-        T: PageTable,
-        S: Swapper;
+        F: FnMut(&Frame) -> Option<(bool, bool)>;
 }
 
 /// Implement swap in & out execution
@@ -215,21 +222,63 @@ impl<T: PageTable, M: SwapManager, S: Swapper> SwapExt<T, M, S> {
      */
     pub fn swap_out_any<T2: InactivePageTable>(&mut self) -> Result<PhysAddr, SwapError> {
         info!("COME in to swap_out_any");
-        let victim: Option<Frame> = {
-            let Self {
-                ref mut page_table,
-                ref mut swap_manager,
-                ref mut swapper,
-            } = self;
-            swap_manager.pop(page_table, swapper)
-        };
-        info!("swap out page {}", victim.unwrap().get_virtaddr());
+        let Self {
+            ref mut page_table,
+            ref mut swap_manager,
+            ..
+        } = self;
+        let victim = swap_manager.pop(|frame| unsafe {
+            let pt = &*(frame.get_page_table() as *const T2);
+            pt.with(|| {
+                let entry = page_table.get_entry(frame.get_virtaddr())?;
+                let bits = (entry.accessed(), entry.dirty());
+                entry.clear_accessed();
+                entry.update();
+                Some(bits)
+            })
+        });
         match victim {
             None => Err(SwapError::NoSwapped),
-            Some(frame) => self.swap_out::<T2>(&frame),
+            Some(frame) => {
+                info!("swap out page {:#x}", frame.get_virtaddr());
+                self.swap_out::<T2>(&frame)
+            }
         }
     }
 
+    /*
+     **  @brief  Select a victim page to swap out by the swap manager, and remove it from the swappable pages
+     **  @param  access: F            get the accessed and dirty bits of a page and clear the accessed bit,
+     **                               see `SwapManager::pop`
+     **  @retval Option<Frame>        the Frame of the victim page, if there is any swappable page
+     */
+    pub fn select_victim<F>(&mut self, access: F) -> Option<Frame>
+    where
+        F: FnMut(&Frame) -> Option<(bool, bool)>,
+    {
+        self.swap_manager.pop(access)
+    }
+
+    /*
+     **  @brief  Remove the pages in the range from swappable pages
+     **          Called before the pages are unmapped
+     **  @param  token: usize         the inactive page table token for the virtual addresses
+     **  @param  start: VirtAddr      the start virual address of the range
+     **  @param  end: VirtAddr        the end virual address of the range (exclusive)
+     **  @retval none
+     */
+    pub fn remove_range(&mut self, token: usize, start: VirtAddr, end: VirtAddr) {
+        self.swap_manager.remove_range(token, start, end);
+    }
+
+    /*
+     **  @brief  Get the swapper used
+     **  @retval &mut S               the swapper
+     */
+    pub fn swapper(&mut self) -> &mut S {
+        &mut self.swapper
+    }
+
     /*
      **  @brief  Swap out page
      **  @param  frame: Frame       the Frame of page recording the page info
@@ -237,7 +286,10 @@ impl<T: PageTable, M: SwapManager, S: Swapper> SwapExt<T, M, S> {
      **                               the physics address of the original map target frame if success,
      **                               the error if failed
      */
-    fn swap_out<T2: InactivePageTable>(&mut self, frame: &Frame) -> Result<PhysAddr, SwapError> {
+    pub fn swap_out<T2: InactivePageTable>(
+        &mut self,
+        frame: &Frame,
+    ) -> Result<PhysAddr, SwapError> {
         let Self {
             ref mut page_table,
             ref mut swapper,
@@ -248,13 +300,20 @@ impl<T: PageTable, M: SwapManager, S: Swapper> SwapExt<T, M, S> {
             pt.with(|| {
                 //use core::slice;
                 //let data = unsafe { slice::from_raw_parts_mut((frame.virtaddr & !(PAGE_SIZE - 1)) as *mut u8, PAGE_SIZE) };
-                let data = page_table.get_page_slice_mut(frame.get_virtaddr());
                 let entry = page_table
                     .get_entry(frame.get_virtaddr())
                     .ok_or(SwapError::NotMapped)?;
                 if entry.swapped() {
                     return Err(SwapError::AlreadySwapped);
                 }
+                if !entry.present() {
+                    return Err(SwapError::NotMapped);
+                }
+                if entry.readonly_shared() || entry.writable_shared() {
+                    return Err(SwapError::Shared);
+                }
+                let data = page_table.get_page_slice_mut(frame.get_virtaddr());
+                let entry = page_table.get_entry(frame.get_virtaddr()).unwrap();
                 //assert!(!entry.swapped(), "Page already swapped!");
                 let token = swapper.swap_out(data).map_err(|_| SwapError::IOError)?;
                 //let token = swapper.swap_out(data).unwrap();
@@ -276,7 +335,7 @@ impl<T: PageTable, M: SwapManager, S: Swapper> SwapExt<T, M, S> {
      **  @retval Result<()), SwapError>
      **                               the execute result, and the error if failed
      */
-    fn swap_in<T2: InactivePageTable>(
+    pub fn swap_in<T2: InactivePageTable>(
         &mut self,
         pt: *mut T2,
         addr: VirtAddr,
@@ -291,17 +350,21 @@ impl<T: PageTable, M: SwapManager, S: Swapper> SwapExt<T, M, S> {
             return Err(SwapError::NotSwapped);
         }
         let token = entry.target() / PAGE_SIZE;
+        // the page may be readonly, make it writable to fill the data
+        let writable = entry.writable();
         entry.set_target(target);
         entry.set_swapped(false);
         entry.set_present(true);
+        entry.set_writable(true);
         entry.update();
         let data = self.page_table.get_page_slice_mut(addr);
-        self.swapper
-            .swap_in(token, data)
-            .map_err(|_| SwapError::IOError)?;
+        let result = self.swapper.swap_in(token, data);
+        let entry = self.page_table.get_entry(addr).unwrap();
+        entry.set_writable(writable);
+        entry.update();
+        result.map_err(|_| SwapError::IOError)?;
         let pttoken = unsafe { (*pt).token() };
         let frame = Frame::new(pt as usize, addr, pttoken);
-;
         self.swap_manager.push(frame);
         Ok(())
     }
@@ -378,6 +441,8 @@ pub enum SwapError {
     NotMapped,
     /// attempt to swap in a page that is already in the memory
     NotSwapped,
+    /// attempt to swap out a page shared by copy-on-write
+    Shared,
     /// there are no page to be swapped out
     NoSwapped,
     /// swap failed due to IO error while interact with device
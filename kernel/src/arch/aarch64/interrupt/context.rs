@@ -40,8 +40,43 @@ impl TrapFrame {
         tf.spsr = 0b1101_00_0000; // To EL 0, enable IRQ
         tf
     }
+}
+
+/// Helpers for signal delivery
+impl TrapFrame {
     pub fn is_user(&self) -> bool {
-        unimplemented!()
+        // SPSR_EL1.M[3:0] == 0b0000 means EL0t
+        self.spsr & 0b1111 == 0
+    }
+    pub fn get_sp(&self) -> usize {
+        self.sp
+    }
+    pub fn set_sp(&mut self, sp: usize) {
+        self.sp = sp;
+    }
+    /// Run the signal `handler` on stack `sp` with `args`, returning to `ret_addr`.
+    pub fn setup_signal_handler(
+        &mut self,
+        handler: usize,
+        sp: usize,
+        ret_addr: usize,
+        args: [usize; 3],
+    ) {
+        self.elr = handler;
+        self.sp = sp;
+        self.x30 = ret_addr; // lr
+        self.x0 = args[0];
+        self.x1to29[0] = args[1];
+        self.x1to29[1] = args[2];
+    }
+    /// Restore the user context saved before running a signal handler.
+    /// Privileged states are kept untouched. Return false if it can not be returned to.
+    pub fn restore_signal_context(&mut self, saved: &TrapFrame) -> bool {
+        const NZCV: usize = 0xf000_0000;
+        let spsr = self.spsr;
+        *self = *saved;
+        self.spsr = (spsr & !NZCV) | (saved.spsr & NZCV);
+        true
     }
 }
 
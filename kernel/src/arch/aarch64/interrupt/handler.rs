@@ -63,6 +63,9 @@ pub extern "C" fn rust_trap(info: Info, esr: u32, tf: &mut TrapFrame) {
         Kind::Irq => handle_irq(tf),
         _ => crate::trap::error(tf),
     }
+    if tf.is_user() {
+        crate::process::signal::handle_signal(tf);
+    }
     trace!("Interrupt end");
 }
 
@@ -77,8 +80,9 @@ fn handle_syscall(num: u16, tf: &mut TrapFrame) {
     }
 
     // svc instruction has been skipped in syscall (ref: J1.1.2, page 6152)
+    let id = tf.x1to29[7] as usize;
     let ret = crate::syscall::syscall(
-        tf.x1to29[7] as usize,
+        id,
         [
             tf.x0,
             tf.x1to29[0],
@@ -89,7 +93,10 @@ fn handle_syscall(num: u16, tf: &mut TrapFrame) {
         ],
         tf,
     );
-    tf.x0 = ret as usize;
+    // rt_sigreturn has restored all registers
+    if id != crate::arch::syscall::SYS_RT_SIGRETURN {
+        tf.x0 = ret as usize;
+    }
 }
 
 fn handle_page_fault(tf: &mut TrapFrame) {
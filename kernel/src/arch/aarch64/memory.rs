@@ -16,7 +16,6 @@ pub fn init() {
 }
 
 fn init_frame_allocator() {
-    use bitmap_allocator::BitAlloc;
     use core::ops::Range;
 
     let end = super::board::probe_memory()
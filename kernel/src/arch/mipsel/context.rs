@@ -89,6 +89,46 @@ impl TrapFrame {
     }
 }
 
+/// Helpers for signal delivery
+impl TrapFrame {
+    pub fn is_user(&self) -> bool {
+        // KSU field of CP0 status
+        (self.status.bits >> 3) & 0b11 == 0b10
+    }
+    pub fn get_sp(&self) -> usize {
+        self.sp
+    }
+    pub fn set_sp(&mut self, sp: usize) {
+        self.sp = sp;
+    }
+    /// Run the signal `handler` on stack `sp` with `args`, returning to `ret_addr`.
+    pub fn setup_signal_handler(
+        &mut self,
+        handler: usize,
+        sp: usize,
+        ret_addr: usize,
+        args: [usize; 3],
+    ) {
+        self.epc = handler;
+        self.sp = sp;
+        self.ra = ret_addr;
+        self.t9 = handler; // PIC code computes gp from t9
+        self.a0 = args[0];
+        self.a1 = args[1];
+        self.a2 = args[2];
+    }
+    /// Restore the user context saved before running a signal handler.
+    /// Privileged states are kept untouched. Return false if it can not be returned to.
+    pub fn restore_signal_context(&mut self, saved: &TrapFrame) -> bool {
+        let mut restored = saved.clone();
+        core::mem::swap(&mut restored.status, &mut self.status);
+        core::mem::swap(&mut restored.cause, &mut self.cause);
+        restored.vaddr = self.vaddr;
+        *self = restored;
+        true
+    }
+}
+
 use core::fmt::{Debug, Error, Formatter};
 impl Debug for TrapFrame {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
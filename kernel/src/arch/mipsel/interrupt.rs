@@ -67,9 +67,9 @@ pub extern "C" fn rust_trap(tf: &mut TrapFrame) {
     match tf.cause.cause() {
         E::Interrupt => interrupt_dispatcher(tf),
         E::Syscall => syscall(tf),
-        E::TLBModification => page_fault(tf),
-        E::TLBLoadMiss => page_fault(tf),
-        E::TLBStoreMiss => page_fault(tf),
+        E::TLBModification => page_fault(tf, true),
+        E::TLBLoadMiss => page_fault(tf, false),
+        E::TLBStoreMiss => page_fault(tf, false),
         E::ReservedInstruction => {
             if !reserved_inst(tf) {
                 error!("Unhandled Exception @ CPU{}: {:?} ", 0, tf.cause.cause());
@@ -86,6 +86,9 @@ pub extern "C" fn rust_trap(tf: &mut TrapFrame) {
             crate::trap::error(tf)
         }
     }
+    if tf.is_user() {
+        crate::process::signal::handle_signal(tf);
+    }
     trace!("Interrupt end");
 }
 
@@ -148,7 +151,12 @@ fn syscall(tf: &mut TrapFrame) {
     let arguments = [tf.a0, tf.a1, tf.a2, tf.a3, tf.t0, tf.t1];
     trace!("MIPS syscall {} invoked with {:?}", tf.v0, arguments);
 
-    let ret = crate::syscall::syscall(tf.v0, arguments, tf) as isize;
+    let id = tf.v0;
+    let ret = crate::syscall::syscall(id, arguments, tf) as isize;
+    if id == super::syscall::SYS_RT_SIGRETURN {
+        // all registers have been restored
+        return;
+    }
     // comply with mips n32 abi, always return a positive value
     // https://git.musl-libc.org/cgit/musl/tree/arch/mipsn32/syscall_arch.h
     if (ret < 0) {
@@ -229,7 +237,8 @@ fn reserved_inst(tf: &mut TrapFrame) -> bool {
     false
 }
 
-fn page_fault(tf: &mut TrapFrame) {
+/// Handle TLB misses, and writes to readonly pages if `modification`
+fn page_fault(tf: &mut TrapFrame, modification: bool) {
     // TODO: set access/dirty bit
     let addr = tf.vaddr;
     trace!("\nEXCEPTION: Page Fault @ {:#x}", addr);
@@ -251,10 +260,15 @@ fn page_fault(tf: &mut TrapFrame) {
                 tlb_entry.entry_lo1.valid()
             };
 
-            if !tlb_valid {
+            if !tlb_valid || modification {
                 if !crate::memory::handle_page_fault(addr) {
                     crate::trap::error(tf);
                 }
+                if modification {
+                    // the entry may have been changed, e.g. copied on write,
+                    // it is refilled on the next TLB miss
+                    return;
+                }
             }
 
             tlb::write_tlb_random(tlb_entry)
@@ -121,13 +121,19 @@ impl Entry for PageEntry {
         self.0.set(frame, flags);
     }
     fn writable_shared(&self) -> bool {
-        false
+        self.0.flags().contains(EF::RESERVED1)
     }
     fn readonly_shared(&self) -> bool {
-        false
+        self.0.flags().contains(EF::RESERVED2)
+    }
+    fn set_shared(&mut self, writable: bool) {
+        let flags = self.0.flags_mut();
+        flags.set(EF::RESERVED1, writable);
+        flags.set(EF::RESERVED2, !writable);
+    }
+    fn clear_shared(&mut self) {
+        self.0.flags_mut().remove(EF::RESERVED1 | EF::RESERVED2);
     }
-    fn set_shared(&mut self, writable: bool) {}
-    fn clear_shared(&mut self) {}
     fn swapped(&self) -> bool {
         self.0.flags().contains(EF::RESERVED1)
     }
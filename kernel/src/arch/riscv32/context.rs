@@ -53,6 +53,41 @@ impl TrapFrame {
     }
 }
 
+/// Helpers for signal delivery
+impl TrapFrame {
+    pub fn is_user(&self) -> bool {
+        self.sstatus.spp() == sstatus::SPP::User
+    }
+    pub fn get_sp(&self) -> usize {
+        self.x[2]
+    }
+    pub fn set_sp(&mut self, sp: usize) {
+        self.x[2] = sp;
+    }
+    /// Run the signal `handler` on stack `sp` with `args`, returning to `ret_addr`.
+    pub fn setup_signal_handler(
+        &mut self,
+        handler: usize,
+        sp: usize,
+        ret_addr: usize,
+        args: [usize; 3],
+    ) {
+        self.sepc = handler;
+        self.x[2] = sp;
+        self.x[1] = ret_addr; // ra
+        self.x[10] = args[0];
+        self.x[11] = args[1];
+        self.x[12] = args[2];
+    }
+    /// Restore the user context saved before running a signal handler.
+    /// Privileged states are kept untouched. Return false if it can not be returned to.
+    pub fn restore_signal_context(&mut self, saved: &TrapFrame) -> bool {
+        self.x = saved.x;
+        self.sepc = saved.sepc;
+        true
+    }
+}
+
 use core::fmt::{Debug, Error, Formatter};
 impl Debug for TrapFrame {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
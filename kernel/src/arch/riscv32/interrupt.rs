@@ -70,6 +70,9 @@ pub extern "C" fn rust_trap(tf: &mut TrapFrame) {
         Trap::Exception(E::InstructionPageFault) => page_fault(tf),
         _ => crate::trap::error(tf),
     }
+    if tf.is_user() {
+        crate::process::signal::handle_signal(tf);
+    }
     trace!("Interrupt end");
 }
 
@@ -119,12 +122,16 @@ fn timer() {
 
 fn syscall(tf: &mut TrapFrame) {
     tf.sepc += 4; // Must before syscall, because of fork.
+    let id = tf.x[17];
     let ret = crate::syscall::syscall(
-        tf.x[17],
+        id,
         [tf.x[10], tf.x[11], tf.x[12], tf.x[13], tf.x[14], tf.x[15]],
         tf,
     );
-    tf.x[10] = ret as usize;
+    // rt_sigreturn has restored all registers
+    if id != super::syscall::SYS_RT_SIGRETURN {
+        tf.x[10] = ret as usize;
+    }
 }
 
 fn page_fault(tf: &mut TrapFrame) {
@@ -66,6 +66,7 @@
 
 use super::consts::*;
 use super::TrapFrame;
+use crate::arch::syscall::SYS_RT_SIGRETURN;
 use crate::drivers::DRIVERS;
 use bitflags::*;
 use log::*;
@@ -111,6 +112,9 @@ pub extern "C" fn rust_trap(tf: &mut TrapFrame) {
         DivideError | GeneralProtectionFault => error(tf),
         _ => panic!("Unhandled interrupt {:x}", tf.trap_num),
     }
+    if tf.is_user() {
+        crate::process::signal::handle_signal(tf);
+    }
 }
 
 fn breakpoint() {
@@ -185,11 +189,18 @@ fn ide() {
     trace!("\nInterupt: IDE");
 }
 
+/// Return whether the context is returned to by `iretq`, since `sysretq` clobbers rcx and r11
 #[no_mangle]
-pub extern "C" fn syscall(tf: &mut TrapFrame) {
+pub extern "C" fn syscall(tf: &mut TrapFrame) -> bool {
     trace!("\nInterupt: Syscall {:#x?}", tf.rax);
+    let id = tf.rax;
     let ret = crate::syscall::syscall(tf.rax, [tf.rdi, tf.rsi, tf.rdx, tf.r10, tf.r8, tf.r9], tf);
-    tf.rax = ret as usize;
+    // rt_sigreturn has restored all registers
+    if id != SYS_RT_SIGRETURN {
+        tf.rax = ret as usize;
+    }
+    crate::process::signal::handle_signal(tf);
+    id == SYS_RT_SIGRETURN
 }
 
 fn syscall32(tf: &mut TrapFrame) {
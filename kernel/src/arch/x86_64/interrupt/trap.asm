@@ -173,6 +173,10 @@ syscall_return:
     # disable interrupt
     cli
 
+    # the context restored by rt_sigreturn needs its rcx and r11
+    test al, al
+    jnz trap_ret
+
     mov rdi, rsp
     call set_return_rsp
 
@@ -86,6 +86,58 @@ impl TrapFrame {
     }
 }
 
+/// Helpers for signal delivery
+impl TrapFrame {
+    pub fn is_user(&self) -> bool {
+        self.cs & 0x3 == 0x3
+    }
+    pub fn get_sp(&self) -> usize {
+        self.rsp
+    }
+    pub fn set_sp(&mut self, sp: usize) {
+        self.rsp = sp;
+    }
+    /// Run the signal `handler` on stack `sp` with `args`.
+    /// The return address has been pushed at `sp`.
+    pub fn setup_signal_handler(
+        &mut self,
+        handler: usize,
+        sp: usize,
+        _ret_addr: usize,
+        args: [usize; 3],
+    ) {
+        self.rip = handler;
+        self.rsp = sp;
+        self.rdi = args[0];
+        self.rsi = args[1];
+        self.rdx = args[2];
+    }
+    /// Restore the user context saved before running a signal handler.
+    /// Privileged states are kept untouched.
+    /// Return false if its rip or fs base is not canonical, which would fault in the kernel.
+    pub fn restore_signal_context(&mut self, saved: &TrapFrame) -> bool {
+        // CF, PF, AF, ZF, SF, DF, OF
+        const USER_FLAGS: usize = 0xcd5;
+        if !is_canonical(saved.rip) || !is_canonical(saved.fsbase) {
+            return false;
+        }
+        let (cs, ss, rflags) = (self.cs, self.ss, self.rflags);
+        let fpstate_offset = self.fpstate_offset;
+        *self = saved.clone();
+        self.cs = cs;
+        self.ss = ss;
+        self.rflags = (rflags & !USER_FLAGS) | (saved.rflags & USER_FLAGS);
+        self.fpstate_offset = fpstate_offset;
+        true
+    }
+}
+
+/// Whether bits 63:47 of `addr` are all the same
+fn is_canonical(addr: usize) -> bool {
+    let high = addr >> 47;
+    high == 0 || high == (1 << 17) - 1
+}
+
 #[derive(Debug, Default)]
 #[repr(C)]
 struct ContextData {
@@ -1,5 +1,4 @@
 use crate::consts::KERNEL_OFFSET;
-use bitmap_allocator::BitAlloc;
 // Depends on kernel
 use super::{BootInfo, MemoryRegionType};
 use crate::memory::{active_table, alloc_frame, init_heap, FRAME_ALLOCATOR};
@@ -6,3 +6,7 @@ pub const MAX_CPU_NUM: usize = 64;
 pub const MAX_PROCESS_NUM: usize = 128;
 
 pub const USEC_PER_TICK: usize = 10000;
+
+/// Address space reserved for the heap after the program break,
+/// where mmap without an address hint will not be placed.
+pub const USER_HEAP_SIZE: usize = 256 * 1024 * 1024;
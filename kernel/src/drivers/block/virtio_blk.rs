@@ -127,6 +127,10 @@ impl Driver for VirtIOBlkDriver {
         format!("virtio_block")
     }
 
+    fn block_count(&self) -> Option<usize> {
+        Some(self.0.lock().capacity)
+    }
+
     fn read_block(&self, block_id: usize, buf: &mut [u8]) -> bool {
         let mut driver = self.0.lock();
         // ensure header page is mapped
@@ -219,6 +219,19 @@ impl Driver for VirtIOGpuDriver {
     fn get_id(&self) -> String {
         format!("virtio_gpu")
     }
+
+    fn get_framebuffer(&self) -> (usize, u32, u32) {
+        let driver = self.0.lock();
+        (driver.frame_buffer, driver.rect.width, driver.rect.height)
+    }
+
+    fn flush_framebuffer(&self) {
+        let mut driver = self.0.lock();
+        // ensure header page is mapped
+        let header_addr = &*driver.header as *const VirtIOHeader as usize;
+        active_table().map_if_not_exists(header_addr, header_addr);
+        flush_frame_buffer_to_screen(&mut driver);
+    }
 }
 
 fn request(driver: &mut VirtIOGpu) {
@@ -6,7 +6,6 @@ use lazy_static::lazy_static;
 use smoltcp::wire::{EthernetAddress, IpAddress, IpCidr, Ipv4Address};
 use spin::RwLock;
 
-use crate::sync::Condvar;
 use rcore_fs::dev::BlockDevice;
 
 #[allow(dead_code)]
@@ -89,6 +88,22 @@ pub trait Driver: Send + Sync {
     fn write_block(&self, block_id: usize, buf: &[u8]) -> bool {
         unimplemented!("not a block driver")
     }
+
+    // number of 512-byte blocks, if the driver knows it
+    fn block_count(&self) -> Option<usize> {
+        None
+    }
+
+    // gpu related drivers should implement these
+    // get the address, width and height of the frame buffer in 32-bit pixels
+    fn get_framebuffer(&self) -> (usize, u32, u32) {
+        unimplemented!("not a gpu driver")
+    }
+
+    // copy the frame buffer to the screen
+    fn flush_framebuffer(&self) {
+        unimplemented!("not a gpu driver")
+    }
 }
 
 lazy_static! {
@@ -100,6 +115,14 @@ lazy_static! {
 
 pub struct BlockDriver(Arc<Driver>);
 
+impl BlockDriver {
+    /// Size of the device in bytes, if the driver knows it
+    pub fn size(&self) -> Option<usize> {
+        let count = self.0.block_count()?;
+        Some(count << <Self as BlockDevice>::BLOCK_SIZE_LOG2)
+    }
+}
+
 impl BlockDevice for BlockDriver {
     const BLOCK_SIZE_LOG2: u8 = 9; // 512
     fn read_at(&self, block_id: usize, buf: &mut [u8]) -> bool {
@@ -111,10 +134,6 @@ impl BlockDevice for BlockDriver {
     }
 }
 
-lazy_static! {
-    pub static ref SOCKET_ACTIVITY: Condvar = Condvar::new();
-}
-
 #[cfg(any(target_arch = "riscv32", target_arch = "riscv64", target_arch = "mips"))]
 pub fn init(dtb: usize) {
     device_tree::init(dtb);
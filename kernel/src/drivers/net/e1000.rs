@@ -17,10 +17,10 @@ use isomorphic_drivers::net::ethernet::structs::EthernetAddress as DriverEtherne
 use rcore_memory::PAGE_SIZE;
 
 use crate::drivers::provider::Provider;
-use crate::net::SOCKETS;
+use crate::net::{poll_sockets, SOCKETS};
 use crate::sync::SpinNoIrqLock as Mutex;
 
-use super::super::{DeviceType, Driver, DRIVERS, NET_DRIVERS, SOCKET_ACTIVITY};
+use super::super::{DeviceType, Driver, DRIVERS, NET_DRIVERS};
 
 #[derive(Clone)]
 pub struct E1000Driver(Arc<Mutex<E1000<Provider>>>);
@@ -44,13 +44,11 @@ impl Driver for E1000Interface {
         if data {
             let timestamp = Instant::from_millis(crate::trap::uptime_msec() as i64);
             let mut sockets = SOCKETS.lock();
-            match self.iface.lock().poll(&mut sockets, timestamp) {
-                Ok(_) => {
-                    SOCKET_ACTIVITY.notify_all();
-                }
-                Err(err) => {
-                    debug!("poll got err {}", err);
-                }
+            let result = poll_sockets(&mut sockets, |sockets| {
+                self.iface.lock().poll(sockets, timestamp)
+            });
+            if let Err(err) = result {
+                debug!("poll got err {}", err);
             }
         }
 
@@ -85,13 +83,11 @@ impl Driver for E1000Interface {
     fn poll(&self) {
         let timestamp = Instant::from_millis(crate::trap::uptime_msec() as i64);
         let mut sockets = SOCKETS.lock();
-        match self.iface.lock().poll(&mut sockets, timestamp) {
-            Ok(_) => {
-                SOCKET_ACTIVITY.notify_all();
-            }
-            Err(err) => {
-                debug!("poll got err {}", err);
-            }
+        let result = poll_sockets(&mut sockets, |sockets| {
+            self.iface.lock().poll(sockets, timestamp)
+        });
+        if let Err(err) = result {
+            debug!("poll got err {}", err);
         }
     }
 
@@ -16,11 +16,11 @@ use smoltcp::wire::*;
 use smoltcp::Result;
 
 use crate::memory::active_table;
-use crate::net::SOCKETS;
+use crate::net::{poll_sockets, SOCKETS};
 use crate::sync::FlagsGuard;
 use crate::sync::SpinNoIrqLock as Mutex;
 
-use super::super::{provider::Provider, DeviceType, Driver, DRIVERS, NET_DRIVERS, SOCKET_ACTIVITY};
+use super::super::{provider::Provider, DeviceType, Driver, DRIVERS, NET_DRIVERS};
 
 #[derive(Clone)]
 struct IXGBEDriver {
@@ -53,13 +53,11 @@ impl Driver for IXGBEInterface {
         if handled {
             let timestamp = Instant::from_millis(crate::trap::uptime_msec() as i64);
             let mut sockets = SOCKETS.lock();
-            match self.iface.lock().poll(&mut sockets, timestamp) {
-                Ok(_) => {
-                    SOCKET_ACTIVITY.notify_all();
-                }
-                Err(err) => {
-                    debug!("poll got err {}", err);
-                }
+            let result = poll_sockets(&mut sockets, |sockets| {
+                self.iface.lock().poll(sockets, timestamp)
+            });
+            if let Err(err) = result {
+                debug!("poll got err {}", err);
             }
         }
 
@@ -94,13 +92,11 @@ impl Driver for IXGBEInterface {
     fn poll(&self) {
         let timestamp = Instant::from_millis(crate::trap::uptime_msec() as i64);
         let mut sockets = SOCKETS.lock();
-        match self.iface.lock().poll(&mut sockets, timestamp) {
-            Ok(_) => {
-                SOCKET_ACTIVITY.notify_all();
-            }
-            Err(err) => {
-                debug!("poll got err {}", err);
-            }
+        let result = poll_sockets(&mut sockets, |sockets| {
+            self.iface.lock().poll(sockets, timestamp)
+        });
+        if let Err(err) = result {
+            debug!("poll got err {}", err);
         }
     }
 
@@ -0,0 +1,457 @@
+//! Device file system, mounted at `/dev`
+//!
+//! Besides the standard character devices and the console, it has a block node
+//! for each of `BLK_DRIVERS`, named `sda`, `sdb`, ..., and `fb0` for the GPU.
+//! `ptmx` creates ptys, whose slaves are in devpts mounted on the directory `pts`.
+//!
+//! There is no hardware RNG, so `random` and `urandom` are NOT cryptographically secure.
+//!
+//! The nodes made by `mknod` on other file systems refer to these devices and the pty
+//! slaves by the device numbers of Linux, see `device_of`.
+
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use core::any::Any;
+use core::slice;
+
+use rcore_fs::dev::Device;
+use rcore_fs::vfs::*;
+
+use super::devpts::DevPtsINode;
+use super::pty::Pty;
+use super::{inner_inode, Tty, CONSOLE, PSEUDO_FS_INFO, STDIN, STDOUT};
+use crate::drivers::{BlockDriver, DeviceType, Driver, BLK_DRIVERS, DRIVERS};
+use crate::sync::{Condvar, SpinNoIrqLock as Mutex};
+
+pub struct DevFS;
+
+impl FileSystem for DevFS {
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn root_inode(&self) -> Arc<INode> {
+        Arc::new(DevINode(Node::Root))
+    }
+
+    fn info(&self) -> &'static FsInfo {
+        &PSEUDO_FS_INFO
+    }
+}
+
+/// A file of devfs
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Node {
+    Root,
+    Null,
+    Zero,
+    /// Both `random` and `urandom`, which never block and are not cryptographically secure
+    Random,
+    /// Both `console` and `tty`
+    Console,
+    Framebuffer,
+    /// Each open creates a pty
+    Ptmx,
+    /// The mount point of devpts
+    Pts,
+    /// The block device at this index of `BLK_DRIVERS`
+    Block(usize),
+}
+
+pub struct DevINode(Node);
+
+// _IOR('F', 0, struct fb_var_screeninfo)
+pub const FBIOGET_VSCREENINFO: u32 = 0x4600;
+
+/// `struct fb_var_screeninfo` of Linux
+#[repr(C)]
+#[derive(Default)]
+pub struct FbVarScreenInfo {
+    xres: u32,
+    yres: u32,
+    xres_virtual: u32,
+    yres_virtual: u32,
+    xoffset: u32,
+    yoffset: u32,
+    bits_per_pixel: u32,
+    grayscale: u32,
+    /// offset, length and msb_right of each color
+    red: [u32; 3],
+    green: [u32; 3],
+    blue: [u32; 3],
+    transp: [u32; 3],
+    /// timings and flags, which are not used
+    _rest: [u32; 20],
+}
+
+lazy_static! {
+    /// State of the xorshift generator of `/dev/urandom`
+    static ref RANDOM_STATE: Mutex<u64> = Mutex::new(0);
+    /// Device numbers of the nodes made by `mknod`, by the address of their file system
+    /// and their inode id. The file systems have nowhere to keep them, so they are lost on reboot.
+    static ref DEVICE_NUMBERS: Mutex<BTreeMap<(usize, usize), usize>> = Mutex::new(BTreeMap::new());
+}
+
+// major device numbers of Linux
+const MEM_MAJOR: usize = 1;
+const TTYAUX_MAJOR: usize = 5;
+const SCSI_DISK0_MAJOR: usize = 8;
+const FB_MAJOR: usize = 29;
+const UNIX98_PTY_SLAVE_MAJOR: usize = 136;
+
+/// Major and minor numbers of the device number `dev`, encoded like `makedev` of glibc
+fn major_minor(dev: usize) -> (usize, usize) {
+    let dev = dev as u64;
+    let major = ((dev >> 8) & 0xfff) | ((dev >> 32) & !0xfff);
+    let minor = (dev & 0xff) | ((dev >> 12) & !0xff);
+    (major as usize, minor as usize)
+}
+
+/// Key of the node `inode` in `DEVICE_NUMBERS`
+fn device_key(inode: &Arc<INode>) -> Result<(usize, usize)> {
+    let inode = inner_inode(inode);
+    let fs = &*inode.fs() as *const FileSystem as *const u8 as usize;
+    Ok((fs, inode.metadata()?.inode))
+}
+
+/// Make the node `inode` created by `mknod` refer to the device number `dev`
+pub fn set_device_number(inode: &Arc<INode>, dev: usize) -> Result<()> {
+    let key = device_key(inode)?;
+    DEVICE_NUMBERS.lock().insert(key, dev);
+    Ok(())
+}
+
+/// The node of devfs or devpts that the device node `inode` made by `mknod` refers to,
+/// if its device exists
+pub fn device_of(inode: &Arc<INode>) -> Option<Arc<INode>> {
+    let type_ = inode.metadata().ok()?.type_;
+    let key = device_key(inode).ok()?;
+    let dev = *DEVICE_NUMBERS.lock().get(&key)?;
+    match major_minor(dev) {
+        (UNIX98_PTY_SLAVE_MAJOR, index) if type_ == FileType::CharDevice => {
+            let pty = Pty::get(index)?;
+            Some(Arc::new(DevPtsINode::Slave(pty)))
+        }
+        (major, minor) => {
+            let node = Node::from_dev(major, minor).filter(|node| node.type_() == type_)?;
+            Some(Arc::new(DevINode(node)))
+        }
+    }
+}
+
+/// Fill `buf` with pseudo random bytes, which are predictable from the state
+/// and must not be used for cryptography.
+fn fill_random(buf: &mut [u8]) {
+    let mut state = RANDOM_STATE.lock();
+    // `rand` is 0 on some platforms, the time is a different seed for each boot.
+    // They are mixed in on each read, which makes the state harder to follow.
+    *state ^= crate::arch::rand::rand() ^ (crate::trap::uptime_msec() as u64);
+    if *state == 0 {
+        *state = 1;
+    }
+    for chunk in buf.chunks_mut(8) {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        for (i, x) in chunk.iter_mut().enumerate() {
+            *x = (*state >> (i * 8)) as u8;
+        }
+    }
+}
+
+fn block_driver(index: usize) -> Result<Arc<BlockDriver>> {
+    BLK_DRIVERS
+        .read()
+        .get(index)
+        .cloned()
+        .ok_or(FsError::EntryNotFound)
+}
+
+fn gpu_driver() -> Option<Arc<Driver>> {
+    DRIVERS
+        .read()
+        .iter()
+        .find(|driver| driver.device_type() == DeviceType::Gpu)
+        .cloned()
+}
+
+/// Address and length in bytes of the frame buffer
+fn framebuffer() -> Result<(usize, usize)> {
+    let driver = gpu_driver().ok_or(FsError::EntryNotFound)?;
+    let (addr, width, height) = driver.get_framebuffer();
+    Ok((addr, (width * height * 4) as usize))
+}
+
+impl DevINode {
+    /// Condvars notified when the readiness of the device may change
+    pub fn wait_queues(&self) -> Vec<Arc<Condvar>> {
+        match self.0 {
+            Node::Console => vec![CONSOLE.pushed.clone()],
+            _ => Vec::new(),
+        }
+    }
+
+    /// The terminal it refers to, if it is one
+    pub fn tty(&self) -> Option<Arc<Tty>> {
+        match self.0 {
+            Node::Console => Some(CONSOLE.clone()),
+            _ => None,
+        }
+    }
+
+    /// The block device it refers to, if it is one
+    pub fn block_device(&self) -> Option<Arc<BlockDriver>> {
+        match self.0 {
+            Node::Block(index) => block_driver(index).ok(),
+            _ => None,
+        }
+    }
+
+    /// Whether it is `/dev/ptmx`, whose opens are the masters of new ptys
+    pub fn is_ptmx(&self) -> bool {
+        self.0 == Node::Ptmx
+    }
+}
+
+impl Node {
+    fn id(&self) -> usize {
+        match *self {
+            Node::Root => 1,
+            Node::Null => 2,
+            Node::Zero => 3,
+            Node::Random => 4,
+            Node::Console => 5,
+            Node::Framebuffer => 6,
+            Node::Ptmx => 7,
+            Node::Pts => 8,
+            Node::Block(index) => 0x100 + index,
+        }
+    }
+
+    /// The device with the major and minor numbers of Linux, if there is one
+    fn from_dev(major: usize, minor: usize) -> Option<Node> {
+        match (major, minor) {
+            (MEM_MAJOR, 3) => Some(Node::Null),
+            (MEM_MAJOR, 5) => Some(Node::Zero),
+            (MEM_MAJOR, 8) | (MEM_MAJOR, 9) => Some(Node::Random),
+            (TTYAUX_MAJOR, 0) | (TTYAUX_MAJOR, 1) => Some(Node::Console),
+            (TTYAUX_MAJOR, 2) => Some(Node::Ptmx),
+            (FB_MAJOR, 0) if gpu_driver().is_some() => Some(Node::Framebuffer),
+            // only whole disks, which have 15 partitions after each
+            (SCSI_DISK0_MAJOR, minor) if minor % 16 == 0 => {
+                let index = minor / 16;
+                block_driver(index).ok().map(|_| Node::Block(index))
+            }
+            _ => None,
+        }
+    }
+
+    fn type_(&self) -> FileType {
+        match self {
+            Node::Root | Node::Pts => FileType::Dir,
+            Node::Block(_) => FileType::BlockDevice,
+            _ => FileType::CharDevice,
+        }
+    }
+
+    /// Entries of the root directory, except `.` and `..`
+    fn entries() -> Vec<(String, Node)> {
+        let mut entries = Vec::new();
+        entries.push((String::from("null"), Node::Null));
+        entries.push((String::from("zero"), Node::Zero));
+        entries.push((String::from("random"), Node::Random));
+        entries.push((String::from("urandom"), Node::Random));
+        entries.push((String::from("console"), Node::Console));
+        entries.push((String::from("tty"), Node::Console));
+        entries.push((String::from("ptmx"), Node::Ptmx));
+        entries.push((String::from("pts"), Node::Pts));
+        if gpu_driver().is_some() {
+            entries.push((String::from("fb0"), Node::Framebuffer));
+        }
+        // the names are the same as the sources of mount
+        for index in 0..BLK_DRIVERS.read().len().min(26) {
+            let name = format!("sd{}", (b'a' + index as u8) as char);
+            entries.push((name, Node::Block(index)));
+        }
+        entries
+    }
+}
+
+impl INode for DevINode {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        match self.0 {
+            Node::Root | Node::Pts => Err(FsError::IsDir),
+            // only the masters opened from it can be read
+            Node::Ptmx => Err(FsError::NotSupported),
+            Node::Null => Ok(0),
+            Node::Zero => {
+                for x in buf.iter_mut() {
+                    *x = 0;
+                }
+                Ok(buf.len())
+            }
+            Node::Random => {
+                fill_random(buf);
+                Ok(buf.len())
+            }
+            Node::Console if buf.is_empty() => Ok(0),
+            Node::Console => STDIN.read_at(offset, buf),
+            Node::Framebuffer => {
+                let (addr, size) = framebuffer()?;
+                if offset >= size {
+                    return Ok(0);
+                }
+                let len = buf.len().min(size - offset);
+                let data = unsafe { slice::from_raw_parts((addr + offset) as *const u8, len) };
+                buf[..len].copy_from_slice(data);
+                Ok(len)
+            }
+            Node::Block(index) => {
+                let driver = block_driver(index)?;
+                Device::read_at(&*driver, offset, buf).ok_or(FsError::DeviceError)
+            }
+        }
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        match self.0 {
+            Node::Root | Node::Pts => Err(FsError::IsDir),
+            Node::Ptmx => Err(FsError::NotSupported),
+            // written data is discarded, or mixed into nothing
+            Node::Null | Node::Zero | Node::Random => Ok(buf.len()),
+            Node::Console => STDOUT.write_at(offset, buf),
+            Node::Framebuffer => {
+                let (addr, size) = framebuffer()?;
+                if offset >= size {
+                    return Err(FsError::NoDeviceSpace);
+                }
+                let len = buf.len().min(size - offset);
+                let data = unsafe { slice::from_raw_parts_mut((addr + offset) as *mut u8, len) };
+                data.copy_from_slice(&buf[..len]);
+                gpu_driver().unwrap().flush_framebuffer();
+                Ok(len)
+            }
+            Node::Block(index) => {
+                let driver = block_driver(index)?;
+                Device::write_at(&*driver, offset, buf).ok_or(FsError::DeviceError)
+            }
+        }
+    }
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: self.0 != Node::Console || CONSOLE.can_read(),
+            write: true,
+            error: false,
+        })
+    }
+    fn metadata(&self) -> Result<Metadata> {
+        let type_ = self.0.type_();
+        let (mode, nlinks, size) = match self.0 {
+            Node::Root | Node::Pts => (0o755, 2, 0),
+            Node::Block(_) => (0o660, 1, 0),
+            Node::Framebuffer => (0o660, 1, framebuffer()?.1),
+            _ => (0o666, 1, 0),
+        };
+        Ok(Metadata {
+            dev: 0,
+            inode: self.0.id(),
+            size,
+            blk_size: 512,
+            blocks: 0,
+            atime: Timespec { sec: 0, nsec: 0 },
+            mtime: Timespec { sec: 0, nsec: 0 },
+            ctime: Timespec { sec: 0, nsec: 0 },
+            type_,
+            mode,
+            nlinks,
+            uid: 0,
+            gid: 0,
+        })
+    }
+    fn set_metadata(&self, _metadata: &Metadata) -> Result<()> {
+        Ok(())
+    }
+    fn sync_all(&self) -> Result<()> {
+        Ok(())
+    }
+    fn sync_data(&self) -> Result<()> {
+        Ok(())
+    }
+    fn resize(&self, _len: usize) -> Result<()> {
+        match self.0 {
+            Node::Root | Node::Pts => Err(FsError::IsDir),
+            // truncating a device does nothing
+            _ => Ok(()),
+        }
+    }
+    fn create(&self, _name: &str, _type_: FileType, _mode: u32) -> Result<Arc<INode>> {
+        Err(FsError::NotSupported)
+    }
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn link(&self, _name: &str, _other: &Arc<INode>) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn move_(&self, _old_name: &str, _target: &Arc<INode>, _new_name: &str) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn find(&self, name: &str) -> Result<Arc<INode>> {
+        if self.0.type_() != FileType::Dir {
+            return Err(FsError::NotDir);
+        }
+        let node = match name {
+            "." => self.0,
+            ".." => Node::Root,
+            // `pts` is empty until devpts is mounted on it
+            _ if self.0 == Node::Pts => return Err(FsError::EntryNotFound),
+            _ => {
+                let entry = Node::entries().into_iter().find(|(entry, _)| entry == name);
+                entry.ok_or(FsError::EntryNotFound)?.1
+            }
+        };
+        Ok(Arc::new(DevINode(node)))
+    }
+    fn get_entry(&self, id: usize) -> Result<String> {
+        if self.0.type_() != FileType::Dir {
+            return Err(FsError::NotDir);
+        }
+        match id {
+            0 => Ok(String::from(".")),
+            1 => Ok(String::from("..")),
+            _ if self.0 == Node::Pts => Err(FsError::EntryNotFound),
+            _ => Node::entries()
+                .into_iter()
+                .nth(id - 2)
+                .map(|(name, _)| name)
+                .ok_or(FsError::EntryNotFound),
+        }
+    }
+    fn io_control(&self, cmd: u32, data: usize) -> Result<()> {
+        match self.0 {
+            Node::Framebuffer if cmd == FBIOGET_VSCREENINFO => {
+                let driver = gpu_driver().ok_or(FsError::EntryNotFound)?;
+                let (_, width, height) = driver.get_framebuffer();
+                let mut info = FbVarScreenInfo::default();
+                info.xres = width;
+                info.yres = height;
+                info.xres_virtual = width;
+                info.yres_virtual = height;
+                info.bits_per_pixel = 32;
+                // B8G8R8A8
+                info.red = [16, 8, 0];
+                info.green = [8, 8, 0];
+                info.blue = [0, 8, 0];
+                info.transp = [24, 8, 0];
+                // the pointer is checked by `sys_ioctl`
+                unsafe { *(data as *mut FbVarScreenInfo) = info };
+                Ok(())
+            }
+            _ => Err(FsError::NotSupported),
+        }
+    }
+    fn fs(&self) -> Arc<FileSystem> {
+        Arc::new(DevFS)
+    }
+    fn as_any_ref(&self) -> &Any {
+        self
+    }
+}
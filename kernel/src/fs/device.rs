@@ -1,5 +1,7 @@
 //! Implement Device
 
+use alloc::vec::Vec;
+
 use rcore_fs::dev::*;
 use spin::RwLock;
 
@@ -33,6 +35,47 @@ impl Device for MemBuf {
     }
 }
 
+/// A device in kernel heap, for file systems in memory
+pub struct RamDisk(RwLock<Vec<u8>>);
+
+impl RamDisk {
+    /// Create a device of `size` bytes, or `None` if the memory of the file systems
+    /// in memory is not enough, see `reserve_fs_memory`
+    pub fn new(size: usize) -> Option<Self> {
+        if !super::reserve_fs_memory(size) {
+            return None;
+        }
+        Some(RamDisk(RwLock::new(vec![0; size])))
+    }
+}
+
+impl Drop for RamDisk {
+    fn drop(&mut self) {
+        super::release_fs_memory(self.0.read().len());
+    }
+}
+
+impl Device for RamDisk {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Option<usize> {
+        let data = self.0.read();
+        if offset > data.len() {
+            return None;
+        }
+        let len = buf.len().min(data.len() - offset);
+        buf[..len].copy_from_slice(&data[offset..offset + len]);
+        Some(len)
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Option<usize> {
+        let mut data = self.0.write();
+        if offset > data.len() {
+            return None;
+        }
+        let len = buf.len().min(data.len() - offset);
+        data[offset..offset + len].copy_from_slice(&buf[..len]);
+        Some(len)
+    }
+}
+
 #[cfg(target_arch = "x86_64")]
 impl BlockDevice for ide::IDE {
     const BLOCK_SIZE_LOG2: u8 = 9;
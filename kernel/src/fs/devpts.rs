@@ -0,0 +1,150 @@
+//! File system of the slaves of the ptys, mounted at `/dev/pts`
+//!
+//! The root directory has an entry named by the index of each pty whose master is open.
+
+use alloc::{string::String, sync::Arc};
+use core::any::Any;
+
+use rcore_fs::vfs::*;
+
+use super::pty::Pty;
+use super::PSEUDO_FS_INFO;
+
+pub struct DevPtsFS;
+
+impl FileSystem for DevPtsFS {
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn root_inode(&self) -> Arc<INode> {
+        Arc::new(DevPtsINode::Root)
+    }
+
+    fn info(&self) -> &'static FsInfo {
+        &PSEUDO_FS_INFO
+    }
+}
+
+/// A file of devpts
+pub enum DevPtsINode {
+    Root,
+    /// The slave of a pty, which is opened by `Pty::open_slave`
+    Slave(Arc<Pty>),
+}
+
+/// Inode id of the slave of the pty at `index`
+pub fn slave_id(index: usize) -> usize {
+    index + 2
+}
+
+impl INode for DevPtsINode {
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize> {
+        match self {
+            DevPtsINode::Root => Err(FsError::IsDir),
+            // only the opens of the slave can be read
+            DevPtsINode::Slave(_) => Err(FsError::NotSupported),
+        }
+    }
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        match self {
+            DevPtsINode::Root => Err(FsError::IsDir),
+            DevPtsINode::Slave(_) => Err(FsError::NotSupported),
+        }
+    }
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: true,
+            write: true,
+            error: false,
+        })
+    }
+    fn metadata(&self) -> Result<Metadata> {
+        match self {
+            DevPtsINode::Root => Ok(Metadata {
+                dev: 0,
+                inode: 1,
+                size: 0,
+                blk_size: 0,
+                blocks: 0,
+                atime: Timespec { sec: 0, nsec: 0 },
+                mtime: Timespec { sec: 0, nsec: 0 },
+                ctime: Timespec { sec: 0, nsec: 0 },
+                type_: FileType::Dir,
+                mode: 0o755,
+                nlinks: 2,
+                uid: 0,
+                gid: 0,
+            }),
+            DevPtsINode::Slave(pty) => Ok(pty.slave_metadata(slave_id(pty.index()))),
+        }
+    }
+    fn set_metadata(&self, metadata: &Metadata) -> Result<()> {
+        if let DevPtsINode::Slave(pty) = self {
+            pty.set_slave_metadata(metadata);
+        }
+        Ok(())
+    }
+    fn sync_all(&self) -> Result<()> {
+        Ok(())
+    }
+    fn sync_data(&self) -> Result<()> {
+        Ok(())
+    }
+    fn resize(&self, _len: usize) -> Result<()> {
+        match self {
+            DevPtsINode::Root => Err(FsError::IsDir),
+            DevPtsINode::Slave(_) => Ok(()),
+        }
+    }
+    fn create(&self, _name: &str, _type_: FileType, _mode: u32) -> Result<Arc<INode>> {
+        Err(FsError::NotSupported)
+    }
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn link(&self, _name: &str, _other: &Arc<INode>) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn move_(&self, _old_name: &str, _target: &Arc<INode>, _new_name: &str) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn find(&self, name: &str) -> Result<Arc<INode>> {
+        if let DevPtsINode::Slave(_) = self {
+            return Err(FsError::NotDir);
+        }
+        match name {
+            "." | ".." => Ok(Arc::new(DevPtsINode::Root)),
+            _ => {
+                let pty = name
+                    .parse()
+                    .ok()
+                    .and_then(Pty::get)
+                    .ok_or(FsError::EntryNotFound)?;
+                Ok(Arc::new(DevPtsINode::Slave(pty)))
+            }
+        }
+    }
+    fn get_entry(&self, id: usize) -> Result<String> {
+        if let DevPtsINode::Slave(_) = self {
+            return Err(FsError::NotDir);
+        }
+        match id {
+            0 => Ok(String::from(".")),
+            1 => Ok(String::from("..")),
+            _ => Pty::indexes()
+                .get(id - 2)
+                .map(|index| format!("{}", index))
+                .ok_or(FsError::EntryNotFound),
+        }
+    }
+    fn io_control(&self, _cmd: u32, _data: usize) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn fs(&self) -> Arc<FileSystem> {
+        Arc::new(DevPtsFS)
+    }
+    fn as_any_ref(&self) -> &Any {
+        self
+    }
+}
@@ -0,0 +1,169 @@
+//! Epoll instances, which report the readiness of the files in their interest lists
+
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+
+use bitflags::bitflags;
+
+use super::FileLike;
+use crate::sync::{Condvar, SpinNoIrqLock as Mutex};
+use crate::syscall::SysError;
+
+bitflags! {
+    pub struct EpollEvents: u32 {
+        /// There is data to read.
+        const IN = 0x001;
+        /// There is urgent data to read.
+        const PRI = 0x002;
+        /// Writing is now possible.
+        const OUT = 0x004;
+        /// Error condition, always reported
+        const ERR = 0x008;
+        /// Hang up, always reported
+        const HUP = 0x010;
+        /// The peer has shut down writing.
+        const RDHUP = 0x2000;
+        const EXCLUSIVE = 1 << 28;
+        const WAKEUP = 1 << 29;
+        /// Disable the file after reporting an event.
+        const ONESHOT = 1 << 30;
+        /// Edge triggered: report an event only when the file becomes ready.
+        const ET = 1 << 31;
+    }
+}
+
+/// `struct epoll_event` of Linux, which is packed on x86_64
+#[repr(C)]
+#[cfg_attr(target_arch = "x86_64", repr(packed))]
+#[derive(Clone, Copy)]
+pub struct EpollEvent {
+    pub events: u32,
+    pub data: u64,
+}
+
+/// A file in the interest list
+struct Interest {
+    events: EpollEvents,
+    data: u64,
+    /// Readiness when it was last polled, for edge triggered events,
+    /// without the events the file has blocked for since
+    last: EpollEvents,
+    /// Disabled after an event with `ONESHOT`, until it is modified
+    disabled: bool,
+}
+
+/// An epoll instance, shared by the file descriptors referring to it
+#[derive(Clone, Default)]
+pub struct EpollInstance(Arc<Mutex<BTreeMap<usize, Interest>>>);
+
+impl Interest {
+    fn new(event: &EpollEvent) -> Self {
+        Interest {
+            events: EpollEvents::from_bits_truncate(event.events),
+            data: event.data,
+            last: EpollEvents::empty(),
+            disabled: false,
+        }
+    }
+}
+
+/// Readiness of `file`
+fn readiness(file: &FileLike) -> EpollEvents {
+    let status = match file.poll() {
+        Ok(status) => status,
+        Err(_) => return EpollEvents::ERR,
+    };
+    let mut events = EpollEvents::empty();
+    events.set(EpollEvents::IN, status.read);
+    events.set(EpollEvents::OUT, status.write);
+    events.set(EpollEvents::HUP, status.error);
+    events
+}
+
+impl EpollInstance {
+    pub fn new() -> Self {
+        EpollInstance::default()
+    }
+
+    pub fn add(&self, fd: usize, event: &EpollEvent) -> Result<(), SysError> {
+        let mut interests = self.0.lock();
+        if interests.contains_key(&fd) {
+            return Err(SysError::EEXIST);
+        }
+        interests.insert(fd, Interest::new(event));
+        Ok(())
+    }
+
+    pub fn modify(&self, fd: usize, event: &EpollEvent) -> Result<(), SysError> {
+        let mut interests = self.0.lock();
+        let interest = interests.get_mut(&fd).ok_or(SysError::ENOENT)?;
+        *interest = Interest::new(event);
+        Ok(())
+    }
+
+    pub fn remove(&self, fd: usize) -> Result<(), SysError> {
+        let mut interests = self.0.lock();
+        interests.remove(&fd).ok_or(SysError::ENOENT)?;
+        Ok(())
+    }
+
+    /// Forget that `fd` was ready for `events` once it would block for them,
+    /// so that it is reported again when it becomes ready, even if it is not polled meanwhile
+    pub fn rearm(&self, fd: usize, events: EpollEvents) {
+        if let Some(interest) = self.0.lock().get_mut(&fd) {
+            interest.last.remove(events);
+        }
+    }
+
+    /// Events of at most `max_events` ready files in `files`.
+    /// Files which are closed are removed from the interest list.
+    pub fn poll(&self, files: &BTreeMap<usize, FileLike>, max_events: usize) -> Vec<EpollEvent> {
+        let mut interests = self.0.lock();
+        let closed: Vec<usize> = interests
+            .keys()
+            .filter(|fd| !files.contains_key(fd))
+            .cloned()
+            .collect();
+        for fd in closed {
+            interests.remove(&fd);
+        }
+
+        let mut events = Vec::new();
+        for (fd, interest) in interests.iter_mut() {
+            if events.len() == max_events {
+                break;
+            }
+            if interest.disabled {
+                continue;
+            }
+            let ready =
+                readiness(&files[fd]) & (interest.events | EpollEvents::ERR | EpollEvents::HUP);
+            let last = interest.last;
+            interest.last = ready;
+            if ready.is_empty()
+                || (interest.events.contains(EpollEvents::ET) && (ready - last).is_empty())
+            {
+                continue;
+            }
+            if interest.events.contains(EpollEvents::ONESHOT) {
+                interest.disabled = true;
+            }
+            events.push(EpollEvent {
+                events: ready.bits(),
+                data: interest.data,
+            });
+        }
+        events
+    }
+
+    /// Condvars notified when the files of the interest list in `files` may become ready
+    pub fn wait_queues(&self, files: &BTreeMap<usize, FileLike>) -> Vec<Arc<Condvar>> {
+        let interests = self.0.lock();
+        let mut queues = Vec::new();
+        for fd in interests.keys() {
+            if let Some(file) = files.get(fd) {
+                queues.extend(file.wait_queues());
+            }
+        }
+        queues
+    }
+}
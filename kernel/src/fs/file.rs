@@ -1,14 +1,21 @@
 //! File handle for process
 
-use alloc::{string::String, sync::Arc};
+use alloc::{string::String, sync::Arc, vec::Vec};
 
 use rcore_fs::vfs::{FsError, INode, Metadata, PollStatus, Result};
+use spin::RwLock;
+
+use super::{inner_inode, DevINode, Pipe, Pty, PtyMaster, PtySlave, Stdin, Stdout, Tty, CONSOLE};
+use crate::sync::Condvar;
 
 #[derive(Clone)]
 pub struct FileHandle {
     inode: Arc<INode>,
     offset: u64,
-    options: OpenOptions,
+    /// Status flags of the open file, shared with its duplicates
+    options: Arc<RwLock<OpenOptions>>,
+    /// The absolute path it was opened with, or a description like `pipe`
+    path: String,
 }
 
 #[derive(Debug, Clone)]
@@ -17,6 +24,8 @@ pub struct OpenOptions {
     pub write: bool,
     /// Before each write, the file offset is positioned at the end of the file.
     pub append: bool,
+    /// Reads and writes fail with `EAGAIN` instead of blocking.
+    pub nonblock: bool,
 }
 
 #[derive(Debug)]
@@ -27,11 +36,12 @@ pub enum SeekFrom {
 }
 
 impl FileHandle {
-    pub fn new(inode: Arc<INode>, options: OpenOptions) -> Self {
+    pub fn new(inode: Arc<INode>, options: OpenOptions, path: String) -> Self {
         FileHandle {
             inode,
             offset: 0,
-            options,
+            options: Arc::new(RwLock::new(options)),
+            path,
         }
     }
 
@@ -42,7 +52,7 @@ impl FileHandle {
     }
 
     pub fn read_at(&mut self, offset: usize, buf: &mut [u8]) -> Result<usize> {
-        if !self.options.read {
+        if !self.options.read().read {
             return Err(FsError::InvalidParam); // FIXME: => EBADF
         }
         let len = self.inode.read_at(offset, buf)?;
@@ -50,7 +60,7 @@ impl FileHandle {
     }
 
     pub fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        let offset = match self.options.append {
+        let offset = match self.options.read().append {
             true => self.inode.metadata()?.size as u64,
             false => self.offset,
         } as usize;
@@ -60,7 +70,7 @@ impl FileHandle {
     }
 
     pub fn write_at(&mut self, offset: usize, buf: &[u8]) -> Result<usize> {
-        if !self.options.write {
+        if !self.options.read().write {
             return Err(FsError::InvalidParam); // FIXME: => EBADF
         }
         let len = self.inode.write_at(offset, buf)?;
@@ -77,7 +87,7 @@ impl FileHandle {
     }
 
     pub fn set_len(&mut self, len: u64) -> Result<()> {
-        if !self.options.write {
+        if !self.options.read().write {
             return Err(FsError::InvalidParam); // FIXME: => EBADF
         }
         self.inode.resize(len as usize)?;
@@ -85,10 +95,12 @@ impl FileHandle {
     }
 
     pub fn sync_all(&mut self) -> Result<()> {
+        super::mmap::sync_file(&self.inode);
         self.inode.sync_all()
     }
 
     pub fn sync_data(&mut self) -> Result<()> {
+        super::mmap::sync_file(&self.inode);
         self.inode.sync_data()
     }
 
@@ -96,12 +108,8 @@ impl FileHandle {
         self.inode.metadata()
     }
 
-    pub fn lookup_follow(&self, path: &str, max_follow: usize) -> Result<Arc<INode>> {
-        self.inode.lookup_follow(path, max_follow)
-    }
-
     pub fn read_entry(&mut self) -> Result<String> {
-        if !self.options.read {
+        if !self.options.read().read {
             return Err(FsError::InvalidParam); // FIXME: => EBADF
         }
         let name = self.inode.get_entry(self.offset as usize)?;
@@ -113,7 +121,72 @@ impl FileHandle {
         self.inode.poll()
     }
 
+    /// Condvars notified when the readiness of the file may change.
+    /// It is empty for files which are always ready.
+    pub fn wait_queues(&self) -> Vec<Arc<Condvar>> {
+        let inode = inner_inode(&self.inode).as_any_ref();
+        if let Some(pipe) = inode.downcast_ref::<Pipe>() {
+            pipe.wait_queues()
+        } else if inode.is::<Stdin>() {
+            vec![CONSOLE.pushed.clone()]
+        } else if let Some(inode) = inode.downcast_ref::<DevINode>() {
+            inode.wait_queues()
+        } else if let Some(master) = inode.downcast_ref::<PtyMaster>() {
+            master.wait_queues()
+        } else if let Some(slave) = inode.downcast_ref::<PtySlave>() {
+            slave.wait_queues()
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// The pipe it refers to, if it is one
+    pub fn pipe(&self) -> Option<&Pipe> {
+        inner_inode(&self.inode).as_any_ref().downcast_ref::<Pipe>()
+    }
+
+    /// The terminal it refers to, if it is one
+    pub fn tty(&self) -> Option<Arc<Tty>> {
+        let inode = inner_inode(&self.inode).as_any_ref();
+        if inode.is::<Stdin>() || inode.is::<Stdout>() {
+            Some(CONSOLE.clone())
+        } else if let Some(inode) = inode.downcast_ref::<DevINode>() {
+            inode.tty()
+        } else if let Some(slave) = inode.downcast_ref::<PtySlave>() {
+            Some(slave.pty().tty().clone())
+        } else {
+            None
+        }
+    }
+
+    /// The pty it is the master of, if it is one
+    pub fn pty_master(&self) -> Option<Arc<Pty>> {
+        let inode = inner_inode(&self.inode).as_any_ref();
+        inode
+            .downcast_ref::<PtyMaster>()
+            .map(|master| master.pty().clone())
+    }
+
     pub fn io_control(&self, cmd: u32, arg: usize) -> Result<()> {
         self.inode.io_control(cmd, arg)
     }
+
+    pub fn inode(&self) -> Arc<INode> {
+        self.inode.clone()
+    }
+
+    pub fn options(&self) -> OpenOptions {
+        self.options.read().clone()
+    }
+
+    /// Change the status flags which `fcntl` can set
+    pub fn set_status(&self, append: bool, nonblock: bool) {
+        let mut options = self.options.write();
+        options.append = append;
+        options.nonblock = nonblock;
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
 }
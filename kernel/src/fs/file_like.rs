@@ -1,60 +1,144 @@
 use core::fmt;
 
-use super::FileHandle;
-use crate::net::Socket;
+use super::{EpollInstance, FileHandle, Pty, Tty};
+use crate::net::{Socket, SocketStatus};
+use crate::sync::Condvar;
 use crate::syscall::{SysError, SysResult};
 use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 use rcore_fs::vfs::PollStatus;
+use spin::RwLock;
 
 // TODO: merge FileLike to FileHandle ?
 // TODO: fix dup and remove Clone
 #[derive(Clone)]
 pub enum FileLike {
     File(FileHandle),
-    Socket(Box<dyn Socket>),
+    Socket(Box<dyn Socket>, Arc<RwLock<SocketStatus>>),
+    Epoll(EpollInstance),
 }
 
 impl FileLike {
+    pub fn new_socket(socket: Box<dyn Socket>, nonblock: bool) -> Self {
+        let status = SocketStatus {
+            nonblock,
+            ..SocketStatus::default()
+        };
+        FileLike::Socket(socket, Arc::new(RwLock::new(status)))
+    }
     pub fn read(&mut self, buf: &mut [u8]) -> SysResult {
         let len = match self {
             FileLike::File(file) => file.read(buf)?,
-            FileLike::Socket(socket) => socket.read(buf).0?,
+            FileLike::Socket(socket, _) => socket.read(buf).0?,
+            FileLike::Epoll(_) => return Err(SysError::EINVAL),
         };
         Ok(len)
     }
     pub fn write(&mut self, buf: &[u8]) -> SysResult {
         let len = match self {
-            FileLike::File(file) => file.write(buf)?,
-            FileLike::Socket(socket) => socket.write(buf, None)?,
+            FileLike::File(file) => match file.write(buf) {
+                Err(_) if file.pipe().map_or(false, |pipe| pipe.is_broken()) => {
+                    return Err(SysError::EPIPE);
+                }
+                result => result?,
+            },
+            FileLike::Socket(socket, _) => socket.write(buf, None)?,
+            FileLike::Epoll(_) => return Err(SysError::EINVAL),
         };
         Ok(len)
     }
     pub fn ioctl(&mut self, request: usize, arg1: usize, arg2: usize, arg3: usize) -> SysResult {
         match self {
             FileLike::File(file) => file.io_control(request as u32, arg1)?,
-            FileLike::Socket(socket) => {
+            FileLike::Socket(socket, _) => {
                 socket.ioctl(request, arg1, arg2, arg3)?;
             }
+            FileLike::Epoll(_) => return Err(SysError::ENOTTY),
         }
         Ok(0)
     }
     pub fn poll(&self) -> Result<PollStatus, SysError> {
         let status = match self {
             FileLike::File(file) => file.poll()?,
-            FileLike::Socket(socket) => {
+            FileLike::Socket(socket, _) => {
                 let (read, write, error) = socket.poll();
                 PollStatus { read, write, error }
             }
+            // TODO: report the readiness of the files in its interest list
+            FileLike::Epoll(_) => PollStatus {
+                read: false,
+                write: false,
+                error: false,
+            },
         };
         Ok(status)
     }
+    /// Condvars notified when the readiness of the file may change,
+    /// which `poll`, `select` and `epoll` wait on
+    pub fn wait_queues(&self) -> Vec<Arc<Condvar>> {
+        match self {
+            FileLike::File(file) => file.wait_queues(),
+            FileLike::Socket(socket, _) => socket.wait_queues(),
+            // it is never ready
+            FileLike::Epoll(_) => Vec::new(),
+        }
+    }
+    /// Whether reading from it, or writing to it if `write`, has to wait.
+    /// Regular files never do.
+    pub fn would_block(&self, write: bool) -> Result<bool, SysError> {
+        if let FileLike::File(file) = self {
+            let options = file.options();
+            // let the operation fail if it is not permitted
+            if (write && !options.write) || (!write && !options.read) {
+                return Ok(false);
+            }
+        }
+        if self.wait_queues().is_empty() {
+            return Ok(false);
+        }
+        let status = self.poll()?;
+        let ready = if write { status.write } else { status.read };
+        Ok(!ready && !status.error)
+    }
+    /// The terminal it refers to, if it is one
+    pub fn tty(&self) -> Option<Arc<Tty>> {
+        match self {
+            FileLike::File(file) => file.tty(),
+            _ => None,
+        }
+    }
+    /// The pty it is the master of, if it is one
+    pub fn pty_master(&self) -> Option<Arc<Pty>> {
+        match self {
+            FileLike::File(file) => file.pty_master(),
+            _ => None,
+        }
+    }
+    /// Whether operations fail with `EAGAIN` instead of blocking
+    pub fn nonblock(&self) -> bool {
+        match self {
+            FileLike::File(file) => file.options().nonblock,
+            FileLike::Socket(_, status) => status.read().nonblock,
+            FileLike::Epoll(_) => false,
+        }
+    }
+    /// Timeout in msecs of waiting to read, or to write if `write`, 0 for none
+    pub fn timeout(&self, write: bool) -> usize {
+        match self {
+            FileLike::Socket(_, status) if write => status.read().send_timeout,
+            FileLike::Socket(_, status) => status.read().recv_timeout,
+            _ => 0,
+        }
+    }
 }
 
 impl fmt::Debug for FileLike {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             FileLike::File(_) => write!(f, "File"),
-            FileLike::Socket(_) => write!(f, "Socket"),
+            FileLike::Socket(..) => write!(f, "Socket"),
+            FileLike::Epoll(_) => write!(f, "Epoll"),
         }
     }
 }
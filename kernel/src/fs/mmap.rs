@@ -0,0 +1,258 @@
+//! Pages of memory mapped files
+//!
+//! Private mappings read pages directly from the `INode` on page faults.
+//! Shared mappings get the pages from a `PageCache` shared by all mappers of the file,
+//! which writes them back to the `INode` when asked by the `Shared` handler.
+//!
+//! The handler runs while the page table is edited, with the temporary page in use,
+//! so it reads in and writes back the pages through their mappings.
+//! Pages of files dirty on unmap are written back by `write_back_unmapped` after that.
+
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{fmt, mem};
+
+use rcore_fs::vfs::INode;
+use rcore_memory::memory_set::handler::{Read, SharedPages};
+use rcore_memory::paging::PageTableExt;
+use rcore_memory::{PhysAddr, PAGE_SIZE};
+
+use super::inode_addr;
+use crate::memory::{active_table, alloc_frame, dealloc_frame};
+use crate::sync::SpinNoIrqLock;
+
+/// `INode` read by the `File` handler of private mappings
+#[derive(Clone)]
+pub struct INodeForMap(pub Arc<INode>);
+
+impl Read for INodeForMap {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        self.0.read_at(offset, buf).unwrap_or(0)
+    }
+}
+
+impl fmt::Debug for INodeForMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("INodeForMap")
+    }
+}
+
+struct CachedPage {
+    frame: PhysAddr,
+    refs: usize,
+    /// Whether it was unmapped dirty, and not written back yet
+    dirty: bool,
+}
+
+/// Pages of a file shared by its mappers, or of shared anonymous memory if there is no `inode`
+struct PageCache {
+    inode: Option<Arc<INode>>,
+    pages: SpinNoIrqLock<BTreeMap<usize, CachedPage>>,
+}
+
+lazy_static! {
+    /// Page caches of files, by the address of their `INode`
+    static ref PAGE_CACHES: SpinNoIrqLock<BTreeMap<usize, Weak<PageCache>>> =
+        SpinNoIrqLock::new(BTreeMap::new());
+    /// Pages of files unmapped dirty, with their offsets, to be written back
+    static ref UNMAPPED_DIRTY: SpinNoIrqLock<Vec<(Arc<PageCache>, usize)>> =
+        SpinNoIrqLock::new(Vec::new());
+}
+
+impl PageCache {
+    /// Write the `data` of the page at `offset` back to the file, without extending it
+    fn write_back(&self, offset: usize, data: &[u8]) {
+        let inode = match &self.inode {
+            Some(inode) => inode,
+            None => return,
+        };
+        let size = match inode.metadata() {
+            Ok(metadata) => metadata.size,
+            Err(_) => return,
+        };
+        if offset >= size {
+            return;
+        }
+        let len = (size - offset).min(PAGE_SIZE);
+        if inode.write_at(offset, &data[..len]).is_err() {
+            warn!("failed to write back mapped page at {:#x}", offset);
+        }
+    }
+
+    /// Write the page at `offset` in `frame` back to the file.
+    /// The page table must not be being edited.
+    fn write_back_frame(&self, offset: usize, frame: PhysAddr) {
+        active_table().with_temporary_map(frame, |_, data: &mut [u8; PAGE_SIZE]| {
+            self.write_back(offset, data);
+        });
+    }
+}
+
+impl Drop for PageCache {
+    fn drop(&mut self) {
+        // the areas are dropped after they are unmapped, out of editing the page table
+        for (&offset, page) in self.pages.lock().iter() {
+            if page.dirty {
+                self.write_back_frame(offset, page.frame);
+            }
+            dealloc_frame(page.frame);
+        }
+        if let Some(inode) = &self.inode {
+            let key = inode_addr(inode);
+            let mut caches = PAGE_CACHES.lock();
+            // a new cache of the same file may have been created
+            if caches
+                .get(&key)
+                .map_or(false, |cache| cache.upgrade().is_none())
+            {
+                caches.remove(&key);
+            }
+        }
+    }
+}
+
+/// Reference to a `PageCache`, used by the `Shared` handler
+#[derive(Clone)]
+pub struct CachedPages(Arc<PageCache>);
+
+impl CachedPages {
+    /// Get the page cache of `inode`, shared by all its mappers
+    pub fn of_file(inode: Arc<INode>) -> Self {
+        let key = inode_addr(&inode);
+        let mut caches = PAGE_CACHES.lock();
+        if let Some(cache) = caches.get(&key).and_then(|cache| cache.upgrade()) {
+            return CachedPages(cache);
+        }
+        let cache = Arc::new(PageCache {
+            inode: Some(inode),
+            pages: SpinNoIrqLock::new(BTreeMap::new()),
+        });
+        caches.insert(key, Arc::downgrade(&cache));
+        CachedPages(cache)
+    }
+
+    /// Make zeroed pages of shared anonymous memory,
+    /// which live until the last mapping is removed
+    pub fn anonymous() -> Self {
+        CachedPages(Arc::new(PageCache {
+            inode: None,
+            pages: SpinNoIrqLock::new(BTreeMap::new()),
+        }))
+    }
+}
+
+/// Write back the pages of files unmapped dirty, e.g. by munmap or exit.
+/// Called at the end of syscalls, when no page table is being edited.
+pub fn write_back_unmapped() {
+    let unmapped = mem::replace(&mut *UNMAPPED_DIRTY.lock(), Vec::new());
+    for (cache, offset) in unmapped {
+        let mut pages = cache.pages.lock();
+        let unused = match pages.get_mut(&offset) {
+            Some(page) => {
+                if page.dirty {
+                    cache.write_back_frame(offset, page.frame);
+                    page.dirty = false;
+                }
+                page.refs == 0
+            }
+            None => false,
+        };
+        // the pages of files are kept only when mapped
+        if unused {
+            let page = pages.remove(&offset).unwrap();
+            dealloc_frame(page.frame);
+        }
+    }
+}
+
+/// Write back the mapped pages of `inode`, including those written by any mappers
+pub fn sync_file(inode: &Arc<INode>) {
+    let cache = PAGE_CACHES
+        .lock()
+        .get(&inode_addr(inode))
+        .and_then(|cache| cache.upgrade());
+    if let Some(cache) = cache {
+        let mut pages = cache.pages.lock();
+        for (&offset, page) in pages.iter_mut() {
+            cache.write_back_frame(offset, page.frame);
+            page.dirty = false;
+            if page.refs == 0 {
+                dealloc_frame(page.frame);
+            }
+        }
+        // the pages of files are kept only when mapped
+        let unmapped: Vec<_> = pages
+            .iter()
+            .filter(|(_, page)| page.refs == 0)
+            .map(|(&offset, _)| offset)
+            .collect();
+        for offset in unmapped {
+            pages.remove(&offset);
+        }
+    }
+}
+
+impl SharedPages for CachedPages {
+    fn get<'a>(
+        &self,
+        offset: usize,
+        read_in: impl FnOnce(PhysAddr) -> &'a mut [u8],
+    ) -> Option<PhysAddr> {
+        let mut pages = self.0.pages.lock();
+        if let Some(page) = pages.get_mut(&offset) {
+            page.refs += 1;
+            return Some(page.frame);
+        }
+        let frame = alloc_frame()?;
+        let data = read_in(frame);
+        let len = match &self.0.inode {
+            Some(inode) => inode.read_at(offset, data).unwrap_or(0),
+            None => 0,
+        };
+        for x in data[len..].iter_mut() {
+            *x = 0;
+        }
+        let page = CachedPage {
+            frame,
+            refs: 1,
+            dirty: false,
+        };
+        pages.insert(offset, page);
+        Some(frame)
+    }
+
+    fn put(&self, offset: usize, dirty: bool) {
+        let mut pages = self.0.pages.lock();
+        let page = pages.get_mut(&offset).expect("page is not cached");
+        // the page table may not be active, so the page can't be written back here
+        page.dirty |= dirty;
+        page.refs -= 1;
+        if dirty && self.0.inode.is_some() {
+            UNMAPPED_DIRTY.lock().push((self.0.clone(), offset));
+        }
+        // pages of anonymous memory are kept for other mappers
+        if page.refs == 0 && !page.dirty && self.0.inode.is_some() {
+            dealloc_frame(page.frame);
+            pages.remove(&offset);
+        }
+    }
+
+    fn sync(&self, offset: usize, data: &[u8]) {
+        let mut pages = self.0.pages.lock();
+        if let Some(page) = pages.get_mut(&offset) {
+            self.0.write_back(offset, data);
+            page.dirty = false;
+        }
+    }
+}
+
+impl fmt::Debug for CachedPages {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CachedPages")
+            .field("file", &self.0.inode.is_some())
+            .finish()
+    }
+}
@@ -1,21 +1,58 @@
-use alloc::{sync::Arc, vec::Vec};
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use rcore_fs::vfs::*;
 use rcore_fs_sfs::SimpleFileSystem;
 
 #[cfg(target_arch = "x86_64")]
 use crate::arch::driver::ide;
+use crate::consts::KERNEL_HEAP_SIZE;
+use crate::drivers::{BlockDriver, BLK_DRIVERS};
+use crate::syscall::SysError;
 
+pub use self::devfs::{
+    device_of, set_device_number, DevFS, DevINode, FbVarScreenInfo, FBIOGET_VSCREENINFO,
+};
+pub use self::device::RamDisk;
+pub use self::devpts::{DevPtsFS, DevPtsINode};
+pub use self::epoll::{EpollEvent, EpollEvents, EpollInstance};
 pub use self::file::*;
 pub use self::file_like::*;
-pub use self::pipe::Pipe;
-pub use self::stdio::{STDIN, STDOUT};
+pub use self::mmap::{write_back_unmapped, CachedPages, INodeForMap};
+pub use self::mount::{inner_inode, inode_addr, mount, mount_table, umount, MountFS, MountInfo};
+pub use self::pipe::{Pipe, PipeEnd, PIPE_BUF};
+pub use self::procfs::{is_procfs, lend_process, ProcFS};
+pub use self::pty::{Pty, PtyMaster, PtySlave};
+pub use self::stdio::{Stdin, Stdout, STDIN, STDOUT};
+pub use self::tmpfs::TmpFS;
+pub use self::tty::{Tty, CONSOLE};
 
+mod devfs;
 mod device;
+mod devpts;
+mod epoll;
 mod file;
 mod file_like;
+mod mmap;
+mod mount;
 mod pipe;
+mod procfs;
+mod pty;
 mod stdio;
+mod tmpfs;
+pub mod tty;
+
+/// Info of the file systems in memory without blocks, like procfs
+pub static PSEUDO_FS_INFO: FsInfo = FsInfo {
+    bsize: 4096,
+    frsize: 4096,
+    blocks: 0,
+    bfree: 0,
+    bavail: 0,
+    files: 0,
+    ffree: 0,
+    namemax: 255,
+};
 
 /// Hard link user programs
 #[cfg(feature = "link_user")]
@@ -58,12 +95,150 @@ lazy_static! {
             Arc::new(unsafe { device::MemBuf::new(_user_img_start, _user_img_end) })
         };
 
+        // named like the block nodes of devfs, so that it is found busy by `is_mounted`
+        #[cfg(not(feature = "link_user"))]
+        let source = "/dev/sda";
+        #[cfg(feature = "link_user")]
+        let source = "rootfs";
+
         let sfs = SimpleFileSystem::open(device).expect("failed to open SFS");
-        sfs.root_inode()
+        let info = MountInfo {
+            source: String::from(source),
+            target: String::from("/"),
+            fstype: String::from("sfs"),
+        };
+        MountFS::new(sfs, info).expect("failed to mount SFS").root_inode()
     };
 }
 
-pub const FOLLOW_MAX_DEPTH: usize = 1;
+/// Mount the kernel file systems on the root file system
+pub fn init() {
+    mount_kernel_fs("dev", "devfs", Arc::new(DevFS));
+    mount_kernel_fs("dev/pts", "devpts", Arc::new(DevPtsFS));
+    mount_kernel_fs("proc", "proc", Arc::new(ProcFS));
+    mount_kernel_fs("tmp", "tmpfs", TmpFS::new(KERNEL_HEAP_SIZE / 4));
+}
+
+/// Mount `fs` on `/<name>`, creating the directory in the root if needed
+fn mount_kernel_fs(name: &str, fstype: &str, fs: Arc<FileSystem>) {
+    let dir = match lookup_at(&ROOT_INODE, name, true) {
+        Ok(dir) => dir,
+        Err(_) => match ROOT_INODE.create(name, FileType::Dir, 0o755) {
+            Ok(dir) => dir,
+            Err(err) => {
+                warn!("failed to create /{}: {:?}", name, err);
+                return;
+            }
+        },
+    };
+    let info = MountInfo {
+        source: String::from(fstype),
+        target: format!("/{}", name),
+        fstype: String::from(fstype),
+    };
+    if let Err(err) = mount(&dir, fs, info) {
+        warn!("failed to mount {} on /{}: {:?}", fstype, name, err);
+    }
+}
+
+/// Bytes of the kernel heap which the file systems in memory may take in total
+const FS_MEMORY_CAPACITY: usize = KERNEL_HEAP_SIZE / 4;
+
+/// Bytes taken by the file systems in memory
+static FS_MEMORY_USED: AtomicUsize = AtomicUsize::new(0);
+
+/// Take `size` bytes of the memory shared by all the file systems in memory,
+/// or return false if there is not enough left
+pub fn reserve_fs_memory(size: usize) -> bool {
+    loop {
+        let used = FS_MEMORY_USED.load(Ordering::SeqCst);
+        if size > FS_MEMORY_CAPACITY - used {
+            return false;
+        }
+        let new = used + size;
+        if FS_MEMORY_USED.compare_and_swap(used, new, Ordering::SeqCst) == used {
+            return true;
+        }
+    }
+}
+
+/// Give back `size` bytes taken by `reserve_fs_memory`
+pub fn release_fs_memory(size: usize) {
+    FS_MEMORY_USED.fetch_sub(size, Ordering::SeqCst);
+}
+
+/// Find the block device by its name, like `sda` or `/dev/sda` for the first one
+pub fn block_device(name: &str) -> Option<Arc<BlockDriver>> {
+    let name = name.trim_start_matches("/dev/");
+    if name.len() != 3 || !name.starts_with("sd") {
+        return None;
+    }
+    let index = name.as_bytes()[2].checked_sub(b'a')? as usize;
+    BLK_DRIVERS.read().get(index).cloned()
+}
+
+/// Whether a mounted file system is on the block device `device`
+pub fn is_mounted(device: &Arc<BlockDriver>) -> bool {
+    mount_table()
+        .iter()
+        .filter_map(|info| block_device(&info.source))
+        .any(|used| Arc::ptr_eq(&used, device))
+}
+
+/// Maximum number of symbolic links followed in a lookup, as Linux
+pub const FOLLOW_MAX_DEPTH: usize = 40;
+
+/// Look up `path` relative to the directory `dir`, following the symbolic links
+/// of every component but the last one, which is followed only if `follow`.
+/// Absolute paths, and links to them, start from `ROOT_INODE`.
+pub fn lookup_at(
+    dir: &Arc<INode>,
+    path: &str,
+    follow: bool,
+) -> core::result::Result<Arc<INode>, SysError> {
+    let mut follows = 0;
+    let mut current = dir.clone();
+    let mut rest = String::from(path);
+    loop {
+        if rest.starts_with('/') {
+            current = ROOT_INODE.clone();
+        }
+        let path = rest.trim_start_matches('/');
+        if path.is_empty() {
+            return Ok(current);
+        }
+        let (name, next) = match path.find('/') {
+            Some(pos) => match path[pos..].trim_start_matches('/') {
+                // a trailing slash requires a directory, which `.` checks
+                "" => (&path[..pos], "."),
+                next => (&path[..pos], next),
+            },
+            None => (path, ""),
+        };
+        if current.metadata()?.type_ != FileType::Dir {
+            return Err(SysError::ENOTDIR);
+        }
+        let inode = current.find(name)?;
+        if inode.metadata()?.type_ == FileType::SymLink && (follow || !next.is_empty()) {
+            follows += 1;
+            if follows > FOLLOW_MAX_DEPTH {
+                return Err(SysError::ELOOP);
+            }
+            // the target is relative to the directory of the link, which is `current`
+            let target = String::from_utf8(inode.read_as_vec()?).map_err(|_| SysError::ENOENT)?;
+            if target.is_empty() {
+                return Err(SysError::ENOENT);
+            }
+            rest = match next {
+                "" => target,
+                next => target + "/" + next,
+            };
+        } else {
+            rest = String::from(next);
+            current = inode;
+        }
+    }
+}
 
 pub trait INodeExt {
     fn read_as_vec(&self) -> Result<Vec<u8>>;
@@ -0,0 +1,303 @@
+//! Mount table of the virtual file system
+//!
+//! Every mounted file system is wrapped in a `MountFS`, which keeps the file systems
+//! mounted on its directories. Its `MNode`s cross the mount points in `find`,
+//! so that lookups from `ROOT_INODE` see a single tree.
+
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::any::Any;
+
+use rcore_fs::vfs::*;
+use spin::RwLock;
+
+use crate::syscall::SysError;
+
+/// An entry of the mount table
+#[derive(Debug, Clone)]
+pub struct MountInfo {
+    /// The device, or `none` if there is no device
+    pub source: String,
+    /// Absolute path of the mount point
+    pub target: String,
+    pub fstype: String,
+}
+
+/// A file system in the mount tree
+pub struct MountFS {
+    info: MountInfo,
+    /// Root directory of the wrapped file system
+    inner_root: Arc<INode>,
+    root_id: usize,
+    /// File systems mounted on the directories of this one, by their inode id
+    mounts: RwLock<BTreeMap<usize, Arc<MountFS>>>,
+    /// The directory it is mounted on, `None` for the root file system
+    mountpoint: Option<Arc<MNode>>,
+    self_ref: Weak<MountFS>,
+}
+
+/// An `INode` in the mount tree
+pub struct MNode {
+    inode: Arc<INode>,
+    vfs: Arc<MountFS>,
+}
+
+impl MountFS {
+    /// Wrap `fs` as the root of the mount tree
+    pub fn new(fs: Arc<FileSystem>, info: MountInfo) -> Result<Arc<Self>> {
+        Self::new_at(fs, info, None)
+    }
+
+    fn new_at(
+        fs: Arc<FileSystem>,
+        info: MountInfo,
+        mountpoint: Option<Arc<MNode>>,
+    ) -> Result<Arc<Self>> {
+        let inner_root = fs.root_inode();
+        let root_id = inner_root.metadata()?.inode;
+        Ok(MountFS {
+            info,
+            inner_root,
+            root_id,
+            mounts: RwLock::new(BTreeMap::new()),
+            mountpoint,
+            self_ref: Weak::new(),
+        }
+        .wrap())
+    }
+
+    /// Wrap pure `MountFS` with `Arc<..>`.
+    /// Used in constructors.
+    fn wrap(self) -> Arc<Self> {
+        let fs = Arc::new(self);
+        let weak = Arc::downgrade(&fs);
+        let ptr = Arc::into_raw(fs) as *mut Self;
+        unsafe {
+            (*ptr).self_ref = weak;
+            Arc::from_raw(ptr)
+        }
+    }
+
+    fn root(&self) -> MNode {
+        MNode {
+            inode: self.inner_root.clone(),
+            vfs: self.self_ref.upgrade().unwrap(),
+        }
+    }
+
+    fn push_mount_table(&self, table: &mut Vec<MountInfo>) {
+        table.push(self.info.clone());
+        for fs in self.mounts.read().values() {
+            fs.push_mount_table(table);
+        }
+    }
+}
+
+impl FileSystem for MountFS {
+    fn sync(&self) -> Result<()> {
+        self.inner_root.fs().sync()?;
+        for fs in self.mounts.read().values() {
+            fs.sync()?;
+        }
+        Ok(())
+    }
+
+    fn root_inode(&self) -> Arc<INode> {
+        Arc::new(self.root())
+    }
+
+    fn info(&self) -> &'static FsInfo {
+        self.inner_root.fs().info()
+    }
+}
+
+impl MNode {
+    fn wrap(&self, inode: Arc<INode>) -> MNode {
+        MNode {
+            inode,
+            vfs: self.vfs.clone(),
+        }
+    }
+
+    /// Whether it is the root directory of its file system
+    fn is_root(&self) -> bool {
+        self.inode.metadata().ok().map(|info| info.inode) == Some(self.vfs.root_id)
+    }
+
+    /// The root of the file system mounted on it if there is one, or itself
+    fn overlaid(self) -> MNode {
+        if self.vfs.mounts.read().is_empty() {
+            return self;
+        }
+        let id = match self.inode.metadata() {
+            Ok(info) => info.inode,
+            Err(_) => return self,
+        };
+        let fs = self.vfs.mounts.read().get(&id).cloned();
+        match fs {
+            Some(fs) => fs.root().overlaid(),
+            None => self,
+        }
+    }
+
+    /// The `INode` to pass to the wrapped file system for `other`
+    fn unwrap_other<'a>(&self, other: &'a Arc<INode>) -> Result<&'a Arc<INode>> {
+        match other.as_any_ref().downcast_ref::<MNode>() {
+            Some(node) if Arc::ptr_eq(&node.vfs, &self.vfs) => Ok(&node.inode),
+            Some(_) => Err(FsError::NotSameFs),
+            None => Ok(other),
+        }
+    }
+}
+
+impl INode for MNode {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        self.inode.read_at(offset, buf)
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        self.inode.write_at(offset, buf)
+    }
+    fn poll(&self) -> Result<PollStatus> {
+        self.inode.poll()
+    }
+    fn metadata(&self) -> Result<Metadata> {
+        self.inode.metadata()
+    }
+    fn set_metadata(&self, metadata: &Metadata) -> Result<()> {
+        self.inode.set_metadata(metadata)
+    }
+    fn sync_all(&self) -> Result<()> {
+        self.inode.sync_all()
+    }
+    fn sync_data(&self) -> Result<()> {
+        self.inode.sync_data()
+    }
+    fn resize(&self, len: usize) -> Result<()> {
+        self.inode.resize(len)
+    }
+    fn create(&self, name: &str, type_: FileType, mode: u32) -> Result<Arc<INode>> {
+        let inode = self.inode.create(name, type_, mode)?;
+        Ok(Arc::new(self.wrap(inode)))
+    }
+    fn unlink(&self, name: &str) -> Result<()> {
+        if !self.vfs.mounts.read().is_empty() {
+            let id = self.inode.find(name)?.metadata()?.inode;
+            if self.vfs.mounts.read().contains_key(&id) {
+                // a mount point is busy, which `FsError` can not tell
+                return Err(FsError::DirNotEmpty);
+            }
+        }
+        self.inode.unlink(name)
+    }
+    fn link(&self, name: &str, other: &Arc<INode>) -> Result<()> {
+        self.inode.link(name, self.unwrap_other(other)?)
+    }
+    fn move_(&self, old_name: &str, target: &Arc<INode>, new_name: &str) -> Result<()> {
+        let target = self.unwrap_other(target)?;
+        self.inode.move_(old_name, target, new_name)
+    }
+    fn find(&self, name: &str) -> Result<Arc<INode>> {
+        let node = match name {
+            // go up from the root to the directory it is mounted on
+            ".." if self.vfs.mountpoint.is_some() && self.is_root() => {
+                return self.vfs.mountpoint.as_ref().unwrap().find("..");
+            }
+            "." | ".." => self.wrap(self.inode.find(name)?),
+            _ => self.wrap(self.inode.find(name)?).overlaid(),
+        };
+        Ok(Arc::new(node))
+    }
+    fn get_entry(&self, id: usize) -> Result<String> {
+        self.inode.get_entry(id)
+    }
+    fn io_control(&self, cmd: u32, data: usize) -> Result<()> {
+        self.inode.io_control(cmd, data)
+    }
+    fn fs(&self) -> Arc<FileSystem> {
+        self.vfs.clone()
+    }
+    fn as_any_ref(&self) -> &Any {
+        self
+    }
+}
+
+/// The `INode` of the file system below the mount tree
+pub fn inner_inode(inode: &Arc<INode>) -> &Arc<INode> {
+    match inode.as_any_ref().downcast_ref::<MNode>() {
+        Some(node) => &node.inode,
+        None => inode,
+    }
+}
+
+/// Address of the `INode` of the file system below the mount tree,
+/// which is the same however the file was looked up
+pub fn inode_addr(inode: &Arc<INode>) -> usize {
+    &**inner_inode(inode) as *const INode as *const u8 as usize
+}
+
+fn as_mnode(inode: &Arc<INode>) -> core::result::Result<&MNode, SysError> {
+    inode
+        .as_any_ref()
+        .downcast_ref::<MNode>()
+        .ok_or(SysError::EINVAL)
+}
+
+/// Entries of the mount table, each after the one it is mounted on
+pub fn mount_table() -> Vec<MountInfo> {
+    let mut table = Vec::new();
+    if let Ok(root) = as_mnode(&super::ROOT_INODE) {
+        root.vfs.push_mount_table(&mut table);
+    }
+    table
+}
+
+/// Mount `fs` on the directory `target`
+pub fn mount(
+    target: &Arc<INode>,
+    fs: Arc<FileSystem>,
+    info: MountInfo,
+) -> core::result::Result<(), SysError> {
+    let target = as_mnode(target)?;
+    let metadata = target.inode.metadata()?;
+    if metadata.type_ != FileType::Dir {
+        return Err(SysError::ENOTDIR);
+    }
+    // `ROOT_INODE` can not be covered
+    if target.vfs.mountpoint.is_none() && target.is_root() {
+        return Err(SysError::EBUSY);
+    }
+    let mut mounts = target.vfs.mounts.write();
+    if mounts.contains_key(&metadata.inode) {
+        return Err(SysError::EBUSY);
+    }
+    let mountpoint = Arc::new(target.wrap(target.inode.clone()));
+    let fs = MountFS::new_at(fs, info, Some(mountpoint))?;
+    mounts.insert(metadata.inode, fs);
+    Ok(())
+}
+
+/// Unmount the file system whose root directory is `target`.
+/// Unless `detach`, fail if any of its files is still in use.
+pub fn umount(target: Arc<INode>, detach: bool) -> core::result::Result<(), SysError> {
+    let target = as_mnode(&target)?;
+    let mountpoint = match &target.vfs.mountpoint {
+        Some(mountpoint) if target.is_root() => mountpoint,
+        _ => return Err(SysError::EINVAL),
+    };
+    let id = mountpoint.inode.metadata()?.inode;
+    {
+        let mut mounts = mountpoint.vfs.mounts.write();
+        // referenced by the mount table and `target`,
+        // or also by open files, mappings and mounts on it
+        if !detach && Arc::strong_count(&target.vfs) > 2 {
+            return Err(SysError::EBUSY);
+        }
+        mounts.remove(&id);
+    }
+    target.vfs.sync()?;
+    Ok(())
+}
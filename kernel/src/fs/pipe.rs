@@ -1,55 +1,204 @@
 //! Implement INode for Pipe
+//!
+//! Anonymous pipes are created in pairs. The ends of a FIFO are opened one at a time,
+//! and all the opens of the same FIFO share its `PipeData` until every end is closed.
 
-use alloc::{collections::vec_deque::VecDeque, string::String, sync::Arc};
+use alloc::{
+    collections::{vec_deque::VecDeque, BTreeMap},
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
 use core::any::Any;
+use core::cmp::min;
 
 use rcore_fs::vfs::*;
 
+use super::inode_addr;
 use crate::sync::Condvar;
 use crate::sync::SpinNoIrqLock as Mutex;
+use crate::syscall::SysError;
 
-#[derive(Clone)]
+/// Default capacity of a pipe
+pub const PIPE_DEFAULT_SIZE: usize = 64 * 1024;
+/// Writes of at most this many bytes are not interleaved with other writes
+pub const PIPE_BUF: usize = 4096;
+
+#[derive(Clone, Copy, PartialEq)]
 pub enum PipeEnd {
     Read,
     Write,
+    /// Both a read end and a write end, which only a FIFO can be opened as
+    Both,
+}
+
+impl PipeEnd {
+    fn can_read(self) -> bool {
+        self != PipeEnd::Write
+    }
+
+    fn can_write(self) -> bool {
+        self != PipeEnd::Read
+    }
+}
+
+lazy_static! {
+    /// Data of the open FIFOs, by the address of their inode
+    static ref FIFOS: Mutex<BTreeMap<usize, Weak<Mutex<PipeData>>>> = Mutex::new(BTreeMap::new());
 }
 
 pub struct PipeData {
     buf: VecDeque<u8>,
-    new_data: Condvar,
+    capacity: usize,
+    /// Number of open read ends
+    readers: usize,
+    /// Number of open write ends
+    writers: usize,
+    /// Notified when data is written or the last write end is closed
+    new_data: Arc<Condvar>,
+    /// Notified when data is read or the last read end is closed
+    new_space: Arc<Condvar>,
+    /// The FIFO it belongs to, which is kept so that its address in `FIFOS` is not reused
+    fifo: Option<Arc<INode>>,
 }
 
-#[derive(Clone)]
+/// An end of a pipe, the pipe is closed when all the ends of one direction are dropped
 pub struct Pipe {
     data: Arc<Mutex<PipeData>>,
     direction: PipeEnd,
 }
 
+impl PipeData {
+    fn new(fifo: Option<Arc<INode>>) -> Self {
+        PipeData {
+            buf: VecDeque::new(),
+            capacity: PIPE_DEFAULT_SIZE,
+            readers: 0,
+            writers: 0,
+            new_data: Arc::new(Condvar::new()),
+            new_space: Arc::new(Condvar::new()),
+            fifo,
+        }
+    }
+}
+
 impl Pipe {
     /// Create a pair of INode: (read, write)
     pub fn create_pair() -> (Pipe, Pipe) {
-        let inner = PipeData {
-            buf: VecDeque::new(),
-            new_data: Condvar::new(),
-        };
-        let data = Arc::new(Mutex::new(inner));
+        let data = Arc::new(Mutex::new(PipeData::new(None)));
         (
-            Pipe {
-                data: data.clone(),
-                direction: PipeEnd::Read,
-            },
-            Pipe {
-                data: data.clone(),
-                direction: PipeEnd::Write,
-            },
+            Pipe::new_end(&data, PipeEnd::Read),
+            Pipe::new_end(&data, PipeEnd::Write),
         )
     }
 
-    pub fn can_read(&self) -> bool {
-        if let PipeEnd::Read = self.direction {
-            self.data.lock().buf.len() > 0
-        } else {
-            false
+    /// Open an end of the FIFO `inode`, sharing the data of its other open ends
+    pub fn open_fifo(inode: &Arc<INode>, direction: PipeEnd) -> Pipe {
+        let addr = inode_addr(inode);
+        let mut fifos = FIFOS.lock();
+        let data = match fifos.get(&addr).and_then(Weak::upgrade) {
+            Some(data) => data,
+            None => {
+                let data = Arc::new(Mutex::new(PipeData::new(Some(inode.clone()))));
+                fifos.insert(addr, Arc::downgrade(&data));
+                data
+            }
+        };
+        Pipe::new_end(&data, direction)
+    }
+
+    fn new_end(data: &Arc<Mutex<PipeData>>, direction: PipeEnd) -> Pipe {
+        {
+            let mut inner = data.lock();
+            // wake up the opens of a FIFO waiting for this end
+            if direction.can_read() {
+                inner.readers += 1;
+                inner.new_space.notify_all();
+            }
+            if direction.can_write() {
+                inner.writers += 1;
+                inner.new_data.notify_all();
+            }
+        }
+        Pipe {
+            data: data.clone(),
+            direction,
+        }
+    }
+
+    /// Condvars notified when the readiness of this end may change
+    pub fn wait_queues(&self) -> Vec<Arc<Condvar>> {
+        let data = self.data.lock();
+        let mut queues = Vec::new();
+        if self.direction.can_read() {
+            queues.push(data.new_data.clone());
+        }
+        if self.direction.can_write() {
+            queues.push(data.new_space.clone());
+        }
+        queues
+    }
+
+    /// Whether an end of the other direction is open
+    pub fn has_peer(&self) -> bool {
+        let data = self.data.lock();
+        match self.direction {
+            PipeEnd::Read => data.writers > 0,
+            PipeEnd::Write => data.readers > 0,
+            PipeEnd::Both => true,
+        }
+    }
+
+    /// Whether it is a write end and all the read ends are closed
+    pub fn is_broken(&self) -> bool {
+        self.direction == PipeEnd::Write && self.data.lock().readers == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.data.lock().capacity
+    }
+
+    /// Change the capacity, which can not be less than the buffered data
+    pub fn set_capacity(&self, capacity: usize) -> core::result::Result<(), SysError> {
+        let mut data = self.data.lock();
+        if capacity < data.buf.len() {
+            return Err(SysError::EBUSY);
+        }
+        data.capacity = capacity;
+        data.new_space.notify_all();
+        Ok(())
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        let addr = {
+            let mut data = self.data.lock();
+            if self.direction.can_read() {
+                data.readers -= 1;
+                if data.readers == 0 {
+                    // writers get `EPIPE`
+                    data.new_space.notify_all();
+                }
+            }
+            if self.direction.can_write() {
+                data.writers -= 1;
+                if data.writers == 0 {
+                    // readers get end of file
+                    data.new_data.notify_all();
+                }
+            }
+            match data.fifo {
+                Some(ref fifo) if data.readers == 0 && data.writers == 0 => inode_addr(fifo),
+                _ => return,
+            }
+        };
+        // the data of a FIFO is discarded once all its ends are closed,
+        // unless it has been opened again meanwhile
+        let mut fifos = FIFOS.lock();
+        let data = self.data.lock();
+        if data.readers == 0 && data.writers == 0 {
+            fifos.remove(&addr);
         }
     }
 }
@@ -57,8 +206,6 @@ impl Pipe {
 // TODO: better way to provide default impl?
 macro_rules! impl_inode {
     () => {
-        fn poll(&self) -> Result<PollStatus> { Err(FsError::NotSupported) }
-        fn metadata(&self) -> Result<Metadata> { Err(FsError::NotSupported) }
         fn set_metadata(&self, _metadata: &Metadata) -> Result<()> { Ok(()) }
         fn sync_all(&self) -> Result<()> { Ok(()) }
         fn sync_data(&self) -> Result<()> { Ok(()) }
@@ -76,33 +223,80 @@ macro_rules! impl_inode {
 }
 
 impl INode for Pipe {
+    /// Block until there is data, or return 0 once all the write ends are closed
     fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
-        if let PipeEnd::Read = self.direction {
-            let mut data = self.data.lock();
-            if let Some(ch) = data.buf.pop_front() {
-                buf[0] = ch;
-                Ok(1)
-            } else {
-                Ok(0)
+        if !self.direction.can_read() {
+            return Err(FsError::InvalidParam);
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut data = self.data.lock();
+        loop {
+            if !data.buf.is_empty() {
+                let len = min(buf.len(), data.buf.len());
+                for (x, y) in buf.iter_mut().zip(data.buf.drain(..len)) {
+                    *x = y;
+                }
+                data.new_space.notify_all();
+                return Ok(len);
             }
-        } else {
-            Ok(0)
+            if data.writers == 0 {
+                return Ok(0);
+            }
+            let new_data = data.new_data.clone();
+            data = new_data.wait(data);
         }
     }
 
+    /// Block until there is space, writing at most `PIPE_BUF` bytes at once.
+    /// Fail once all the read ends are closed, which the caller reports as `EPIPE`.
     fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
-        if let PipeEnd::Write = self.direction {
-            if buf.len() > 0 {
-                let mut data = self.data.lock();
-                data.buf.push_back(buf[0]);
+        if !self.direction.can_write() {
+            return Err(FsError::InvalidParam);
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut data = self.data.lock();
+        loop {
+            if data.readers == 0 {
+                return Err(FsError::InvalidParam);
+            }
+            let space = data.capacity.saturating_sub(data.buf.len());
+            // a write of at most `PIPE_BUF` bytes is not split
+            if space >= buf.len() || (buf.len() > PIPE_BUF && space > 0) {
+                let len = min(buf.len(), space);
+                data.buf.extend(&buf[..len]);
                 data.new_data.notify_all();
-                Ok(1)
-            } else {
-                Ok(0)
+                return Ok(len);
             }
-        } else {
-            Ok(0)
+            let new_space = data.new_space.clone();
+            data = new_space.wait(data);
         }
     }
+
+    fn poll(&self) -> Result<PollStatus> {
+        let data = self.data.lock();
+        let mut status = PollStatus {
+            read: false,
+            write: false,
+            error: false,
+        };
+        if self.direction.can_read() {
+            status.read = !data.buf.is_empty() || data.writers == 0;
+            status.error |= data.writers == 0;
+        }
+        if self.direction.can_write() {
+            status.write = data.readers == 0 || data.capacity - data.buf.len() >= PIPE_BUF;
+            status.error |= data.readers == 0;
+        }
+        Ok(status)
+    }
+    /// The metadata of the FIFO it belongs to, anonymous pipes have none
+    fn metadata(&self) -> Result<Metadata> {
+        let fifo = self.data.lock().fifo.clone();
+        fifo.ok_or(FsError::NotSupported)?.metadata()
+    }
     impl_inode!();
 }
@@ -0,0 +1,507 @@
+//! Process file system, mounted at `/proc`
+//!
+//! Its files are generated from `PROCESSES` and the kernel state when they are read.
+
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use core::any::Any;
+
+use rcore_fs::vfs::*;
+use rcore_memory::PAGE_SIZE;
+
+use super::{inner_inode, mount_table, FileLike, Tty, PSEUDO_FS_INFO};
+use crate::memory::FRAME_ALLOCATOR;
+use crate::process::{current_thread, Process, PROCESSES};
+use crate::swap;
+use crate::sync::SpinNoIrqLock as Mutex;
+use crate::thread;
+
+#[cfg(target_arch = "x86_64")]
+const ARCH: &str = "x86_64";
+#[cfg(target_arch = "aarch64")]
+const ARCH: &str = "aarch64";
+#[cfg(target_arch = "riscv32")]
+const ARCH: &str = "riscv32";
+#[cfg(target_arch = "riscv64")]
+const ARCH: &str = "riscv64";
+#[cfg(target_arch = "mips")]
+const ARCH: &str = "mips";
+
+lazy_static! {
+    /// Addresses of the current processes lent by the syscalls that have them locked, by tid
+    static ref LENT_PROCESSES: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+}
+
+pub struct ProcFS;
+
+impl FileSystem for ProcFS {
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn root_inode(&self) -> Arc<INode> {
+        Arc::new(ProcINode(Node::Root))
+    }
+
+    fn info(&self) -> &'static FsInfo {
+        &PSEUDO_FS_INFO
+    }
+}
+
+/// A file of procfs
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Node {
+    Root,
+    Cpuinfo,
+    Meminfo,
+    Mounts,
+    SelfLink,
+    Uptime,
+    /// The directory of the process `pid`
+    Proc(usize),
+    Cmdline(usize),
+    Cwd(usize),
+    Exe(usize),
+    Fds(usize),
+    Maps(usize),
+    Stat(usize),
+    Status(usize),
+    /// The file descriptor `fd` of the process `pid`
+    Fd(usize, usize),
+}
+
+pub struct ProcINode(Node);
+
+/// Pid of `proc` by the process table, without locking it
+fn pid_of(proc: &Arc<Mutex<Process>>) -> usize {
+    PROCESSES
+        .read()
+        .iter()
+        .find(|(_, weak)| weak.upgrade().map_or(false, |p| Arc::ptr_eq(&p, proc)))
+        .map_or(0, |(&pid, _)| pid)
+}
+
+fn current_pid() -> usize {
+    pid_of(&current_thread().proc)
+}
+
+/// Whether `inode` is a file of procfs
+pub fn is_procfs(inode: &Arc<INode>) -> bool {
+    inner_inode(inode).downcast_ref::<ProcINode>().is_some()
+}
+
+/// Call `f` while procfs reads the current process from `proc`, which the caller has locked.
+/// The caller must not change the process meanwhile, e.g. through a file it borrows.
+pub fn lend_process<T>(proc: &Process, f: impl FnOnce() -> T) -> T {
+    let tid = thread::current().id();
+    let addr = proc as *const Process as usize;
+    let old = LENT_PROCESSES.lock().insert(tid, addr);
+    let ret = f();
+    let mut lent = LENT_PROCESSES.lock();
+    match old {
+        Some(old) => lent.insert(tid, old),
+        None => lent.remove(&tid),
+    };
+    ret
+}
+
+fn process_of(pid: usize) -> Result<Arc<Mutex<Process>>> {
+    PROCESSES
+        .read()
+        .get(&pid)
+        .and_then(|proc| proc.upgrade())
+        .ok_or(FsError::EntryNotFound)
+}
+
+/// Call `f` with the process `pid` locked, to take a snapshot of it
+fn with_process<T>(pid: usize, f: impl FnOnce(&Process) -> T) -> Result<T> {
+    let proc = process_of(pid)?;
+    if !Arc::ptr_eq(&proc, &current_thread().proc) {
+        return Ok(f(&proc.lock()));
+    }
+    let lent = LENT_PROCESSES.lock().get(&thread::current().id()).cloned();
+    if let Some(addr) = lent {
+        // it is locked and borrowed by the caller of `lend_process`, which waits for us
+        return Ok(f(unsafe { &*(addr as *const Process) }));
+    }
+    // It is locked by a sibling thread, or by a syscall of ours that has not lent it,
+    // which would never unlock it if we waited.
+    match proc.try_lock() {
+        Some(guard) => Ok(f(&guard)),
+        None => Err(FsError::DeviceError),
+    }
+}
+
+/// Name of the process in `stat` and `status`
+fn comm(proc: &Process) -> String {
+    match proc.exec_path.rsplit('/').next() {
+        Some(name) if !name.is_empty() => name.chars().take(15).collect(),
+        _ => String::from("kernel"),
+    }
+}
+
+/// Fields of `stat` and `status`, with the parent and the controlling terminal
+/// to find the pid and the foreground process group after unlocking
+struct Stat {
+    comm: String,
+    parent: Option<Arc<Mutex<Process>>>,
+    pgid: usize,
+    sid: usize,
+    ctty: Option<Arc<Tty>>,
+    stopped: bool,
+    threads: usize,
+    vm_size: usize,
+}
+
+impl Stat {
+    fn of(proc: &Process) -> Stat {
+        Stat {
+            comm: comm(proc),
+            parent: proc.parent.clone(),
+            pgid: proc.pgid,
+            sid: proc.sid,
+            ctty: proc.ctty.clone(),
+            stopped: proc.stopped,
+            threads: proc.threads.len(),
+            vm_size: vm_size(proc),
+        }
+    }
+
+    fn ppid(&self) -> usize {
+        self.parent.as_ref().map_or(0, pid_of)
+    }
+
+    /// The foreground process group of the controlling terminal, or -1 without one
+    fn tpgid(&self) -> isize {
+        self.ctty
+            .as_ref()
+            .and_then(|tty| tty.foreground_group())
+            .map_or(-1, |pgid| pgid as isize)
+    }
+}
+
+fn vm_size(proc: &Process) -> usize {
+    proc.vm
+        .iter()
+        .map(|area| area.end_addr() - area.start_addr())
+        .sum()
+}
+
+fn perm(allowed: bool, c: char) -> char {
+    if allowed {
+        c
+    } else {
+        '-'
+    }
+}
+
+impl Node {
+    fn id(&self) -> usize {
+        match *self {
+            Node::Root => 1,
+            Node::Cpuinfo => 2,
+            Node::Meminfo => 3,
+            Node::Mounts => 4,
+            Node::SelfLink => 5,
+            Node::Uptime => 6,
+            Node::Proc(pid) => (pid + 1) << 16,
+            Node::Cmdline(pid) => ((pid + 1) << 16) + 1,
+            Node::Cwd(pid) => ((pid + 1) << 16) + 2,
+            Node::Exe(pid) => ((pid + 1) << 16) + 3,
+            Node::Fds(pid) => ((pid + 1) << 16) + 4,
+            Node::Maps(pid) => ((pid + 1) << 16) + 5,
+            Node::Stat(pid) => ((pid + 1) << 16) + 6,
+            Node::Status(pid) => ((pid + 1) << 16) + 7,
+            Node::Fd(pid, fd) => ((pid + 1) << 16) + 0x100 + fd,
+        }
+    }
+
+    fn type_(&self) -> FileType {
+        match self {
+            Node::Root | Node::Proc(_) | Node::Fds(_) => FileType::Dir,
+            Node::SelfLink | Node::Cwd(_) | Node::Exe(_) | Node::Fd(..) => FileType::SymLink,
+            _ => FileType::File,
+        }
+    }
+
+    /// The parent of the directory
+    fn parent(&self) -> Node {
+        match *self {
+            Node::Fds(pid) => Node::Proc(pid),
+            _ => Node::Root,
+        }
+    }
+
+    /// Entries of the directory, except `.` and `..`
+    fn entries(&self) -> Result<Vec<(String, Node)>> {
+        let mut entries = Vec::new();
+        match *self {
+            Node::Root => {
+                entries.push((String::from("cpuinfo"), Node::Cpuinfo));
+                entries.push((String::from("meminfo"), Node::Meminfo));
+                entries.push((String::from("mounts"), Node::Mounts));
+                entries.push((String::from("self"), Node::SelfLink));
+                entries.push((String::from("uptime"), Node::Uptime));
+                for (&pid, proc) in PROCESSES.read().iter() {
+                    if proc.upgrade().is_some() {
+                        entries.push((format!("{}", pid), Node::Proc(pid)));
+                    }
+                }
+            }
+            Node::Proc(pid) => {
+                process_of(pid)?;
+                entries.push((String::from("cmdline"), Node::Cmdline(pid)));
+                entries.push((String::from("cwd"), Node::Cwd(pid)));
+                entries.push((String::from("exe"), Node::Exe(pid)));
+                entries.push((String::from("fd"), Node::Fds(pid)));
+                entries.push((String::from("maps"), Node::Maps(pid)));
+                entries.push((String::from("stat"), Node::Stat(pid)));
+                entries.push((String::from("status"), Node::Status(pid)));
+            }
+            Node::Fds(pid) => {
+                let fds: Vec<usize> =
+                    with_process(pid, |proc| proc.files.keys().cloned().collect())?;
+                for fd in fds {
+                    entries.push((format!("{}", fd), Node::Fd(pid, fd)));
+                }
+            }
+            _ => return Err(FsError::NotDir),
+        }
+        Ok(entries)
+    }
+
+    /// Content of the file, or the target of the symbolic link
+    fn content(&self) -> Result<String> {
+        let content = match *self {
+            Node::Cpuinfo => format!("processor\t: 0\nmodel name\t: {}\n\n", ARCH),
+            Node::Meminfo => {
+                let (total, free) = FRAME_ALLOCATOR.lock().count();
+                let (swap_total, swap_free) = swap::swap_info();
+                let kb = PAGE_SIZE / 1024;
+                let mut content = String::new();
+                for &(name, size) in [
+                    ("MemTotal:", total * kb),
+                    ("MemFree:", free * kb),
+                    ("MemAvailable:", free * kb),
+                    ("SwapTotal:", swap_total / 1024),
+                    ("SwapFree:", swap_free / 1024),
+                ]
+                .iter()
+                {
+                    content += &format!("{:<15}{:>8} kB\n", name, size);
+                }
+                content
+            }
+            Node::Mounts => {
+                let mut content = String::new();
+                for info in mount_table() {
+                    content += &format!("{} {} {} rw 0 0\n", info.source, info.target, info.fstype);
+                }
+                content
+            }
+            Node::SelfLink => format!("{}", current_pid()),
+            Node::Uptime => {
+                let msec = crate::trap::uptime_msec();
+                format!("{}.{:02} 0.00\n", msec / 1000, msec % 1000 / 10)
+            }
+            Node::Cmdline(pid) => with_process(pid, |proc| {
+                let mut content = String::new();
+                for arg in proc.args.iter() {
+                    content += arg;
+                    content.push('\0');
+                }
+                content
+            })?,
+            Node::Cwd(pid) => with_process(pid, |proc| proc.cwd.clone())?,
+            Node::Exe(pid) => with_process(pid, |proc| proc.exec_path.clone())?,
+            Node::Maps(pid) => with_process(pid, |proc| {
+                let mut content = String::new();
+                for area in proc.vm.iter() {
+                    let attr = area.attr();
+                    let name = match area.name() {
+                        "user_stack" => "stack",
+                        name => name,
+                    };
+                    content += &format!(
+                        "{:08x}-{:08x} {}{}{}p 00000000 00:00 0 [{}]\n",
+                        area.start_addr(),
+                        area.end_addr(),
+                        perm(attr.is_user(), 'r'),
+                        perm(attr.is_user() && !attr.is_readonly(), 'w'),
+                        perm(attr.is_user() && attr.is_execute(), 'x'),
+                        name
+                    );
+                }
+                content
+            })?,
+            Node::Stat(pid) => {
+                let stat = with_process(pid, Stat::of)?;
+                let state = if stat.stopped {
+                    'T'
+                } else if pid == current_pid() {
+                    'R'
+                } else {
+                    'S'
+                };
+                // pid, comm, state, ppid, pgrp, session, tty_nr, tpgid, flags,
+                // minflt, cminflt, majflt, cmajflt, utime, stime, cutime, cstime,
+                // priority, nice, num_threads, itrealvalue, starttime, vsize, rss
+                let mut content = format!(
+                    "{} ({}) {} {} {} {} 0 {} 0 0 0 0 0 0 0 0 0 20 0 {} 0 0 {} 0",
+                    pid,
+                    stat.comm,
+                    state,
+                    stat.ppid(),
+                    stat.pgid,
+                    stat.sid,
+                    stat.tpgid(),
+                    stat.threads,
+                    stat.vm_size
+                );
+                // the other fields are unknown
+                for _ in 24..52 {
+                    content += " 0";
+                }
+                content.push('\n');
+                content
+            }
+            Node::Status(pid) => {
+                let stat = with_process(pid, Stat::of)?;
+                let state = if stat.stopped {
+                    "T (stopped)"
+                } else if pid == current_pid() {
+                    "R (running)"
+                } else {
+                    "S (sleeping)"
+                };
+                format!(
+                    "Name:\t{}\nState:\t{}\nTgid:\t{}\nPid:\t{}\nPPid:\t{}\nThreads:\t{}\nVmSize:\t{:>8} kB\n",
+                    stat.comm,
+                    state,
+                    pid,
+                    pid,
+                    stat.ppid(),
+                    stat.threads,
+                    stat.vm_size / 1024
+                )
+            }
+            Node::Fd(pid, fd) => with_process(pid, |proc| match proc.files.get(&fd) {
+                Some(FileLike::File(file)) => Ok(String::from(file.path())),
+                Some(FileLike::Socket(..)) => Ok(String::from("socket")),
+                Some(FileLike::Epoll(_)) => Ok(String::from("anon_inode:[eventpoll]")),
+                None => Err(FsError::EntryNotFound),
+            })??,
+            _ => return Err(FsError::IsDir),
+        };
+        Ok(content)
+    }
+}
+
+impl INode for ProcINode {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let content = self.0.content()?;
+        let content = content.as_bytes();
+        if offset >= content.len() {
+            return Ok(0);
+        }
+        let len = buf.len().min(content.len() - offset);
+        buf[..len].copy_from_slice(&content[offset..offset + len]);
+        Ok(len)
+    }
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::NotSupported)
+    }
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: true,
+            write: false,
+            error: false,
+        })
+    }
+    fn metadata(&self) -> Result<Metadata> {
+        let type_ = self.0.type_();
+        let (mode, nlinks) = match type_ {
+            FileType::Dir => (0o555, 2),
+            FileType::SymLink => (0o777, 1),
+            _ => (0o444, 1),
+        };
+        Ok(Metadata {
+            dev: 0,
+            inode: self.0.id(),
+            size: 0,
+            blk_size: 0,
+            blocks: 0,
+            atime: Timespec { sec: 0, nsec: 0 },
+            mtime: Timespec { sec: 0, nsec: 0 },
+            ctime: Timespec { sec: 0, nsec: 0 },
+            type_,
+            mode,
+            nlinks,
+            uid: 0,
+            gid: 0,
+        })
+    }
+    fn set_metadata(&self, _metadata: &Metadata) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn sync_all(&self) -> Result<()> {
+        Ok(())
+    }
+    fn sync_data(&self) -> Result<()> {
+        Ok(())
+    }
+    fn resize(&self, _len: usize) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn create(&self, _name: &str, _type_: FileType, _mode: u32) -> Result<Arc<INode>> {
+        Err(FsError::NotSupported)
+    }
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn link(&self, _name: &str, _other: &Arc<INode>) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn move_(&self, _old_name: &str, _target: &Arc<INode>, _new_name: &str) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn find(&self, name: &str) -> Result<Arc<INode>> {
+        if self.0.type_() != FileType::Dir {
+            return Err(FsError::NotDir);
+        }
+        let node = match name {
+            "." => self.0,
+            ".." => self.0.parent(),
+            _ => {
+                self.0
+                    .entries()?
+                    .into_iter()
+                    .find(|(entry, _)| entry == name)
+                    .ok_or(FsError::EntryNotFound)?
+                    .1
+            }
+        };
+        Ok(Arc::new(ProcINode(node)))
+    }
+    fn get_entry(&self, id: usize) -> Result<String> {
+        match id {
+            0 => Ok(String::from(".")),
+            1 => Ok(String::from("..")),
+            _ => self
+                .0
+                .entries()?
+                .into_iter()
+                .nth(id - 2)
+                .map(|(name, _)| name)
+                .ok_or(FsError::EntryNotFound),
+        }
+    }
+    fn io_control(&self, _cmd: u32, _data: usize) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn fs(&self) -> Arc<FileSystem> {
+        Arc::new(ProcFS)
+    }
+    fn as_any_ref(&self) -> &Any {
+        self
+    }
+}
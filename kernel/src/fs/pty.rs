@@ -0,0 +1,351 @@
+//! Pseudo terminals
+//!
+//! Each open of `/dev/ptmx` creates a pty, whose master is the file it opens,
+//! and whose slave is `/dev/pts/<index>`. The slave is a terminal: what is written
+//! to the master is its input, and its output is read from the master.
+
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::any::Any;
+use core::cmp::min;
+
+use rcore_fs::vfs::*;
+
+use super::{DevPtsFS, Tty};
+use crate::sync::{Condvar, SpinNoIrqLock as Mutex};
+use crate::syscall::SysError;
+
+/// Maximum number of ptys, as the default `kernel.pty.max` of Linux
+pub const PTY_MAX: usize = 4096;
+/// The output of the slave waits for the master to read beyond this many bytes
+const OUTPUT_SIZE: usize = 64 * 1024;
+
+lazy_static! {
+    /// The ptys whose master is open, by their index
+    static ref PTYS: Mutex<BTreeMap<usize, Weak<Pty>>> = Mutex::new(BTreeMap::new());
+}
+
+/// A pseudo terminal, shared by its master and the opens of its slave
+pub struct Pty {
+    index: usize,
+    tty: Arc<Tty>,
+    /// The output of the slave, read by the master
+    output: Arc<Mutex<VecDeque<u8>>>,
+    /// Notified when there is output, or the slave is closed
+    output_pushed: Arc<Condvar>,
+    /// Notified when the output is read, or the master is closed
+    output_drained: Arc<Condvar>,
+    state: Mutex<PtyState>,
+}
+
+struct PtyState {
+    /// The slave can not be opened until `unlockpt`
+    locked: bool,
+    master_open: bool,
+    /// Number of opens of the slave
+    slaves: usize,
+    /// Whether the slave has ever been opened, before which the master is not hung up
+    slave_opened: bool,
+    /// Mode and owner of the slave, which `grantpt` checks
+    mode: u32,
+    uid: usize,
+    gid: usize,
+}
+
+/// The master of a pty, created by opening `/dev/ptmx`
+pub struct PtyMaster(Arc<Pty>);
+
+/// An open of the slave of a pty
+pub struct PtySlave(Arc<Pty>);
+
+impl Pty {
+    /// Create a pty with the lowest free index, whose slave is owned by `uid` and `gid`
+    pub fn new(uid: usize, gid: usize) -> Result<PtyMaster, SysError> {
+        let mut ptys = PTYS.lock();
+        let index = (0..PTY_MAX)
+            .find(|index| !ptys.contains_key(index))
+            .ok_or(SysError::ENOSPC)?;
+        let output = Arc::new(Mutex::new(VecDeque::new()));
+        let output_pushed = Arc::new(Condvar::new());
+        let tty = {
+            let output = output.clone();
+            let output_pushed = output_pushed.clone();
+            Tty::new(move |data| {
+                output.lock().extend(data);
+                output_pushed.notify_all();
+            })
+        };
+        let pty = Arc::new(Pty {
+            index,
+            tty: Arc::new(tty),
+            output,
+            output_pushed,
+            output_drained: Arc::new(Condvar::new()),
+            state: Mutex::new(PtyState {
+                locked: true,
+                master_open: true,
+                slaves: 0,
+                slave_opened: false,
+                mode: 0o620,
+                uid,
+                gid,
+            }),
+        });
+        ptys.insert(index, Arc::downgrade(&pty));
+        Ok(PtyMaster(pty))
+    }
+
+    /// The pty at `index`, if its master is open
+    pub fn get(index: usize) -> Option<Arc<Pty>> {
+        PTYS.lock().get(&index).and_then(Weak::upgrade)
+    }
+
+    /// Indexes of the ptys whose master is open
+    pub fn indexes() -> Vec<usize> {
+        PTYS.lock().keys().cloned().collect()
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn tty(&self) -> &Arc<Tty> {
+        &self.tty
+    }
+
+    /// `TIOCSPTLCK`
+    pub fn set_locked(&self, locked: bool) {
+        self.state.lock().locked = locked;
+    }
+
+    /// Open the slave, which fails with `EIO` while it is locked or the master is closed
+    pub fn open_slave(pty: &Arc<Pty>) -> Result<PtySlave, SysError> {
+        let mut state = pty.state.lock();
+        if state.locked || !state.master_open {
+            return Err(SysError::EIO);
+        }
+        state.slaves += 1;
+        state.slave_opened = true;
+        Ok(PtySlave(pty.clone()))
+    }
+
+    /// Metadata of the slave, with the inode id `inode`
+    pub fn slave_metadata(&self, inode: usize) -> Metadata {
+        let state = self.state.lock();
+        Metadata {
+            dev: 0,
+            inode,
+            size: 0,
+            blk_size: 0,
+            blocks: 0,
+            atime: Timespec { sec: 0, nsec: 0 },
+            mtime: Timespec { sec: 0, nsec: 0 },
+            ctime: Timespec { sec: 0, nsec: 0 },
+            type_: FileType::CharDevice,
+            mode: state.mode as u16,
+            nlinks: 1,
+            uid: state.uid,
+            gid: state.gid,
+        }
+    }
+
+    /// Change the mode and the owner of the slave, by `chmod` and `chown` in `grantpt`
+    pub fn set_slave_metadata(&self, metadata: &Metadata) {
+        let mut state = self.state.lock();
+        state.mode = metadata.mode as u32 & 0o7777;
+        state.uid = metadata.uid;
+        state.gid = metadata.gid;
+    }
+
+    /// Whether the slave has been opened, and every open of it is closed since
+    fn slave_closed(&self) -> bool {
+        let state = self.state.lock();
+        state.slave_opened && state.slaves == 0
+    }
+
+    fn master_open(&self) -> bool {
+        self.state.lock().master_open
+    }
+}
+
+impl PtyMaster {
+    pub fn pty(&self) -> &Arc<Pty> {
+        &self.0
+    }
+
+    pub fn wait_queues(&self) -> Vec<Arc<Condvar>> {
+        vec![self.0.output_pushed.clone()]
+    }
+}
+
+impl PtySlave {
+    pub fn pty(&self) -> &Arc<Pty> {
+        &self.0
+    }
+
+    pub fn wait_queues(&self) -> Vec<Arc<Condvar>> {
+        vec![self.0.tty.pushed.clone(), self.0.output_drained.clone()]
+    }
+}
+
+impl Drop for PtyMaster {
+    /// The slave is hung up and removed from devpts.
+    /// Its processes are not signalled, since the closing process may be locked.
+    fn drop(&mut self) {
+        self.0.state.lock().master_open = false;
+        PTYS.lock().remove(&self.0.index);
+        self.0.tty.hangup();
+        self.0.output_drained.notify_all();
+    }
+}
+
+impl Drop for PtySlave {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock();
+        state.slaves -= 1;
+        if state.slaves == 0 {
+            // the master reads `EIO`
+            drop(state);
+            self.0.output_pushed.notify_all();
+        }
+    }
+}
+
+/// The methods which neither the master nor the slave support
+macro_rules! impl_inode {
+    () => {
+        fn sync_all(&self) -> Result<()> {
+            Ok(())
+        }
+        fn sync_data(&self) -> Result<()> {
+            Ok(())
+        }
+        fn resize(&self, _len: usize) -> Result<()> {
+            Ok(())
+        }
+        fn create(&self, _name: &str, _type_: FileType, _mode: u32) -> Result<Arc<INode>> {
+            Err(FsError::NotDir)
+        }
+        fn unlink(&self, _name: &str) -> Result<()> {
+            Err(FsError::NotDir)
+        }
+        fn link(&self, _name: &str, _other: &Arc<INode>) -> Result<()> {
+            Err(FsError::NotDir)
+        }
+        fn move_(&self, _old_name: &str, _target: &Arc<INode>, _new_name: &str) -> Result<()> {
+            Err(FsError::NotDir)
+        }
+        fn find(&self, _name: &str) -> Result<Arc<INode>> {
+            Err(FsError::NotDir)
+        }
+        fn get_entry(&self, _id: usize) -> Result<String> {
+            Err(FsError::NotDir)
+        }
+        fn io_control(&self, _cmd: u32, _data: usize) -> Result<()> {
+            Err(FsError::NotSupported)
+        }
+        fn fs(&self) -> Arc<FileSystem> {
+            Arc::new(DevPtsFS)
+        }
+        fn as_any_ref(&self) -> &Any {
+            self
+        }
+    };
+}
+
+impl INode for PtyMaster {
+    /// Block until the slave writes, or fail with `EIO` once the slave is opened and closed
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut output = self.0.output.lock();
+        loop {
+            if !output.is_empty() {
+                let len = min(buf.len(), output.len());
+                for (x, y) in buf.iter_mut().zip(output.drain(..len)) {
+                    *x = y;
+                }
+                self.0.output_drained.notify_all();
+                return Ok(len);
+            }
+            if self.0.slave_closed() {
+                return Err(FsError::DeviceError);
+            }
+            output = self.0.output_pushed.wait(output);
+        }
+    }
+    /// Type the data on the slave
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        for &c in buf {
+            self.0.tty.push(c);
+        }
+        Ok(buf.len())
+    }
+    fn poll(&self) -> Result<PollStatus> {
+        let slave_closed = self.0.slave_closed();
+        Ok(PollStatus {
+            read: !self.0.output.lock().is_empty() || slave_closed,
+            write: true,
+            error: slave_closed,
+        })
+    }
+    fn metadata(&self) -> Result<Metadata> {
+        let mut metadata = self.0.slave_metadata(0);
+        metadata.mode = 0o666;
+        metadata.uid = 0;
+        metadata.gid = 0;
+        Ok(metadata)
+    }
+    fn set_metadata(&self, _metadata: &Metadata) -> Result<()> {
+        Ok(())
+    }
+    impl_inode!();
+}
+
+impl INode for PtySlave {
+    /// Read the input, or the end of file once the master is closed
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        Ok(self.0.tty.read(buf))
+    }
+    /// Wait until the master reads the output, or fail with `EIO` once it is closed
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        let mut output = self.0.output.lock();
+        loop {
+            if !self.0.master_open() {
+                return Err(FsError::DeviceError);
+            }
+            if output.len() < OUTPUT_SIZE {
+                break;
+            }
+            output = self.0.output_drained.wait(output);
+        }
+        // the output is pushed back by the terminal
+        drop(output);
+        self.0.tty.write(buf);
+        Ok(buf.len())
+    }
+    fn poll(&self) -> Result<PollStatus> {
+        let master_open = self.0.master_open();
+        Ok(PollStatus {
+            read: self.0.tty.can_read(),
+            write: !master_open || self.0.output.lock().len() < OUTPUT_SIZE,
+            error: !master_open,
+        })
+    }
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(self.0.slave_metadata(super::devpts::slave_id(self.0.index)))
+    }
+    fn set_metadata(&self, metadata: &Metadata) -> Result<()> {
+        self.0.set_slave_metadata(metadata);
+        Ok(())
+    }
+    impl_inode!();
+}
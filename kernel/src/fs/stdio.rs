@@ -1,38 +1,26 @@
 //! Implement INode for Stdin & Stdout
 
-use alloc::{collections::vec_deque::VecDeque, string::String, sync::Arc};
+use alloc::{string::String, sync::Arc};
 use core::any::Any;
 
 use rcore_fs::vfs::*;
 
-use crate::sync::Condvar;
-use crate::sync::SpinNoIrqLock as Mutex;
+use super::CONSOLE;
 
+/// The input of the console
 #[derive(Default)]
-pub struct Stdin {
-    buf: Mutex<VecDeque<char>>,
-    pub pushed: Condvar,
-}
+pub struct Stdin;
 
 impl Stdin {
-    pub fn push(&self, c: char) {
-        self.buf.lock().push_back(c);
-        self.pushed.notify_one();
-    }
-    pub fn pop(&self) -> char {
-        loop {
-            let ret = self.buf.lock().pop_front();
-            match ret {
-                Some(c) => return c,
-                None => self.pushed._wait(),
-            }
-        }
-    }
-    pub fn can_read(&self) -> bool {
-        self.buf.lock().len() > 0
+    /// Read a byte, waiting until there is one
+    pub fn pop(&self) -> u8 {
+        let mut buf = [0];
+        while CONSOLE.read(&mut buf) == 0 {}
+        buf[0]
     }
 }
 
+/// The output of the console
 #[derive(Default)]
 pub struct Stdout;
 
@@ -41,32 +29,6 @@ lazy_static! {
     pub static ref STDOUT: Arc<Stdout> = Arc::new(Stdout::default());
 }
 
-// 32bits total, command in lower 16bits, size of the parameter structure in the lower 14 bits of the upper 16 bits
-// higher 2 bits: 01 = write, 10 = read
-
-#[cfg(not(target_arch = "mips"))]
-const TCGETS: u32 = 0x5401;
-#[cfg(target_arch = "mips")]
-const TCGETS: u32 = 0x540D;
-
-#[cfg(not(target_arch = "mips"))]
-const TIOCGPGRP: u32 = 0x540F;
-// _IOR('t', 119, int)
-#[cfg(target_arch = "mips")]
-const TIOCGPGRP: u32 = 0x4_004_74_77;
-
-#[cfg(not(target_arch = "mips"))]
-const TIOCSPGRP: u32 = 0x5410;
-// _IOW('t', 118, int)
-#[cfg(target_arch = "mips")]
-const TIOCSPGRP: u32 = 0x8_004_74_76;
-
-#[cfg(not(target_arch = "mips"))]
-const TIOCGWINSZ: u32 = 0x5413;
-// _IOR('t', 104, struct winsize)
-#[cfg(target_arch = "mips")]
-const TIOCGWINSZ: u32 = 0x4_008_74_68;
-
 // TODO: better way to provide default impl?
 macro_rules! impl_inode {
     () => {
@@ -81,39 +43,23 @@ macro_rules! impl_inode {
         fn move_(&self, _old_name: &str, _target: &Arc<INode>, _new_name: &str) -> Result<()> { Err(FsError::NotDir) }
         fn find(&self, _name: &str) -> Result<Arc<INode>> { Err(FsError::NotDir) }
         fn get_entry(&self, _id: usize) -> Result<String> { Err(FsError::NotDir) }
-        fn io_control(&self, cmd: u32, data: usize) -> Result<()> {
-            match cmd {
-                TCGETS | TIOCGWINSZ | TIOCSPGRP => {
-                    // pretend to be tty
-                    Ok(())
-                },
-                TIOCGPGRP => {
-                    // pretend to be have a tty process group
-                    // TODO: verify pointer
-                    unsafe {
-                        *(data as *mut u32) = 0
-                    };
-                    Ok(())
-                }
-                _ => Err(FsError::NotSupported)
-            }
-        }
+        // the requests of terminals are handled by `sys_ioctl`
+        fn io_control(&self, _cmd: u32, _data: usize) -> Result<()> { Err(FsError::NotSupported) }
         fn fs(&self) -> Arc<FileSystem> { unimplemented!() }
         fn as_any_ref(&self) -> &Any { self }
     };
 }
 
 impl INode for Stdin {
-    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
-        buf[0] = self.pop() as u8;
-        Ok(1)
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        Ok(CONSOLE.read(buf))
     }
     fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
         unimplemented!()
     }
     fn poll(&self) -> Result<PollStatus> {
         Ok(PollStatus {
-            read: self.can_read(),
+            read: CONSOLE.can_read(),
             write: false,
             error: false,
         })
@@ -126,10 +72,7 @@ impl INode for Stdout {
         unimplemented!()
     }
     fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
-        use core::str;
-        //we do not care the utf-8 things, we just want to print it!
-        let s = unsafe { str::from_utf8_unchecked(buf) };
-        print!("{}", s);
+        CONSOLE.write(buf);
         Ok(buf.len())
     }
     fn poll(&self) -> Result<PollStatus> {
@@ -0,0 +1,486 @@
+//! Temporary file system in kernel heap, mounted at `/tmp`
+
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::any::Any;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use rcore_fs::vfs::*;
+use spin::{Mutex, RwLock};
+
+use super::{release_fs_memory, reserve_fs_memory};
+use crate::syscall::TimeSpec;
+
+pub struct TmpFS {
+    root: Arc<TmpINode>,
+    next_id: AtomicUsize,
+    /// Limit of the total size of the files in bytes
+    capacity: usize,
+    used: AtomicUsize,
+    /// Its capacity in blocks, with all of them free, since `FsInfo` is static
+    info: &'static FsInfo,
+    /// Held when changing directories, so that they can be locked in any order
+    namespace: Mutex<()>,
+    self_ref: Weak<TmpFS>,
+}
+
+pub struct TmpINode(RwLock<INodeData>);
+
+struct INodeData {
+    metadata: Metadata,
+    /// Content of the file, or the target of the symbolic link
+    content: Vec<u8>,
+    children: BTreeMap<String, Arc<TmpINode>>,
+    /// The directory containing it, or itself for the root
+    parent: Weak<TmpINode>,
+    this: Weak<TmpINode>,
+    fs: Weak<TmpFS>,
+}
+
+/// Block size of the files, which take memory by bytes
+const BLOCK_SIZE: usize = 4096;
+
+fn now() -> Timespec {
+    TimeSpec::get_epoch().into()
+}
+
+fn is_dot(name: &str) -> bool {
+    name == "." || name == ".."
+}
+
+impl TmpFS {
+    /// Create an empty file system, whose files take at most `capacity` bytes
+    /// of the memory shared by the file systems in memory
+    pub fn new(capacity: usize) -> Arc<Self> {
+        let fs = TmpFS {
+            root: TmpINode::new(1, FileType::Dir, 0o1777),
+            next_id: AtomicUsize::new(2),
+            capacity,
+            used: AtomicUsize::new(0),
+            info: Box::leak(Box::new(FsInfo {
+                bsize: BLOCK_SIZE,
+                frsize: BLOCK_SIZE,
+                blocks: capacity / BLOCK_SIZE,
+                bfree: capacity / BLOCK_SIZE,
+                bavail: capacity / BLOCK_SIZE,
+                files: 0,
+                ffree: 0,
+                namemax: 255,
+            })),
+            namespace: Mutex::new(()),
+            self_ref: Weak::new(),
+        }
+        .wrap();
+        {
+            let mut root = fs.root.0.write();
+            root.fs = fs.self_ref.clone();
+            root.parent = root.this.clone();
+        }
+        fs
+    }
+
+    /// Wrap pure `TmpFS` with `Arc<..>`.
+    /// Used in constructors.
+    fn wrap(self) -> Arc<Self> {
+        let fs = Arc::new(self);
+        let weak = Arc::downgrade(&fs);
+        let ptr = Arc::into_raw(fs) as *mut Self;
+        unsafe {
+            (*ptr).self_ref = weak;
+            Arc::from_raw(ptr)
+        }
+    }
+
+    /// Account for the memory of a file growing from `old` to `new` bytes
+    fn resize(&self, old: usize, new: usize) -> Result<()> {
+        if new <= old {
+            self.used.fetch_sub(old - new, Ordering::Relaxed);
+            release_fs_memory(old - new);
+            return Ok(());
+        }
+        let used = self.used.fetch_add(new - old, Ordering::Relaxed);
+        if used + new - old > self.capacity || !reserve_fs_memory(new - old) {
+            self.used.fetch_sub(new - old, Ordering::Relaxed);
+            return Err(FsError::NoDeviceSpace);
+        }
+        Ok(())
+    }
+}
+
+impl FileSystem for TmpFS {
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn root_inode(&self) -> Arc<INode> {
+        self.root.clone()
+    }
+
+    fn info(&self) -> &'static FsInfo {
+        self.info
+    }
+}
+
+impl TmpINode {
+    fn new(id: usize, type_: FileType, mode: u32) -> Arc<Self> {
+        let time = now();
+        let metadata = Metadata {
+            dev: 0,
+            inode: id,
+            size: 0,
+            blk_size: BLOCK_SIZE,
+            blocks: 0,
+            atime: time,
+            mtime: time,
+            ctime: time,
+            type_,
+            mode: mode as u16,
+            nlinks: if type_ == FileType::Dir { 2 } else { 1 },
+            uid: 0,
+            gid: 0,
+        };
+        let inode = Arc::new(TmpINode(RwLock::new(INodeData {
+            metadata,
+            content: Vec::new(),
+            children: BTreeMap::new(),
+            parent: Weak::new(),
+            this: Weak::new(),
+            fs: Weak::new(),
+        })));
+        inode.0.write().this = Arc::downgrade(&inode);
+        inode
+    }
+
+    fn tmpfs(&self) -> Arc<TmpFS> {
+        self.0.read().fs.upgrade().unwrap()
+    }
+
+    /// Downcast `other` to an `TmpINode` in the same file system
+    fn same_fs<'a>(&self, other: &'a Arc<INode>) -> Result<&'a TmpINode> {
+        let other = other
+            .as_any_ref()
+            .downcast_ref::<TmpINode>()
+            .ok_or(FsError::NotSameFs)?;
+        if !Arc::ptr_eq(&self.tmpfs(), &other.tmpfs()) {
+            return Err(FsError::NotSameFs);
+        }
+        Ok(other)
+    }
+
+    /// Whether it is `inode` or one of the directories containing it
+    fn is_ancestor_of(&self, inode: &TmpINode) -> bool {
+        let mut inode = match inode.0.read().this.upgrade() {
+            Some(inode) => inode,
+            None => return false,
+        };
+        loop {
+            if ptr::eq(self, &*inode) {
+                return true;
+            }
+            let parent = inode.0.read().parent.upgrade();
+            match parent {
+                Some(parent) if !Arc::ptr_eq(&parent, &inode) => inode = parent,
+                _ => return false,
+            }
+        }
+    }
+}
+
+impl INodeData {
+    /// Resize the content to `len` bytes, which is also its capacity,
+    /// so that the memory it takes is accounted exactly
+    fn resize_content(&mut self, len: usize) -> Result<()> {
+        let fs = self.fs.upgrade().unwrap();
+        fs.resize(self.content.capacity(), len)?;
+        if len > self.content.len() {
+            let additional = len - self.content.len();
+            self.content.reserve_exact(additional);
+        }
+        self.content.resize(len, 0);
+        self.content.shrink_to_fit();
+        Ok(())
+    }
+
+    /// Check that it is a directory which can have new entries
+    fn check_dir(&self) -> Result<()> {
+        if self.metadata.type_ != FileType::Dir {
+            return Err(FsError::NotDir);
+        }
+        if self.metadata.nlinks == 0 {
+            return Err(FsError::DirRemoved);
+        }
+        Ok(())
+    }
+
+    /// Update the links after the entry of `child` is removed from this directory
+    fn unlinked(&mut self, child: &TmpINode) {
+        let mut child = child.0.write();
+        if child.metadata.type_ == FileType::Dir {
+            child.metadata.nlinks = 0;
+            self.metadata.nlinks -= 1;
+        } else {
+            child.metadata.nlinks -= 1;
+        }
+        child.metadata.ctime = now();
+        self.metadata.mtime = child.metadata.ctime;
+        self.metadata.ctime = child.metadata.ctime;
+    }
+}
+
+/// Check that `old` can replace `new` when moved to its name
+fn check_replace(old: &TmpINode, new: &TmpINode) -> Result<()> {
+    let old_is_dir = old.0.read().metadata.type_ == FileType::Dir;
+    let new = new.0.read();
+    match (old_is_dir, new.metadata.type_ == FileType::Dir) {
+        (true, true) if !new.children.is_empty() => Err(FsError::DirNotEmpty),
+        (true, false) => Err(FsError::NotDir),
+        (false, true) => Err(FsError::IsDir),
+        _ => Ok(()),
+    }
+}
+
+impl Drop for TmpINode {
+    fn drop(&mut self) {
+        let data = self.0.read();
+        let size = data.content.capacity();
+        // the shared memory is given back even if the file system is gone
+        release_fs_memory(size);
+        if let Some(fs) = data.fs.upgrade() {
+            fs.used.fetch_sub(size, Ordering::Relaxed);
+        }
+    }
+}
+
+impl INode for TmpINode {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let data = self.0.read();
+        if data.metadata.type_ == FileType::Dir {
+            return Err(FsError::IsDir);
+        }
+        if offset >= data.content.len() {
+            return Ok(0);
+        }
+        let len = buf.len().min(data.content.len() - offset);
+        buf[..len].copy_from_slice(&data.content[offset..offset + len]);
+        Ok(len)
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        let mut data = self.0.write();
+        if data.metadata.type_ == FileType::Dir {
+            return Err(FsError::IsDir);
+        }
+        let end = offset + buf.len();
+        if end > data.content.len() {
+            data.resize_content(end)?;
+        }
+        data.content[offset..end].copy_from_slice(buf);
+        data.metadata.mtime = now();
+        data.metadata.ctime = data.metadata.mtime;
+        Ok(buf.len())
+    }
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: true,
+            write: true,
+            error: false,
+        })
+    }
+    fn metadata(&self) -> Result<Metadata> {
+        let data = self.0.read();
+        let mut metadata = data.metadata.clone();
+        metadata.size = data.content.len();
+        metadata.blocks = (metadata.size + 511) / 512;
+        Ok(metadata)
+    }
+    fn set_metadata(&self, metadata: &Metadata) -> Result<()> {
+        let mut data = self.0.write();
+        data.metadata.atime = metadata.atime;
+        data.metadata.mtime = metadata.mtime;
+        data.metadata.mode = metadata.mode;
+        data.metadata.uid = metadata.uid;
+        data.metadata.gid = metadata.gid;
+        data.metadata.ctime = now();
+        Ok(())
+    }
+    fn sync_all(&self) -> Result<()> {
+        Ok(())
+    }
+    fn sync_data(&self) -> Result<()> {
+        Ok(())
+    }
+    fn resize(&self, len: usize) -> Result<()> {
+        let mut data = self.0.write();
+        if data.metadata.type_ == FileType::Dir {
+            return Err(FsError::IsDir);
+        }
+        data.resize_content(len)?;
+        data.metadata.mtime = now();
+        data.metadata.ctime = data.metadata.mtime;
+        Ok(())
+    }
+    fn create(&self, name: &str, type_: FileType, mode: u32) -> Result<Arc<INode>> {
+        let fs = self.tmpfs();
+        let _namespace = fs.namespace.lock();
+        let mut data = self.0.write();
+        data.check_dir()?;
+        if is_dot(name) || data.children.contains_key(name) {
+            return Err(FsError::EntryExist);
+        }
+        let id = fs.next_id.fetch_add(1, Ordering::Relaxed);
+        let inode = TmpINode::new(id, type_, mode);
+        {
+            let mut child = inode.0.write();
+            child.fs = data.fs.clone();
+            child.parent = data.this.clone();
+        }
+        if type_ == FileType::Dir {
+            data.metadata.nlinks += 1;
+        }
+        data.metadata.mtime = now();
+        data.metadata.ctime = data.metadata.mtime;
+        data.children.insert(String::from(name), inode.clone());
+        Ok(inode)
+    }
+    fn unlink(&self, name: &str) -> Result<()> {
+        if is_dot(name) {
+            return Err(FsError::InvalidParam);
+        }
+        let fs = self.tmpfs();
+        let _namespace = fs.namespace.lock();
+        let mut data = self.0.write();
+        if data.metadata.type_ != FileType::Dir {
+            return Err(FsError::NotDir);
+        }
+        let child = data
+            .children
+            .get(name)
+            .cloned()
+            .ok_or(FsError::EntryNotFound)?;
+        if !child.0.read().children.is_empty() {
+            return Err(FsError::DirNotEmpty);
+        }
+        data.children.remove(name);
+        data.unlinked(&child);
+        Ok(())
+    }
+    fn link(&self, name: &str, other: &Arc<INode>) -> Result<()> {
+        let other = self.same_fs(other)?;
+        let fs = self.tmpfs();
+        let _namespace = fs.namespace.lock();
+        if other.0.read().metadata.type_ == FileType::Dir {
+            return Err(FsError::IsDir);
+        }
+        let mut data = self.0.write();
+        data.check_dir()?;
+        if is_dot(name) || data.children.contains_key(name) {
+            return Err(FsError::EntryExist);
+        }
+        let mut other = other.0.write();
+        other.metadata.nlinks += 1;
+        other.metadata.ctime = now();
+        data.metadata.mtime = other.metadata.ctime;
+        data.metadata.ctime = other.metadata.ctime;
+        data.children
+            .insert(String::from(name), other.this.upgrade().unwrap());
+        Ok(())
+    }
+    fn move_(&self, old_name: &str, target: &Arc<INode>, new_name: &str) -> Result<()> {
+        if is_dot(old_name) || is_dot(new_name) {
+            return Err(FsError::InvalidParam);
+        }
+        let target = self.same_fs(target)?;
+        let fs = self.tmpfs();
+        let _namespace = fs.namespace.lock();
+        let node = self.0.read().children.get(old_name).cloned();
+        let node = node.ok_or(FsError::EntryNotFound)?;
+        // a directory can not be moved into itself
+        if node.is_ancestor_of(target) {
+            return Err(FsError::InvalidParam);
+        }
+        if ptr::eq(self, target) && old_name == new_name {
+            return Ok(());
+        }
+        let replaced = target.0.read().children.get(new_name).cloned();
+        if let Some(replaced) = &replaced {
+            // both are links to the same file
+            if Arc::ptr_eq(replaced, &node) {
+                return Ok(());
+            }
+            check_replace(&node, replaced)?;
+        }
+        target.0.read().check_dir()?;
+
+        let is_dir = node.0.read().metadata.type_ == FileType::Dir;
+        let time = now();
+        {
+            let mut data = self.0.write();
+            data.children.remove(old_name);
+            if is_dir && !ptr::eq(self, target) {
+                data.metadata.nlinks -= 1;
+            }
+            data.metadata.mtime = time;
+            data.metadata.ctime = time;
+        }
+        {
+            let mut data = target.0.write();
+            if let Some(replaced) = replaced {
+                data.children.remove(new_name);
+                data.unlinked(&replaced);
+            }
+            if is_dir && !ptr::eq(self, target) {
+                data.metadata.nlinks += 1;
+            }
+            data.children.insert(String::from(new_name), node.clone());
+            let mut node = node.0.write();
+            node.parent = data.this.clone();
+            node.metadata.ctime = time;
+        }
+        Ok(())
+    }
+    fn find(&self, name: &str) -> Result<Arc<INode>> {
+        let data = self.0.read();
+        if data.metadata.type_ != FileType::Dir {
+            return Err(FsError::NotDir);
+        }
+        let inode = match name {
+            "." => data.this.upgrade(),
+            ".." => data.parent.upgrade(),
+            _ => data.children.get(name).cloned(),
+        };
+        match inode {
+            Some(inode) => Ok(inode),
+            None => Err(FsError::EntryNotFound),
+        }
+    }
+    fn get_entry(&self, id: usize) -> Result<String> {
+        let data = self.0.read();
+        if data.metadata.type_ != FileType::Dir {
+            return Err(FsError::NotDir);
+        }
+        match id {
+            0 => Ok(String::from(".")),
+            1 => Ok(String::from("..")),
+            _ => data
+                .children
+                .keys()
+                .nth(id - 2)
+                .cloned()
+                .ok_or(FsError::EntryNotFound),
+        }
+    }
+    fn io_control(&self, _cmd: u32, _data: usize) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn fs(&self) -> Arc<FileSystem> {
+        self.tmpfs()
+    }
+    fn as_any_ref(&self) -> &Any {
+        self
+    }
+}
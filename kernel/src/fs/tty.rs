@@ -0,0 +1,653 @@
+//! Terminals: the line discipline between the device and the readers,
+//! and the job control of the sessions they control
+//!
+//! The input typed on the device is pushed byte by byte. It is edited into lines
+//! in canonical mode, echoed, and the keys of `ISIG` signal the foreground process group.
+//! The output is written to the device, with `\n` translated into `\r\n` by `ONLCR`.
+
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc, vec::Vec};
+use core::{mem, str};
+
+use crate::process::signal::{send_signal_to_group, SIGINT, SIGQUIT, SIGTSTP};
+use crate::process::PROCESSES;
+use crate::sync::{Condvar, SpinNoIrqLock as Mutex};
+
+#[cfg(not(target_arch = "mips"))]
+pub const TCGETS: u32 = 0x5401;
+#[cfg(target_arch = "mips")]
+pub const TCGETS: u32 = 0x540D;
+
+#[cfg(not(target_arch = "mips"))]
+pub const TCSETS: u32 = 0x5402;
+#[cfg(target_arch = "mips")]
+pub const TCSETS: u32 = 0x540E;
+
+/// `TCSETS` after the output is written
+#[cfg(not(target_arch = "mips"))]
+pub const TCSETSW: u32 = 0x5403;
+#[cfg(target_arch = "mips")]
+pub const TCSETSW: u32 = 0x540F;
+
+/// `TCSETSW` and discard the input
+#[cfg(not(target_arch = "mips"))]
+pub const TCSETSF: u32 = 0x5404;
+#[cfg(target_arch = "mips")]
+pub const TCSETSF: u32 = 0x5410;
+
+#[cfg(not(target_arch = "mips"))]
+pub const TIOCSCTTY: u32 = 0x540E;
+#[cfg(target_arch = "mips")]
+pub const TIOCSCTTY: u32 = 0x5480;
+
+#[cfg(not(target_arch = "mips"))]
+pub const TIOCGPGRP: u32 = 0x540F;
+// _IOR('t', 119, int)
+#[cfg(target_arch = "mips")]
+pub const TIOCGPGRP: u32 = 0x4_004_74_77;
+
+#[cfg(not(target_arch = "mips"))]
+pub const TIOCSPGRP: u32 = 0x5410;
+// _IOW('t', 118, int)
+#[cfg(target_arch = "mips")]
+pub const TIOCSPGRP: u32 = 0x8_004_74_76;
+
+#[cfg(not(target_arch = "mips"))]
+pub const TIOCGWINSZ: u32 = 0x5413;
+// _IOR('t', 104, struct winsize)
+#[cfg(target_arch = "mips")]
+pub const TIOCGWINSZ: u32 = 0x4_008_74_68;
+
+#[cfg(not(target_arch = "mips"))]
+pub const TIOCSWINSZ: u32 = 0x5414;
+// _IOW('t', 103, struct winsize)
+#[cfg(target_arch = "mips")]
+pub const TIOCSWINSZ: u32 = 0x8_008_74_67;
+
+#[cfg(not(target_arch = "mips"))]
+pub const TIOCGSID: u32 = 0x5429;
+#[cfg(target_arch = "mips")]
+pub const TIOCGSID: u32 = 0x7416;
+
+/// The index of the pty of a master
+// _IOR('T', 0x30, unsigned int)
+#[cfg(not(target_arch = "mips"))]
+pub const TIOCGPTN: u32 = 0x8_004_54_30;
+#[cfg(target_arch = "mips")]
+pub const TIOCGPTN: u32 = 0x4_004_54_30;
+
+/// Lock or unlock the slave of a pty
+// _IOW('T', 0x31, int)
+#[cfg(not(target_arch = "mips"))]
+pub const TIOCSPTLCK: u32 = 0x4_004_54_31;
+#[cfg(target_arch = "mips")]
+pub const TIOCSPTLCK: u32 = 0x8_004_54_31;
+
+// input modes
+pub const ISTRIP: u32 = 0o40;
+pub const INLCR: u32 = 0o100;
+pub const IGNCR: u32 = 0o200;
+pub const ICRNL: u32 = 0o400;
+pub const IXON: u32 = 0o2000;
+
+// output modes
+pub const OPOST: u32 = 0o1;
+pub const ONLCR: u32 = 0o4;
+
+// control modes
+pub const B38400: u32 = 0o17;
+pub const CS8: u32 = 0o60;
+pub const CREAD: u32 = 0o200;
+pub const HUPCL: u32 = 0o2000;
+
+// local modes
+pub const ISIG: u32 = 0o1;
+pub const ICANON: u32 = 0o2;
+pub const ECHO: u32 = 0o10;
+pub const ECHOE: u32 = 0o20;
+pub const ECHOK: u32 = 0o40;
+pub const ECHONL: u32 = 0o100;
+pub const NOFLSH: u32 = 0o200;
+pub const ECHOCTL: u32 = 0o1000;
+pub const ECHOKE: u32 = 0o4000;
+#[cfg(not(target_arch = "mips"))]
+pub const IEXTEN: u32 = 0o100000;
+#[cfg(target_arch = "mips")]
+pub const IEXTEN: u32 = 0o400;
+
+// indices of the control characters
+pub const VINTR: usize = 0;
+pub const VQUIT: usize = 1;
+pub const VERASE: usize = 2;
+pub const VKILL: usize = 3;
+#[cfg(not(target_arch = "mips"))]
+pub const VEOF: usize = 4;
+#[cfg(target_arch = "mips")]
+pub const VEOF: usize = 16;
+pub const VTIME: usize = 5;
+#[cfg(not(target_arch = "mips"))]
+pub const VMIN: usize = 6;
+#[cfg(target_arch = "mips")]
+pub const VMIN: usize = 4;
+pub const VSTART: usize = 8;
+pub const VSTOP: usize = 9;
+pub const VSUSP: usize = 10;
+#[cfg(not(target_arch = "mips"))]
+pub const VEOL: usize = 11;
+#[cfg(target_arch = "mips")]
+pub const VEOL: usize = 17;
+pub const VREPRINT: usize = 12;
+pub const VDISCARD: usize = 13;
+pub const VWERASE: usize = 14;
+pub const VLNEXT: usize = 15;
+#[cfg(not(target_arch = "mips"))]
+pub const VEOL2: usize = 16;
+#[cfg(target_arch = "mips")]
+pub const VEOL2: usize = 6;
+
+#[cfg(not(target_arch = "mips"))]
+pub const NCCS: usize = 19;
+#[cfg(target_arch = "mips")]
+pub const NCCS: usize = 23;
+
+/// Maximum length of a line in canonical mode, including the newline
+const MAX_CANON: usize = 4096;
+
+/// `struct termios` of Linux, which `TCGETS` and `TCSETS` use
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Termios {
+    pub iflag: u32,
+    pub oflag: u32,
+    pub cflag: u32,
+    pub lflag: u32,
+    pub line: u8,
+    /// Control characters, 0 disables one
+    pub cc: [u8; NCCS],
+}
+
+impl Default for Termios {
+    /// The modes of a terminal when it is created, which are the same as Linux
+    fn default() -> Self {
+        let mut cc = [0; NCCS];
+        cc[VINTR] = 0x03; // ^C
+        cc[VQUIT] = 0x1c; // ^\
+        cc[VERASE] = 0x7f; // DEL
+        cc[VKILL] = 0x15; // ^U
+        cc[VEOF] = 0x04; // ^D
+        cc[VTIME] = 0;
+        cc[VMIN] = 1;
+        cc[VSTART] = 0x11; // ^Q
+        cc[VSTOP] = 0x13; // ^S
+        cc[VSUSP] = 0x1a; // ^Z
+        cc[VREPRINT] = 0x12; // ^R
+        cc[VDISCARD] = 0x0f; // ^O
+        cc[VWERASE] = 0x17; // ^W
+        cc[VLNEXT] = 0x16; // ^V
+        Termios {
+            iflag: ICRNL | IXON,
+            oflag: OPOST | ONLCR,
+            cflag: B38400 | CS8 | CREAD | HUPCL,
+            lflag: ISIG | ICANON | ECHO | ECHOE | ECHOK | ECHOCTL | ECHOKE | IEXTEN,
+            line: 0,
+            cc,
+        }
+    }
+}
+
+/// `struct winsize` of Linux
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct WinSize {
+    pub row: u16,
+    pub col: u16,
+    pub xpixel: u16,
+    pub ypixel: u16,
+}
+
+/// A terminal, which can be the controlling terminal of a session
+pub struct Tty {
+    ldisc: Mutex<LineDiscipline>,
+    jobs: Mutex<Jobs>,
+    /// Notified when there is input to read
+    pub pushed: Arc<Condvar>,
+    /// Write the output to the device
+    output: Box<dyn Fn(&[u8]) + Send + Sync>,
+    /// Whether the input is pushed by an interrupt handler
+    interrupt_input: bool,
+}
+
+/// Modes and input of a terminal
+struct LineDiscipline {
+    termios: Termios,
+    winsize: WinSize,
+    /// Lines completed in canonical mode, an empty one is an end of file
+    lines: VecDeque<Vec<u8>>,
+    /// The line being edited in canonical mode
+    editing: Vec<u8>,
+    /// Input which can be read in non-canonical mode
+    raw: VecDeque<u8>,
+    /// The device is gone, reading it returns the end of file
+    hung_up: bool,
+}
+
+#[derive(Default)]
+struct Jobs {
+    /// The session it is the controlling terminal of
+    session: Option<usize>,
+    /// The process group of the session which may read it,
+    /// and receives the signals of its keys
+    foreground: usize,
+}
+
+lazy_static! {
+    /// The terminal of the serial port and the keyboard, i.e. stdin, stdout and `/dev/console`
+    pub static ref CONSOLE: Arc<Tty> = {
+        let mut tty = Tty::new(|data| {
+            // we do not care the utf-8 things, we just want to print it!
+            print!("{}", unsafe { str::from_utf8_unchecked(data) });
+        });
+        tty.interrupt_input = true;
+        tty.set_winsize(console_size());
+        Arc::new(tty)
+    };
+    /// Process groups and the signals typed for them on terminals whose input is pushed
+    /// by system calls, which may be holding the lock of their process
+    static ref TYPED_SIGNALS: Mutex<Vec<(usize, usize)>> = Mutex::new(Vec::new());
+}
+
+/// Send the signals typed on terminals by the system call which is returning,
+/// which holds no lock of a process now
+pub fn send_typed_signals() {
+    let signals = mem::replace(&mut *TYPED_SIGNALS.lock(), Vec::new());
+    for (pgid, sig) in signals {
+        send_signal_to_group(pgid, sig);
+    }
+}
+
+/// Size of the screen of the console, or the usual one of a serial terminal
+fn console_size() -> WinSize {
+    #[cfg(all(target_arch = "x86_64", not(feature = "nographic")))]
+    {
+        use crate::arch::driver::vga::{BUFFER_HEIGHT, BUFFER_WIDTH};
+        WinSize {
+            row: BUFFER_HEIGHT as u16,
+            col: BUFFER_WIDTH as u16,
+            ..WinSize::default()
+        }
+    }
+    #[cfg(not(all(target_arch = "x86_64", not(feature = "nographic"))))]
+    {
+        WinSize {
+            row: 24,
+            col: 80,
+            ..WinSize::default()
+        }
+    }
+}
+
+impl LineDiscipline {
+    fn new() -> Self {
+        LineDiscipline {
+            termios: Termios::default(),
+            winsize: WinSize::default(),
+            lines: VecDeque::new(),
+            editing: Vec::new(),
+            raw: VecDeque::new(),
+            hung_up: false,
+        }
+    }
+
+    fn lflag(&self, flag: u32) -> bool {
+        self.termios.lflag & flag != 0
+    }
+
+    /// Whether `c` is the control character at `index`, which may be disabled
+    fn is_char(&self, c: u8, index: usize) -> bool {
+        self.termios.cc[index] != 0 && self.termios.cc[index] == c
+    }
+
+    fn set_termios(&mut self, termios: &Termios) {
+        let was_canonical = self.lflag(ICANON);
+        self.termios = *termios;
+        // the input is kept when the mode changes
+        match (was_canonical, self.lflag(ICANON)) {
+            (true, false) => {
+                for line in self.lines.drain(..) {
+                    self.raw.extend(line);
+                }
+                self.raw.extend(self.editing.drain(..));
+            }
+            (false, true) => self.editing.extend(self.raw.drain(..)),
+            _ => {}
+        }
+    }
+
+    fn flush_input(&mut self) {
+        self.lines.clear();
+        self.editing.clear();
+        self.raw.clear();
+    }
+
+    /// Process the byte `c` typed on the terminal, putting what to echo into `echo`.
+    /// Return the signal it generates, if any.
+    fn receive(&mut self, mut c: u8, echo: &mut Vec<u8>) -> Option<usize> {
+        let iflag = self.termios.iflag;
+        if iflag & ISTRIP != 0 {
+            c &= 0x7f;
+        }
+        if c == b'\r' {
+            if iflag & IGNCR != 0 {
+                return None;
+            }
+            if iflag & ICRNL != 0 {
+                c = b'\n';
+            }
+        } else if c == b'\n' && iflag & INLCR != 0 {
+            c = b'\r';
+        }
+
+        if self.lflag(ISIG) {
+            let sig = if self.is_char(c, VINTR) {
+                Some(SIGINT)
+            } else if self.is_char(c, VQUIT) {
+                Some(SIGQUIT)
+            } else if self.is_char(c, VSUSP) {
+                Some(SIGTSTP)
+            } else {
+                None
+            };
+            if sig.is_some() {
+                if !self.lflag(NOFLSH) {
+                    self.flush_input();
+                }
+                self.echo(c, echo);
+                return sig;
+            }
+        }
+
+        if self.lflag(ICANON) {
+            self.edit(c, echo);
+        } else {
+            self.raw.push_back(c);
+            self.echo(c, echo);
+        }
+        None
+    }
+
+    /// Edit the line in canonical mode with `c`
+    fn edit(&mut self, c: u8, echo: &mut Vec<u8>) {
+        if self.is_char(c, VERASE) {
+            self.erase(echo);
+        } else if self.is_char(c, VWERASE) && self.lflag(IEXTEN) {
+            while self.editing.last() == Some(&b' ') {
+                self.erase(echo);
+            }
+            while self.editing.last().map_or(false, |&last| last != b' ') {
+                self.erase(echo);
+            }
+        } else if self.is_char(c, VKILL) {
+            if self.lflag(ECHOKE) {
+                while !self.editing.is_empty() {
+                    self.erase(echo);
+                }
+            } else {
+                self.editing.clear();
+                self.echo(c, echo);
+                if self.lflag(ECHOK) {
+                    echo.push(b'\n');
+                }
+            }
+        } else if self.is_char(c, VEOF) {
+            // the line is read without a delimiter, an empty one is an end of file
+            let line = self.editing.drain(..).collect();
+            self.lines.push_back(line);
+        } else if c == b'\n' || self.is_char(c, VEOL) || self.is_char(c, VEOL2) {
+            self.editing.push(c);
+            let line = self.editing.drain(..).collect();
+            self.lines.push_back(line);
+            if c == b'\n' && self.lflag(ECHONL) && !self.lflag(ECHO) {
+                echo.push(c);
+            } else {
+                self.echo(c, echo);
+            }
+        } else if self.editing.len() < MAX_CANON - 1 {
+            self.editing.push(c);
+            self.echo(c, echo);
+        }
+    }
+
+    /// Erase the last character of the line being edited
+    fn erase(&mut self, echo: &mut Vec<u8>) {
+        let c = match self.editing.pop() {
+            Some(c) => c,
+            None => return,
+        };
+        if !self.lflag(ECHO) {
+            return;
+        }
+        if !self.lflag(ECHOE) {
+            let erase = self.termios.cc[VERASE];
+            self.echo(erase, echo);
+            return;
+        }
+        // control characters are echoed as two columns
+        let width = if is_control(c) && self.lflag(ECHOCTL) {
+            2
+        } else {
+            1
+        };
+        for _ in 0..width {
+            echo.extend_from_slice(b"\x08 \x08");
+        }
+    }
+
+    /// Echo `c` if `ECHO`, control characters as `^X` if `ECHOCTL`
+    fn echo(&self, c: u8, echo: &mut Vec<u8>) {
+        if !self.lflag(ECHO) {
+            return;
+        }
+        if is_control(c) && self.lflag(ECHOCTL) {
+            echo.push(b'^');
+            echo.push(c ^ 0x40);
+        } else {
+            echo.push(c);
+        }
+    }
+
+    /// Whether reading does not have to wait.
+    /// In non-canonical mode, `VMIN` being 0 makes reading never wait,
+    /// otherwise it waits for 1 byte. `VTIME` is not supported.
+    fn can_read(&self) -> bool {
+        if self.hung_up {
+            true
+        } else if self.lflag(ICANON) {
+            !self.lines.is_empty()
+        } else {
+            !self.raw.is_empty() || self.termios.cc[VMIN] == 0
+        }
+    }
+
+    /// Read the input into `buf`, at most a line in canonical mode.
+    /// Return `None` if it has to wait.
+    fn read(&mut self, buf: &mut [u8]) -> Option<usize> {
+        if !self.can_read() {
+            return None;
+        }
+        if self.lflag(ICANON) && !self.lines.is_empty() {
+            let line = self.lines.front_mut().unwrap();
+            let len = buf.len().min(line.len());
+            for (x, c) in buf.iter_mut().zip(line.drain(..len)) {
+                *x = c;
+            }
+            if line.is_empty() {
+                self.lines.pop_front();
+            }
+            Some(len)
+        } else {
+            let len = buf.len().min(self.raw.len());
+            for (x, c) in buf.iter_mut().zip(self.raw.drain(..len)) {
+                *x = c;
+            }
+            Some(len)
+        }
+    }
+}
+
+/// Whether `c` is a control character which is echoed as `^X`
+fn is_control(c: u8) -> bool {
+    (c < 0x20 && c != b'\t' && c != b'\n') || c == 0x7f
+}
+
+impl Tty {
+    pub fn new(output: impl Fn(&[u8]) + Send + Sync + 'static) -> Self {
+        Tty {
+            ldisc: Mutex::new(LineDiscipline::new()),
+            jobs: Mutex::new(Jobs::default()),
+            pushed: Arc::new(Condvar::new()),
+            output: Box::new(output),
+            interrupt_input: false,
+        }
+    }
+
+    /// Handle the byte `c` typed on the terminal.
+    ///
+    /// Called by the interrupt handler of the console, or by writing the master of a pty.
+    /// The signals typed on a pty are sent by `send_typed_signals`.
+    pub fn push(&self, c: u8) {
+        let mut echo = Vec::new();
+        let mut ldisc = self.ldisc.lock();
+        let sig = ldisc.receive(c, &mut echo);
+        let readable = ldisc.can_read();
+        drop(ldisc);
+        if !echo.is_empty() {
+            self.write(&echo);
+        }
+        if let Some(sig) = sig {
+            if self.interrupt_input {
+                self.signal_foreground(sig);
+            } else if let Some(pgid) = self.foreground_group() {
+                TYPED_SIGNALS.lock().push((pgid, sig));
+            }
+        }
+        if readable {
+            // wake up both the readers and the threads polling it
+            self.pushed.notify_all();
+        }
+    }
+
+    /// Read the input, waiting until there is some. Return 0 at the end of file.
+    pub fn read(&self, buf: &mut [u8]) -> usize {
+        loop {
+            if let Some(len) = self.ldisc.lock().read(buf) {
+                return len;
+            }
+            self.pushed._wait();
+        }
+    }
+
+    pub fn can_read(&self) -> bool {
+        self.ldisc.lock().can_read()
+    }
+
+    /// The device is gone, wake up the readers to find the end of file
+    pub fn hangup(&self) {
+        self.ldisc.lock().hung_up = true;
+        self.pushed.notify_all();
+    }
+
+    pub fn is_hung_up(&self) -> bool {
+        self.ldisc.lock().hung_up
+    }
+
+    /// Write `buf` to the device, translating `\n` into `\r\n` if `OPOST` and `ONLCR`
+    pub fn write(&self, buf: &[u8]) {
+        let oflag = self.ldisc.lock().termios.oflag;
+        if oflag & OPOST == 0 || oflag & ONLCR == 0 || !buf.contains(&b'\n') {
+            (self.output)(buf);
+            return;
+        }
+        let mut data = Vec::with_capacity(buf.len() * 2);
+        for &c in buf {
+            if c == b'\n' {
+                data.push(b'\r');
+            }
+            data.push(c);
+        }
+        (self.output)(&data);
+    }
+
+    pub fn termios(&self) -> Termios {
+        self.ldisc.lock().termios
+    }
+
+    /// Change the modes, discarding the input if `flush`
+    pub fn set_termios(&self, termios: &Termios, flush: bool) {
+        let mut ldisc = self.ldisc.lock();
+        if flush {
+            ldisc.flush_input();
+        }
+        ldisc.set_termios(termios);
+        let readable = ldisc.can_read();
+        drop(ldisc);
+        if readable {
+            self.pushed.notify_all();
+        }
+    }
+
+    pub fn winsize(&self) -> WinSize {
+        self.ldisc.lock().winsize
+    }
+
+    /// Change the window size. Return whether it is changed.
+    pub fn set_winsize(&self, winsize: WinSize) -> bool {
+        let mut ldisc = self.ldisc.lock();
+        let changed = ldisc.winsize != winsize;
+        ldisc.winsize = winsize;
+        changed
+    }
+
+    /// The session it controls
+    pub fn session(&self) -> Option<usize> {
+        self.jobs.lock().session
+    }
+
+    pub fn foreground(&self) -> usize {
+        self.jobs.lock().foreground
+    }
+
+    pub fn set_foreground(&self, pgid: usize) {
+        self.jobs.lock().foreground = pgid;
+    }
+
+    /// Become the controlling terminal of session `sid`, with `pgid` in the foreground.
+    /// Return false if it controls another session, unless it is stolen.
+    pub fn set_session(&self, sid: usize, pgid: usize, steal: bool) -> bool {
+        let mut jobs = self.jobs.lock();
+        if jobs.session.map_or(false, |session| session != sid) && !steal {
+            return false;
+        }
+        jobs.session = Some(sid);
+        jobs.foreground = pgid;
+        true
+    }
+
+    /// The foreground process group, if it controls a session
+    pub fn foreground_group(&self) -> Option<usize> {
+        let jobs = self.jobs.lock();
+        jobs.session.map(|_| jobs.foreground)
+    }
+
+    /// Send `sig` to the foreground process group of the session it controls
+    pub fn signal_foreground(&self, sig: usize) {
+        let pgid = match self.foreground_group() {
+            Some(pgid) => pgid,
+            None => return,
+        };
+        // the interrupted code may be holding the process table on this CPU
+        if self.interrupt_input && PROCESSES.try_read().is_none() {
+            warn!("signal {} of the terminal is lost", sig);
+            return;
+        }
+        send_signal_to_group(pgid, sig);
+    }
+}
@@ -34,6 +34,7 @@ mod memory;
 mod net;
 mod process;
 mod shell;
+mod swap;
 mod sync;
 mod syscall;
 mod trap;
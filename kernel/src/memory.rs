@@ -2,11 +2,14 @@ use super::HEAP_ALLOCATOR;
 pub use crate::arch::paging::*;
 use crate::consts::MEMORY_OFFSET;
 use crate::process::process_unsafe;
+use crate::swap::{self, Swappable};
 use crate::sync::SpinNoIrqLock;
 use bitmap_allocator::BitAlloc;
 use buddy_system_allocator::LockedHeap;
+use core::ops::Range;
 use lazy_static::*;
 use log::*;
+use rcore_memory::cow::FrameRcMap;
 pub use rcore_memory::memory_set::{handler::*, MemoryArea, MemoryAttr};
 use rcore_memory::*;
 
@@ -14,15 +17,45 @@ pub type MemorySet = rcore_memory::memory_set::MemorySet<InactivePageTable0>;
 
 // x86_64 support up to 64G memory
 #[cfg(target_arch = "x86_64")]
-pub type FrameAlloc = bitmap_allocator::BitAlloc16M;
+type FrameBitAlloc = bitmap_allocator::BitAlloc16M;
 
 // RISCV has 1G memory
 #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
-pub type FrameAlloc = bitmap_allocator::BitAlloc1M;
+type FrameBitAlloc = bitmap_allocator::BitAlloc1M;
 
 // Raspberry Pi 3 has 1G memory
 #[cfg(any(target_arch = "aarch64", target_arch = "mips"))]
-pub type FrameAlloc = bitmap_allocator::BitAlloc1M;
+type FrameBitAlloc = bitmap_allocator::BitAlloc1M;
+
+/// Allocator of frame numbers, which also counts the frames
+#[derive(Default)]
+pub struct FrameAlloc {
+    inner: FrameBitAlloc,
+    total: usize,
+    free: usize,
+}
+
+impl FrameAlloc {
+    /// Add the frames in `range` to the allocator
+    pub fn insert(&mut self, range: Range<usize>) {
+        self.total += range.end - range.start;
+        self.free += range.end - range.start;
+        self.inner.insert(range);
+    }
+    pub fn alloc(&mut self) -> Option<usize> {
+        let frame = self.inner.alloc()?;
+        self.free -= 1;
+        Some(frame)
+    }
+    pub fn dealloc(&mut self, frame: usize) {
+        self.inner.dealloc(frame);
+        self.free += 1;
+    }
+    /// Get the number of frames in total and free
+    pub fn count(&self) -> (usize, usize) {
+        (self.total, self.free)
+    }
+}
 
 lazy_static! {
     pub static ref FRAME_ALLOCATOR: SpinNoIrqLock<FrameAlloc> =
@@ -57,9 +90,11 @@ impl FrameAllocator for GlobalFrameAlloc {
             .lock()
             .alloc()
             .map(|id| id * PAGE_SIZE + MEMORY_OFFSET);
+        // try to swap out a page when out of memory,
+        // with the allocator unlocked since frames may be freed meanwhile.
+        let ret = ret.or_else(swap::swap_out_any);
         trace!("Allocate frame: {:x?}", ret);
         ret
-        // TODO: try to swap out when alloc failed
     }
     fn dealloc(&self, target: usize) {
         trace!("Deallocate frame: {:x}", target);
@@ -69,6 +104,28 @@ impl FrameAllocator for GlobalFrameAlloc {
     }
 }
 
+lazy_static! {
+    /// Reference counts of frames shared by copy-on-write
+    static ref FRAME_RC_MAP: SpinNoIrqLock<FrameRcMap> = SpinNoIrqLock::new(FrameRcMap::default());
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GlobalFrameRcMap;
+
+impl FrameRcMapLock for GlobalFrameRcMap {
+    fn with<R>(&self, f: impl FnOnce(&mut FrameRcMap) -> R) -> R {
+        f(&mut FRAME_RC_MAP.lock())
+    }
+}
+
+/// Share the user pages of `handler` by copy-on-write on fork,
+/// and make them swappable
+pub fn copy_on_write<H: MemoryHandler + Clone>(
+    handler: H,
+) -> CopyOnWrite<Swappable<H>, GlobalFrameAlloc, GlobalFrameRcMap> {
+    CopyOnWrite::new(Swappable::new(handler), GlobalFrameAlloc, GlobalFrameRcMap)
+}
+
 pub fn alloc_frame() -> Option<usize> {
     GlobalFrameAlloc.alloc()
 }
@@ -109,7 +166,17 @@ pub fn handle_page_fault(addr: usize) -> bool {
     debug!("page fault @ {:#x}", addr);
 
     // This is safe as long as page fault never happens in page fault handler
-    unsafe { process_unsafe().vm.handle_page_fault(addr) }
+    let mut proc = unsafe { process_unsafe() };
+    let vm = &mut proc.vm;
+    if swap::swap_in(vm, addr) {
+        return true;
+    }
+    if !vm.handle_page_fault(addr) {
+        return false;
+    }
+    // the page is newly allocated
+    swap::set_swappable(vm, addr);
+    true
 }
 
 pub fn init_heap() {
@@ -1,6 +1,7 @@
 use crate::arch::rand;
-use crate::drivers::{NET_DRIVERS, SOCKET_ACTIVITY};
-use crate::sync::SpinNoIrqLock as Mutex;
+use crate::drivers::NET_DRIVERS;
+use crate::fs::FileLike;
+use crate::sync::{Condvar, SpinNoIrqLock as Mutex, WaitAny};
 use crate::syscall::*;
 use crate::util;
 use alloc::boxed::Box;
@@ -14,6 +15,8 @@ use core::slice;
 use smoltcp::socket::*;
 use smoltcp::wire::*;
 
+use super::UnixEndpoint;
+
 #[derive(Clone, Debug)]
 pub struct LinkLevelEndpoint {
     pub interface_index: usize,
@@ -47,6 +50,36 @@ pub enum Endpoint {
     Ip(IpEndpoint),
     LinkLevel(LinkLevelEndpoint),
     Netlink(NetlinkEndpoint),
+    Unix(UnixEndpoint),
+}
+
+/// Credentials of a process, `struct ucred` of Linux
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UCred {
+    pub pid: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// Ancillary data of `sendmsg` and `recvmsg`
+#[derive(Clone, Default)]
+pub struct Ancillary {
+    /// Files passed by `SCM_RIGHTS`
+    pub rights: Vec<FileLike>,
+    /// Credentials passed by `SCM_CREDENTIALS`
+    pub cred: Option<UCred>,
+}
+
+/// Status of an open socket, shared by its duplicates
+#[derive(Debug, Default)]
+pub struct SocketStatus {
+    /// Operations fail with `EAGAIN` instead of blocking.
+    pub nonblock: bool,
+    /// `SO_RCVTIMEO` in msecs, 0 if receiving never times out
+    pub recv_timeout: usize,
+    /// `SO_SNDTIMEO` in msecs, 0 if sending never times out
+    pub send_timeout: usize,
 }
 
 /// Common methods that a socket must have
@@ -81,6 +114,29 @@ pub trait Socket: Send + Sync {
         warn!("ioctl is unimplemented for this socket");
         Ok(0)
     }
+    /// Like `write`, with the ancillary data of `sendmsg`
+    fn send_msg(
+        &self,
+        data: &[u8],
+        sendto_endpoint: Option<Endpoint>,
+        ancillary: Ancillary,
+    ) -> SysResult {
+        if !ancillary.rights.is_empty() || ancillary.cred.is_some() {
+            // only local domain sockets can pass them
+            return Err(SysError::EINVAL);
+        }
+        self.write(data, sendto_endpoint)
+    }
+    /// Like `read`, with the ancillary data for `recvmsg`
+    fn recv_msg(&self, data: &mut [u8]) -> (SysResult, Endpoint, Ancillary) {
+        let (result, endpoint) = self.read(data);
+        (result, endpoint, Ancillary::default())
+    }
+    /// Condvars notified when the readiness of the socket may change,
+    /// none if it never does
+    fn wait_queues(&self) -> Vec<Arc<Condvar>> {
+        Vec::new()
+    }
     fn box_clone(&self) -> Box<dyn Socket>;
 }
 
@@ -97,6 +153,76 @@ lazy_static! {
     /// every socket operation needs to lock this.
     pub static ref SOCKETS: Mutex<SocketSet<'static, 'static, 'static>> =
         Mutex::new(SocketSet::new(vec![]));
+
+    /// Wait queues of the sockets in `SOCKETS`, locked only with `SOCKETS` locked first
+    static ref SOCKET_WAIT_QUEUES: Mutex<Vec<SocketWaitQueue>> = Mutex::new(Vec::new());
+}
+
+/// Readiness of the socket `handle` in `sockets` as `[can_recv, can_send, is_open, is_active]`,
+/// whose waiters are notified when it changes
+type Readiness = fn(&mut SocketSet<'static, 'static, 'static>, SocketHandle) -> [bool; 4];
+
+/// Wait queue of a socket in `SOCKETS`
+struct SocketWaitQueue {
+    handle: SocketHandle,
+    /// Number of `GlobalSocketHandle`s of the socket
+    refs: usize,
+    activity: Arc<Condvar>,
+    readiness: Readiness,
+}
+
+/// Poll the interfaces over `sockets` by `poll`,
+/// and notify the sockets whose readiness it has changed.
+pub fn poll_sockets<T>(
+    sockets: &mut SocketSet<'static, 'static, 'static>,
+    poll: impl FnOnce(&mut SocketSet<'static, 'static, 'static>) -> T,
+) -> T {
+    let queues = SOCKET_WAIT_QUEUES.lock();
+    let before: Vec<[bool; 4]> = queues
+        .iter()
+        .map(|queue| (queue.readiness)(sockets, queue.handle))
+        .collect();
+    let result = poll(sockets);
+    for (queue, before) in queues.iter().zip(before) {
+        if (queue.readiness)(sockets, queue.handle) != before {
+            queue.activity.notify_all();
+        }
+    }
+    result
+}
+
+fn tcp_readiness(
+    sockets: &mut SocketSet<'static, 'static, 'static>,
+    handle: SocketHandle,
+) -> [bool; 4] {
+    let socket = sockets.get::<TcpSocket>(handle);
+    [
+        socket.can_recv(),
+        socket.can_send(),
+        socket.is_open(),
+        socket.is_active(),
+    ]
+}
+
+fn udp_readiness(
+    sockets: &mut SocketSet<'static, 'static, 'static>,
+    handle: SocketHandle,
+) -> [bool; 4] {
+    let socket = sockets.get::<UdpSocket>(handle);
+    [
+        socket.can_recv(),
+        socket.can_send(),
+        socket.is_open(),
+        false,
+    ]
+}
+
+fn raw_readiness(
+    sockets: &mut SocketSet<'static, 'static, 'static>,
+    handle: SocketHandle,
+) -> [bool; 4] {
+    let socket = sockets.get::<RawSocket>(handle);
+    [socket.can_recv(), socket.can_send(), false, false]
 }
 
 #[derive(Debug, Clone)]
@@ -133,9 +259,44 @@ pub struct NetlinkSocketState {
 #[derive(Debug)]
 struct GlobalSocketHandle(SocketHandle);
 
+impl GlobalSocketHandle {
+    /// Add `socket` to `sockets`, whose waiters are notified when its `readiness` changes
+    fn add<T>(
+        sockets: &mut SocketSet<'static, 'static, 'static>,
+        socket: T,
+        readiness: Readiness,
+    ) -> Self
+    where
+        T: Into<smoltcp::socket::Socket<'static, 'static, 'static>>,
+    {
+        let handle = sockets.add(socket);
+        SOCKET_WAIT_QUEUES.lock().push(SocketWaitQueue {
+            handle,
+            refs: 1,
+            activity: Arc::new(Condvar::new()),
+            readiness,
+        });
+        GlobalSocketHandle(handle)
+    }
+
+    /// The condvar notified when the readiness of the socket may change
+    fn activity(&self) -> Arc<Condvar> {
+        let queues = SOCKET_WAIT_QUEUES.lock();
+        let queue = queues.iter().find(|queue| queue.handle == self.0).unwrap();
+        queue.activity.clone()
+    }
+}
+
 impl Clone for GlobalSocketHandle {
     fn clone(&self) -> Self {
-        SOCKETS.lock().retain(self.0);
+        let mut sockets = SOCKETS.lock();
+        sockets.retain(self.0);
+        let mut queues = SOCKET_WAIT_QUEUES.lock();
+        let queue = queues
+            .iter_mut()
+            .find(|queue| queue.handle == self.0)
+            .unwrap();
+        queue.refs += 1;
         Self(self.0)
     }
 }
@@ -145,6 +306,16 @@ impl Drop for GlobalSocketHandle {
         let mut sockets = SOCKETS.lock();
         sockets.release(self.0);
         sockets.prune();
+        let mut queues = SOCKET_WAIT_QUEUES.lock();
+        let i = queues
+            .iter()
+            .position(|queue| queue.handle == self.0)
+            .unwrap();
+        queues[i].refs -= 1;
+        if queues[i].refs == 0 {
+            queues.remove(i);
+        }
+        drop(queues);
 
         // send FIN immediately when applicable
         drop(sockets);
@@ -157,7 +328,7 @@ impl TcpSocketState {
         let rx_buffer = TcpSocketBuffer::new(vec![0; TCP_RECVBUF]);
         let tx_buffer = TcpSocketBuffer::new(vec![0; TCP_SENDBUF]);
         let socket = TcpSocket::new(rx_buffer, tx_buffer);
-        let handle = GlobalSocketHandle(SOCKETS.lock().add(socket));
+        let handle = GlobalSocketHandle::add(&mut SOCKETS.lock(), socket, tcp_readiness);
 
         TcpSocketState {
             handle,
@@ -169,7 +340,7 @@ impl TcpSocketState {
 
 impl Socket for TcpSocketState {
     fn read(&self, data: &mut [u8]) -> (SysResult, Endpoint) {
-        spin_and_wait(&[&SOCKET_ACTIVITY], move || {
+        spin_and_wait(&[self.handle.activity()], move || {
             poll_ifaces();
             let mut sockets = SOCKETS.lock();
             let mut socket = sockets.get::<TcpSocket>(self.handle.0);
@@ -247,6 +418,12 @@ impl Socket for TcpSocketState {
         let mut socket = sockets.get::<TcpSocket>(self.handle.0);
 
         if let Endpoint::Ip(ip) = endpoint {
+            match socket.state() {
+                TcpState::SynSent => return Err(SysError::EALREADY),
+                TcpState::Closed => {}
+                TcpState::Listen => return Err(SysError::EINVAL),
+                _ => return Err(SysError::EISCONN),
+            }
             let temp_port = get_ephemeral_port();
 
             match socket.connect(ip, temp_port) {
@@ -255,27 +432,14 @@ impl Socket for TcpSocketState {
                     drop(socket);
                     drop(sockets);
 
-                    // wait for connection result
-                    loop {
-                        poll_ifaces();
-
-                        let mut sockets = SOCKETS.lock();
-                        let socket = sockets.get::<TcpSocket>(self.handle.0);
-                        match socket.state() {
-                            TcpState::SynSent => {
-                                // still connecting
-                                drop(socket);
-                                drop(sockets);
-                                debug!("poll for connection wait");
-                                SOCKET_ACTIVITY._wait();
-                            }
-                            TcpState::Established => {
-                                break Ok(0);
-                            }
-                            _ => {
-                                break Err(SysError::ECONNREFUSED);
-                            }
-                        }
+                    // send SYN, the caller waits for the socket to become writable
+                    poll_ifaces();
+                    let mut sockets = SOCKETS.lock();
+                    let socket = sockets.get::<TcpSocket>(self.handle.0);
+                    match socket.state() {
+                        TcpState::SynSent => Err(SysError::EINPROGRESS),
+                        TcpState::Established => Ok(0),
+                        _ => Err(SysError::ECONNREFUSED),
                     }
                 }
                 Err(_) => Err(SysError::ENOBUFS),
@@ -330,6 +494,9 @@ impl Socket for TcpSocketState {
     fn accept(&mut self) -> Result<(Box<dyn Socket>, Endpoint), SysError> {
         let endpoint = self.local_endpoint.ok_or(SysError::EINVAL)?;
         loop {
+            // join the queue before checking, so that the notification is not missed
+            let mut waiter = WaitAny::new();
+            waiter.add(self.handle.activity());
             let mut sockets = SOCKETS.lock();
             let socket = sockets.get::<TcpSocket>(self.handle.0);
 
@@ -342,7 +509,7 @@ impl Socket for TcpSocketState {
                     let tx_buffer = TcpSocketBuffer::new(vec![0; TCP_SENDBUF]);
                     let mut socket = TcpSocket::new(rx_buffer, tx_buffer);
                     socket.listen(endpoint).unwrap();
-                    let new_handle = GlobalSocketHandle(sockets.add(socket));
+                    let new_handle = GlobalSocketHandle::add(&mut sockets, socket, tcp_readiness);
                     let old_handle = ::core::mem::replace(&mut self.handle, new_handle);
 
                     Box::new(TcpSocketState {
@@ -360,7 +527,7 @@ impl Socket for TcpSocketState {
             // avoid deadlock
             drop(socket);
             drop(sockets);
-            SOCKET_ACTIVITY._wait();
+            waiter.wait(0);
         }
     }
 
@@ -390,6 +557,10 @@ impl Socket for TcpSocketState {
         }
     }
 
+    fn wait_queues(&self) -> Vec<Arc<Condvar>> {
+        vec![self.handle.activity()]
+    }
+
     fn box_clone(&self) -> Box<dyn Socket> {
         Box::new(self.clone())
     }
@@ -406,7 +577,7 @@ impl UdpSocketState {
             vec![0; UDP_SENDBUF],
         );
         let socket = UdpSocket::new(rx_buffer, tx_buffer);
-        let handle = GlobalSocketHandle(SOCKETS.lock().add(socket));
+        let handle = GlobalSocketHandle::add(&mut SOCKETS.lock(), socket, udp_readiness);
 
         UdpSocketState {
             handle,
@@ -427,6 +598,9 @@ struct ArpReq {
 impl Socket for UdpSocketState {
     fn read(&self, data: &mut [u8]) -> (SysResult, Endpoint) {
         loop {
+            // join the queue before checking, so that the notification is not missed
+            let mut waiter = WaitAny::new();
+            waiter.add(self.handle.activity());
             let mut sockets = SOCKETS.lock();
             let mut socket = sockets.get::<UdpSocket>(self.handle.0);
 
@@ -449,7 +623,8 @@ impl Socket for UdpSocketState {
 
             // avoid deadlock
             drop(socket);
-            SOCKET_ACTIVITY._wait()
+            drop(sockets);
+            waiter.wait(0)
         }
     }
 
@@ -578,6 +753,10 @@ impl Socket for UdpSocketState {
         self.remote_endpoint.clone().map(|e| Endpoint::Ip(e))
     }
 
+    fn wait_queues(&self) -> Vec<Arc<Condvar>> {
+        vec![self.handle.activity()]
+    }
+
     fn box_clone(&self) -> Box<dyn Socket> {
         Box::new(self.clone())
     }
@@ -599,7 +778,7 @@ impl RawSocketState {
             rx_buffer,
             tx_buffer,
         );
-        let handle = GlobalSocketHandle(SOCKETS.lock().add(socket));
+        let handle = GlobalSocketHandle::add(&mut SOCKETS.lock(), socket, raw_readiness);
 
         RawSocketState {
             handle,
@@ -611,6 +790,9 @@ impl RawSocketState {
 impl Socket for RawSocketState {
     fn read(&self, data: &mut [u8]) -> (SysResult, Endpoint) {
         loop {
+            // join the queue before checking, so that the notification is not missed
+            let mut waiter = WaitAny::new();
+            waiter.add(self.handle.activity());
             let mut sockets = SOCKETS.lock();
             let mut socket = sockets.get::<RawSocket>(self.handle.0);
 
@@ -629,7 +811,7 @@ impl Socket for RawSocketState {
             // avoid deadlock
             drop(socket);
             drop(sockets);
-            SOCKET_ACTIVITY._wait()
+            waiter.wait(0)
         }
     }
 
@@ -683,13 +865,19 @@ impl Socket for RawSocketState {
     }
 
     fn poll(&self) -> (bool, bool, bool) {
-        unimplemented!()
+        let mut sockets = SOCKETS.lock();
+        let socket = sockets.get::<RawSocket>(self.handle.0);
+        (socket.can_recv(), socket.can_send(), false)
     }
 
     fn connect(&mut self, _endpoint: Endpoint) -> SysResult {
         unimplemented!()
     }
 
+    fn wait_queues(&self) -> Vec<Arc<Condvar>> {
+        vec![self.handle.activity()]
+    }
+
     fn box_clone(&self) -> Box<dyn Socket> {
         Box::new(self.clone())
     }
@@ -732,7 +920,8 @@ impl Socket for PacketSocketState {
     }
 
     fn poll(&self) -> (bool, bool, bool) {
-        unimplemented!()
+        // it can not receive
+        (false, true, false)
     }
 
     fn connect(&mut self, _endpoint: Endpoint) -> SysResult {
@@ -1060,7 +1249,8 @@ impl Socket for NetlinkSocketState {
     }
 
     fn poll(&self) -> (bool, bool, bool) {
-        unimplemented!()
+        // reading never blocks, it returns 0 if there is no reply
+        (true, true, false)
     }
 
     fn connect(&mut self, _endpoint: Endpoint) -> SysResult {
@@ -0,0 +1,580 @@
+//! Local domain sockets, bound to socket files or names in the abstract namespace
+
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::cmp::min;
+use core::mem;
+
+use rcore_fs::vfs::{FileType, FsError, INode};
+
+use super::{Ancillary, Endpoint, Socket, UCred};
+use crate::fs::{inode_addr, lookup_at, ROOT_INODE};
+use crate::process::current_thread;
+use crate::sync::{Condvar, SpinNoIrqLock as Mutex};
+use crate::syscall::{spin_and_wait, SocketType, SysError, SysResult};
+
+/// Address of a local domain socket
+#[derive(Clone, Debug, PartialEq)]
+pub enum UnixEndpoint {
+    Unnamed,
+    /// Absolute path of the socket file
+    Path(String),
+    /// Name in the abstract namespace, without the leading zero byte
+    Abstract(Vec<u8>),
+}
+
+/// Bytes a socket can hold before its senders block
+const UNIX_RECVBUF: usize = 64 * 1024;
+/// Connections waiting to be accepted before connecting blocks
+const UNIX_BACKLOG: usize = 16;
+
+#[cfg(not(target_arch = "mips"))]
+const SOL_SOCKET: usize = 1;
+#[cfg(not(target_arch = "mips"))]
+const SO_PASSCRED: usize = 16;
+#[cfg(target_arch = "mips")]
+const SOL_SOCKET: usize = 0xffff;
+#[cfg(target_arch = "mips")]
+const SO_PASSCRED: usize = 17;
+
+/// Key of a bound socket
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Key {
+    /// Address of the socket file, see `inode_addr`
+    File(usize),
+    Abstract(Vec<u8>),
+}
+
+lazy_static! {
+    static ref BOUND_SOCKETS: Mutex<BTreeMap<Key, Weak<UnixSocket>>> = Mutex::new(BTreeMap::new());
+}
+
+struct Message {
+    data: Vec<u8>,
+    from: UnixEndpoint,
+    ancillary: Ancillary,
+    /// Credentials of the sender if not given in `ancillary`
+    cred: UCred,
+}
+
+#[derive(Default)]
+struct Inner {
+    endpoint: UnixEndpoint,
+    /// The socket file it is bound to, which keeps its key in `BOUND_SOCKETS` unique
+    file: Option<Arc<INode>>,
+    /// The connected socket, or the default destination of a datagram socket
+    peer: Option<Weak<UnixSocket>>,
+    /// The listening socket whose full backlog the last connection attempt failed on
+    connecting: Option<Weak<UnixSocket>>,
+    /// Connections waiting to be accepted, `None` unless listening
+    backlog: Option<VecDeque<Arc<UnixSocket>>>,
+    messages: VecDeque<Message>,
+    /// Bytes of the first message already read by a stream socket
+    offset: usize,
+    /// Bytes in `messages` not read yet
+    len: usize,
+    read_shutdown: bool,
+    write_shutdown: bool,
+    /// Whether credentials are received with the messages
+    pass_cred: bool,
+}
+
+impl Default for UnixEndpoint {
+    fn default() -> Self {
+        UnixEndpoint::Unnamed
+    }
+}
+
+struct UnixSocket {
+    type_: SocketType,
+    inner: Mutex<Inner>,
+    /// Notified whenever `inner` changes or the socket is closed.
+    /// It is shared so that waiters do not keep the socket open.
+    activity: Arc<Condvar>,
+}
+
+#[derive(Clone)]
+pub struct UnixSocketState(Arc<UnixSocket>);
+
+/// Credentials of the current process, which is not locked by the system calls sending
+fn current_cred() -> UCred {
+    let proc = current_thread().proc.lock();
+    UCred {
+        pid: proc.pid.get() as u32,
+        uid: proc.cred.user.real as u32,
+        gid: proc.cred.group.real as u32,
+    }
+}
+
+/// Call `action` on `socket` until it returns `Some`,
+/// or return `None` once the socket is closed
+fn wait_for<T>(
+    socket: &Weak<UnixSocket>,
+    mut action: impl FnMut(&UnixSocket) -> Option<T>,
+) -> Option<T> {
+    let activity = socket.upgrade()?.activity.clone();
+    spin_and_wait(&[activity], || match socket.upgrade() {
+        Some(socket) => action(&socket).map(Some),
+        None => Some(None),
+    })
+}
+
+/// Find the socket bound to `endpoint`
+fn lookup(endpoint: &UnixEndpoint) -> Result<Arc<UnixSocket>, SysError> {
+    let key = match endpoint {
+        UnixEndpoint::Path(path) => {
+            let inode = lookup_at(&ROOT_INODE, path, true)?;
+            if inode.metadata()?.type_ != FileType::Socket {
+                return Err(SysError::ECONNREFUSED);
+            }
+            Key::File(inode_addr(&inode))
+        }
+        UnixEndpoint::Abstract(name) => Key::Abstract(name.clone()),
+        UnixEndpoint::Unnamed => return Err(SysError::EINVAL),
+    };
+    let socket = BOUND_SOCKETS.lock().get(&key).and_then(|s| s.upgrade());
+    socket.ok_or(SysError::ECONNREFUSED)
+}
+
+impl UnixSocket {
+    fn new(type_: SocketType) -> Self {
+        UnixSocket {
+            type_,
+            inner: Mutex::new(Inner::default()),
+            activity: Arc::new(Condvar::new()),
+        }
+    }
+
+    fn notify(&self) {
+        self.activity.notify_all();
+    }
+
+    /// Whether messages are read as a byte stream
+    fn is_stream(&self) -> bool {
+        self.type_ == SocketType::Stream
+    }
+
+    /// Whether it needs a connection to send and receive
+    fn is_connection_mode(&self) -> bool {
+        self.type_ != SocketType::Datagram
+    }
+
+    /// Queue as much of `data` as fits, or `None` if it is full
+    fn push(
+        &self,
+        data: &[u8],
+        from: &UnixEndpoint,
+        ancillary: &mut Ancillary,
+    ) -> Option<SysResult> {
+        let mut inner = self.inner.lock();
+        if inner.read_shutdown {
+            return Some(Err(SysError::EPIPE));
+        }
+        if self.is_stream() && data.is_empty() {
+            // an empty message would read as end of file
+            return Some(Ok(0));
+        }
+        let space = UNIX_RECVBUF.saturating_sub(inner.len);
+        let len = if self.is_stream() {
+            min(space, data.len())
+        } else {
+            data.len()
+        };
+        // a datagram is queued whole, and a stream as much as fits
+        let full = if self.is_stream() {
+            len == 0
+        } else {
+            len > space && !inner.messages.is_empty()
+        };
+        if full {
+            return None;
+        }
+        inner.messages.push_back(Message {
+            data: data[..len].to_vec(),
+            from: from.clone(),
+            ancillary: mem::replace(ancillary, Ancillary::default()),
+            cred: current_cred(),
+        });
+        inner.len += len;
+        drop(inner);
+        self.notify();
+        Some(Ok(len))
+    }
+
+    /// Read the queued messages into `data`, or `None` to wait for them
+    fn pop(&self, data: &mut [u8]) -> Option<(SysResult, Endpoint, Ancillary)> {
+        let mut guard = self.inner.lock();
+        let inner = &mut *guard;
+        if inner.messages.is_empty() {
+            let peer_closed = match &inner.peer {
+                Some(peer) => peer.upgrade().is_none(),
+                None => false,
+            };
+            let endpoint = Endpoint::Unix(UnixEndpoint::Unnamed);
+            if inner.read_shutdown || (self.is_connection_mode() && peer_closed) {
+                return Some((Ok(0), endpoint, Ancillary::default()));
+            }
+            if self.is_connection_mode() && inner.peer.is_none() {
+                return Some((Err(SysError::ENOTCONN), endpoint, Ancillary::default()));
+            }
+            return None;
+        }
+        let from = inner.messages[0].from.clone();
+        let mut ancillary = Ancillary::default();
+        let mut cred = None;
+        let mut read = 0;
+        while let Some(message) = inner.messages.front_mut() {
+            let message_cred = message.ancillary.cred.unwrap_or(message.cred);
+            // files and credentials are received with the first byte of their message
+            if read > 0 && (!message.ancillary.rights.is_empty() || cred != Some(message_cred)) {
+                break;
+            }
+            if inner.offset == 0 {
+                ancillary.rights = mem::replace(&mut message.ancillary.rights, Vec::new());
+                cred = Some(message_cred);
+            }
+            let len = min(data.len() - read, message.data.len() - inner.offset);
+            data[read..read + len].copy_from_slice(&message.data[inner.offset..inner.offset + len]);
+            read += len;
+            inner.offset += len;
+            if !self.is_stream() || inner.offset == message.data.len() {
+                // the rest of a datagram is discarded
+                inner.len -= message.data.len() - (inner.offset - len);
+                inner.messages.pop_front();
+                inner.offset = 0;
+            } else {
+                inner.len -= len;
+            }
+            if !self.is_stream() || read == data.len() {
+                break;
+            }
+        }
+        if inner.pass_cred {
+            ancillary.cred = cred;
+        }
+        drop(guard);
+        self.notify();
+        Some((Ok(read), Endpoint::Unix(from), ancillary))
+    }
+}
+
+impl Drop for UnixSocket {
+    fn drop(&mut self) {
+        let mut inner = self.inner.lock();
+        let key = match &inner.endpoint {
+            UnixEndpoint::Path(_) => inner.file.as_ref().map(|file| Key::File(inode_addr(file))),
+            UnixEndpoint::Abstract(name) => Some(Key::Abstract(name.clone())),
+            UnixEndpoint::Unnamed => None,
+        };
+        if let Some(key) = key {
+            let mut bound = BOUND_SOCKETS.lock();
+            // the name may be bound again once this socket can not be upgraded
+            if bound.get(&key).map_or(false, |s| s.upgrade().is_none()) {
+                bound.remove(&key);
+            }
+        }
+        let peer = inner.peer.take();
+        drop(inner);
+        if let Some(peer) = peer.and_then(|peer| peer.upgrade()) {
+            peer.notify();
+        }
+        self.notify();
+    }
+}
+
+impl UnixSocketState {
+    pub fn new(type_: SocketType) -> Self {
+        UnixSocketState(Arc::new(UnixSocket::new(type_)))
+    }
+
+    /// Create a pair of connected sockets for `socketpair`
+    pub fn new_pair(type_: SocketType) -> (Self, Self) {
+        let a = Arc::new(UnixSocket::new(type_));
+        let b = Arc::new(UnixSocket::new(type_));
+        a.inner.lock().peer = Some(Arc::downgrade(&b));
+        b.inner.lock().peer = Some(Arc::downgrade(&a));
+        (UnixSocketState(a), UnixSocketState(b))
+    }
+}
+
+impl Socket for UnixSocketState {
+    fn read(&self, data: &mut [u8]) -> (SysResult, Endpoint) {
+        let (result, endpoint, _) = self.recv_msg(data);
+        (result, endpoint)
+    }
+
+    fn write(&self, data: &[u8], sendto_endpoint: Option<Endpoint>) -> SysResult {
+        self.send_msg(data, sendto_endpoint, Ancillary::default())
+    }
+
+    fn poll(&self) -> (bool, bool, bool) {
+        let (input, listening, peer) = {
+            let inner = self.0.inner.lock();
+            let input = match &inner.backlog {
+                Some(backlog) => !backlog.is_empty(),
+                None => !inner.messages.is_empty() || inner.read_shutdown,
+            };
+            (input, inner.backlog.is_some(), inner.peer.clone())
+        };
+        match peer {
+            Some(peer) => match peer.upgrade() {
+                Some(peer) => {
+                    let inner = peer.inner.lock();
+                    let output = !inner.read_shutdown && inner.len < UNIX_RECVBUF;
+                    (input, output, false)
+                }
+                // reading returns end of file
+                None => (self.0.is_connection_mode() || input, false, false),
+            },
+            // hang up if it is neither connected nor listening, as Linux does
+            None if self.0.is_connection_mode() => (input, false, !listening),
+            None => (input, true, false),
+        }
+    }
+
+    fn connect(&mut self, endpoint: Endpoint) -> SysResult {
+        let endpoint = match endpoint {
+            Endpoint::Unix(endpoint) => endpoint,
+            _ => return Err(SysError::EINVAL),
+        };
+        let target = lookup(&endpoint)?;
+        if target.type_ != self.0.type_ {
+            return Err(SysError::EPROTOTYPE);
+        }
+        if !self.0.is_connection_mode() {
+            self.0.inner.lock().peer = Some(Arc::downgrade(&target));
+            return Ok(0);
+        }
+
+        // the socket to be accepted
+        let server = Arc::new(UnixSocket::new(self.0.type_));
+        {
+            let mut inner = self.0.inner.lock();
+            if inner.peer.is_some() {
+                return Err(SysError::EISCONN);
+            }
+            if inner.backlog.is_some() {
+                return Err(SysError::EINVAL);
+            }
+            inner.peer = Some(Arc::downgrade(&server));
+        }
+        {
+            let mut inner = server.inner.lock();
+            inner.endpoint = endpoint;
+            inner.peer = Some(Arc::downgrade(&self.0));
+        }
+        let result = match &mut target.inner.lock().backlog {
+            // the caller waits for the target to accept a connection
+            Some(backlog) if backlog.len() >= UNIX_BACKLOG => Err(SysError::EAGAIN),
+            Some(backlog) => {
+                backlog.push_back(server);
+                Ok(0)
+            }
+            None => Err(SysError::ECONNREFUSED),
+        };
+        target.notify();
+        let mut inner = self.0.inner.lock();
+        inner.connecting = match result {
+            Err(SysError::EAGAIN) => Some(Arc::downgrade(&target)),
+            _ => None,
+        };
+        if result.is_err() {
+            inner.peer = None;
+        }
+        result
+    }
+
+    fn bind(&mut self, endpoint: Endpoint) -> SysResult {
+        let endpoint = match endpoint {
+            Endpoint::Unix(endpoint) => endpoint,
+            _ => return Err(SysError::EINVAL),
+        };
+        if self.0.inner.lock().endpoint != UnixEndpoint::Unnamed {
+            return Err(SysError::EINVAL);
+        }
+        // the socket file is created before locking, since it may do I/O
+        let (key, file) = match &endpoint {
+            UnixEndpoint::Path(path) => {
+                let mut split = path.rsplitn(2, '/');
+                let name = split.next().unwrap();
+                let dir = match split.next() {
+                    Some("") | None => "/",
+                    Some(dir) => dir,
+                };
+                let dir = lookup_at(&ROOT_INODE, dir, true)?;
+                let file = match dir.create(name, FileType::Socket, 0o777) {
+                    Ok(file) => file,
+                    Err(FsError::EntryExist) => return Err(SysError::EADDRINUSE),
+                    Err(err) => return Err(SysError::from(err)),
+                };
+                (Key::File(inode_addr(&file)), Some(file))
+            }
+            UnixEndpoint::Abstract(name) => (Key::Abstract(name.clone()), None),
+            UnixEndpoint::Unnamed => return Err(SysError::EINVAL),
+        };
+        let mut inner = self.0.inner.lock();
+        if inner.endpoint != UnixEndpoint::Unnamed {
+            // bound by another thread meanwhile
+            return Err(SysError::EINVAL);
+        }
+        let mut bound = BOUND_SOCKETS.lock();
+        if bound.get(&key).and_then(|s| s.upgrade()).is_some() {
+            return Err(SysError::EADDRINUSE);
+        }
+        bound.insert(key, Arc::downgrade(&self.0));
+        inner.endpoint = endpoint;
+        inner.file = file;
+        Ok(0)
+    }
+
+    fn listen(&mut self) -> SysResult {
+        if !self.0.is_connection_mode() {
+            return Err(SysError::EOPNOTSUPP);
+        }
+        let mut inner = self.0.inner.lock();
+        if inner.endpoint == UnixEndpoint::Unnamed || inner.peer.is_some() {
+            return Err(SysError::EINVAL);
+        }
+        if inner.backlog.is_none() {
+            inner.backlog = Some(VecDeque::new());
+        }
+        Ok(0)
+    }
+
+    fn shutdown(&self) -> SysResult {
+        let peer = {
+            let mut inner = self.0.inner.lock();
+            inner.read_shutdown = true;
+            inner.write_shutdown = true;
+            inner.peer.clone()
+        };
+        if let Some(peer) = peer.and_then(|peer| peer.upgrade()) {
+            peer.inner.lock().read_shutdown = true;
+            peer.notify();
+        }
+        self.0.notify();
+        Ok(0)
+    }
+
+    fn accept(&mut self) -> Result<(Box<dyn Socket>, Endpoint), SysError> {
+        let listener = Arc::downgrade(&self.0);
+        let server = wait_for(&listener, |listener| {
+            let mut inner = listener.inner.lock();
+            let result = match &mut inner.backlog {
+                Some(backlog) => Ok(backlog.pop_front()?),
+                None => Err(SysError::EINVAL),
+            };
+            drop(inner);
+            // there is space in the backlog for connecting sockets
+            listener.notify();
+            Some(result)
+        });
+        let server = server.unwrap()?;
+        let peer = server.inner.lock().peer.clone();
+        let remote_endpoint = match peer.and_then(|peer| peer.upgrade()) {
+            Some(peer) => peer.inner.lock().endpoint.clone(),
+            None => UnixEndpoint::Unnamed,
+        };
+        Ok((
+            Box::new(UnixSocketState(server)),
+            Endpoint::Unix(remote_endpoint),
+        ))
+    }
+
+    fn endpoint(&self) -> Option<Endpoint> {
+        Some(Endpoint::Unix(self.0.inner.lock().endpoint.clone()))
+    }
+
+    fn remote_endpoint(&self) -> Option<Endpoint> {
+        let peer = self.0.inner.lock().peer.clone()?.upgrade()?;
+        let endpoint = peer.inner.lock().endpoint.clone();
+        Some(Endpoint::Unix(endpoint))
+    }
+
+    fn setsockopt(&mut self, level: usize, opt: usize, data: &[u8]) -> SysResult {
+        match (level, opt) {
+            (SOL_SOCKET, SO_PASSCRED) => {
+                self.0.inner.lock().pass_cred = data.iter().any(|&x| x != 0);
+                Ok(0)
+            }
+            _ => {
+                warn!("setsockopt is unimplemented");
+                Ok(0)
+            }
+        }
+    }
+
+    fn send_msg(
+        &self,
+        data: &[u8],
+        sendto_endpoint: Option<Endpoint>,
+        mut ancillary: Ancillary,
+    ) -> SysResult {
+        let (peer, from) = {
+            let inner = self.0.inner.lock();
+            if inner.write_shutdown {
+                return Err(SysError::EPIPE);
+            }
+            let peer = match sendto_endpoint {
+                // the address is ignored by connected sockets
+                Some(Endpoint::Unix(endpoint)) if inner.peer.is_none() => {
+                    if self.0.is_connection_mode() {
+                        return Err(SysError::ENOTCONN);
+                    }
+                    let peer = lookup(&endpoint)?;
+                    if peer.type_ != self.0.type_ {
+                        return Err(SysError::EPROTOTYPE);
+                    }
+                    Arc::downgrade(&peer)
+                }
+                Some(Endpoint::Unix(_)) | None => match &inner.peer {
+                    Some(peer) => peer.clone(),
+                    None => return Err(SysError::ENOTCONN),
+                },
+                Some(_) => return Err(SysError::EINVAL),
+            };
+            (peer, inner.endpoint.clone())
+        };
+        if !self.0.is_stream() && data.len() > UNIX_RECVBUF {
+            return Err(SysError::EMSGSIZE);
+        }
+        let peer = match peer.upgrade() {
+            Some(peer) => peer,
+            None if self.0.is_connection_mode() => return Err(SysError::EPIPE),
+            None => return Err(SysError::ECONNREFUSED),
+        };
+        // the rest of a stream is sent by the caller, which waits with the process unlocked
+        match peer.push(data, &from, &mut ancillary) {
+            Some(result) => result,
+            None => Err(SysError::EAGAIN),
+        }
+    }
+
+    fn recv_msg(&self, data: &mut [u8]) -> (SysResult, Endpoint, Ancillary) {
+        let socket = Arc::downgrade(&self.0);
+        wait_for(&socket, |socket| socket.pop(data)).unwrap()
+    }
+
+    fn wait_queues(&self) -> Vec<Arc<Condvar>> {
+        // the peer is notified when there is space to write,
+        // and the target of connecting when there is space in its backlog
+        let mut queues = vec![self.0.activity.clone()];
+        let (peer, connecting) = {
+            let inner = self.0.inner.lock();
+            (inner.peer.clone(), inner.connecting.clone())
+        };
+        for socket in peer.iter().chain(connecting.iter()) {
+            if let Some(socket) = socket.upgrade() {
+                queues.push(socket.activity.clone());
+            }
+        }
+        queues
+    }
+
+    fn box_clone(&self) -> Box<dyn Socket> {
+        Box::new(self.clone())
+    }
+}
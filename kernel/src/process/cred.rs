@@ -0,0 +1,186 @@
+//! User and group ids of processes, and the permission checks using them
+
+use alloc::vec::Vec;
+
+use rcore_fs::vfs::{FileType, Metadata};
+
+use crate::syscall::SysError;
+
+/// Permission to read, in the access modes of `access`
+pub const R_OK: usize = 4;
+/// Permission to write
+pub const W_OK: usize = 2;
+/// Permission to execute, or to search a directory
+pub const X_OK: usize = 1;
+
+/// Set-user-ID on execution
+pub const S_ISUID: usize = 0o4000;
+/// Set-group-ID on execution
+pub const S_ISGID: usize = 0o2000;
+/// Only the owners can remove the entries of a directory
+pub const S_ISVTX: usize = 0o1000;
+
+/// Real, effective and saved ids, of either users or groups
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Ids {
+    pub real: usize,
+    pub effective: usize,
+    pub saved: usize,
+}
+
+/// Credentials of a process, inherited by its children
+#[derive(Debug, Clone, Default)]
+pub struct Credentials {
+    pub user: Ids,
+    pub group: Ids,
+    /// Supplementary groups
+    pub groups: Vec<usize>,
+}
+
+impl Ids {
+    /// `setuid`: a privileged process sets all the ids,
+    /// the others only set the effective id to the real or the saved one
+    pub fn set(&mut self, id: usize, privileged: bool) -> Result<(), SysError> {
+        if privileged {
+            *self = Ids {
+                real: id,
+                effective: id,
+                saved: id,
+            };
+        } else if id == self.real || id == self.saved {
+            self.effective = id;
+        } else {
+            return Err(SysError::EPERM);
+        }
+        Ok(())
+    }
+
+    /// `setreuid`, `None` keeps an id. The saved id becomes the effective one
+    /// if the real id is set, or the effective id is set to another one than the real.
+    pub fn set_re(
+        &mut self,
+        real: Option<usize>,
+        effective: Option<usize>,
+        privileged: bool,
+    ) -> Result<(), SysError> {
+        if !privileged {
+            let real_ok = real.map_or(true, |id| id == self.real || id == self.effective);
+            let effective_ok = effective.map_or(true, |id| self.is_one_of(id));
+            if !real_ok || !effective_ok {
+                return Err(SysError::EPERM);
+            }
+        }
+        let old_real = self.real;
+        if let Some(id) = real {
+            self.real = id;
+        }
+        if let Some(id) = effective {
+            self.effective = id;
+        }
+        if real.is_some() || effective.map_or(false, |id| id != old_real) {
+            self.saved = self.effective;
+        }
+        Ok(())
+    }
+
+    /// `setresuid`, `None` keeps an id.
+    /// Unless privileged, each id can only be set to one of the current ones.
+    pub fn set_res(
+        &mut self,
+        real: Option<usize>,
+        effective: Option<usize>,
+        saved: Option<usize>,
+        privileged: bool,
+    ) -> Result<(), SysError> {
+        let allowed = |id: &Option<usize>| id.map_or(true, |id| self.is_one_of(id));
+        if !privileged && !(allowed(&real) && allowed(&effective) && allowed(&saved)) {
+            return Err(SysError::EPERM);
+        }
+        if let Some(id) = real {
+            self.real = id;
+        }
+        if let Some(id) = effective {
+            self.effective = id;
+        }
+        if let Some(id) = saved {
+            self.saved = id;
+        }
+        Ok(())
+    }
+
+    /// Whether `id` is the real, effective or saved id
+    pub fn is_one_of(&self, id: usize) -> bool {
+        id == self.real || id == self.effective || id == self.saved
+    }
+}
+
+impl Credentials {
+    /// Whether the effective user is root, which bypasses the permission checks
+    pub fn is_root(&self) -> bool {
+        self.user.effective == 0
+    }
+
+    pub fn in_group(&self, gid: usize) -> bool {
+        self.group.effective == gid || self.groups.contains(&gid)
+    }
+
+    /// The credentials with the real ids as the effective ones, which `access` checks with
+    pub fn real(&self) -> Self {
+        let mut cred = self.clone();
+        cred.user.effective = cred.user.real;
+        cred.group.effective = cred.group.real;
+        cred
+    }
+
+    /// Whether the `access` (a mask of `R_OK`, `W_OK` and `X_OK`) to the file is permitted
+    pub fn can_access(&self, metadata: &Metadata, access: usize) -> bool {
+        let mode = metadata.mode as usize;
+        if self.is_root() {
+            // root can execute only what someone can, but search any directory
+            return access & X_OK == 0 || metadata.type_ == FileType::Dir || mode & 0o111 != 0;
+        }
+        // the bits of the owner, the group or the others
+        let shift = if self.user.effective == metadata.uid {
+            6
+        } else if self.in_group(metadata.gid) {
+            3
+        } else {
+            0
+        };
+        access & !(mode >> shift) & 0o7 == 0
+    }
+
+    /// Whether it can change the mode and the group of the file
+    pub fn owns(&self, metadata: &Metadata) -> bool {
+        self.is_root() || self.user.effective == metadata.uid
+    }
+
+    /// Whether it can remove `file` from `dir` if it can write `dir`,
+    /// which is restricted to their owners when `dir` is sticky
+    pub fn can_remove(&self, dir: &Metadata, file: &Metadata) -> bool {
+        dir.mode as usize & S_ISVTX == 0 || self.owns(dir) || self.owns(file)
+    }
+
+    /// Whether it can send a signal to a process with the credentials `target`
+    pub fn can_signal(&self, target: &Credentials) -> bool {
+        let (from, to) = (&self.user, &target.user);
+        self.is_root()
+            || from.real == to.real
+            || from.real == to.saved
+            || from.effective == to.real
+            || from.effective == to.saved
+    }
+
+    /// Take the ids of the set-user-ID or set-group-ID program `metadata` when executing it
+    pub fn exec(&mut self, metadata: &Metadata) {
+        let mode = metadata.mode as usize;
+        if mode & S_ISUID != 0 {
+            self.user.effective = metadata.uid;
+        }
+        if mode & S_ISGID != 0 {
+            self.group.effective = metadata.gid;
+        }
+        self.user.saved = self.user.effective;
+        self.group.saved = self.group.effective;
+    }
+}
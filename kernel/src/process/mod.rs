@@ -7,6 +7,8 @@ use log::*;
 pub use rcore_thread::*;
 
 mod abi;
+pub mod cred;
+pub mod signal;
 pub mod structs;
 
 pub fn init() {
@@ -20,6 +22,7 @@ pub fn init() {
         }
     }
 
+    crate::fs::init();
     crate::shell::run_user_shell();
 
     info!("process: init end");
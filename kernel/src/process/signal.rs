@@ -0,0 +1,608 @@
+//! POSIX signals
+//!
+//! Signal dispositions are shared by all threads in a process.
+//! Every thread has its own blocked mask, and a pending set for the signals
+//! directed to it (`tkill`). Signals sent to the whole process (`kill`) are
+//! pending in the process and taken by the first thread not blocking them.
+//!
+//! Signals are delivered when a thread is about to return to user mode:
+//! a `SignalFrame` holding the interrupted `TrapFrame` is pushed onto the
+//! user stack, and the thread resumes in the handler. When the handler
+//! returns, it lands in a trampoline calling `rt_sigreturn`, which restores
+//! the saved `TrapFrame`.
+//!
+//! A stop signal with the default action stops the whole process: its threads
+//! wait in `handle_signal` until `SIGCONT` is sent.
+
+use alloc::{sync::Arc, vec::Vec};
+use bitflags::bitflags;
+use core::mem::size_of;
+use log::*;
+use rcore_memory::PAGE_SIZE;
+use rcore_thread::Tid;
+
+use super::{current_thread, processor, Process, WaitStatus, PROCESSES};
+use crate::arch::interrupt::TrapFrame;
+use crate::consts::USER_STACK_OFFSET;
+use crate::sync::SpinNoIrqLock as Mutex;
+
+/// Max signal number
+pub const NSIG: usize = 64;
+
+pub const SIGHUP: usize = 1;
+pub const SIGINT: usize = 2;
+pub const SIGQUIT: usize = 3;
+pub const SIGILL: usize = 4;
+pub const SIGTRAP: usize = 5;
+pub const SIGABRT: usize = 6;
+pub const SIGFPE: usize = 8;
+pub const SIGKILL: usize = 9;
+pub const SIGSEGV: usize = 11;
+pub const SIGPIPE: usize = 13;
+pub const SIGALRM: usize = 14;
+pub const SIGTERM: usize = 15;
+
+#[cfg(not(target_arch = "mips"))]
+pub use self::numbers::*;
+#[cfg(not(target_arch = "mips"))]
+mod numbers {
+    pub const SIGBUS: usize = 7;
+    pub const SIGUSR1: usize = 10;
+    pub const SIGUSR2: usize = 12;
+    pub const SIGSTKFLT: usize = 16;
+    pub const SIGCHLD: usize = 17;
+    pub const SIGCONT: usize = 18;
+    pub const SIGSTOP: usize = 19;
+    pub const SIGTSTP: usize = 20;
+    pub const SIGTTIN: usize = 21;
+    pub const SIGTTOU: usize = 22;
+    pub const SIGURG: usize = 23;
+    pub const SIGXCPU: usize = 24;
+    pub const SIGXFSZ: usize = 25;
+    pub const SIGVTALRM: usize = 26;
+    pub const SIGPROF: usize = 27;
+    pub const SIGWINCH: usize = 28;
+    pub const SIGIO: usize = 29;
+    pub const SIGPWR: usize = 30;
+    pub const SIGSYS: usize = 31;
+}
+
+#[cfg(target_arch = "mips")]
+pub use self::numbers::*;
+#[cfg(target_arch = "mips")]
+mod numbers {
+    pub const SIGEMT: usize = 7;
+    pub const SIGBUS: usize = 10;
+    pub const SIGSYS: usize = 12;
+    pub const SIGUSR1: usize = 16;
+    pub const SIGUSR2: usize = 17;
+    pub const SIGCHLD: usize = 18;
+    pub const SIGPWR: usize = 19;
+    pub const SIGWINCH: usize = 20;
+    pub const SIGURG: usize = 21;
+    pub const SIGIO: usize = 22;
+    pub const SIGSTOP: usize = 23;
+    pub const SIGTSTP: usize = 24;
+    pub const SIGCONT: usize = 25;
+    pub const SIGTTIN: usize = 26;
+    pub const SIGTTOU: usize = 27;
+    pub const SIGVTALRM: usize = 28;
+    pub const SIGPROF: usize = 29;
+    pub const SIGXCPU: usize = 30;
+    pub const SIGXFSZ: usize = 31;
+}
+
+/// Default signal handler
+pub const SIG_DFL: usize = 0;
+/// Ignore the signal
+pub const SIG_IGN: usize = 1;
+
+/// A set of signals, bit `n - 1` stands for signal `n`
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Sigset(pub u64);
+
+impl Sigset {
+    pub fn empty() -> Self {
+        Sigset(0)
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+    pub fn contains(&self, sig: usize) -> bool {
+        sig >= 1 && sig <= NSIG && (self.0 >> (sig - 1)) & 1 != 0
+    }
+    pub fn add(&mut self, sig: usize) {
+        if sig >= 1 && sig <= NSIG {
+            self.0 |= 1 << (sig - 1);
+        }
+    }
+    pub fn remove(&mut self, sig: usize) {
+        if sig >= 1 && sig <= NSIG {
+            self.0 &= !(1 << (sig - 1));
+        }
+    }
+    pub fn add_set(&mut self, set: Sigset) {
+        self.0 |= set.0;
+    }
+    pub fn remove_set(&mut self, set: Sigset) {
+        self.0 &= !set.0;
+    }
+    /// The lowest signal in this set which is not in `mask`
+    fn first_unmasked(&self, mask: Sigset) -> Option<usize> {
+        let bits = self.0 & !mask.0;
+        if bits == 0 {
+            None
+        } else {
+            Some(bits.trailing_zeros() as usize + 1)
+        }
+    }
+    /// SIGKILL and SIGSTOP can not be blocked
+    pub fn without_unblockable(mut self) -> Self {
+        self.remove(SIGKILL);
+        self.remove(SIGSTOP);
+        self
+    }
+}
+
+bitflags! {
+    pub struct SignalActionFlags: usize {
+        const NOCLDSTOP = 1;
+        /// The children are reaped as they exit
+        const NOCLDWAIT = 2;
+        /// The handler takes `(sig, info, ucontext)`, which is always passed
+        const SIGINFO = 4;
+        const RESTORER = 0x04000000;
+        const ONSTACK = 0x08000000;
+        const RESTART = 0x10000000;
+        const NODEFER = 0x40000000;
+        const RESETHAND = 0x80000000;
+    }
+}
+
+/// The disposition of a signal
+#[derive(Debug, Clone, Copy)]
+pub struct SignalAction {
+    pub handler: usize,
+    pub flags: SignalActionFlags,
+    /// User provided trampoline, valid if `flags` contains `RESTORER`
+    pub restorer: usize,
+    /// Signals blocked during execution of the handler
+    pub mask: Sigset,
+}
+
+impl Default for SignalAction {
+    fn default() -> Self {
+        SignalAction {
+            handler: SIG_DFL,
+            flags: SignalActionFlags::empty(),
+            restorer: 0,
+            mask: Sigset::empty(),
+        }
+    }
+}
+
+/// What to do for a signal with `SIG_DFL` handler
+#[derive(Debug, Eq, PartialEq)]
+enum DefaultAction {
+    Terminate,
+    Ignore,
+    CoreDump,
+    Stop,
+    Continue,
+}
+
+fn default_action(sig: usize) -> DefaultAction {
+    match sig {
+        SIGCHLD | SIGURG | SIGWINCH => DefaultAction::Ignore,
+        SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => DefaultAction::Stop,
+        SIGCONT => DefaultAction::Continue,
+        SIGQUIT | SIGILL | SIGTRAP | SIGABRT | SIGBUS | SIGFPE | SIGSEGV | SIGXCPU | SIGXFSZ
+        | SIGSYS => DefaultAction::CoreDump,
+        _ => DefaultAction::Terminate,
+    }
+}
+
+/// Whether delivering `sig` under `action` has any effect
+fn is_ignored(sig: usize, action: &SignalAction) -> bool {
+    match action.handler {
+        SIG_IGN => sig != SIGKILL && sig != SIGSTOP,
+        // `SIGCONT` continues the process when it is sent, see `queue_signal`
+        SIG_DFL => match default_action(sig) {
+            DefaultAction::Ignore | DefaultAction::Continue => true,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// `si_code` for signals sent by `kill`
+pub const SI_USER: i32 = 0;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SigInfo {
+    pub signo: i32,
+    #[cfg(not(target_arch = "mips"))]
+    pub errno: i32,
+    pub code: i32,
+    #[cfg(target_arch = "mips")]
+    pub errno: i32,
+    #[cfg(target_pointer_width = "64")]
+    _pad: i32,
+    pub pid: i32,
+    pub uid: u32,
+    _reserved: [u8; SIGINFO_RESERVED],
+}
+
+impl SigInfo {
+    /// The information of `SIGCHLD` about child `pid`, which `waitid` returns
+    pub fn child(pid: usize, uid: usize, status: WaitStatus) -> Self {
+        let (code, status) = status.child_code();
+        let mut reserved = [0; SIGINFO_RESERVED];
+        // `si_status` follows `si_pid` and `si_uid`
+        reserved[..4].copy_from_slice(&status.to_ne_bytes());
+        SigInfo {
+            signo: SIGCHLD as i32,
+            errno: 0,
+            code,
+            #[cfg(target_pointer_width = "64")]
+            _pad: 0,
+            pid: pid as i32,
+            uid: uid as u32,
+            _reserved: reserved,
+        }
+    }
+}
+
+#[cfg(target_pointer_width = "64")]
+const SIGINFO_RESERVED: usize = 128 - 24;
+#[cfg(target_pointer_width = "32")]
+const SIGINFO_RESERVED: usize = 128 - 20;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SignalStack {
+    pub sp: usize,
+    pub flags: i32,
+    pub size: usize,
+}
+
+#[repr(C)]
+#[derive(Clone)]
+pub struct SignalUserContext {
+    pub flags: usize,
+    pub link: usize,
+    pub stack: SignalStack,
+    /// The interrupted context, which is opaque to the user program
+    pub context: TrapFrame,
+    /// Blocked signals before the handler was called
+    pub sig_mask: Sigset,
+}
+
+/// The frame pushed onto the user stack for running a signal handler
+#[repr(C)]
+#[derive(Clone)]
+pub struct SignalFrame {
+    /// Return address of the handler, used on x86_64 only
+    pub ret_addr: usize,
+    pub info: SigInfo,
+    pub ucontext: SignalUserContext,
+}
+
+/// Offset of the signal frame from the user stack pointer
+/// when `rt_sigreturn` is called.
+#[cfg(target_arch = "x86_64")]
+pub const SIGNAL_FRAME_SP_OFFSET: usize = 8; // ret_addr was popped by `ret`
+#[cfg(not(target_arch = "x86_64"))]
+pub const SIGNAL_FRAME_SP_OFFSET: usize = 0;
+
+/// Page holding `SIGRETURN_CODE`, mapped into every user address space
+pub const SIGNAL_TRAMPOLINE: usize = USER_STACK_OFFSET - PAGE_SIZE;
+
+/// Machine code of `rt_sigreturn()`
+#[cfg(target_arch = "x86_64")]
+pub const SIGRETURN_CODE: &[u8] = &[
+    0x48, 0xc7, 0xc0, 0x0f, 0x00, 0x00, 0x00, // mov rax, 15
+    0x0f, 0x05, // syscall
+];
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+pub const SIGRETURN_CODE: &[u8] = &[
+    0x93, 0x08, 0xb0, 0x08, // li a7, 139
+    0x73, 0x00, 0x00, 0x00, // ecall
+];
+#[cfg(target_arch = "aarch64")]
+pub const SIGRETURN_CODE: &[u8] = &[
+    0x68, 0x11, 0x80, 0xd2, // mov x8, #139
+    0x01, 0x00, 0x00, 0xd4, // svc #0
+];
+#[cfg(target_arch = "mips")]
+pub const SIGRETURN_CODE: &[u8] = &[
+    0x61, 0x10, 0x02, 0x24, // li v0, 4193
+    0x0c, 0x00, 0x00, 0x00, // syscall
+];
+
+impl Process {
+    /// Signals pending for thread `tid`
+    fn pending_for(&self, tid: Tid) -> Sigset {
+        let mut set = self.sig_pending;
+        if let Some(thread_set) = self.thread_sig_pending.get(&tid) {
+            set.add_set(*thread_set);
+        }
+        set
+    }
+
+    /// Take the first deliverable signal of thread `tid`
+    fn dequeue_signal(&mut self, tid: Tid, mask: Sigset) -> Option<usize> {
+        let mask = mask.without_unblockable();
+        if let Some(thread_set) = self.thread_sig_pending.get_mut(&tid) {
+            if let Some(sig) = thread_set.first_unmasked(mask) {
+                thread_set.remove(sig);
+                return Some(sig);
+            }
+        }
+        let sig = self.sig_pending.first_unmasked(mask)?;
+        self.sig_pending.remove(sig);
+        Some(sig)
+    }
+
+    /// Remove `sig` from the pending signals of the process and of its threads
+    fn discard_signal(&mut self, sig: usize) {
+        self.sig_pending.remove(sig);
+        for set in self.thread_sig_pending.values_mut() {
+            set.remove(sig);
+        }
+    }
+
+    /// Make `sig` pending in this process, or in thread `tid` if specified.
+    /// Return false if the signal is ignored.
+    ///
+    /// `SIGCONT` continues a stopped process even if it is ignored,
+    /// and discards the pending stop signals, which discard a pending `SIGCONT` in turn.
+    fn queue_signal(&mut self, sig: usize, tid: Option<Tid>) -> bool {
+        if sig == SIGCONT {
+            for &stop in [SIGSTOP, SIGTSTP, SIGTTIN, SIGTTOU].iter() {
+                self.discard_signal(stop);
+            }
+            if self.stopped {
+                info!("signal {}: continue process {}", sig, self.pid);
+                self.stopped = false;
+                self.continued.notify_all();
+            }
+        } else if default_action(sig) == DefaultAction::Stop {
+            self.discard_signal(SIGCONT);
+        }
+        if is_ignored(sig, &self.sig_actions[sig]) {
+            return false;
+        }
+        match tid {
+            Some(tid) => self
+                .thread_sig_pending
+                .entry(tid)
+                .or_insert_with(Sigset::empty)
+                .add(sig),
+            None => self.sig_pending.add(sig),
+        }
+        true
+    }
+}
+
+/// Send `sig` to the process, or to one of its threads if `tid` is specified.
+pub fn send_signal(proc: &Arc<Mutex<Process>>, sig: usize, tid: Option<Tid>) {
+    let mut proc = proc.lock();
+    info!(
+        "send signal {} to process {}, thread {:?}",
+        sig, proc.pid, tid
+    );
+    let was_stopped = proc.stopped;
+    let queued = proc.queue_signal(sig, tid);
+    // the parent is told if it is continued, even though the signal is ignored
+    let parent = if was_stopped && !proc.stopped {
+        proc.state_change = Some(WaitStatus::Continued);
+        proc.parent.clone()
+    } else {
+        None
+    };
+    // interrupt blocking syscalls, they will check pending signals
+    let threads = match (queued, tid) {
+        (false, _) => Vec::new(),
+        (true, Some(tid)) => vec![tid],
+        (true, None) => proc.threads.clone(),
+    };
+    drop(proc);
+    if let Some(parent) = parent {
+        notify_parent_state_change(&parent);
+    }
+    for tid in threads {
+        processor().manager().wakeup(tid);
+    }
+}
+
+/// Processes in the process group `pgid`.
+/// The caller must not be holding the lock of any process.
+pub fn process_group(pgid: usize) -> Vec<Arc<Mutex<Process>>> {
+    PROCESSES
+        .read()
+        .values()
+        .filter_map(|weak| weak.upgrade())
+        .filter(|proc| proc.lock().pgid == pgid)
+        .collect()
+}
+
+/// Send `sig` to each process in the process group `pgid`.
+/// Return false if there is none.
+pub fn send_signal_to_group(pgid: usize, sig: usize) -> bool {
+    let group = process_group(pgid);
+    for proc in group.iter() {
+        send_signal(proc, sig, None);
+    }
+    !group.is_empty()
+}
+
+/// Tell the parent of a process that it is stopped or continued
+fn notify_parent_state_change(parent: &Arc<Mutex<Process>>) {
+    let nocldstop = {
+        let parent = parent.lock();
+        parent.child_exit.notify_all();
+        parent.sig_actions[SIGCHLD]
+            .flags
+            .contains(SignalActionFlags::NOCLDSTOP)
+    };
+    if !nocldstop {
+        send_signal(parent, SIGCHLD, None);
+    }
+}
+
+/// Find the process containing thread `tid`
+pub fn process_of_thread(tid: Tid) -> Option<Arc<Mutex<Process>>> {
+    PROCESSES
+        .read()
+        .values()
+        .filter_map(|weak| weak.upgrade())
+        .find(|proc| proc.lock().threads.contains(&tid))
+}
+
+/// Whether the current thread has a signal to handle.
+/// Blocking syscalls should return `EINTR` if so.
+pub fn has_signal_to_handle() -> bool {
+    let thread = current_thread();
+    let proc = thread.proc.lock();
+    let tid = processor().tid();
+    let mut pending = proc.pending_for(tid);
+    pending.remove_set(thread.sig_mask.without_unblockable());
+    (1..=NSIG)
+        .filter(|&sig| pending.contains(sig))
+        .any(|sig| !is_ignored(sig, &proc.sig_actions[sig]))
+}
+
+/// Deliver pending signals of the current thread before returning to user.
+///
+/// Called at the end of trap handlers, `tf` must come from user mode.
+pub fn handle_signal(tf: &mut TrapFrame) {
+    let thread = current_thread();
+    let tid = processor().tid();
+    let mut proc = thread.proc.lock();
+    loop {
+        // every thread of a stopped process waits here, until it is continued or killed
+        while proc.stopped {
+            let continued = proc.continued.clone();
+            proc = continued.wait(proc);
+        }
+        let sig = match proc.dequeue_signal(tid, thread.sig_mask) {
+            Some(sig) => sig,
+            None => {
+                if let Some(mask) = thread.saved_sig_mask.take() {
+                    thread.sig_mask = mask;
+                }
+                return;
+            }
+        };
+        let action = proc.sig_actions[sig];
+        if is_ignored(sig, &action) {
+            continue;
+        }
+        if action.handler == SIG_DFL && default_action(sig) == DefaultAction::Stop {
+            info!("signal {}: stop process {}", sig, proc.pid);
+            proc.stopped = true;
+            proc.state_change = Some(WaitStatus::Stopped(sig));
+            let parent = proc.parent.clone();
+            drop(proc);
+            if let Some(parent) = parent {
+                notify_parent_state_change(&parent);
+            }
+            proc = thread.proc.lock();
+            continue;
+        }
+        if action.handler == SIG_DFL {
+            info!("signal {}: terminate process {}", sig, proc.pid);
+            drop(proc);
+            crate::syscall::exit_process(WaitStatus::Signaled(sig));
+        }
+
+        info!("signal {}: run handler {:#x}", sig, action.handler);
+        if action.flags.contains(SignalActionFlags::RESETHAND) {
+            proc.sig_actions[sig] = SignalAction::default();
+        }
+        let frame_addr = {
+            let sp = tf.get_sp() - size_of::<SignalFrame>();
+            // leave the red zone of the interrupted function alone
+            let sp = (sp - 128) & !0xf;
+            #[cfg(target_arch = "x86_64")]
+            let sp = sp - 8; // (rsp + 8) should be 16-byte aligned on function entry
+            sp
+        };
+        let frame_ptr = frame_addr as *mut SignalFrame;
+        if proc.vm.check_write_ptr(frame_ptr).is_err() {
+            warn!("signal {}: bad user stack {:#x}", sig, tf.get_sp());
+            drop(proc);
+            crate::syscall::exit_process(WaitStatus::Signaled(SIGSEGV));
+        }
+        drop(proc);
+
+        let frame = SignalFrame {
+            ret_addr: if action.flags.contains(SignalActionFlags::RESTORER) {
+                action.restorer
+            } else {
+                SIGNAL_TRAMPOLINE
+            },
+            info: SigInfo {
+                signo: sig as i32,
+                errno: 0,
+                code: SI_USER,
+                #[cfg(target_pointer_width = "64")]
+                _pad: 0,
+                pid: 0,
+                uid: 0,
+                _reserved: [0; SIGINFO_RESERVED],
+            },
+            ucontext: SignalUserContext {
+                flags: 0,
+                link: 0,
+                stack: SignalStack {
+                    sp: 0,
+                    flags: 0,
+                    size: 0,
+                },
+                context: tf.clone(),
+                // restored by `rt_sigreturn`
+                sig_mask: thread.saved_sig_mask.take().unwrap_or(thread.sig_mask),
+            },
+        };
+        let ret_addr = frame.ret_addr;
+        unsafe {
+            frame_ptr.write(frame);
+        }
+
+        let mut mask = thread.sig_mask;
+        mask.add_set(action.mask);
+        if !action.flags.contains(SignalActionFlags::NODEFER) {
+            mask.add(sig);
+        }
+        thread.sig_mask = mask.without_unblockable();
+
+        let info = unsafe { &(*frame_ptr).info } as *const SigInfo as usize;
+        let ucontext = unsafe { &(*frame_ptr).ucontext } as *const SignalUserContext as usize;
+        tf.setup_signal_handler(action.handler, frame_addr, ret_addr, [sig, info, ucontext]);
+        return;
+    }
+}
+
+/// Restore the context saved by `handle_signal`, for `rt_sigreturn`.
+pub fn restore_signal_context(tf: &mut TrapFrame) -> bool {
+    let frame_ptr = (tf.get_sp() - SIGNAL_FRAME_SP_OFFSET) as *const SignalFrame;
+    if current_thread()
+        .proc
+        .lock()
+        .vm
+        .check_read_ptr(frame_ptr)
+        .is_err()
+    {
+        return false;
+    }
+    let ucontext = unsafe { &(*frame_ptr).ucontext };
+    if !tf.restore_signal_context(&ucontext.context) {
+        return false;
+    }
+    current_thread().sig_mask = ucontext.sig_mask.without_unblockable();
+    true
+}
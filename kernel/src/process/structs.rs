@@ -1,9 +1,17 @@
-use alloc::{boxed::Box, collections::BTreeMap, string::String, sync::Arc, sync::Weak, vec::Vec};
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet},
+    string::String,
+    sync::Arc,
+    sync::Weak,
+    vec::Vec,
+};
 use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use core::str;
 use log::*;
-use rcore_memory::PAGE_SIZE;
+use rcore_memory::{Page, VirtAddr, PAGE_SIZE};
 use rcore_thread::Tid;
 use spin::RwLock;
 use xmas_elf::{
@@ -13,12 +21,17 @@ use xmas_elf::{
 };
 
 use crate::arch::interrupt::{Context, TrapFrame};
-use crate::fs::{FileHandle, FileLike, INodeExt, OpenOptions, FOLLOW_MAX_DEPTH};
-use crate::memory::{ByFrame, GlobalFrameAlloc, KernelStack, MemoryAttr, MemorySet};
+use crate::fs::{lookup_at, FileHandle, FileLike, INodeExt, OpenOptions, Tty};
+use crate::memory::{
+    copy_on_write, ByFrame, Delay, GlobalFrameAlloc, KernelStack, MemoryAttr, MemorySet,
+};
 use crate::net::SOCKETS;
+use crate::swap;
 use crate::sync::{Condvar, SpinNoIrqLock as Mutex};
 
 use super::abi::{self, ProcInitInfo};
+use super::cred::Credentials;
+use super::signal::{self, SignalAction, Sigset, NSIG};
 
 // TODO: avoid pub
 pub struct Thread {
@@ -27,6 +40,17 @@ pub struct Thread {
     /// Kernel performs futex wake when thread exits.
     /// Ref: [http://man7.org/linux/man-pages/man2/set_tid_address.2.html]
     pub clear_child_tid: usize,
+    /// The tid is written here when it is added, with `CLONE_CHILD_SETTID`
+    pub set_child_tid: usize,
+    /// Signals blocked by this thread
+    pub sig_mask: Sigset,
+    /// The mask replaced for the wait of a syscall like `epoll_pwait` which was interrupted,
+    /// to be restored once the signal is handled with the replacing one
+    pub saved_sig_mask: Option<Sigset>,
+    /// CPU time of its process, shared with its `ticks`
+    pub ticks: Arc<AtomicUsize>,
+    /// The tick it was last switched to
+    pub switched_at: usize,
     pub proc: Arc<Mutex<Process>>,
 }
 
@@ -74,20 +98,110 @@ pub struct Process {
     // resources
     pub vm: MemorySet,
     pub files: BTreeMap<usize, FileLike>,
+    /// File descriptors closed on exec, i.e. with `FD_CLOEXEC`
+    pub cloexec_fds: BTreeSet<usize>,
     pub cwd: String,
+    /// Permission bits cleared from the mode of created files
+    pub umask: usize,
+    pub exec_path: String,
+    pub args: Vec<String>,
     futexes: BTreeMap<usize, Arc<Condvar>>,
+    pub brk_start: VirtAddr, // start of the heap, after the ELF segments
+    pub brk: VirtAddr,       // the program break, i.e. end of the heap
 
     // relationship
     pub pid: Pid, // i.e. tgid, usually the tid of first thread
     pub parent: Option<Arc<Mutex<Process>>>,
     pub children: Vec<Weak<Mutex<Process>>>,
     pub threads: Vec<Tid>, // threads in the same process
+    /// Process group, i.e. the job it belongs to
+    pub pgid: usize,
+    /// Session, i.e. the pid of its leader
+    pub sid: usize,
+    /// Controlling terminal of the session
+    pub ctty: Option<Arc<Tty>>,
+    pub cred: Credentials,
 
     // for waiting child
-    pub child_exit: Arc<Condvar>, // notified when the a child process is going to terminate
-    pub child_exit_code: BTreeMap<usize, usize>, // child process store its exit code here
+    pub child_exit: Arc<Condvar>, // notified when a child exits, stops or continues
+    /// Children which have exited, until they are waited for
+    pub zombies: BTreeMap<usize, Zombie>,
+    /// A stop or continuation which its parent has not waited for
+    pub state_change: Option<WaitStatus>,
+    /// Ticks its threads have run, counted when they are switched out
+    pub ticks: Arc<AtomicUsize>,
+    /// Ticks of the children it has waited for, including their own children
+    pub children_ticks: usize,
+    /// Sent to its parent when it exits, `SIGCHLD` unless it is changed by `clone`
+    pub exit_signal: usize,
+    /// Notified when it execs or exits, if it is created by `vfork`
+    pub vfork_done: Option<Arc<Condvar>>,
+    /// The parent whose address space it has borrowed by `vfork`, until it execs or exits
+    pub vm_lender: Option<Arc<Mutex<Process>>>,
+
+    // signals
+    pub sig_actions: [SignalAction; NSIG + 1],
+    pub sig_pending: Sigset, // signals sent to the process
+    pub thread_sig_pending: BTreeMap<Tid, Sigset>, // signals sent to a specific thread
+    /// Stopped by a stop signal, until `SIGCONT`
+    pub stopped: bool,
+    pub continued: Arc<Condvar>, // notified when it is continued
+}
+
+/// How a child has exited, or changed its state, which its parent is told by `wait`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WaitStatus {
+    /// Exited with the code
+    Exited(usize),
+    /// Terminated by the signal
+    Signaled(usize),
+    /// Stopped by the signal
+    Stopped(usize),
+    Continued,
 }
 
+/// `si_code` of `SIGCHLD`
+pub const CLD_EXITED: i32 = 1;
+pub const CLD_KILLED: i32 = 2;
+pub const CLD_STOPPED: i32 = 5;
+pub const CLD_CONTINUED: i32 = 6;
+
+impl WaitStatus {
+    /// The status of `wait4`, encoded as Linux
+    pub fn encode(&self) -> i32 {
+        match *self {
+            WaitStatus::Exited(code) => ((code & 0xff) << 8) as i32,
+            WaitStatus::Signaled(sig) => sig as i32,
+            WaitStatus::Stopped(sig) => ((sig << 8) | 0x7f) as i32,
+            WaitStatus::Continued => 0xffff,
+        }
+    }
+
+    /// `si_code` and `si_status` of `waitid`
+    pub fn child_code(&self) -> (i32, i32) {
+        match *self {
+            WaitStatus::Exited(code) => (CLD_EXITED, (code & 0xff) as i32),
+            WaitStatus::Signaled(sig) => (CLD_KILLED, sig as i32),
+            WaitStatus::Stopped(sig) => (CLD_STOPPED, sig as i32),
+            WaitStatus::Continued => (CLD_CONTINUED, signal::SIGCONT as i32),
+        }
+    }
+}
+
+/// What is left of a child which has exited, for its parent to wait for
+#[derive(Debug, Clone)]
+pub struct Zombie {
+    pub pgid: usize,
+    /// Real user id
+    pub uid: usize,
+    pub status: WaitStatus,
+    /// Ticks it has run, including its children it has waited for
+    pub ticks: usize,
+}
+
+/// The umask of the first process
+const DEFAULT_UMASK: usize = 0o022;
+
 /// Records the mapping between pid and Process struct.
 lazy_static! {
     pub static ref PROCESSES: RwLock<BTreeMap<usize, Weak<Mutex<Process>>>> =
@@ -99,6 +213,11 @@ impl rcore_thread::Context for Thread {
     unsafe fn switch_to(&mut self, target: &mut rcore_thread::Context) {
         use core::mem::transmute;
         let (target, _): (&mut Thread, *const ()) = transmute(target);
+        // a thread runs from when it is switched to, until it switches back to the scheduler
+        let now = crate::trap::TICK;
+        self.ticks
+            .fetch_add(now - self.switched_at, Ordering::Relaxed);
+        target.switched_at = now;
         self.context.switch(&mut target.context);
     }
 
@@ -111,8 +230,18 @@ impl rcore_thread::Context for Thread {
             if let Some(parent) = &proc.parent {
                 let mut parent = parent.lock();
                 parent.children.push(Arc::downgrade(&self.proc));
+            } else {
+                // a process started by the kernel leads a new session
+                proc.pgid = tid;
+                proc.sid = tid;
+                if let Some(tty) = &proc.ctty {
+                    tty.set_session(tid, tid, false);
+                }
             }
         }
+        if self.set_child_tid != 0 {
+            write_tid(&mut proc.vm, self.set_child_tid, tid);
+        }
         // add it to threads
         proc.threads.push(tid);
         PROCESSES
@@ -121,6 +250,33 @@ impl rcore_thread::Context for Thread {
     }
 }
 
+/// Write `tid` at `addr` in `vm`, which may not be the active one.
+/// Nothing is written if it is not writable.
+fn write_tid(vm: &mut MemorySet, addr: usize, tid: Tid) {
+    let ptr = addr as *mut u32;
+    if vm.check_write_ptr(ptr).is_err() {
+        return;
+    }
+    // the page may be shared by copy-on-write, or not allocated yet,
+    // while page faults are handled in the active one
+    vm.handle_page_fault(addr);
+    unsafe { vm.with(|| ptr.write(tid as u32)) };
+}
+
+/// Context of a new thread returning to user with `tf`,
+/// whose thread pointer is set to `tls` if it is given
+unsafe fn new_user_context(
+    tf: &TrapFrame,
+    kstack: &KernelStack,
+    token: usize,
+    tls: Option<usize>,
+) -> Context {
+    match tls {
+        Some(tls) => Context::new_clone(tf, tf.get_sp(), kstack.top(), token, tls),
+        None => Context::new_fork(tf, kstack.top(), token),
+    }
+}
+
 impl Thread {
     /// Make a struct for the init thread
     pub unsafe fn new_init() -> Box<Thread> {
@@ -128,6 +284,11 @@ impl Thread {
             context: Context::null(),
             kstack: KernelStack::new(),
             clear_child_tid: 0,
+            set_child_tid: 0,
+            sig_mask: Sigset::empty(),
+            saved_sig_mask: None,
+            ticks: Arc::new(AtomicUsize::new(0)),
+            switched_at: 0,
             // safety: this field will never be used
             proc: core::mem::uninitialized(),
         })
@@ -137,22 +298,49 @@ impl Thread {
     pub fn new_kernel(entry: extern "C" fn(usize) -> !, arg: usize) -> Box<Thread> {
         let vm = MemorySet::new();
         let kstack = KernelStack::new();
+        let ticks = Arc::new(AtomicUsize::new(0));
         Box::new(Thread {
             context: unsafe { Context::new_kernel_thread(entry, arg, kstack.top(), vm.token()) },
             kstack,
             clear_child_tid: 0,
+            set_child_tid: 0,
+            sig_mask: Sigset::empty(),
+            saved_sig_mask: None,
+            ticks: ticks.clone(),
+            switched_at: 0,
             // TODO: kernel thread should not have a process
             proc: Arc::new(Mutex::new(Process {
                 vm,
                 files: BTreeMap::default(),
+                cloexec_fds: BTreeSet::new(),
                 cwd: String::from("/"),
+                umask: DEFAULT_UMASK,
+                exec_path: String::new(),
+                args: Vec::new(),
                 futexes: BTreeMap::default(),
+                brk_start: 0,
+                brk: 0,
                 pid: Pid::uninitialized(),
                 parent: None,
                 children: Vec::new(),
                 threads: Vec::new(),
+                pgid: 0,
+                sid: 0,
+                ctty: None,
+                cred: Credentials::default(),
                 child_exit: Arc::new(Condvar::new()),
-                child_exit_code: BTreeMap::new(),
+                zombies: BTreeMap::new(),
+                state_change: None,
+                ticks,
+                children_ticks: 0,
+                exit_signal: signal::SIGCHLD,
+                vfork_done: None,
+                vm_lender: None,
+                sig_actions: [SignalAction::default(); NSIG + 1],
+                sig_pending: Sigset::empty(),
+                thread_sig_pending: BTreeMap::new(),
+                stopped: false,
+                continued: Arc::new(Condvar::new()),
             })),
         })
     }
@@ -190,15 +378,18 @@ impl Thread {
         // Check interpreter (for dynamic link)
         if let Ok(loader_path) = elf.get_interpreter() {
             // assuming absolute path
-            if let Ok(inode) = crate::fs::ROOT_INODE.lookup_follow(loader_path, FOLLOW_MAX_DEPTH) {
+            if let Ok(inode) = lookup_at(&crate::fs::ROOT_INODE, loader_path, true) {
                 if let Ok(buf) = inode.read_as_vec() {
                     // Elf loader should not have INTERP
                     // No infinite loop
+                    let orig_args = args.clone();
                     args.insert(0, loader_path.into());
                     args.insert(1, exec_path.into());
                     args.remove(2);
                     info!("loader args: {:?}", args);
-                    return Thread::new_user(buf.as_slice(), exec_path, args, envs);
+                    let thread = Thread::new_user(buf.as_slice(), exec_path, args, envs);
+                    thread.proc.lock().args = orig_args;
+                    return thread;
                 } else {
                     warn!("loader specified as {} but failed to read", &loader_path);
                 }
@@ -210,6 +401,17 @@ impl Thread {
         // Make page table
         let mut vm = elf.make_memory_set();
 
+        // Heap, empty until the program break is moved by brk
+        let brk_start = vm.iter().map(|area| area.end_addr()).max().unwrap_or(0);
+        let brk_start = Page::of_addr(brk_start + PAGE_SIZE - 1).start_address();
+        vm.push(
+            brk_start,
+            brk_start,
+            MemoryAttr::default().user(),
+            copy_on_write(Delay::new(GlobalFrameAlloc)),
+            "heap",
+        );
+
         // User stack
         use crate::consts::{USER_STACK_OFFSET, USER_STACK_SIZE};
         let mut ustack_top = {
@@ -219,15 +421,39 @@ impl Thread {
                 ustack_buttom,
                 ustack_top,
                 MemoryAttr::default().user(),
-                ByFrame::new(GlobalFrameAlloc),
+                copy_on_write(ByFrame::new(GlobalFrameAlloc)),
                 "user_stack",
             );
             ustack_top
         };
 
+        // Signal trampoline, readonly after the code is copied
+        vm.push(
+            signal::SIGNAL_TRAMPOLINE,
+            signal::SIGNAL_TRAMPOLINE + PAGE_SIZE,
+            MemoryAttr::default().user().execute(),
+            copy_on_write(ByFrame::new(GlobalFrameAlloc)),
+            "signal_trampoline",
+        );
+        unsafe {
+            vm.with(|| {
+                let code = signal::SIGRETURN_CODE;
+                let target = ::core::slice::from_raw_parts_mut(
+                    signal::SIGNAL_TRAMPOLINE as *mut u8,
+                    code.len(),
+                );
+                target.copy_from_slice(code);
+            });
+        }
+        vm.protect(
+            signal::SIGNAL_TRAMPOLINE,
+            signal::SIGNAL_TRAMPOLINE + PAGE_SIZE,
+            MemoryAttr::default().user().execute().readonly(),
+        );
+
         // Make init info
         let init_info = ProcInitInfo {
-            args,
+            args: args.clone(),
             envs,
             auxv: {
                 let mut map = BTreeMap::new();
@@ -257,7 +483,9 @@ impl Thread {
                     read: true,
                     write: false,
                     append: false,
+                    nonblock: false,
                 },
+                String::from("/dev/console"),
             )),
         );
         files.insert(
@@ -268,7 +496,9 @@ impl Thread {
                     read: false,
                     write: true,
                     append: false,
+                    nonblock: false,
                 },
+                String::from("/dev/console"),
             )),
         );
         files.insert(
@@ -279,11 +509,14 @@ impl Thread {
                     read: false,
                     write: true,
                     append: false,
+                    nonblock: false,
                 },
+                String::from("/dev/console"),
             )),
         );
 
         let entry_addr = elf.header.pt2.entry_point() as usize;
+        let ticks = Arc::new(AtomicUsize::new(0));
 
         Box::new(Thread {
             context: unsafe {
@@ -291,75 +524,153 @@ impl Thread {
             },
             kstack,
             clear_child_tid: 0,
+            set_child_tid: 0,
+            sig_mask: Sigset::empty(),
+            saved_sig_mask: None,
+            ticks: ticks.clone(),
+            switched_at: 0,
             proc: Arc::new(Mutex::new(Process {
                 vm,
                 files,
+                cloexec_fds: BTreeSet::new(),
                 cwd: String::from("/"),
+                umask: DEFAULT_UMASK,
+                exec_path: String::from(exec_path),
+                args,
                 futexes: BTreeMap::default(),
+                brk_start,
+                brk: brk_start,
                 pid: Pid::uninitialized(),
                 parent: None,
                 children: Vec::new(),
                 threads: Vec::new(),
+                pgid: 0,
+                sid: 0,
+                ctty: None,
+                cred: Credentials::default(),
                 child_exit: Arc::new(Condvar::new()),
-                child_exit_code: BTreeMap::new(),
+                zombies: BTreeMap::new(),
+                state_change: None,
+                ticks,
+                children_ticks: 0,
+                exit_signal: signal::SIGCHLD,
+                vfork_done: None,
+                vm_lender: None,
+                sig_actions: [SignalAction::default(); NSIG + 1],
+                sig_pending: Sigset::empty(),
+                thread_sig_pending: BTreeMap::new(),
+                stopped: false,
+                continued: Arc::new(Condvar::new()),
             })),
         })
     }
 
-    /// Fork a new process from current one
-    pub fn fork(&self, tf: &TrapFrame) -> Box<Thread> {
-        // Clone memory set, make a new page table
-        let proc = self.proc.lock();
-        let vm = proc.vm.clone();
+    /// Fork a new process from current one, which returns to user with `tf`.
+    /// Its thread pointer is set to `tls` if it is given.
+    pub fn fork(&self, tf: &TrapFrame, tls: Option<usize>) -> Box<Thread> {
+        self.new_child(tf, tls, false)
+    }
+
+    /// Like `fork`, but the new process borrows the address space of the current one,
+    /// which is left with an empty one until it is given back when the new process
+    /// execs or exits. No other thread may run in the current process meanwhile.
+    pub fn vfork(&self, tf: &TrapFrame, tls: Option<usize>) -> Box<Thread> {
+        self.new_child(tf, tls, true)
+    }
+
+    fn new_child(&self, tf: &TrapFrame, tls: Option<usize>, lend_vm: bool) -> Box<Thread> {
+        let mut proc = self.proc.lock();
+        let vm = if lend_vm {
+            // the pages are found by the token of the page table when they are swapped,
+            // so they can be moved with it
+            core::mem::replace(&mut proc.vm, MemorySet::new())
+        } else {
+            // Fork memory set, make a new page table sharing pages by copy-on-write
+            // swapped pages can not be shared
+            swap::swap_in_all(&mut proc.vm);
+            proc.vm.fork()
+        };
         let files = proc.files.clone();
+        let cloexec_fds = proc.cloexec_fds.clone();
         let cwd = proc.cwd.clone();
+        let umask = proc.umask;
+        let (pgid, sid, ctty) = (proc.pgid, proc.sid, proc.ctty.clone());
+        let cred = proc.cred.clone();
+        let exec_path = proc.exec_path.clone();
+        let args = proc.args.clone();
+        let sig_actions = proc.sig_actions;
+        let (brk_start, brk) = (proc.brk_start, proc.brk);
         drop(proc);
         let parent = Some(self.proc.clone());
-        debug!("fork: finish clone MemorySet");
-
-        // MMU:   copy data to the new space
-        // NoMMU: coping data has been done in `vm.clone()`
-        for area in vm.iter() {
-            let data = Vec::<u8>::from(unsafe { area.as_slice() });
-            unsafe { vm.with(|| area.as_slice_mut().copy_from_slice(data.as_slice())) }
-        }
+        let vm_lender = if lend_vm {
+            Some(self.proc.clone())
+        } else {
+            None
+        };
+        debug!("fork: finish fork MemorySet");
 
-        debug!("fork: temporary copy data!");
         let kstack = KernelStack::new();
+        let ticks = Arc::new(AtomicUsize::new(0));
 
         Box::new(Thread {
-            context: unsafe { Context::new_fork(tf, kstack.top(), vm.token()) },
+            context: unsafe { new_user_context(tf, &kstack, vm.token(), tls) },
             kstack,
             clear_child_tid: 0,
+            set_child_tid: 0,
+            sig_mask: self.sig_mask,
+            saved_sig_mask: None,
+            ticks: ticks.clone(),
+            switched_at: 0,
             proc: Arc::new(Mutex::new(Process {
                 vm,
                 files,
+                cloexec_fds,
                 cwd,
+                umask,
+                exec_path,
+                args,
                 futexes: BTreeMap::default(),
+                brk_start,
+                brk,
                 pid: Pid::uninitialized(),
                 parent,
                 children: Vec::new(),
                 threads: Vec::new(),
+                pgid,
+                sid,
+                ctty,
+                cred,
                 child_exit: Arc::new(Condvar::new()),
-                child_exit_code: BTreeMap::new(),
+                zombies: BTreeMap::new(),
+                state_change: None,
+                ticks,
+                children_ticks: 0,
+                exit_signal: signal::SIGCHLD,
+                vfork_done: None,
+                vm_lender,
+                sig_actions,
+                sig_pending: Sigset::empty(),
+                thread_sig_pending: BTreeMap::new(),
+                stopped: false,
+                continued: Arc::new(Condvar::new()),
             })),
         })
     }
 
-    /// Create a new thread in the same process.
-    pub fn clone(
-        &self,
-        tf: &TrapFrame,
-        stack_top: usize,
-        tls: usize,
-        clear_child_tid: usize,
-    ) -> Box<Thread> {
+    /// Create a new thread in the same process, which returns to user with `tf`.
+    /// Its thread pointer is set to `tls` if it is given.
+    pub fn clone(&self, tf: &TrapFrame, tls: Option<usize>, clear_child_tid: usize) -> Box<Thread> {
         let kstack = KernelStack::new();
         let token = self.proc.lock().vm.token();
         Box::new(Thread {
-            context: unsafe { Context::new_clone(tf, stack_top, kstack.top(), token, tls) },
+            context: unsafe { new_user_context(tf, &kstack, token, tls) },
             kstack,
             clear_child_tid,
+            set_child_tid: 0,
+            sig_mask: self.sig_mask,
+            saved_sig_mask: None,
+            ticks: self.ticks.clone(),
+            switched_at: 0,
             proc: self.proc.clone(),
         })
     }
@@ -367,7 +678,35 @@ impl Thread {
 
 impl Process {
     pub fn get_free_fd(&self) -> usize {
-        (0..).find(|i| !self.files.contains_key(i)).unwrap()
+        self.get_free_fd_from(0)
+    }
+    /// The lowest free file descriptor which is not less than `start`
+    pub fn get_free_fd_from(&self, start: usize) -> usize {
+        (start..).find(|i| !self.files.contains_key(i)).unwrap()
+    }
+    /// Set whether `fd` is closed on exec
+    pub fn set_cloexec(&mut self, fd: usize, cloexec: bool) {
+        if cloexec {
+            self.cloexec_fds.insert(fd);
+        } else {
+            self.cloexec_fds.remove(&fd);
+        }
+    }
+    /// Close `fd`, returning the file
+    pub fn close_file(&mut self, fd: usize) -> Option<FileLike> {
+        self.cloexec_fds.remove(&fd);
+        let file_like = self.files.remove(&fd)?;
+        self.remove_epoll_interest(fd);
+        Some(file_like)
+    }
+    /// Remove the closed `fd` from the interest lists of the epoll instances,
+    /// which would report the file it is reused for otherwise
+    fn remove_epoll_interest(&self, fd: usize) {
+        for file_like in self.files.values() {
+            if let FileLike::Epoll(epoll) = file_like {
+                epoll.remove(fd).ok();
+            }
+        }
     }
     pub fn get_futex(&mut self, uaddr: usize) -> Arc<Condvar> {
         if !self.futexes.contains_key(&uaddr) {
@@ -377,10 +716,40 @@ impl Process {
     }
     pub fn clone_for_exec(&mut self, other: &Self) {
         self.files = other.files.clone();
+        for fd in other.cloexec_fds.iter() {
+            self.files.remove(fd);
+            self.remove_epoll_interest(*fd);
+        }
         self.cwd = other.cwd.clone();
+        self.umask = other.umask;
         self.pid = other.pid.clone();
         self.parent = other.parent.clone();
+        self.children = other.children.clone();
         self.threads = other.threads.clone();
+        self.pgid = other.pgid;
+        self.sid = other.sid;
+        self.ctty = other.ctty.clone();
+        self.cred = other.cred.clone();
+        self.child_exit = other.child_exit.clone();
+        self.zombies = other.zombies.clone();
+        self.ticks = other.ticks.clone();
+        self.children_ticks = other.children_ticks;
+        self.exit_signal = other.exit_signal;
+        // ignored signals stay ignored, the others are reset to default
+        for (action, old) in self.sig_actions.iter_mut().zip(other.sig_actions.iter()) {
+            if old.handler == signal::SIG_IGN {
+                *action = *old;
+            }
+        }
+        self.sig_pending = other.sig_pending;
+        self.thread_sig_pending = other.thread_sig_pending.clone();
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        // the page table is going to be freed
+        swap::forget(&self.vm, 0, usize::max_value());
     }
 }
 
@@ -391,7 +760,9 @@ trait ToMemoryAttr {
 impl ToMemoryAttr for Flags {
     fn to_attr(&self) -> MemoryAttr {
         let mut flags = MemoryAttr::default().user();
-        // FIXME: handle readonly
+        if !self.is_write() {
+            flags = flags.readonly();
+        }
         if self.is_execute() {
             flags = flags.execute();
         }
@@ -427,13 +798,14 @@ impl ElfExt for ElfFile<'_> {
                 _ => unreachable!(),
             };
 
-            // Get target slice
+            // Get target slice, writable until the data is copied
+            let attr = ph.flags().to_attr();
             let target = {
                 ms.push(
                     virt_addr,
                     virt_addr + mem_size,
-                    ph.flags().to_attr(),
-                    ByFrame::new(GlobalFrameAlloc),
+                    attr.writable(),
+                    copy_on_write(ByFrame::new(GlobalFrameAlloc)),
                     "elf",
                 );
                 unsafe { ::core::slice::from_raw_parts_mut(virt_addr as *mut u8, mem_size) }
@@ -447,6 +819,7 @@ impl ElfExt for ElfFile<'_> {
                     target[data.len()..].iter_mut().for_each(|x| *x = 0);
                 });
             }
+            ms.protect(virt_addr, virt_addr + mem_size, attr);
         }
         ms
     }
@@ -1,7 +1,8 @@
 //! Kernel shell
 
 use crate::drivers::CMDLINE;
-use crate::fs::{INodeExt, ROOT_INODE};
+use crate::fs::tty::{ECHO, ICANON};
+use crate::fs::{INodeExt, CONSOLE, ROOT_INODE};
 use crate::process::*;
 use alloc::string::String;
 use alloc::vec::Vec;
@@ -10,12 +11,15 @@ use alloc::vec::Vec;
 pub fn run_user_shell() {
     if let Ok(inode) = ROOT_INODE.lookup("busybox") {
         let data = inode.read_as_vec().unwrap();
-        processor().manager().add(Thread::new_user(
+        let thread = Thread::new_user(
             data.as_slice(),
             "busybox",
             vec!["busybox".into(), "sh".into()],
             Vec::new(),
-        ));
+        );
+        // the shell controls the console, to run jobs in the foreground
+        thread.proc.lock().ctty = Some(CONSOLE.clone());
+        processor().manager().add(thread);
     } else {
         processor().manager().add(Thread::new_kernel(shell, 0));
     }
@@ -26,14 +30,21 @@ pub fn run_user_shell() {
     let cmdline = CMDLINE.read();
     let inode = ROOT_INODE.lookup(&cmdline).unwrap();
     let data = inode.read_as_vec().unwrap();
-    processor().manager().add(Thread::new_user(
+    let thread = Thread::new_user(
         data.as_slice(),
         cmdline.split(' ').map(|s| s.into()).collect(),
         Vec::new(),
-    ));
+    );
+    thread.proc.lock().ctty = Some(CONSOLE.clone());
+    processor().manager().add(thread);
 }
 
 pub extern "C" fn shell(_arg: usize) -> ! {
+    // the shell edits the lines and echoes them by itself
+    let mut termios = CONSOLE.termios();
+    termios.lflag &= !(ICANON | ECHO);
+    CONSOLE.set_termios(&termios, false);
+
     let files = ROOT_INODE.list().unwrap();
     println!("Available programs: {:?}", files);
     let mut history = Vec::new();
@@ -198,7 +209,7 @@ fn get_line(history: &mut Vec<Vec<u8>>) -> String {
 }
 
 fn get_char() -> u8 {
-    crate::fs::STDIN.pop() as u8
+    crate::fs::STDIN.pop()
 }
 
 fn put_char(ch: u8) {
@@ -0,0 +1,371 @@
+//! Swap user pages out to a block device or a swap file
+//!
+//! Swapping is enabled by `swapon` on a swap file or a block device.
+//! Pages allocated on page faults of user memory are made swappable.
+//! When no frame is left, `GlobalFrameAlloc` evicts one of them by the swap manager,
+//! and it is swapped in again on the next page fault.
+
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+
+use bitmap_allocator::{BitAlloc, BitAlloc64K};
+use rcore_fs::dev::BlockDevice;
+use rcore_fs::vfs::INode;
+use rcore_memory::memory_set::handler::MemoryHandler;
+use rcore_memory::memory_set::MemoryAttr;
+use rcore_memory::paging::Entry;
+use rcore_memory::paging::PageTable;
+use rcore_memory::swap::enhanced_clock::EnhancedClockSwapManager;
+use rcore_memory::swap::{SwapError, SwapExt, Swapper};
+use rcore_memory::{Frame, Page, PhysAddr, VirtAddr, PAGE_SIZE};
+
+use crate::drivers::BlockDriver;
+use crate::fs::inode_addr;
+use crate::memory::{active_table, alloc_frame, ActivePageTable, InactivePageTable0, MemorySet};
+use crate::process::PROCESSES;
+use crate::sync::SpinNoIrqLock;
+
+const BLOCK_SIZE: usize = 1 << <BlockDriver as BlockDevice>::BLOCK_SIZE_LOG2;
+
+/// Where the swapped pages are stored
+pub enum SwapSpace {
+    /// Blocks of a device, from `start`
+    Partition {
+        device: Arc<BlockDriver>,
+        start: usize,
+    },
+    /// A regular file
+    File(Arc<INode>),
+}
+
+impl SwapSpace {
+    fn read_page(&self, slot: usize, buf: &mut [u8]) -> bool {
+        match self {
+            SwapSpace::Partition { device, start } => {
+                let block = start + slot * (PAGE_SIZE / BLOCK_SIZE);
+                buf.chunks_mut(BLOCK_SIZE)
+                    .enumerate()
+                    .all(|(i, chunk)| device.read_at(block + i, chunk))
+            }
+            SwapSpace::File(inode) => inode.read_at(slot * PAGE_SIZE, buf).ok() == Some(PAGE_SIZE),
+        }
+    }
+
+    fn write_page(&self, slot: usize, buf: &[u8]) -> bool {
+        match self {
+            SwapSpace::Partition { device, start } => {
+                let block = start + slot * (PAGE_SIZE / BLOCK_SIZE);
+                buf.chunks(BLOCK_SIZE)
+                    .enumerate()
+                    .all(|(i, chunk)| device.write_at(block + i, chunk))
+            }
+            SwapSpace::File(inode) => inode.write_at(slot * PAGE_SIZE, buf).ok() == Some(PAGE_SIZE),
+        }
+    }
+
+    fn is_same(&self, other: &SwapSpace) -> bool {
+        match (self, other) {
+            (SwapSpace::File(a), SwapSpace::File(b)) => inode_addr(a) == inode_addr(b),
+            (SwapSpace::Partition { device: a, .. }, SwapSpace::Partition { device: b, .. }) => {
+                Arc::ptr_eq(a, b)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Store swapped pages in page-sized slots of a `SwapSpace`
+pub struct BlockSwapper {
+    space: SwapSpace,
+    slots: Box<BitAlloc64K>,
+    total: usize,
+    used: usize,
+}
+
+impl BlockSwapper {
+    pub fn new(space: SwapSpace, pages: usize) -> Self {
+        let total = pages.min(BitAlloc64K::CAP);
+        let mut slots = Box::new(BitAlloc64K::default());
+        slots.insert(0..total);
+        BlockSwapper {
+            space,
+            slots,
+            total,
+            used: 0,
+        }
+    }
+
+    /// Release the slot of a swapped page which is unmapped
+    fn discard(&mut self, token: usize) {
+        self.slots.dealloc(token);
+        self.used -= 1;
+    }
+}
+
+impl Swapper for BlockSwapper {
+    fn swap_out(&mut self, data: &[u8]) -> Result<usize, ()> {
+        let slot = self.slots.alloc().ok_or(())?;
+        if !self.space.write_page(slot, data) {
+            self.slots.dealloc(slot);
+            return Err(());
+        }
+        self.used += 1;
+        Ok(slot)
+    }
+
+    fn swap_update(&mut self, token: usize, data: &[u8]) -> Result<(), ()> {
+        match self.space.write_page(token, data) {
+            true => Ok(()),
+            false => Err(()),
+        }
+    }
+
+    fn swap_in(&mut self, token: usize, data: &mut [u8]) -> Result<(), ()> {
+        if !self.space.read_page(token, data) {
+            return Err(());
+        }
+        self.discard(token);
+        Ok(())
+    }
+}
+
+struct Swap(SwapExt<ActivePageTable, EnhancedClockSwapManager, BlockSwapper>);
+
+// The active page table is only accessed with the lock held.
+unsafe impl Send for Swap {}
+
+lazy_static! {
+    static ref SWAP: SpinNoIrqLock<Option<Swap>> = SpinNoIrqLock::new(None);
+}
+
+/// Start swapping to `space` of `pages` pages.
+/// Fail if swap is already enabled.
+pub fn swapon(space: SwapSpace, pages: usize) -> Result<(), ()> {
+    let mut swap = SWAP.lock();
+    if swap.is_some() {
+        return Err(());
+    }
+    let swapper = BlockSwapper::new(space, pages);
+    *swap = Some(Swap(SwapExt::new(
+        active_table(),
+        EnhancedClockSwapManager::default(),
+        swapper,
+    )));
+    Ok(())
+}
+
+/// Stop swapping to `space`.
+/// Fail if it is not in use, or some pages are still swapped out to it.
+pub fn swapoff(space: &SwapSpace) -> Result<(), ()> {
+    let mut swap = SWAP.lock();
+    let swapper = match swap.as_mut() {
+        Some(swap) => swap.0.swapper(),
+        None => return Err(()),
+    };
+    if !swapper.space.is_same(space) || swapper.used != 0 {
+        return Err(());
+    }
+    *swap = None;
+    Ok(())
+}
+
+/// Whether `device` is used as the swap partition
+pub fn uses_device(device: &Arc<BlockDriver>) -> bool {
+    match SWAP.lock().as_mut() {
+        Some(Swap(ext)) => match &ext.swapper().space {
+            SwapSpace::Partition { device: used, .. } => Arc::ptr_eq(used, device),
+            SwapSpace::File(_) => false,
+        },
+        None => false,
+    }
+}
+
+/// Total and free swap space in bytes
+pub fn swap_info() -> (usize, usize) {
+    match SWAP.lock().as_mut() {
+        Some(Swap(ext)) => {
+            let swapper = ext.swapper();
+            (
+                swapper.total * PAGE_SIZE,
+                (swapper.total - swapper.used) * PAGE_SIZE,
+            )
+        }
+        None => (0, 0),
+    }
+}
+
+fn is_swappable(vm: &MemorySet, addr: VirtAddr) -> bool {
+    vm.iter().any(|area| area.contains(addr) && area.is_cow())
+}
+
+/// Swap out a page of any process, and return the released frame
+pub fn swap_out_any() -> Option<PhysAddr> {
+    // processes must be dropped after the lock is released,
+    // since dropping a memory set may discard swapped pages.
+    let procs: Vec<_> = PROCESSES
+        .read()
+        .values()
+        .filter_map(|proc| proc.upgrade())
+        .collect();
+    let mut swap = SWAP.lock();
+    let ext = &mut swap.as_mut()?.0;
+    // the page table may have been dropped, so find it by the token.
+    // pages of locked processes are skipped, they may be in use.
+    let access = |victim: &Frame| {
+        let proc = procs
+            .iter()
+            .filter_map(|proc| proc.try_lock())
+            .find(|proc| proc.vm.token() == victim.get_token())?;
+        let mut bits = None;
+        unsafe {
+            proc.vm.with(|| {
+                if let Some(entry) = active_table().get_entry(victim.get_virtaddr()) {
+                    bits = Some((entry.accessed(), entry.dirty()));
+                    entry.clear_accessed();
+                    entry.update();
+                }
+            });
+        }
+        bits
+    };
+    while let Some(victim) = ext.select_victim(&access) {
+        let addr = victim.get_virtaddr();
+        for proc in procs.iter() {
+            let mut proc = match proc.try_lock() {
+                Some(proc) => proc,
+                None => continue,
+            };
+            if proc.vm.token() != victim.get_token() {
+                continue;
+            }
+            if !is_swappable(&proc.vm, addr) {
+                break;
+            }
+            let pt = proc.vm.get_page_table_mut() as *mut InactivePageTable0;
+            let frame = Frame::new(pt as usize, addr, victim.get_token());
+            match ext.swap_out::<InactivePageTable0>(&frame) {
+                Ok(target) => return Some(target),
+                Err(SwapError::IOError) => {
+                    // swap space is full
+                    unsafe { ext.set_swappable(pt, addr) };
+                    return None;
+                }
+                Err(_) => break,
+            }
+        }
+    }
+    None
+}
+
+/// Swap in the page at `addr` of the current memory set, if it has been swapped out.
+/// Return true if the page is swapped in.
+pub fn swap_in(vm: &mut MemorySet, addr: VirtAddr) -> bool {
+    if SWAP.lock().is_none() || !is_swappable(vm, addr) {
+        return false;
+    }
+    let addr = Page::of_addr(addr).start_address();
+    let mut swapped = false;
+    vm.edit(|pt| {
+        if let Some(entry) = pt.get_entry(addr) {
+            swapped = !entry.present() && entry.swapped();
+        }
+    });
+    if !swapped {
+        return false;
+    }
+    let target = match alloc_frame() {
+        Some(target) => target,
+        None => return false,
+    };
+    let pt = vm.get_page_table_mut() as *mut InactivePageTable0;
+    let mut swap = SWAP.lock();
+    let ext = &mut swap
+        .as_mut()
+        .expect("swap is disabled with pages swapped")
+        .0;
+    ext.swap_in(pt, addr, target).is_ok()
+}
+
+/// Swap in all swapped pages of the memory set, e.g. before it is forked
+pub fn swap_in_all(vm: &mut MemorySet) {
+    if SWAP.lock().is_none() {
+        return;
+    }
+    let pages: Vec<_> = vm
+        .iter()
+        .filter(|area| area.is_cow())
+        .flat_map(|area| Page::range_of(area.start_addr(), area.end_addr()))
+        .collect();
+    for page in pages {
+        swap_in(vm, page.start_address());
+    }
+}
+
+/// Make the page at `addr` of the current memory set swappable
+pub fn set_swappable(vm: &mut MemorySet, addr: VirtAddr) {
+    let mut swap = SWAP.lock();
+    let ext = match swap.as_mut() {
+        Some(swap) => &mut swap.0,
+        None => return,
+    };
+    if !is_swappable(vm, addr) {
+        return;
+    }
+    let pt = vm.get_page_table_mut() as *mut InactivePageTable0;
+    unsafe { ext.set_swappable(pt, Page::of_addr(addr).start_address()) };
+}
+
+/// Forget the swappable pages in `[start, end)` of the memory set, before they are unmapped
+pub fn forget(vm: &MemorySet, start: VirtAddr, end: VirtAddr) {
+    if let Some(swap) = SWAP.lock().as_mut() {
+        swap.0.remove_range(vm.token(), start, end);
+    }
+}
+
+/// Handler for swappable pages allocated by the inner handler
+///
+/// It releases the swap space of swapped pages on unmap.
+/// Swapping in is done in `crate::memory::handle_page_fault`.
+#[derive(Debug, Clone)]
+pub struct Swappable<H: MemoryHandler + Clone> {
+    inner: H,
+}
+
+impl<H: MemoryHandler + Clone> MemoryHandler for Swappable<H> {
+    fn box_clone(&self) -> Box<MemoryHandler> {
+        Box::new(self.clone())
+    }
+
+    fn map(&self, pt: &mut PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+        self.inner.map(pt, addr, attr);
+    }
+
+    fn map_eager(&self, pt: &mut PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+        self.inner.map_eager(pt, addr, attr);
+    }
+
+    fn unmap(&self, pt: &mut PageTable, addr: VirtAddr) {
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        if entry.present() || !entry.swapped() {
+            self.inner.unmap(pt, addr);
+            return;
+        }
+        let token = entry.target() / PAGE_SIZE;
+        if let Some(swap) = SWAP.lock().as_mut() {
+            swap.0.swapper().discard(token);
+        }
+        entry.set_swapped(false);
+        // PageTable::unmap requires page to be present
+        entry.set_present(true);
+        pt.unmap(addr);
+    }
+
+    fn handle_page_fault(&self, pt: &mut PageTable, addr: VirtAddr) -> bool {
+        self.inner.handle_page_fault(pt, addr)
+    }
+}
+
+impl<H: MemoryHandler + Clone> Swappable<H> {
+    pub fn new(inner: H) -> Self {
+        Swappable { inner }
+    }
+}
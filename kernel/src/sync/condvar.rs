@@ -4,6 +4,12 @@ use alloc::collections::VecDeque;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 
+lazy_static! {
+    /// Threads waiting with a timeout, and the tick they time out at
+    static ref TIMEOUTS: SpinNoIrqLock<Vec<(usize, Arc<thread::Thread>)>> =
+        SpinNoIrqLock::new(Vec::new());
+}
+
 #[derive(Default)]
 pub struct Condvar {
     wait_queue: SpinNoIrqLock<VecDeque<Arc<thread::Thread>>>,
@@ -25,21 +31,6 @@ impl Condvar {
         });
     }
 
-    pub fn wait_any(condvars: &[&Condvar]) {
-        let token = Arc::new(thread::current());
-        // Avoid racing in the same way as the function above
-        let mut locks = Vec::new();
-        locks.reserve(condvars.len());
-        for condvar in condvars {
-            let mut lock = condvar.wait_queue.lock();
-            lock.push_back(token.clone());
-            locks.push(lock);
-        }
-        thread::park_action(move || {
-            drop(locks);
-        });
-    }
-
     pub fn add_to_wait_queue(&self) -> MutexGuard<VecDeque<Arc<thread::Thread>>, SpinNoIrq> {
         let mut lock = self.wait_queue.lock();
         lock.push_back(Arc::new(thread::current()));
@@ -84,3 +75,78 @@ impl Condvar {
         self.wait_queue.lock().clear();
     }
 }
+
+/// The current thread waiting for any of some condvars to be notified.
+///
+/// The condvars are joined before checking the condition waited for,
+/// so that a notification in between is not lost. They are left when dropped.
+pub struct WaitAny {
+    token: Arc<thread::Thread>,
+    condvars: Vec<Arc<Condvar>>,
+}
+
+impl WaitAny {
+    pub fn new() -> Self {
+        WaitAny {
+            token: Arc::new(thread::current()),
+            condvars: Vec::new(),
+        }
+    }
+
+    /// Join the wait queue of `condvar`, unless it is already joined
+    pub fn add(&mut self, condvar: Arc<Condvar>) {
+        if self.condvars.iter().any(|c| Arc::ptr_eq(c, &condvar)) {
+            return;
+        }
+        condvar.wait_queue.lock().push_back(self.token.clone());
+        self.condvars.push(condvar);
+    }
+
+    pub fn extend(&mut self, condvars: impl IntoIterator<Item = Arc<Condvar>>) {
+        for condvar in condvars {
+            self.add(condvar);
+        }
+    }
+
+    /// Park until a condvar joined is notified, or after `ticks` timer ticks unless it is 0.
+    /// Spurious wakeups are possible.
+    pub fn wait(self, ticks: usize) {
+        if ticks != 0 {
+            let tick = unsafe { crate::trap::TICK } + ticks;
+            TIMEOUTS.lock().push((tick, self.token.clone()));
+        }
+        thread::park_action(|| {
+            // the thread was not parked yet when notified, so wake it up again
+            if self.notified(ticks != 0) {
+                self.token.unpark();
+            }
+        });
+    }
+
+    /// Whether the token was taken out of a wait queue, or out of the timeouts if `timeout`
+    fn notified(&self, timeout: bool) -> bool {
+        let token = &self.token;
+        self.condvars.iter().any(|condvar| {
+            let queue = condvar.wait_queue.lock();
+            !queue.iter().any(|t| Arc::ptr_eq(t, token))
+        }) || (timeout && !TIMEOUTS.lock().iter().any(|(_, t)| Arc::ptr_eq(t, token)))
+    }
+}
+
+impl Drop for WaitAny {
+    fn drop(&mut self) {
+        let token = &self.token;
+        for condvar in self.condvars.iter() {
+            condvar.wait_queue.lock().retain(|t| !Arc::ptr_eq(t, token));
+        }
+        TIMEOUTS.lock().retain(|(_, t)| !Arc::ptr_eq(t, token));
+    }
+}
+
+/// Wake up the threads whose timeout has expired at `tick`
+pub fn expire_timeouts(tick: usize) {
+    let mut timeouts = TIMEOUTS.lock();
+    while let Some(i) = timeouts.iter().position(|&(t, _)| t <= tick) {
+        timeouts.swap_remove(i).1.unpark();
+    }
+}
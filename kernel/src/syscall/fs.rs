@@ -1,44 +1,54 @@
 //! Syscalls for file system
 
-use core::cell::UnsafeCell;
-use core::cmp::min;
+use core::cmp::{max, min};
 use core::mem::size_of;
+use core::ptr;
 #[cfg(not(target_arch = "mips"))]
 use rcore_fs::vfs::Timespec;
 
-use crate::drivers::SOCKET_ACTIVITY;
+use rcore_fs_sfs::SimpleFileSystem;
+use rcore_memory::PAGE_SIZE;
+
+use crate::consts::{KERNEL_HEAP_SIZE, USEC_PER_TICK};
+use crate::fs::tty::{
+    Termios, WinSize, TCGETS, TCSETS, TCSETSF, TCSETSW, TIOCGPGRP, TIOCGPTN, TIOCGSID, TIOCGWINSZ,
+    TIOCSCTTY, TIOCSPGRP, TIOCSPTLCK, TIOCSWINSZ,
+};
 use crate::fs::*;
 use crate::memory::MemorySet;
-use crate::sync::Condvar;
+use crate::process::cred::{R_OK, S_ISGID, S_ISUID, W_OK, X_OK};
+use crate::process::signal::{
+    has_signal_to_handle, process_group, send_signal, send_signal_to_group, SIGPIPE, SIGTTIN,
+    SIGWINCH, SIG_IGN,
+};
+use crate::swap;
+use crate::sync::{MutexGuard, SpinNoIrq, WaitAny};
 
 use bitvec::prelude::{BitSlice, BitVec, LittleEndian};
 
 use super::*;
 
 pub fn sys_read(fd: usize, base: *mut u8, len: usize) -> SysResult {
-    let mut proc = process();
-    if !proc.pid.is_init() {
+    if !process().pid.is_init() {
         // we trust pid 0 process
         info!("read: fd: {}, base: {:?}, len: {:#x}", fd, base, len);
     }
+    wait_for_file(fd, false, false)?;
+    let mut proc = process();
     proc.vm.check_write_array(base, len)?;
     let slice = unsafe { slice::from_raw_parts_mut(base, len) };
-    let file_like = proc.get_file_like(fd)?;
-    let len = file_like.read(slice)?;
+    let len = proc.with_file_like(fd, |file_like| file_like.read(slice))?;
     Ok(len)
 }
 
 pub fn sys_write(fd: usize, base: *const u8, len: usize) -> SysResult {
-    let mut proc = process();
-    if !proc.pid.is_init() {
+    if !process().pid.is_init() {
         // we trust pid 0 process
         info!("write: fd: {}, base: {:?}, len: {:#x}", fd, base, len);
     }
-    proc.vm.check_read_array(base, len)?;
+    process().vm.check_read_array(base, len)?;
     let slice = unsafe { slice::from_raw_parts(base, len) };
-    let file_like = proc.get_file_like(fd)?;
-    let len = file_like.write(slice)?;
-    Ok(len)
+    write_file(fd, slice)
 }
 
 pub fn sys_pread(fd: usize, base: *mut u8, len: usize, offset: usize) -> SysResult {
@@ -50,7 +60,7 @@ pub fn sys_pread(fd: usize, base: *mut u8, len: usize, offset: usize) -> SysResu
     proc.vm.check_write_array(base, len)?;
 
     let slice = unsafe { slice::from_raw_parts_mut(base, len) };
-    let len = proc.get_file(fd)?.read_at(offset, slice)?;
+    let len = proc.with_file(fd, |file| Ok(file.read_at(offset, slice)?))?;
     Ok(len)
 }
 
@@ -70,22 +80,31 @@ pub fn sys_pwrite(fd: usize, base: *const u8, len: usize, offset: usize) -> SysR
 pub fn sys_ppoll(ufds: *mut PollFd, nfds: usize, timeout: *const TimeSpec) -> SysResult {
     let proc = process();
     let timeout_msecs = if timeout.is_null() {
-        1 << 31 // infinity
+        None
     } else {
         proc.vm.check_read_ptr(timeout)?;
-        unsafe { (*timeout).to_msec() }
+        Some(unsafe { (*timeout).to_msec() } as usize)
     };
     drop(proc);
 
-    sys_poll(ufds, nfds, timeout_msecs as usize)
+    poll(ufds, nfds, timeout_msecs)
 }
 
 pub fn sys_poll(ufds: *mut PollFd, nfds: usize, timeout_msecs: usize) -> SysResult {
+    // a negative timeout is infinity
+    let timeout_msecs = match timeout_msecs as i32 {
+        msecs if msecs < 0 => None,
+        msecs => Some(msecs as usize),
+    };
+    poll(ufds, nfds, timeout_msecs)
+}
+
+fn poll(ufds: *mut PollFd, nfds: usize, timeout_msecs: Option<usize>) -> SysResult {
     let proc = process();
     if !proc.pid.is_init() {
         // we trust pid 0 process
         info!(
-            "poll: ufds: {:?}, nfds: {}, timeout_msecs: {:#x}",
+            "poll: ufds: {:?}, nfds: {}, timeout_msecs: {:?}",
             ufds, nfds, timeout_msecs
         );
     }
@@ -99,14 +118,16 @@ pub fn sys_poll(ufds: *mut PollFd, nfds: usize, timeout_msecs: usize) -> SysResu
     }
     drop(proc);
 
-    let begin_time_ms = crate::trap::uptime_msec();
+    let deadline = timeout_msecs.map(|msecs| crate::trap::uptime_msec() + msecs);
     loop {
         use PollEvents as PE;
         let proc = process();
         let mut events = 0;
+        let mut waiter = WaitAny::new();
         for poll in polls.iter_mut() {
             poll.revents = PE::empty();
             if let Some(file_like) = proc.files.get(&(poll.fd as usize)) {
+                waiter.extend(file_like.wait_queues());
                 let status = file_like.poll()?;
                 if status.error {
                     poll.revents |= PE::HUP;
@@ -130,13 +151,9 @@ pub fn sys_poll(ufds: *mut PollFd, nfds: usize, timeout_msecs: usize) -> SysResu
         if events > 0 {
             return Ok(events);
         }
-
-        let current_time_ms = crate::trap::uptime_msec();
-        if timeout_msecs < (1 << 31) && current_time_ms - begin_time_ms > timeout_msecs {
+        if !wait_for_readiness(waiter, deadline)? {
             return Ok(0);
         }
-
-        Condvar::wait_any(&[&STDIN.pushed, &(*SOCKET_ACTIVITY)]);
     }
 }
 
@@ -158,21 +175,23 @@ pub fn sys_select(
     let mut err_fds = FdSet::new(&proc.vm, err, nfds)?;
     let timeout_msecs = if timeout as usize != 0 {
         proc.vm.check_read_ptr(timeout)?;
-        unsafe { *timeout }.to_msec()
+        Some(unsafe { *timeout }.to_msec() as usize)
     } else {
         // infinity
-        1 << 31
+        None
     };
     drop(proc);
 
-    let begin_time_ms = crate::trap::uptime_msec();
+    let deadline = timeout_msecs.map(|msecs| crate::trap::uptime_msec() + msecs);
     loop {
         let proc = process();
         let mut events = 0;
-        for (&fd, file_like) in proc.files.iter() {
-            if fd >= nfds {
+        let mut waiter = WaitAny::new();
+        for (&fd, file_like) in proc.files.range(..nfds) {
+            if !read_fds.contains(fd) && !write_fds.contains(fd) && !err_fds.contains(fd) {
                 continue;
             }
+            waiter.extend(file_like.wait_queues());
             let status = file_like.poll()?;
             if status.error && err_fds.contains(fd) {
                 err_fds.set(fd);
@@ -192,19 +211,216 @@ pub fn sys_select(
         if events > 0 {
             return Ok(events);
         }
-
-        if timeout_msecs == 0 {
-            // no timeout, return now;
+        if !wait_for_readiness(waiter, deadline)? {
             return Ok(0);
         }
+    }
+}
 
-        let current_time_ms = crate::trap::uptime_msec();
-        // infinity check
-        if timeout_msecs < (1 << 31) && current_time_ms - begin_time_ms > timeout_msecs as usize {
-            return Ok(0);
+/// Wait until a condvar joined by `waiter` is notified, or until `deadline` in msecs of uptime.
+/// Return false once the deadline has passed.
+pub fn wait_for_readiness(waiter: WaitAny, deadline: Option<usize>) -> Result<bool, SysError> {
+    let ticks = match deadline {
+        Some(deadline) => {
+            let current_time_ms = crate::trap::uptime_msec();
+            if current_time_ms >= deadline {
+                return Ok(false);
+            }
+            // round up, so that it does not wake up early
+            ((deadline - current_time_ms) * 1000 + USEC_PER_TICK - 1) / USEC_PER_TICK
         }
+        // infinity
+        None => 0,
+    };
+    if has_signal_to_handle() {
+        return Err(SysError::EINTR);
+    }
+    waiter.wait(ticks);
+    Ok(true)
+}
+
+/// Wait with the process unlocked until reading from `fd`, or writing to it if `write`,
+/// does not block. Fail with `EAGAIN` instead if it is non-blocking or `dont_wait`,
+/// or once the timeout of a socket has expired as Linux does.
+pub fn wait_for_file(fd: usize, write: bool, dont_wait: bool) -> Result<(), SysError> {
+    let start = crate::trap::uptime_msec();
+    loop {
+        if !write {
+            check_tty_reader(fd)?;
+        }
+        let proc = process();
+        let file_like = proc.files.get(&fd).ok_or(SysError::EBADF)?;
+        // join the queues before checking, so that the notification is not missed
+        let mut waiter = WaitAny::new();
+        waiter.extend(file_like.wait_queues());
+        if !file_like.would_block(write)? {
+            return Ok(());
+        }
+        if dont_wait || file_like.nonblock() {
+            // edge triggered epoll instances report the file again once it becomes ready
+            let events = if write {
+                EpollEvents::OUT
+            } else {
+                EpollEvents::IN
+            };
+            for file in proc.files.values() {
+                if let FileLike::Epoll(epoll) = file {
+                    epoll.rearm(fd, events);
+                }
+            }
+            return Err(SysError::EAGAIN);
+        }
+        let deadline = match file_like.timeout(write) {
+            0 => None,
+            timeout => Some(start + timeout),
+        };
+        drop(proc);
 
-        Condvar::wait_any(&[&STDIN.pushed, &(*SOCKET_ACTIVITY)]);
+        if !wait_for_readiness(waiter, deadline)? {
+            return Err(SysError::EAGAIN);
+        }
+    }
+}
+
+/// Reading the controlling terminal from the background stops the process group by `SIGTTIN`,
+/// or fails with `EIO` if the signal is ignored or blocked.
+fn check_tty_reader(fd: usize) -> Result<(), SysError> {
+    let proc = process();
+    let tty = match proc.files.get(&fd).and_then(FileLike::tty) {
+        Some(tty) => tty,
+        None => return Ok(()),
+    };
+    let is_ctty = proc
+        .ctty
+        .as_ref()
+        .map_or(false, |ctty| Arc::ptr_eq(ctty, &tty));
+    if !is_ctty || tty.foreground() == proc.pgid {
+        return Ok(());
+    }
+    let pgid = proc.pgid;
+    let ignored =
+        proc.sig_actions[SIGTTIN].handler == SIG_IGN || current_thread().sig_mask.contains(SIGTTIN);
+    drop(proc);
+    if ignored {
+        return Err(SysError::EIO);
+    }
+    send_signal_to_group(pgid, SIGTTIN);
+    Err(SysError::EINTR)
+}
+
+pub fn sys_epoll_create(size: usize) -> SysResult {
+    info!("epoll_create: size: {}", size);
+    // the size is only a hint
+    if size as i32 <= 0 {
+        return Err(SysError::EINVAL);
+    }
+    sys_epoll_create1(0)
+}
+
+pub fn sys_epoll_create1(flags: usize) -> SysResult {
+    info!("epoll_create1: flags: {:#x}", flags);
+    if flags & !EPOLL_CLOEXEC != 0 {
+        return Err(SysError::EINVAL);
+    }
+    let mut proc = process();
+    let fd = proc.get_free_fd();
+    proc.files.insert(fd, FileLike::Epoll(EpollInstance::new()));
+    proc.set_cloexec(fd, flags & EPOLL_CLOEXEC != 0);
+    Ok(fd)
+}
+
+pub fn sys_epoll_ctl(epfd: usize, op: usize, fd: usize, event: *const EpollEvent) -> SysResult {
+    info!(
+        "epoll_ctl: epfd: {}, op: {}, fd: {}, event: {:?}",
+        epfd, op, fd, event
+    );
+    let mut proc = process();
+    if !proc.files.contains_key(&fd) {
+        return Err(SysError::EBADF);
+    }
+    let epoll = proc.get_epoll(epfd)?.clone();
+    if fd == epfd {
+        return Err(SysError::EINVAL);
+    }
+    match op {
+        EPOLL_CTL_ADD | EPOLL_CTL_MOD => {
+            proc.vm.check_read_ptr(event)?;
+            let event = unsafe { &*event };
+            if op == EPOLL_CTL_ADD {
+                epoll.add(fd, event)?;
+            } else {
+                epoll.modify(fd, event)?;
+            }
+        }
+        EPOLL_CTL_DEL => epoll.remove(fd)?,
+        _ => return Err(SysError::EINVAL),
+    }
+    Ok(0)
+}
+
+pub fn sys_epoll_wait(
+    epfd: usize,
+    events: *mut EpollEvent,
+    maxevents: usize,
+    timeout_msecs: usize,
+) -> SysResult {
+    sys_epoll_pwait(epfd, events, maxevents, timeout_msecs, ptr::null(), 0)
+}
+
+pub fn sys_epoll_pwait(
+    epfd: usize,
+    events: *mut EpollEvent,
+    maxevents: usize,
+    timeout_msecs: usize,
+    sigmask: *const Sigset,
+    sigsetsize: usize,
+) -> SysResult {
+    info!(
+        "epoll_pwait: epfd: {}, events: {:?}, maxevents: {}, timeout_msecs: {}, sigmask: {:?}",
+        epfd, events, maxevents, timeout_msecs as i32, sigmask
+    );
+    if maxevents as i32 <= 0 {
+        return Err(SysError::EINVAL);
+    }
+    let mut proc = process();
+    proc.vm.check_write_array(events, maxevents)?;
+    let epoll = proc.get_epoll(epfd)?.clone();
+    drop(proc);
+
+    // a negative timeout is infinity
+    let deadline = match timeout_msecs as i32 {
+        msecs if msecs < 0 => None,
+        msecs => Some(crate::trap::uptime_msec() + msecs as usize),
+    };
+    let old_mask = replace_sig_mask(sigmask, sigsetsize)?;
+    let result = epoll_wait(&epoll, events, maxevents, deadline);
+    restore_sig_mask(old_mask, &result);
+    result
+}
+
+/// Wait until some files in the interest list of `epoll` are ready, or `deadline`
+fn epoll_wait(
+    epoll: &EpollInstance,
+    events: *mut EpollEvent,
+    maxevents: usize,
+    deadline: Option<usize>,
+) -> SysResult {
+    loop {
+        let proc = process();
+        let mut waiter = WaitAny::new();
+        waiter.extend(epoll.wait_queues(&proc.files));
+        let ready = epoll.poll(&proc.files, maxevents);
+        drop(proc);
+
+        if !ready.is_empty() {
+            let events = unsafe { slice::from_raw_parts_mut(events, ready.len()) };
+            events.copy_from_slice(&ready);
+            return Ok(ready.len());
+        }
+        // woken up by the files in the interest list, instead of polling them
+        if !wait_for_readiness(waiter, deadline)? {
+            return Ok(0);
+        }
     }
 }
 
@@ -213,35 +429,75 @@ pub fn sys_readv(fd: usize, iov_ptr: *const IoVec, iov_count: usize) -> SysResul
         "readv: fd: {}, iov: {:?}, count: {}",
         fd, iov_ptr, iov_count
     );
+    wait_for_file(fd, false, false)?;
     let mut proc = process();
     let mut iovs = IoVecs::check_and_new(iov_ptr, iov_count, &proc.vm, true)?;
 
     // read all data to a buf
-    let file_like = proc.get_file_like(fd)?;
     let mut buf = iovs.new_buf(true);
-    let len = file_like.read(buf.as_mut_slice())?;
+    let len = proc.with_file_like(fd, |file_like| file_like.read(buf.as_mut_slice()))?;
     // copy data to user
     iovs.write_all_from_slice(&buf[..len]);
     Ok(len)
 }
 
 pub fn sys_writev(fd: usize, iov_ptr: *const IoVec, iov_count: usize) -> SysResult {
-    let mut proc = process();
-    if !proc.pid.is_init() {
+    if !process().pid.is_init() {
         // we trust pid 0 process
         info!(
             "writev: fd: {}, iov: {:?}, count: {}",
             fd, iov_ptr, iov_count
         );
     }
+    let proc = process();
     let iovs = IoVecs::check_and_new(iov_ptr, iov_count, &proc.vm, false)?;
-
     let buf = iovs.read_all_to_vec();
-    let len = buf.len();
+    drop(proc);
+    write_file(fd, &buf)
+}
 
-    let file_like = proc.get_file_like(fd)?;
-    let len = file_like.write(buf.as_slice())?;
-    Ok(len)
+/// Write `buf` to `fd`, waiting with the process unlocked until all of it is written
+/// unless the file is non-blocking. Send `SIGPIPE` to the thread on `EPIPE`.
+fn write_file(fd: usize, buf: &[u8]) -> SysResult {
+    let is_socket = match process().get_file_like(fd)? {
+        FileLike::Socket(..) => true,
+        _ => false,
+    };
+    if is_socket {
+        let result = send_all(fd, buf.len(), false, |socket, written| {
+            socket.write(&buf[written..], None)
+        });
+        if let Err(SysError::EPIPE) = result {
+            let tid = processor().tid();
+            send_signal(&current_thread().proc, SIGPIPE, Some(tid));
+        }
+        return result;
+    }
+    let mut written = 0;
+    loop {
+        let result = wait_for_file(fd, true, false).and_then(|_| {
+            let mut proc = process();
+            let file_like = proc.get_file_like(fd)?;
+            let len = file_like.write(&buf[written..])?;
+            Ok((len, file_like.nonblock()))
+        });
+        match result {
+            Ok((len, nonblock)) => {
+                written += len;
+                if written == buf.len() || len == 0 || nonblock {
+                    return Ok(written);
+                }
+            }
+            Err(err) => {
+                if let SysError::EPIPE = err {
+                    let tid = processor().tid();
+                    send_signal(&current_thread().proc, SIGPIPE, Some(tid));
+                }
+                // report the written part, like an interrupted write
+                return if written > 0 { Ok(written) } else { Err(err) };
+            }
+        }
+    }
 }
 
 pub fn sys_open(path: *const u8, flags: usize, mode: usize) -> SysResult {
@@ -257,37 +513,137 @@ pub fn sys_openat(dir_fd: usize, path: *const u8, flags: usize, mode: usize) ->
         dir_fd as isize, path, flags, mode
     );
 
-    let inode = if flags.contains(OpenFlags::CREATE) {
+    let follow = !flags.contains(OpenFlags::NOFOLLOW);
+    let (inode, created) = if flags.contains(OpenFlags::CREATE) {
         let (dir_path, file_name) = split_path(&path);
         // relative to cwd
         let dir_inode = proc.lookup_inode_at(dir_fd, dir_path, true)?;
-        match dir_inode.find(file_name) {
+        // an existing link is not followed with `EXCLUSIVE`
+        let follow = follow && !flags.contains(OpenFlags::EXCLUSIVE);
+        match lend_process(&proc, || lookup_at(&dir_inode, file_name, follow)) {
             Ok(file_inode) => {
                 if flags.contains(OpenFlags::EXCLUSIVE) {
                     return Err(SysError::EEXIST);
                 }
-                file_inode
+                (file_inode, false)
             }
-            Err(FsError::EntryNotFound) => {
-                dir_inode.create(file_name, FileType::File, mode as u32)?
+            Err(SysError::ENOENT) => {
+                let file_inode = proc.create_at(&dir_inode, file_name, FileType::File, mode)?;
+                (file_inode, true)
             }
-            Err(e) => return Err(SysError::from(e)),
+            Err(e) => return Err(e),
         }
     } else {
-        proc.lookup_inode_at(dir_fd, &path, true)?
+        (proc.lookup_inode_at(dir_fd, &path, follow)?, false)
     };
 
+    let path = proc.absolute_path(dir_fd, &path)?;
+    let options = flags.to_options();
+    // a new file can be written whatever its mode is
+    if !created {
+        let mut access = 0;
+        if options.read {
+            access |= R_OK;
+        }
+        if options.write || flags.contains(OpenFlags::TRUNCATE) {
+            access |= W_OK;
+        }
+        proc.check_access(&inode, access)?;
+    }
+    let inode: Arc<INode> = match inode.metadata()?.type_ {
+        // only with `NOFOLLOW`
+        FileType::SymLink => return Err(SysError::ELOOP),
+        FileType::NamedPipe => {
+            drop(proc);
+            let pipe = open_fifo(&inode, &options)?;
+            proc = process();
+            Arc::new(pipe)
+        }
+        FileType::CharDevice | FileType::BlockDevice => {
+            open_device(&mut proc, inode, flags.contains(OpenFlags::NOCTTY))?
+        }
+        _ => inode,
+    };
     let fd = proc.get_free_fd();
 
-    let file = FileHandle::new(inode, flags.to_options());
+    let file = FileHandle::new(inode, options, path);
     proc.files.insert(fd, FileLike::File(file));
+    proc.set_cloexec(fd, flags.contains(OpenFlags::CLOEXEC));
     Ok(fd)
 }
 
+/// Open the device `inode`, which is a node of devfs or devpts,
+/// or one made by `mknod` whose device number is looked up by `device_of`.
+///
+/// Each open of `/dev/ptmx` creates a pty. Unless `noctty`, a session leader
+/// without a controlling terminal takes the slave of a pty it opens.
+fn open_device(
+    proc: &mut Process,
+    inode: Arc<INode>,
+    noctty: bool,
+) -> Result<Arc<INode>, SysError> {
+    let is_device = {
+        let node = inner_inode(&inode).as_any_ref();
+        node.is::<DevINode>() || node.is::<DevPtsINode>()
+    };
+    let inode = if is_device {
+        inode
+    } else {
+        device_of(&inode).ok_or(SysError::ENXIO)?
+    };
+    let node = inner_inode(&inode).as_any_ref();
+    if let Some(dev) = node.downcast_ref::<DevINode>() {
+        if !dev.is_ptmx() {
+            return Ok(inode);
+        }
+        let master = Pty::new(proc.cred.user.effective, proc.cred.group.effective)?;
+        return Ok(Arc::new(master));
+    }
+    let pty = match node.downcast_ref::<DevPtsINode>() {
+        Some(DevPtsINode::Slave(pty)) => pty.clone(),
+        _ => return Err(SysError::ENXIO),
+    };
+    let slave = Pty::open_slave(&pty)?;
+    if !noctty
+        && proc.sid == proc.pid.get()
+        && proc.ctty.is_none()
+        && pty.tty().set_session(proc.sid, proc.pgid, false)
+    {
+        proc.ctty = Some(pty.tty().clone());
+    }
+    Ok(Arc::new(slave))
+}
+
+/// Open an end of the FIFO `inode` with the process unlocked, waiting until the other end
+/// is opened too. A non-blocking read end does not wait, and a non-blocking write end
+/// fails with `ENXIO` instead.
+fn open_fifo(inode: &Arc<INode>, options: &OpenOptions) -> Result<Pipe, SysError> {
+    let direction = match (options.read, options.write) {
+        (true, true) => PipeEnd::Both,
+        (false, true) => PipeEnd::Write,
+        _ => PipeEnd::Read,
+    };
+    let pipe = Pipe::open_fifo(inode, direction);
+    loop {
+        let mut waiter = WaitAny::new();
+        waiter.extend(pipe.wait_queues());
+        if pipe.has_peer() {
+            return Ok(pipe);
+        }
+        if options.nonblock {
+            return match direction {
+                PipeEnd::Write => Err(SysError::ENXIO),
+                _ => Ok(pipe),
+            };
+        }
+        wait_for_readiness(waiter, None)?;
+    }
+}
+
 pub fn sys_close(fd: usize) -> SysResult {
     info!("close: fd: {:?}", fd);
     let mut proc = process();
-    proc.files.remove(&fd).ok_or(SysError::EBADF)?;
+    proc.close_file(fd).ok_or(SysError::EBADF)?;
     Ok(0)
 }
 
@@ -296,7 +652,6 @@ pub fn sys_access(path: *const u8, mode: usize) -> SysResult {
 }
 
 pub fn sys_faccessat(dirfd: usize, path: *const u8, mode: usize, flags: usize) -> SysResult {
-    // TODO: check permissions based on uid/gid
     let proc = process();
     let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
     let flags = AtFlags::from_bits_truncate(flags);
@@ -307,7 +662,19 @@ pub fn sys_faccessat(dirfd: usize, path: *const u8, mode: usize, flags: usize) -
             dirfd as isize, path, mode, flags
         );
     }
+    if mode & !(R_OK | W_OK | X_OK) != 0 {
+        return Err(SysError::EINVAL);
+    }
     let inode = proc.lookup_inode_at(dirfd, &path, !flags.contains(AtFlags::SYMLINK_NOFOLLOW))?;
+    // checked with the real ids unless `EACCESS`
+    let cred = if flags.contains(AtFlags::EACCESS) {
+        proc.cred.clone()
+    } else {
+        proc.cred.real()
+    };
+    if !cred.can_access(&inode.metadata()?, mode) {
+        return Err(SysError::EACCES);
+    }
     Ok(0)
 }
 
@@ -375,9 +742,8 @@ pub fn sys_readlinkat(dirfd: usize, path: *const u8, base: *mut u8, len: usize)
 
     let inode = proc.lookup_inode_at(dirfd, &path, false)?;
     if inode.metadata()?.type_ == FileType::SymLink {
-        // TODO: recursive link resolution and loop detection
         let mut slice = unsafe { slice::from_raw_parts_mut(base, len) };
-        let len = inode.read_at(0, &mut slice)?;
+        let len = lend_process(&proc, || inode.read_at(0, &mut slice))?;
         Ok(len)
     } else {
         Err(SysError::EINVAL)
@@ -432,37 +798,117 @@ pub fn sys_getdents64(fd: usize, buf: *mut LinuxDirent64, buf_size: usize) -> Sy
     );
     let mut proc = process();
     proc.vm.check_write_array(buf as *mut u8, buf_size)?;
-    let file = proc.get_file(fd)?;
-    let info = file.metadata()?;
-    if info.type_ != FileType::Dir {
-        return Err(SysError::ENOTDIR);
-    }
-    let mut writer = unsafe { DirentBufWriter::new(buf, buf_size) };
-    loop {
-        let name = match file.read_entry() {
-            Err(FsError::EntryNotFound) => break,
-            r => r,
-        }?;
-        // TODO: get ino from dirent
-        let ok = writer.try_write(0, DirentType::from_type(&info.type_).bits(), &name);
-        if !ok {
-            break;
+    proc.with_file(fd, |file| {
+        let info = file.metadata()?;
+        if info.type_ != FileType::Dir {
+            return Err(SysError::ENOTDIR);
         }
-    }
-    Ok(writer.written_size)
+        let mut writer = unsafe { DirentBufWriter::new(buf, buf_size) };
+        loop {
+            let name = match file.read_entry() {
+                Err(FsError::EntryNotFound) => break,
+                r => r,
+            }?;
+            // TODO: get ino from dirent
+            let ok = writer.try_write(0, DirentType::from_type(&info.type_).bits(), &name);
+            if !ok {
+                break;
+            }
+        }
+        Ok(writer.written_size)
+    })
 }
 
 pub fn sys_dup2(fd1: usize, fd2: usize) -> SysResult {
     info!("dup2: from {} to {}", fd1, fd2);
-    let mut proc = process();
-    // close fd2 first if it is opened
-    proc.files.remove(&fd2);
+    if fd1 == fd2 {
+        // nothing to do if it is valid
+        process().get_file_like(fd1)?;
+        return Ok(fd2);
+    }
+    sys_dup3(fd1, fd2, 0)
+}
 
+pub fn sys_dup3(fd1: usize, fd2: usize, flags: usize) -> SysResult {
+    info!("dup3: from {} to {}, flags: {:#x}", fd1, fd2, flags);
+    let flags = OpenFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+    if !OpenFlags::CLOEXEC.contains(flags) || fd1 == fd2 {
+        return Err(SysError::EINVAL);
+    }
+    let mut proc = process();
     let file_like = proc.get_file_like(fd1)?.clone();
+    // close fd2 first if it is opened
+    proc.close_file(fd2);
     proc.files.insert(fd2, file_like);
+    proc.set_cloexec(fd2, flags.contains(OpenFlags::CLOEXEC));
     Ok(fd2)
 }
 
+pub fn sys_fcntl(fd: usize, cmd: usize, arg: usize) -> SysResult {
+    info!("fcntl: fd: {}, cmd: {}, arg: {:#x}", fd, cmd, arg);
+    let mut proc = process();
+    match cmd {
+        F_DUPFD | F_DUPFD_CLOEXEC => {
+            let file_like = proc.get_file_like(fd)?.clone();
+            let new_fd = proc.get_free_fd_from(arg);
+            proc.files.insert(new_fd, file_like);
+            proc.set_cloexec(new_fd, cmd == F_DUPFD_CLOEXEC);
+            Ok(new_fd)
+        }
+        F_GETFD => {
+            proc.get_file_like(fd)?;
+            if proc.cloexec_fds.contains(&fd) {
+                Ok(FD_CLOEXEC)
+            } else {
+                Ok(0)
+            }
+        }
+        F_SETFD => {
+            proc.get_file_like(fd)?;
+            proc.set_cloexec(fd, arg & FD_CLOEXEC != 0);
+            Ok(0)
+        }
+        F_GETFL => match proc.get_file_like(fd)? {
+            FileLike::File(file) => Ok(OpenFlags::from_options(&file.options()).bits()),
+            FileLike::Socket(_, status) if status.read().nonblock => {
+                Ok((OpenFlags::RDWR | OpenFlags::NONBLOCK).bits())
+            }
+            _ => Ok(OpenFlags::RDWR.bits()),
+        },
+        F_SETFL => {
+            // the access mode and creation flags are ignored
+            let flags = OpenFlags::from_bits_truncate(arg);
+            match proc.get_file_like(fd)? {
+                FileLike::File(file) => file.set_status(
+                    flags.contains(OpenFlags::APPEND),
+                    flags.contains(OpenFlags::NONBLOCK),
+                ),
+                FileLike::Socket(_, status) => {
+                    status.write().nonblock = flags.contains(OpenFlags::NONBLOCK);
+                }
+                FileLike::Epoll(_) => {}
+            }
+            Ok(0)
+        }
+        F_SETPIPE_SZ => {
+            let pipe = proc.get_file(fd)?.pipe().ok_or(SysError::EBADF)?;
+            // rounded up to pages, like Linux
+            let size = max(arg, PIPE_BUF);
+            let size = (size + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE;
+            if size > PIPE_MAX_SIZE {
+                return Err(SysError::EPERM);
+            }
+            pipe.set_capacity(size)?;
+            Ok(size)
+        }
+        F_GETPIPE_SZ => {
+            let pipe = proc.get_file(fd)?.pipe().ok_or(SysError::EBADF)?;
+            Ok(pipe.capacity())
+        }
+        _ => Err(SysError::EINVAL),
+    }
+}
+
 pub fn sys_ioctl(fd: usize, request: usize, arg1: usize, arg2: usize, arg3: usize) -> SysResult {
     info!(
         "ioctl: fd: {}, request: {:x}, args: {} {} {}",
@@ -470,7 +916,132 @@ pub fn sys_ioctl(fd: usize, request: usize, arg1: usize, arg2: usize, arg3: usiz
     );
     let mut proc = process();
     let file_like = proc.get_file_like(fd)?;
-    file_like.ioctl(request, arg1, arg2, arg3)
+    let master = file_like.pty_master();
+    // the requests to the master of a pty are for its slave
+    let tty = match master {
+        Some(ref pty) => Some(pty.tty().clone()),
+        None => file_like.tty(),
+    };
+    match tty {
+        Some(tty) => tty_ioctl(proc, &tty, master, request as u32, arg1),
+        None => {
+            // the requests of devices which write to the pointer are checked here,
+            // since the inodes cannot check it
+            if request as u32 == FBIOGET_VSCREENINFO {
+                proc.vm.check_write_ptr(arg1 as *mut FbVarScreenInfo)?;
+            }
+            let file_like = proc.get_file_like(fd)?;
+            file_like.ioctl(request, arg1, arg2, arg3)
+        }
+    }
+}
+
+/// The requests of terminals, whose pointers are checked with the process,
+/// and the requests for job control depend on its session,
+/// unless they are made to the `master` of the pty.
+fn tty_ioctl(
+    mut proc: MutexGuard<Process, SpinNoIrq>,
+    tty: &Arc<Tty>,
+    master: Option<Arc<Pty>>,
+    cmd: u32,
+    arg: usize,
+) -> SysResult {
+    let is_ctty = proc
+        .ctty
+        .as_ref()
+        .map_or(false, |ctty| Arc::ptr_eq(ctty, tty))
+        && tty.session() == Some(proc.sid);
+    match cmd {
+        TIOCGPTN if master.is_some() => {
+            let ptr = arg as *mut u32;
+            proc.vm.check_write_ptr(ptr)?;
+            unsafe { *ptr = master.unwrap().index() as u32 };
+            Ok(0)
+        }
+        // `unlockpt`
+        TIOCSPTLCK if master.is_some() => {
+            let ptr = arg as *const i32;
+            proc.vm.check_read_ptr(ptr)?;
+            master.unwrap().set_locked(unsafe { *ptr } != 0);
+            Ok(0)
+        }
+        TCGETS => {
+            let ptr = arg as *mut Termios;
+            proc.vm.check_write_ptr(ptr)?;
+            unsafe { *ptr = tty.termios() };
+            Ok(0)
+        }
+        // the output is written synchronously, so there is nothing to wait for
+        TCSETS | TCSETSW | TCSETSF => {
+            let ptr = arg as *const Termios;
+            proc.vm.check_read_ptr(ptr)?;
+            tty.set_termios(unsafe { &*ptr }, cmd == TCSETSF);
+            Ok(0)
+        }
+        TIOCGWINSZ => {
+            let ptr = arg as *mut WinSize;
+            proc.vm.check_write_ptr(ptr)?;
+            unsafe { *ptr = tty.winsize() };
+            Ok(0)
+        }
+        TIOCSWINSZ => {
+            let ptr = arg as *const WinSize;
+            proc.vm.check_read_ptr(ptr)?;
+            let winsize = unsafe { *ptr };
+            drop(proc);
+            if tty.set_winsize(winsize) {
+                tty.signal_foreground(SIGWINCH);
+            }
+            Ok(0)
+        }
+        TIOCSCTTY => {
+            if is_ctty {
+                return Ok(0);
+            }
+            // only a session leader without one can take it,
+            // from another session if root asks to steal it
+            let steal = arg == 1 && proc.cred.is_root();
+            if proc.sid != proc.pid.get()
+                || proc.ctty.is_some()
+                || !tty.set_session(proc.sid, proc.pgid, steal)
+            {
+                return Err(SysError::EPERM);
+            }
+            proc.ctty = Some(tty.clone());
+            Ok(0)
+        }
+        _ if !is_ctty && master.is_none() => Err(SysError::ENOTTY),
+        TIOCGPGRP | TIOCGSID => {
+            let ptr = arg as *mut i32;
+            proc.vm.check_write_ptr(ptr)?;
+            let id = match cmd {
+                TIOCGPGRP => tty.foreground(),
+                _ => tty.session().ok_or(SysError::ENOTTY)?,
+            };
+            unsafe { *ptr = id as i32 };
+            Ok(0)
+        }
+        TIOCSPGRP => {
+            let ptr = arg as *const i32;
+            proc.vm.check_read_ptr(ptr)?;
+            let pgid = unsafe { *ptr };
+            if pgid < 0 {
+                return Err(SysError::EINVAL);
+            }
+            let sid = proc.sid;
+            drop(proc);
+            // it must be a process group in the session
+            if !process_group(pgid as usize)
+                .iter()
+                .any(|proc| proc.lock().sid == sid)
+            {
+                return Err(SysError::EPERM);
+            }
+            tty.set_foreground(pgid as usize);
+            Ok(0)
+        }
+        _ => Err(SysError::ENOTTY),
+    }
 }
 
 pub fn sys_chdir(path: *const u8) -> SysResult {
@@ -486,33 +1057,123 @@ pub fn sys_chdir(path: *const u8) -> SysResult {
     if info.type_ != FileType::Dir {
         return Err(SysError::ENOTDIR);
     }
+    proc.check_access(&inode, X_OK)?;
 
-    // BUGFIX: '..' and '.'
-    if path.len() > 0 {
-        let cwd = match path.as_bytes()[0] {
-            b'/' => String::from("/"),
-            _ => proc.cwd.clone(),
-        };
-        let mut cwd_vec: Vec<_> = cwd.split("/").filter(|&x| x != "").collect();
-        let path_split = path.split("/").filter(|&x| x != "");
-        for seg in path_split {
-            if seg == ".." {
-                cwd_vec.pop();
-            } else if seg == "." {
-                // nothing to do here.
-            } else {
-                cwd_vec.push(seg);
-            }
-        }
-        proc.cwd = String::from("");
-        for seg in cwd_vec {
-            proc.cwd.push_str("/");
-            proc.cwd.push_str(seg);
+    proc.cwd = proc.absolute_path(AT_FDCWD, &path)?;
+    Ok(0)
+}
+
+pub fn sys_umask(mask: usize) -> SysResult {
+    info!("umask: mask: {:#o}", mask);
+    let mut proc = process();
+    let old_mask = proc.umask;
+    proc.umask = mask & 0o777;
+    Ok(old_mask)
+}
+
+pub fn sys_chmod(path: *const u8, mode: usize) -> SysResult {
+    sys_fchmodat(AT_FDCWD, path, mode, 0)
+}
+
+pub fn sys_fchmod(fd: usize, mode: usize) -> SysResult {
+    info!("fchmod: fd: {}, mode: {:#o}", fd, mode);
+    let mut proc = process();
+    let inode = proc.get_file(fd)?.inode();
+    chmod(&proc, &inode, mode)
+}
+
+pub fn sys_fchmodat(dirfd: usize, path: *const u8, mode: usize, flags: usize) -> SysResult {
+    let proc = process();
+    let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
+    info!(
+        "fchmodat: dirfd: {}, path: {:?}, mode: {:#o}, flags: {:#x}",
+        dirfd as isize, path, mode, flags
+    );
+    // the mode of a symbolic link is not used, so it is always followed
+    let inode = proc.lookup_inode_at(dirfd, &path, true)?;
+    chmod(&proc, &inode, mode)
+}
+
+/// Change the permission bits of `inode`, which only its owner can do
+fn chmod(proc: &Process, inode: &Arc<INode>, mode: usize) -> SysResult {
+    let mut metadata = inode.metadata()?;
+    if !proc.cred.owns(&metadata) {
+        return Err(SysError::EPERM);
+    }
+    let mut mode = mode & 0o7777;
+    if !proc.cred.is_root() && !proc.cred.in_group(metadata.gid) {
+        mode &= !S_ISGID;
+    }
+    metadata.mode = mode as u16;
+    inode.set_metadata(&metadata)?;
+    Ok(0)
+}
+
+pub fn sys_chown(path: *const u8, owner: usize, group: usize) -> SysResult {
+    sys_fchownat(AT_FDCWD, path, owner, group, 0)
+}
+
+pub fn sys_lchown(path: *const u8, owner: usize, group: usize) -> SysResult {
+    let flags = AtFlags::SYMLINK_NOFOLLOW.bits();
+    sys_fchownat(AT_FDCWD, path, owner, group, flags)
+}
+
+pub fn sys_fchown(fd: usize, owner: usize, group: usize) -> SysResult {
+    info!(
+        "fchown: fd: {}, owner: {}, group: {}",
+        fd, owner as isize, group as isize
+    );
+    let mut proc = process();
+    let inode = proc.get_file(fd)?.inode();
+    chown(&proc, &inode, owner, group)
+}
+
+pub fn sys_fchownat(
+    dirfd: usize,
+    path: *const u8,
+    owner: usize,
+    group: usize,
+    flags: usize,
+) -> SysResult {
+    let proc = process();
+    let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
+    let flags = AtFlags::from_bits_truncate(flags);
+    info!(
+        "fchownat: dirfd: {}, path: {:?}, owner: {}, group: {}, flags: {:?}",
+        dirfd as isize, path, owner as isize, group as isize, flags
+    );
+    // an empty path refers to `dirfd` itself
+    if path.is_empty() && !flags.contains(AtFlags::EMPTY_PATH) {
+        return Err(SysError::ENOENT);
+    }
+    let inode = proc.lookup_inode_at(dirfd, &path, !flags.contains(AtFlags::SYMLINK_NOFOLLOW))?;
+    chown(&proc, &inode, owner, group)
+}
+
+/// Change the owner and the group of `inode`, an id of -1 is not changed.
+/// Only root can change the owner, and the owner can change the group to one of its groups.
+fn chown(proc: &Process, inode: &Arc<INode>, owner: usize, group: usize) -> SysResult {
+    let mut metadata = inode.metadata()?;
+    let cred = &proc.cred;
+    // ids are 32 bits
+    let (owner, group) = (owner as u32, group as u32);
+    if owner != core::u32::MAX && owner as usize != metadata.uid {
+        if !cred.is_root() {
+            return Err(SysError::EPERM);
         }
-        if proc.cwd == "" {
-            proc.cwd = String::from("/");
+        metadata.uid = owner as usize;
+    }
+    if group != core::u32::MAX && group as usize != metadata.gid {
+        if !cred.is_root() && !(cred.owns(&metadata) && cred.in_group(group as usize)) {
+            return Err(SysError::EPERM);
         }
+        metadata.gid = group as usize;
     }
+    // a program does not keep set-user-ID and set-group-ID for another owner, as Linux
+    if metadata.type_ == FileType::File && metadata.mode & 0o111 != 0 {
+        metadata.mode &= !((S_ISUID | S_ISGID) as u16);
+    }
+    inode.set_metadata(&metadata)?;
     Ok(0)
 }
 
@@ -536,8 +1197,8 @@ pub fn sys_renameat(
 
     let (old_dir_path, old_file_name) = split_path(&oldpath);
     let (new_dir_path, new_file_name) = split_path(&newpath);
-    let old_dir_inode = proc.lookup_inode_at(olddirfd, old_dir_path, false)?;
-    let new_dir_inode = proc.lookup_inode_at(newdirfd, new_dir_path, false)?;
+    let old_dir_inode = proc.lookup_inode_at(olddirfd, old_dir_path, true)?;
+    let new_dir_inode = proc.lookup_inode_at(newdirfd, new_dir_path, true)?;
     old_dir_inode.move_(old_file_name, &new_dir_inode, new_file_name)?;
     Ok(0)
 }
@@ -560,7 +1221,44 @@ pub fn sys_mkdirat(dirfd: usize, path: *const u8, mode: usize) -> SysResult {
     if inode.find(file_name).is_ok() {
         return Err(SysError::EEXIST);
     }
-    inode.create(file_name, FileType::Dir, mode as u32)?;
+    proc.create_at(&inode, file_name, FileType::Dir, mode)?;
+    Ok(0)
+}
+
+pub fn sys_mknod(path: *const u8, mode: usize, dev: usize) -> SysResult {
+    sys_mknodat(AT_FDCWD, path, mode, dev)
+}
+
+pub fn sys_mknodat(dirfd: usize, path: *const u8, mode: usize, dev: usize) -> SysResult {
+    let proc = process();
+    let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
+    info!(
+        "mknodat: dirfd: {}, path: {:?}, mode: {:#o}, dev: {:#x}",
+        dirfd as isize, path, mode, dev
+    );
+
+    let type_ = match StatMode::from_bits_truncate(mode as u32) & StatMode::TYPE_MASK {
+        StatMode::NULL | StatMode::FILE => FileType::File,
+        StatMode::FIFO => FileType::NamedPipe,
+        StatMode::CHAR => FileType::CharDevice,
+        StatMode::BLOCK => FileType::BlockDevice,
+        StatMode::SOCKET => FileType::Socket,
+        _ => return Err(SysError::EINVAL),
+    };
+    let is_device = type_ == FileType::CharDevice || type_ == FileType::BlockDevice;
+    // a device node would give access to the device whatever the mode of its node in devfs
+    if is_device && !proc.cred.is_root() {
+        return Err(SysError::EPERM);
+    }
+    let (dir_path, file_name) = split_path(&path);
+    let inode = proc.lookup_inode_at(dirfd, dir_path, true)?;
+    if inode.find(file_name).is_ok() {
+        return Err(SysError::EEXIST);
+    }
+    let file_inode = proc.create_at(&inode, file_name, type_, mode)?;
+    if is_device {
+        set_device_number(&file_inode, dev)?;
+    }
     Ok(0)
 }
 
@@ -575,6 +1273,7 @@ pub fn sys_rmdir(path: *const u8) -> SysResult {
     if file_inode.metadata()?.type_ != FileType::Dir {
         return Err(SysError::ENOTDIR);
     }
+    proc.check_remove(&dir_inode, &file_inode)?;
     dir_inode.unlink(file_name)?;
     Ok(0)
 }
@@ -600,12 +1299,40 @@ pub fn sys_linkat(
     );
 
     let (new_dir_path, new_file_name) = split_path(&newpath);
-    let inode = proc.lookup_inode_at(olddirfd, &oldpath, true)?;
+    let follow = flags.contains(AtFlags::SYMLINK_FOLLOW);
+    let inode = proc.lookup_inode_at(olddirfd, &oldpath, follow)?;
     let new_dir_inode = proc.lookup_inode_at(newdirfd, new_dir_path, true)?;
     new_dir_inode.link(new_file_name, &inode)?;
     Ok(0)
 }
 
+pub fn sys_symlink(target: *const u8, linkpath: *const u8) -> SysResult {
+    sys_symlinkat(target, AT_FDCWD, linkpath)
+}
+
+pub fn sys_symlinkat(target: *const u8, newdirfd: usize, linkpath: *const u8) -> SysResult {
+    let proc = process();
+    let target = unsafe { proc.vm.check_and_clone_cstr(target)? };
+    let linkpath = unsafe { proc.vm.check_and_clone_cstr(linkpath)? };
+    info!(
+        "symlinkat: target: {:?}, newdirfd: {}, linkpath: {:?}",
+        target, newdirfd as isize, linkpath
+    );
+    if target.is_empty() {
+        return Err(SysError::ENOENT);
+    }
+
+    let (dir_path, file_name) = split_path(&linkpath);
+    let dir_inode = proc.lookup_inode_at(newdirfd, dir_path, true)?;
+    if dir_inode.find(file_name).is_ok() {
+        return Err(SysError::EEXIST);
+    }
+    // the target is not checked, a link can dangle
+    let inode = proc.create_at(&dir_inode, file_name, FileType::SymLink, 0o777)?;
+    inode.write_at(0, target.as_bytes())?;
+    Ok(0)
+}
+
 pub fn sys_unlink(path: *const u8) -> SysResult {
     sys_unlinkat(AT_FDCWD, path, 0)
 }
@@ -625,12 +1352,23 @@ pub fn sys_unlinkat(dirfd: usize, path: *const u8, flags: usize) -> SysResult {
     if file_inode.metadata()?.type_ == FileType::Dir {
         return Err(SysError::EISDIR);
     }
+    proc.check_remove(&dir_inode, &file_inode)?;
     dir_inode.unlink(file_name)?;
     Ok(0)
 }
 
 pub fn sys_pipe(fds: *mut u32) -> SysResult {
-    info!("pipe: fds: {:?}", fds);
+    sys_pipe2(fds, 0)
+}
+
+pub fn sys_pipe2(fds: *mut u32, flags: usize) -> SysResult {
+    info!("pipe2: fds: {:?}, flags: {:#x}", fds, flags);
+    let flags = OpenFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+    if !(OpenFlags::CLOEXEC | OpenFlags::NONBLOCK).contains(flags) {
+        return Err(SysError::EINVAL);
+    }
+    let nonblock = flags.contains(OpenFlags::NONBLOCK);
+    let cloexec = flags.contains(OpenFlags::CLOEXEC);
 
     let mut proc = process();
     proc.vm.check_write_array(fds, 2)?;
@@ -645,9 +1383,12 @@ pub fn sys_pipe(fds: *mut u32) -> SysResult {
                 read: true,
                 write: false,
                 append: false,
+                nonblock,
             },
+            String::from("pipe"),
         )),
     );
+    proc.set_cloexec(read_fd, cloexec);
 
     let write_fd = proc.get_free_fd();
     proc.files.insert(
@@ -658,9 +1399,12 @@ pub fn sys_pipe(fds: *mut u32) -> SysResult {
                 read: false,
                 write: true,
                 append: false,
+                nonblock,
             },
+            String::from("pipe"),
         )),
     );
+    proc.set_cloexec(write_fd, cloexec);
 
     unsafe {
         fds.write(read_fd as u32);
@@ -677,6 +1421,106 @@ pub fn sys_sync() -> SysResult {
     Ok(0)
 }
 
+/// Mount a file system of `fstype` on the directory `target`.
+///
+/// The `source` of `sfs` is a block device named like `sda`,
+/// or `none` to create an empty one in memory of the `size=` in `data`.
+/// `tmpfs` keeps its files in memory, and `devpts` has the slaves of the ptys.
+pub fn sys_mount(
+    source: *const u8,
+    target: *const u8,
+    fstype: *const u8,
+    flags: usize,
+    data: *const u8,
+) -> SysResult {
+    let proc = process();
+    let source = unsafe { proc.vm.check_and_clone_cstr(source)? };
+    let target = unsafe { proc.vm.check_and_clone_cstr(target)? };
+    let fstype = unsafe { proc.vm.check_and_clone_cstr(fstype)? };
+    let data = if data.is_null() {
+        String::new()
+    } else {
+        unsafe { proc.vm.check_and_clone_cstr(data)? }
+    };
+    let flags = MountFlags::from_bits_truncate(flags);
+    info!(
+        "mount: source: {:?}, target: {:?}, fstype: {:?}, flags: {:?}, data: {:?}",
+        source, target, fstype, flags, data
+    );
+    if !proc.cred.is_root() {
+        return Err(SysError::EPERM);
+    }
+    // other flags are ignored
+    if flags.intersects(MountFlags::REMOUNT | MountFlags::BIND | MountFlags::MOVE) {
+        return Err(SysError::EINVAL);
+    }
+    let inode = proc.lookup_inode(&target)?;
+    let info = MountInfo {
+        source: source.clone(),
+        target: proc.absolute_path(AT_FDCWD, &target)?,
+        fstype: fstype.clone(),
+    };
+    let fs: Arc<FileSystem> = match fstype.as_str() {
+        "sfs" if source == "none" => {
+            let size = parse_size_option(&data, RAMDISK_SIZE).ok_or(SysError::EINVAL)?;
+            let device = RamDisk::new(size).ok_or(SysError::ENOMEM)?;
+            SimpleFileSystem::create(Arc::new(device), size)
+        }
+        "sfs" => {
+            let device = block_device(&source).ok_or(SysError::ENOTBLK)?;
+            // a second file system on it would corrupt the first one
+            if is_mounted(&device) || swap::uses_device(&device) {
+                return Err(SysError::EBUSY);
+            }
+            SimpleFileSystem::open(device)?
+        }
+        "devpts" => Arc::new(DevPtsFS),
+        "tmpfs" | "ramfs" => {
+            // the files take memory only when written
+            let size = parse_size_option(&data, KERNEL_HEAP_SIZE / 4).ok_or(SysError::EINVAL)?;
+            if size > KERNEL_HEAP_SIZE / 4 {
+                return Err(SysError::ENOMEM);
+            }
+            TmpFS::new(size)
+        }
+        _ => return Err(SysError::ENODEV),
+    };
+    mount(&inode, fs, info)?;
+    Ok(0)
+}
+
+pub fn sys_umount2(target: *const u8, flags: usize) -> SysResult {
+    let proc = process();
+    let target = unsafe { proc.vm.check_and_clone_cstr(target)? };
+    let flags = UmountFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+    info!("umount2: target: {:?}, flags: {:?}", target, flags);
+    if !proc.cred.is_root() {
+        return Err(SysError::EPERM);
+    }
+    let follow = !flags.contains(UmountFlags::NOFOLLOW);
+    let inode = proc.lookup_inode_at(AT_FDCWD, &target, follow)?;
+    umount(inode, flags.contains(UmountFlags::DETACH))?;
+    Ok(0)
+}
+
+/// Size of the file systems in memory if not given by `size=`
+const RAMDISK_SIZE: usize = 1024 * 1024;
+
+/// Parse the `size=` option in bytes with an optional `k`, `m` or `g` suffix
+fn parse_size_option(data: &str, default: usize) -> Option<usize> {
+    let size = match data.split(',').find(|opt| opt.starts_with("size=")) {
+        Some(opt) => &opt[5..],
+        None => return Some(default),
+    };
+    let (num, unit) = match size.chars().last()? {
+        'k' | 'K' => (&size[..size.len() - 1], 1 << 10),
+        'm' | 'M' => (&size[..size.len() - 1], 1 << 20),
+        'g' | 'G' => (&size[..size.len() - 1], 1 << 30),
+        _ => (size, 1),
+    };
+    num.parse::<usize>().ok()?.checked_mul(unit)
+}
+
 pub fn sys_sendfile(
     out_fd: usize,
     in_fd: usize,
@@ -687,27 +1531,27 @@ pub fn sys_sendfile(
         "sendfile: out: {}, in: {}, offset_ptr: {:?}, count: {}",
         out_fd, in_fd, offset_ptr, count
     );
-    let proc = process();
-    // We know it's save, pacify the borrow checker
-    let proc_cell = UnsafeCell::new(proc);
-    let in_file = unsafe { (*proc_cell.get()).get_file(in_fd)? };
-    let out_file = unsafe { (*proc_cell.get()).get_file(out_fd)? };
+    let mut proc = process();
+    proc.get_file(in_fd)?;
+    proc.get_file(out_fd)?;
     let mut buffer = [0u8; 1024];
 
     let mut read_offset = if !offset_ptr.is_null() {
         unsafe {
-            (*proc_cell.get()).vm.check_read_ptr(offset_ptr)?;
+            proc.vm.check_read_ptr(offset_ptr)?;
             offset_ptr.read()
         }
     } else {
-        in_file.seek(SeekFrom::Current(0))? as usize
+        proc.get_file(in_fd)?.seek(SeekFrom::Current(0))? as usize
     };
 
     // read from specified offset and write new offset back
     let mut bytes_read = 0;
     while bytes_read < count {
         let len = min(buffer.len(), count - bytes_read);
-        let read_len = in_file.read_at(read_offset, &mut buffer[..len])?;
+        let read_len = proc.with_file(in_fd, |in_file| {
+            Ok(in_file.read_at(read_offset, &mut buffer[..len])?)
+        })?;
         if read_len == 0 {
             break;
         }
@@ -715,7 +1559,7 @@ pub fn sys_sendfile(
         read_offset += read_len;
         let mut bytes_written = 0;
         while bytes_written < read_len {
-            let write_len = out_file.write(&buffer[bytes_written..])?;
+            let write_len = proc.get_file(out_fd)?.write(&buffer[bytes_written..])?;
             if write_len == 0 {
                 return Err(SysError::EBADF);
             }
@@ -728,7 +1572,8 @@ pub fn sys_sendfile(
             offset_ptr.write(read_offset);
         }
     } else {
-        in_file.seek(SeekFrom::Current(bytes_read as i64))?;
+        proc.get_file(in_fd)?
+            .seek(SeekFrom::Current(bytes_read as i64))?;
     }
     return Ok(bytes_read);
 }
@@ -737,12 +1582,44 @@ impl Process {
     pub fn get_file_like(&mut self, fd: usize) -> Result<&mut FileLike, SysError> {
         self.files.get_mut(&fd).ok_or(SysError::EBADF)
     }
+    pub fn get_epoll(&mut self, fd: usize) -> Result<&mut EpollInstance, SysError> {
+        match self.get_file_like(fd)? {
+            FileLike::Epoll(epoll) => Ok(epoll),
+            _ => Err(SysError::EINVAL),
+        }
+    }
     pub fn get_file(&mut self, fd: usize) -> Result<&mut FileHandle, SysError> {
         match self.get_file_like(fd)? {
             FileLike::File(file) => Ok(file),
             _ => Err(SysError::EBADF),
         }
     }
+    /// Call `f` with the file `fd`, which may be a file of procfs about this process.
+    /// Then the process is lent to procfs, and `f` is given a copy of the file,
+    /// which is put back to keep its offset.
+    pub fn with_file_like<T>(
+        &mut self,
+        fd: usize,
+        f: impl FnOnce(&mut FileLike) -> Result<T, SysError>,
+    ) -> Result<T, SysError> {
+        let mut copy = match self.get_file_like(fd)? {
+            FileLike::File(file) if is_procfs(&file.inode()) => FileLike::File(file.clone()),
+            file_like => return f(file_like),
+        };
+        let ret = lend_process(self, || f(&mut copy));
+        *self.get_file_like(fd)? = copy;
+        ret
+    }
+    pub fn with_file<T>(
+        &mut self,
+        fd: usize,
+        f: impl FnOnce(&mut FileHandle) -> Result<T, SysError>,
+    ) -> Result<T, SysError> {
+        self.with_file_like(fd, |file_like| match file_like {
+            FileLike::File(file) => f(file),
+            _ => Err(SysError::EBADF),
+        })
+    }
     /// Lookup INode from the process.
     ///
     /// - If `path` is relative, then it is interpreted relative to the directory
@@ -753,7 +1630,8 @@ impl Process {
     ///
     /// - If `path` is absolute, then `dirfd` is ignored.
     ///
-    /// - If `follow` is true, then dereference `path` if it is a symbolic link.
+    /// - Symbolic links in the directories of `path` are always followed. If `follow` is true,
+    ///   then dereference `path` too if it is a symbolic link.
     pub fn lookup_inode_at(
         &self,
         dirfd: usize,
@@ -764,23 +1642,100 @@ impl Process {
             "lookup_inode_at: dirfd: {:?}, cwd: {:?}, path: {:?}, follow: {:?}",
             dirfd as isize, self.cwd, path, follow
         );
-        let follow_max_depth = if follow { FOLLOW_MAX_DEPTH } else { 0 };
-        if dirfd == AT_FDCWD {
-            Ok(ROOT_INODE
-                .lookup(&self.cwd)?
-                .lookup_follow(path, follow_max_depth)?)
-        } else {
-            let file = match self.files.get(&dirfd).ok_or(SysError::EBADF)? {
-                FileLike::File(file) => file,
-                _ => return Err(SysError::EBADF),
+        // the path may go through procfs about this process
+        lend_process(self, || {
+            let dir = if path.starts_with('/') {
+                // `lookup_at` starts from `ROOT_INODE`
+                ROOT_INODE.clone()
+            } else if dirfd == AT_FDCWD {
+                lookup_at(&ROOT_INODE, &self.cwd, true)?
+            } else {
+                match self.files.get(&dirfd).ok_or(SysError::EBADF)? {
+                    FileLike::File(file) => file.inode(),
+                    _ => return Err(SysError::EBADF),
+                }
             };
-            Ok(file.lookup_follow(path, follow_max_depth)?)
-        }
+            lookup_at(&dir, path, follow)
+        })
     }
 
     pub fn lookup_inode(&self, path: &str) -> Result<Arc<INode>, SysError> {
         self.lookup_inode_at(AT_FDCWD, path, true)
     }
+
+    /// Check that the process may `access` `inode`, with a mask of `R_OK`, `W_OK` and `X_OK`
+    pub fn check_access(&self, inode: &Arc<INode>, access: usize) -> Result<(), SysError> {
+        if self.cred.can_access(&inode.metadata()?, access) {
+            Ok(())
+        } else {
+            Err(SysError::EACCES)
+        }
+    }
+
+    /// Check that the process may remove the entry `inode` of the directory `dir`
+    pub fn check_remove(&self, dir: &Arc<INode>, inode: &Arc<INode>) -> Result<(), SysError> {
+        self.check_access(dir, W_OK | X_OK)?;
+        if self.cred.can_remove(&dir.metadata()?, &inode.metadata()?) {
+            Ok(())
+        } else {
+            Err(SysError::EPERM)
+        }
+    }
+
+    /// Create `name` in the directory `dir`, owned by the process,
+    /// with the permission bits of `mode` which are not in its umask
+    pub fn create_at(
+        &self,
+        dir: &Arc<INode>,
+        name: &str,
+        type_: FileType,
+        mode: usize,
+    ) -> Result<Arc<INode>, SysError> {
+        self.check_access(dir, W_OK | X_OK)?;
+        let inode = dir.create(name, type_, (mode & 0o7777 & !self.umask) as u32)?;
+        let mut metadata = inode.metadata()?;
+        let (uid, gid) = (self.cred.user.effective, self.cred.group.effective);
+        if (metadata.uid, metadata.gid) != (uid, gid) {
+            metadata.uid = uid;
+            metadata.gid = gid;
+            inode.set_metadata(&metadata)?;
+        }
+        Ok(inode)
+    }
+
+    /// Get the absolute path of `path` relative to `dirfd` like `lookup_inode_at`,
+    /// with `.` and `..` removed.
+    pub fn absolute_path(&self, dirfd: usize, path: &str) -> Result<String, SysError> {
+        let base = if path.starts_with('/') {
+            "/"
+        } else if dirfd == AT_FDCWD {
+            self.cwd.as_str()
+        } else {
+            match self.files.get(&dirfd).ok_or(SysError::EBADF)? {
+                FileLike::File(file) => file.path(),
+                _ => return Err(SysError::EBADF),
+            }
+        };
+        let mut segs = Vec::new();
+        for seg in base.split('/').chain(path.split('/')) {
+            match seg {
+                "" | "." => {}
+                ".." => {
+                    segs.pop();
+                }
+                _ => segs.push(seg),
+            }
+        }
+        let mut abs_path = String::new();
+        for seg in segs {
+            abs_path.push('/');
+            abs_path.push_str(seg);
+        }
+        if abs_path.is_empty() {
+            abs_path.push('/');
+        }
+        Ok(abs_path)
+    }
 }
 
 /// Split a `path` str to `(base_path, file_name)`
@@ -814,10 +1769,35 @@ impl From<FsError> for SysError {
     }
 }
 
+bitflags! {
+    struct MountFlags: usize {
+        /// Change the flags of a mount
+        const REMOUNT = 32;
+        /// Bind a directory to another place
+        const BIND = 4096;
+        /// Move a mount to another place
+        const MOVE = 8192;
+    }
+}
+
+bitflags! {
+    struct UmountFlags: usize {
+        /// Unmount even if busy
+        const FORCE = 1;
+        /// Unmount lazily, by making it unreachable
+        const DETACH = 2;
+        /// Do not dereference `target` if it is a symbolic link
+        const NOFOLLOW = 8;
+    }
+}
+
 bitflags! {
     struct AtFlags: usize {
         const EMPTY_PATH = 0x1000;
         const SYMLINK_NOFOLLOW = 0x100;
+        /// Check with the effective ids in `faccessat`
+        const EACCESS = 0x200;
+        const SYMLINK_FOLLOW = 0x400;
     }
 }
 
@@ -833,10 +1813,18 @@ bitflags! {
         const CREATE = 1 << 6;
         /// error if CREATE and the file exists
         const EXCLUSIVE = 1 << 7;
+        /// a terminal does not become the controlling terminal
+        const NOCTTY = 1 << 8;
         /// truncate file upon open
         const TRUNCATE = 1 << 9;
         /// append on each write
         const APPEND = 1 << 10;
+        /// reads and writes do not block
+        const NONBLOCK = 1 << 11;
+        /// fail if the file is a symbolic link
+        const NOFOLLOW = 1 << 17;
+        /// close on exec
+        const CLOEXEC = 1 << 19;
     }
 }
 
@@ -854,8 +1842,19 @@ impl OpenFlags {
             read: self.readable(),
             write: self.writable(),
             append: self.contains(OpenFlags::APPEND),
+            nonblock: self.contains(OpenFlags::NONBLOCK),
         }
     }
+    fn from_options(options: &OpenOptions) -> Self {
+        let mut flags = match (options.read, options.write) {
+            (true, true) => OpenFlags::RDWR,
+            (false, true) => OpenFlags::WRONLY,
+            _ => OpenFlags::RDONLY,
+        };
+        flags.set(OpenFlags::APPEND, options.append);
+        flags.set(OpenFlags::NONBLOCK, options.nonblock);
+        flags
+    }
 }
 
 #[derive(Debug)]
@@ -1364,8 +2363,29 @@ impl FdSet {
     /// Check to see whether `fd` is in original `FdSet`
     /// Fd should be less than nfds
     fn contains(&self, fd: usize) -> bool {
-        self.origin[fd]
+        fd < self.origin.len() && self.origin[fd]
     }
 }
 
-const AT_FDCWD: usize = -100isize as usize;
+pub const AT_FDCWD: usize = -100isize as usize;
+
+const EPOLL_CLOEXEC: usize = 0x80000;
+
+const F_DUPFD: usize = 0;
+const F_GETFD: usize = 1;
+const F_SETFD: usize = 2;
+const F_GETFL: usize = 3;
+const F_SETFL: usize = 4;
+const F_DUPFD_CLOEXEC: usize = 1030;
+const F_SETPIPE_SZ: usize = 1031;
+const F_GETPIPE_SZ: usize = 1032;
+
+/// Maximum capacity of a pipe that `F_SETPIPE_SZ` can set
+const PIPE_MAX_SIZE: usize = 1024 * 1024;
+
+/// Close on exec, the only file descriptor flag
+const FD_CLOEXEC: usize = 1;
+
+const EPOLL_CTL_ADD: usize = 1;
+const EPOLL_CTL_DEL: usize = 2;
+const EPOLL_CTL_MOD: usize = 3;
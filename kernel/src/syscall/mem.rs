@@ -1,10 +1,12 @@
-use rcore_memory::memory_set::handler::{ByFrame, Delay};
+use rcore_memory::memory_set::handler::{Delay, File, Shared};
 use rcore_memory::memory_set::MemoryAttr;
-use rcore_memory::paging::PageTable;
 use rcore_memory::Page;
 use rcore_memory::PAGE_SIZE;
 
-use crate::memory::GlobalFrameAlloc;
+use crate::consts::USER_HEAP_SIZE;
+use crate::fs::{inner_inode, is_mounted, CachedPages, DevINode, INodeForMap};
+use crate::memory::{copy_on_write, GlobalFrameAlloc};
+use crate::swap;
 
 use super::*;
 
@@ -27,50 +29,71 @@ pub fn sys_mmap(
     if addr == 0 {
         // although NULL can be a valid address
         // but in C, NULL is regarded as allocation failure
-        // so just skip it
-        addr = PAGE_SIZE;
+        // so just skip it, and leave room for the heap to grow
+        addr = proc.brk_start + USER_HEAP_SIZE;
     }
 
+    // check the file before removing the old mapping
+    let inode = if flags.contains(MmapFlags::ANONYMOUS) {
+        None
+    } else {
+        if offset % PAGE_SIZE != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let file = proc.get_file(fd)?;
+        let options = file.options();
+        let shared_write = flags.contains(MmapFlags::SHARED) && prot.contains(MmapProt::WRITE);
+        if !options.read || (shared_write && !options.write) {
+            return Err(SysError::EACCES);
+        }
+        Some(file.inode())
+    };
+
     if flags.contains(MmapFlags::FIXED) {
         // we have to map it to addr, so remove the old mapping first
+        swap::forget(&proc.vm, addr, addr + len);
         proc.vm.pop_with_split(addr, addr + len);
     } else {
         addr = proc.vm.find_free_area(addr, len);
     }
 
-    if flags.contains(MmapFlags::ANONYMOUS) {
-        if flags.contains(MmapFlags::SHARED) {
-            return Err(SysError::EINVAL);
-        }
-        proc.vm.push(
+    let attr = prot.to_attr();
+    match (inode, flags.contains(MmapFlags::SHARED)) {
+        (None, false) => proc.vm.push(
             addr,
             addr + len,
-            prot.to_attr(),
-            Delay::new(GlobalFrameAlloc),
+            attr,
+            copy_on_write(Delay::new(GlobalFrameAlloc)),
             "mmap_anon",
-        );
-        return Ok(addr);
-    } else {
-        // only check
-        let _ = proc.get_file(fd)?;
-
-        // TODO: delay mmap file
-        proc.vm.push(
+        ),
+        (None, true) => proc.vm.push(
             addr,
             addr + len,
-            prot.to_attr(),
-            ByFrame::new(GlobalFrameAlloc),
+            attr,
+            Shared::new(CachedPages::anonymous(), addr, 0),
+            "mmap_anon_shared",
+        ),
+        (Some(inode), false) => proc.vm.push(
+            addr,
+            addr + len,
+            attr,
+            copy_on_write(File::new(
+                INodeForMap(inode),
+                addr,
+                offset,
+                GlobalFrameAlloc,
+            )),
             "mmap_file",
-        );
-        let data = unsafe { slice::from_raw_parts_mut(addr as *mut u8, len) };
-        let file = proc.get_file(fd)?;
-        let read_len = file.read_at(offset, data)?;
-        if read_len != data.len() {
-            // use count() to consume the iterator
-            data[read_len..].iter_mut().map(|x| *x = 0).count();
-        }
-        return Ok(addr);
+        ),
+        (Some(inode), true) => proc.vm.push(
+            addr,
+            addr + len,
+            attr,
+            Shared::new(CachedPages::of_file(inode), addr, offset),
+            "mmap_file_shared",
+        ),
     }
+    Ok(addr)
 }
 
 pub fn sys_mprotect(addr: usize, len: usize, prot: usize) -> SysResult {
@@ -79,37 +102,128 @@ pub fn sys_mprotect(addr: usize, len: usize, prot: usize) -> SysResult {
         "mprotect: addr={:#x}, size={:#x}, prot={:?}",
         addr, len, prot
     );
+    if addr % PAGE_SIZE != 0 {
+        return Err(SysError::EINVAL);
+    }
+    if len == 0 {
+        return Ok(0);
+    }
+    let end = addr
+        .checked_add(len)
+        .and_then(|end| end.checked_add(PAGE_SIZE - 1))
+        .ok_or(SysError::ENOMEM)?;
+    let end = Page::of_addr(end).start_address();
 
     let mut proc = process();
-    let attr = prot.to_attr();
-
-    // FIXME: properly set the attribute of the area
-    //        now some mut ptr check is fault
-    let memory_area = proc
-        .vm
-        .iter()
-        .find(|area| area.is_overlap_with(addr, addr + len));
-    if memory_area.is_none() {
+    if !proc.vm.protect(addr, end, prot.to_attr()) {
         return Err(SysError::ENOMEM);
     }
-    proc.vm.edit(|pt| {
-        for page in Page::range_of(addr, addr + len) {
-            let entry = pt
-                .get_entry(page.start_address())
-                .expect("failed to get entry");
-            attr.apply(entry);
-        }
-    });
     Ok(0)
 }
 
 pub fn sys_munmap(addr: usize, len: usize) -> SysResult {
     info!("munmap addr={:#x}, size={:#x}", addr, len);
     let mut proc = process();
+    // dirty pages of shared file mappings are written back by the handler
+    swap::forget(&proc.vm, addr, addr + len);
     proc.vm.pop_with_split(addr, addr + len);
     Ok(0)
 }
 
+/// Set the program break to `addr`, and return the new one.
+/// The old one is returned on failure, or if `addr` is below the start of the heap.
+pub fn sys_brk(addr: usize) -> SysResult {
+    info!("brk: addr={:#x}", addr);
+    let mut proc = process();
+    if addr < proc.brk_start || addr > usize::max_value() - PAGE_SIZE {
+        return Ok(proc.brk);
+    }
+    let old_end = Page::of_addr(proc.brk + PAGE_SIZE - 1).start_address();
+    let new_end = Page::of_addr(addr + PAGE_SIZE - 1).start_address();
+    if new_end < old_end {
+        swap::forget(&proc.vm, new_end, old_end);
+    }
+    let brk_start = proc.brk_start;
+    if !proc.vm.resize("heap", brk_start, new_end) {
+        return Ok(proc.brk);
+    }
+    proc.brk = addr;
+    Ok(addr)
+}
+
+/// Write back the dirty pages of shared file mappings in the range
+pub fn sys_msync(addr: usize, len: usize, flags: usize) -> SysResult {
+    info!(
+        "msync: addr={:#x}, size={:#x}, flags={:#x}",
+        addr, len, flags
+    );
+    if addr % PAGE_SIZE != 0 {
+        return Err(SysError::EINVAL);
+    }
+    if len == 0 {
+        return Ok(0);
+    }
+    let mut proc = process();
+    let end = addr.checked_add(len).ok_or(SysError::ENOMEM)?;
+    if !proc.vm.iter().any(|area| area.is_overlap_with(addr, end)) {
+        return Err(SysError::ENOMEM);
+    }
+    proc.vm.sync(addr, end);
+    Ok(0)
+}
+
+pub fn sys_swapon(path: *const u8, flags: usize) -> SysResult {
+    let proc = process();
+    let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
+    info!("swapon: path: {:?}, flags: {:#x}", path, flags);
+    if !proc.cred.is_root() {
+        return Err(SysError::EPERM);
+    }
+    let inode = proc.lookup_inode(&path)?;
+    let (space, size) = swap_space(inode)?;
+    if let swap::SwapSpace::Partition { device, .. } = &space {
+        // it would overwrite the file system
+        if is_mounted(device) {
+            return Err(SysError::EBUSY);
+        }
+    }
+    swap::swapon(space, size / PAGE_SIZE).map_err(|_| SysError::EBUSY)?;
+    Ok(0)
+}
+
+pub fn sys_swapoff(path: *const u8) -> SysResult {
+    let proc = process();
+    let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
+    info!("swapoff: path: {:?}", path);
+    if !proc.cred.is_root() {
+        return Err(SysError::EPERM);
+    }
+    let inode = proc.lookup_inode(&path)?;
+    let (space, _) = swap_space(inode)?;
+    swap::swapoff(&space).map_err(|_| SysError::EINVAL)?;
+    Ok(0)
+}
+
+/// The swap space on a regular file or a whole block device, and its size in bytes.
+/// A device whose size the driver does not know can not be used.
+fn swap_space(inode: Arc<INode>) -> Result<(swap::SwapSpace, usize), SysError> {
+    let info = inode.metadata()?;
+    match info.type_ {
+        FileType::File => Ok((swap::SwapSpace::File(inode), info.size)),
+        FileType::BlockDevice => {
+            let device = inner_inode(&inode)
+                .as_any_ref()
+                .downcast_ref::<DevINode>()
+                .and_then(|dev| dev.block_device())
+                .ok_or(SysError::ENXIO)?;
+            let size = device.size().ok_or(SysError::EINVAL)?;
+            let space = swap::SwapSpace::Partition { device, start: 0 };
+            Ok((space, size))
+        }
+        _ => Err(SysError::EINVAL),
+    }
+}
+
 bitflags! {
     pub struct MmapProt: usize {
         /// Data cannot be accessed
@@ -153,12 +267,17 @@ bitflags! {
 
 impl MmapProt {
     fn to_attr(self) -> MemoryAttr {
-        let mut attr = MemoryAttr::default().user();
+        let mut attr = MemoryAttr::default();
+        // PROT_NONE pages can not be accessed by the user
+        if self != MmapProt::NONE {
+            attr = attr.user();
+        }
         if self.contains(MmapProt::EXEC) {
             attr = attr.execute();
         }
-        // FIXME: see sys_mprotect
-        //        if !self.contains(MmapProt::WRITE) { attr = attr.readonly(); }
+        if !self.contains(MmapProt::WRITE) {
+            attr = attr.readonly();
+        }
         attr
     }
 }
@@ -1,8 +1,10 @@
 use super::*;
 use crate::arch::cpu;
 use crate::consts::USER_STACK_SIZE;
+use crate::process::signal::has_signal_to_handle;
 use core::mem::size_of;
 use core::sync::atomic::{AtomicI32, Ordering};
+use rcore_memory::PAGE_SIZE;
 
 pub fn sys_arch_prctl(code: i32, addr: usize, tf: &mut TrapFrame) -> SysResult {
     const ARCH_SET_FS: i32 = 0x1002;
@@ -53,7 +55,17 @@ pub fn sys_sysinfo(sys_info: *mut SysInfo) -> SysResult {
     let proc = process();
     proc.vm.check_write_ptr(sys_info)?;
 
-    let sysinfo = SysInfo::default();
+    let (totalswap, freeswap) = crate::swap::swap_info();
+    let (total_frames, free_frames) = crate::memory::FRAME_ALLOCATOR.lock().count();
+    let sysinfo = SysInfo {
+        uptime: (crate::trap::uptime_msec() / 1000) as u64,
+        totalram: (total_frames * PAGE_SIZE) as u64,
+        freeram: (free_frames * PAGE_SIZE) as u64,
+        totalswap: totalswap as u64,
+        freeswap: freeswap as u64,
+        mem_unit: 1,
+        ..SysInfo::default()
+    };
     unsafe { *sys_info = sysinfo };
     Ok(0)
 }
@@ -96,6 +108,9 @@ pub fn sys_futex(uaddr: usize, op: u32, val: i32, timeout: *const TimeSpec) -> S
             }
             // FIXME: support timeout
             queue._wait();
+            if has_signal_to_handle() {
+                return Err(SysError::EINTR);
+            }
             Ok(0)
         }
         OP_WAKE => {
@@ -10,8 +10,10 @@ use rcore_memory::VMError;
 use crate::arch::cpu;
 use crate::arch::interrupt::TrapFrame;
 use crate::arch::syscall::*;
+use crate::fs::EpollEvent;
+use crate::process::signal::{SigInfo, Sigset};
 use crate::process::*;
-use crate::sync::Condvar;
+use crate::sync::{Condvar, WaitAny};
 use crate::thread;
 use crate::util;
 
@@ -20,7 +22,9 @@ use self::fs::*;
 use self::mem::*;
 use self::misc::*;
 pub use self::net::*;
-use self::proc::*;
+pub use self::proc::*;
+use self::signal::*;
+pub use self::time::TimeSpec;
 use self::time::*;
 
 mod custom;
@@ -29,6 +33,7 @@ mod mem;
 mod misc;
 mod net;
 mod proc;
+mod signal;
 mod time;
 
 /// System call dispatcher
@@ -58,18 +63,20 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
         // 10
         SYS_MPROTECT => sys_mprotect(args[0], args[1], args[2]),
         SYS_MUNMAP => sys_munmap(args[0], args[1]),
-        SYS_BRK => {
-            warn!("sys_brk is unimplemented");
-            Ok(0)
-        }
-        SYS_RT_SIGACTION => {
-            warn!("sys_sigaction is unimplemented");
-            Ok(0)
-        }
-        SYS_RT_SIGPROCMASK => {
-            warn!("sys_sigprocmask is unimplemented");
-            Ok(0)
-        }
+        SYS_BRK => sys_brk(args[0]),
+        SYS_RT_SIGACTION => sys_rt_sigaction(
+            args[0],
+            args[1] as *const SigAction,
+            args[2] as *mut SigAction,
+            args[3],
+        ),
+        SYS_RT_SIGPROCMASK => sys_rt_sigprocmask(
+            args[0],
+            args[1] as *const Sigset,
+            args[2] as *mut Sigset,
+            args[3],
+        ),
+        SYS_RT_SIGRETURN => sys_rt_sigreturn(tf),
         SYS_IOCTL => sys_ioctl(args[0], args[1], args[2], args[3], args[4]),
         SYS_PREAD64 => sys_pread(args[0], args[1] as *mut u8, args[2], args[3]),
         SYS_PWRITE64 => sys_pwrite(args[0], args[1] as *const u8, args[2], args[3]),
@@ -77,6 +84,7 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
         // 20
         SYS_WRITEV => sys_writev(args[0], args[1] as *const IoVec, args[2]),
         SYS_SCHED_YIELD => sys_yield(),
+        SYS_MSYNC => sys_msync(args[0], args[1], args[2]),
         SYS_MADVISE => {
             warn!("sys_madvise is unimplemented");
             Ok(0)
@@ -108,7 +116,7 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
             args[4] as *mut SockAddr,
             args[5] as *mut u32,
         ),
-        //        SYS_SENDMSG => sys_sendmsg(),
+        SYS_SENDMSG => sys_sendmsg(args[0], args[1] as *const MsgHdr, args[2]),
         SYS_RECVMSG => sys_recvmsg(args[0], args[1] as *mut MsgHdr, args[2]),
         SYS_SHUTDOWN => sys_shutdown(args[0], args[1]),
         SYS_BIND => sys_bind(args[0], args[1] as *const SockAddr, args[2]),
@@ -116,6 +124,7 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
         SYS_LISTEN => sys_listen(args[0], args[1]),
         SYS_GETSOCKNAME => sys_getsockname(args[0], args[1] as *mut SockAddr, args[2] as *mut u32),
         SYS_GETPEERNAME => sys_getpeername(args[0], args[1] as *mut SockAddr, args[2] as *mut u32),
+        SYS_SOCKETPAIR => sys_socketpair(args[0], args[1], args[2], args[3] as *mut u32),
         SYS_SETSOCKOPT => sys_setsockopt(args[0], args[1], args[2], args[3] as *const u8, args[4]),
         SYS_GETSOCKOPT => sys_getsockopt(
             args[0],
@@ -124,6 +133,7 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
             args[3] as *mut u8,
             args[4] as *mut u32,
         ),
+        #[cfg(target_arch = "x86_64")]
         SYS_CLONE => sys_clone(
             args[0],
             args[1],
@@ -132,6 +142,16 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
             args[4],
             tf,
         ),
+        // the thread pointer comes before the child tid on the other arches
+        #[cfg(not(target_arch = "x86_64"))]
+        SYS_CLONE => sys_clone(
+            args[0],
+            args[1],
+            args[2] as *mut u32,
+            args[4] as *mut u32,
+            args[3],
+            tf,
+        ),
         SYS_EXECVE => sys_exec(
             args[0] as *const u8,
             args[1] as *const *const u8,
@@ -140,13 +160,15 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
         ),
         // 60
         SYS_EXIT => sys_exit(args[0] as usize),
-        SYS_WAIT4 => sys_wait4(args[0] as isize, args[1] as *mut i32), // TODO: wait4
-        SYS_KILL => sys_kill(args[0], args[1]),
+        SYS_WAIT4 => sys_wait4(
+            args[0] as isize,
+            args[1] as *mut i32,
+            args[2],
+            args[3] as *mut RUsage,
+        ),
+        SYS_KILL => sys_kill(args[0] as isize, args[1]),
         SYS_UNAME => sys_uname(args[0] as *mut u8),
-        SYS_FCNTL => {
-            warn!("sys_fcntl is unimplemented");
-            Ok(0)
-        }
+        SYS_FCNTL => sys_fcntl(args[0], args[1], args[2]),
         SYS_FLOCK => {
             warn!("sys_flock is unimplemented");
             Ok(0)
@@ -158,64 +180,41 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
         SYS_GETCWD => sys_getcwd(args[0] as *mut u8, args[1]),
         // 80
         SYS_CHDIR => sys_chdir(args[0] as *const u8),
-        SYS_FCHMOD => {
-            warn!("sys_fchmod is unimplemented");
-            Ok(0)
-        }
-        SYS_FCHOWN => {
-            warn!("sys_fchown is unimplemented");
-            Ok(0)
-        }
-        SYS_UMASK => {
-            warn!("sys_umask is unimplemented");
-            Ok(0o777)
-        }
+        SYS_FCHMOD => sys_fchmod(args[0], args[1]),
+        SYS_FCHOWN => sys_fchown(args[0], args[1], args[2]),
+        SYS_UMASK => sys_umask(args[0]),
         SYS_GETTIMEOFDAY => sys_gettimeofday(args[0] as *mut TimeVal, args[1] as *const u8),
         //        SYS_GETRLIMIT => sys_getrlimit(),
         SYS_GETRUSAGE => sys_getrusage(args[0], args[1] as *mut RUsage),
         SYS_SYSINFO => sys_sysinfo(args[0] as *mut SysInfo),
-        SYS_GETUID => {
-            warn!("sys_getuid is unimplemented");
-            Ok(0)
-        }
-        SYS_GETGID => {
-            warn!("sys_getgid is unimplemented");
-            Ok(0)
-        }
-        SYS_SETUID => {
-            warn!("sys_setuid is unimplemented");
-            Ok(0)
-        }
-        SYS_GETEUID => {
-            warn!("sys_geteuid is unimplemented");
-            Ok(0)
-        }
-        SYS_GETEGID => {
-            warn!("sys_getegid is unimplemented");
-            Ok(0)
-        }
-        SYS_SETPGID => {
-            warn!("sys_setpgid is unimplemented");
-            Ok(0)
-        }
+        SYS_GETUID => sys_getuid(),
+        SYS_GETGID => sys_getgid(),
+        SYS_SETUID => sys_setuid(args[0]),
+        SYS_SETGID => sys_setgid(args[0]),
+        SYS_GETEUID => sys_geteuid(),
+        SYS_GETEGID => sys_getegid(),
+        SYS_SETPGID => sys_setpgid(args[0] as isize, args[1] as isize),
         // 110
         SYS_GETPPID => sys_getppid(),
-        SYS_SETSID => {
-            warn!("sys_setsid is unimplemented");
-            Ok(0)
-        }
-        SYS_GETPGID => {
-            warn!("sys_getpgid is unimplemented");
-            Ok(0)
-        }
-        SYS_GETGROUPS => {
-            warn!("sys_getgroups is unimplemented");
-            Ok(0)
-        }
-        SYS_SETGROUPS => {
-            warn!("sys_setgroups is unimplemented");
-            Ok(0)
-        }
+        SYS_SETSID => sys_setsid(),
+        SYS_SETREUID => sys_setreuid(args[0], args[1]),
+        SYS_SETREGID => sys_setregid(args[0], args[1]),
+        SYS_GETGROUPS => sys_getgroups(args[0], args[1] as *mut u32),
+        SYS_SETGROUPS => sys_setgroups(args[0], args[1] as *const u32),
+        SYS_SETRESUID => sys_setresuid(args[0], args[1], args[2]),
+        SYS_GETRESUID => sys_getresuid(
+            args[0] as *mut u32,
+            args[1] as *mut u32,
+            args[2] as *mut u32,
+        ),
+        SYS_SETRESGID => sys_setresgid(args[0], args[1], args[2]),
+        SYS_GETRESGID => sys_getresgid(
+            args[0] as *mut u32,
+            args[1] as *mut u32,
+            args[2] as *mut u32,
+        ),
+        SYS_GETPGID => sys_getpgid(args[0]),
+        SYS_GETSID => sys_getsid(args[0]),
         SYS_SIGALTSTACK => {
             warn!("sys_sigaltstack is unimplemented");
             Ok(0)
@@ -229,20 +228,22 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
             Err(SysError::EACCES)
         }
         SYS_SETPRIORITY => sys_set_priority(args[0]),
+        SYS_SWAPON => sys_swapon(args[0] as *const u8, args[1]),
+        SYS_SWAPOFF => sys_swapoff(args[0] as *const u8),
         SYS_PRCTL => {
             warn!("prctl is unimplemented");
             Ok(0)
         }
         //        SYS_SETRLIMIT => sys_setrlimit(),
         SYS_SYNC => sys_sync(),
-        SYS_MOUNT => {
-            warn!("mount is unimplemented");
-            Err(SysError::EACCES)
-        }
-        SYS_UMOUNT2 => {
-            warn!("umount2 is unimplemented");
-            Err(SysError::EACCES)
-        }
+        SYS_MOUNT => sys_mount(
+            args[0] as *const u8,
+            args[1] as *const u8,
+            args[2] as *const u8,
+            args[3],
+            args[4] as *const u8,
+        ),
+        SYS_UMOUNT2 => sys_umount2(args[0] as *const u8, args[1]),
         SYS_REBOOT => sys_reboot(
             args[0] as u32,
             args[1] as u32,
@@ -250,6 +251,7 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
             args[3] as *const u8,
         ),
         SYS_GETTID => sys_gettid(),
+        SYS_TKILL => sys_tkill(args[0], args[1]),
         SYS_FUTEX => sys_futex(
             args[0],
             args[1] as u32,
@@ -264,14 +266,20 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
         }
         SYS_CLOCK_GETTIME => sys_clock_gettime(args[0], args[1] as *mut TimeSpec),
         SYS_EXIT_GROUP => sys_exit_group(args[0]),
+        SYS_TGKILL => sys_tgkill(args[0], args[1], args[2]),
+        SYS_WAITID => sys_waitid(
+            args[0],
+            args[1],
+            args[2] as *mut SigInfo,
+            args[3],
+            args[4] as *mut RUsage,
+        ),
+        SYS_EPOLL_CTL => sys_epoll_ctl(args[0], args[1], args[2], args[3] as *const EpollEvent),
         SYS_OPENAT => sys_openat(args[0], args[1] as *const u8, args[2], args[3]),
         SYS_MKDIRAT => sys_mkdirat(args[0], args[1] as *const u8, args[2]),
-        //        SYS_MKNODAT => sys_mknod(),
+        SYS_MKNODAT => sys_mknodat(args[0], args[1] as *const u8, args[2], args[3]),
         // 260
-        SYS_FCHOWNAT => {
-            warn!("sys_fchownat is unimplemented");
-            Ok(0)
-        }
+        SYS_FCHOWNAT => sys_fchownat(args[0], args[1] as *const u8, args[2], args[3], args[4]),
         SYS_NEWFSTATAT => sys_fstatat(args[0], args[1] as *const u8, args[2] as *mut Stat, args[3]),
         SYS_UNLINKAT => sys_unlinkat(args[0], args[1] as *const u8, args[2]),
         SYS_RENAMEAT => sys_renameat(args[0], args[1] as *const u8, args[2], args[3] as *const u8),
@@ -282,14 +290,11 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
             args[3] as *const u8,
             args[4],
         ),
-        SYS_SYMLINKAT => Err(SysError::EACCES),
+        SYS_SYMLINKAT => sys_symlinkat(args[0] as *const u8, args[1], args[2] as *const u8),
         SYS_READLINKAT => {
             sys_readlinkat(args[0], args[1] as *const u8, args[2] as *mut u8, args[3])
         }
-        SYS_FCHMODAT => {
-            warn!("sys_fchmodat is unimplemented");
-            Ok(0)
-        }
+        SYS_FCHMODAT => sys_fchmodat(args[0], args[1] as *const u8, args[2], args[3]),
         SYS_FACCESSAT => sys_faccessat(args[0], args[1] as *const u8, args[2], args[3]),
         SYS_PPOLL => sys_ppoll(args[0] as *mut PollFd, args[1], args[2] as *const TimeSpec), // ignore sigmask
         // 280
@@ -297,13 +302,23 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
             warn!("sys_utimensat is unimplemented");
             Ok(0)
         }
-        SYS_ACCEPT4 => sys_accept(args[0], args[1] as *mut SockAddr, args[2] as *mut u32), // use accept for accept4
-        SYS_EPOLL_CREATE1 => {
-            warn!("sys_epoll_create1 is unimplemented");
-            Err(SysError::ENOSYS)
-        }
-        SYS_DUP3 => sys_dup2(args[0], args[1]), // TODO: handle `flags`
-        SYS_PIPE2 => sys_pipe(args[0] as *mut u32), // TODO: handle `flags`
+        SYS_EPOLL_PWAIT => sys_epoll_pwait(
+            args[0],
+            args[1] as *mut EpollEvent,
+            args[2],
+            args[3],
+            args[4] as *const Sigset,
+            args[5],
+        ),
+        SYS_ACCEPT4 => sys_accept4(
+            args[0],
+            args[1] as *mut SockAddr,
+            args[2] as *mut u32,
+            args[3],
+        ),
+        SYS_EPOLL_CREATE1 => sys_epoll_create1(args[0]),
+        SYS_DUP3 => sys_dup3(args[0], args[1], args[2]),
+        SYS_PIPE2 => sys_pipe2(args[0] as *mut u32, args[1]),
         SYS_PRLIMIT64 => sys_prlimit64(
             args[0],
             args[1],
@@ -334,6 +349,8 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
             }
         }
     };
+    crate::fs::tty::send_typed_signals();
+    crate::fs::write_back_unmapped();
     if !pid.is_init() {
         // we trust pid 0 process
         debug!(
@@ -351,6 +368,17 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
 fn mips_syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> Option<SysResult> {
     let ret = match id {
         SYS_OPEN => sys_open(args[0] as *const u8, args[1], args[2]),
+        SYS_WAITPID => sys_wait4(
+            args[0] as isize,
+            args[1] as *mut i32,
+            args[2],
+            0 as *mut RUsage,
+        ),
+        SYS_MKNOD => sys_mknod(args[0] as *const u8, args[1], args[2]),
+        SYS_SYMLINK => sys_symlink(args[0] as *const u8, args[1] as *const u8),
+        SYS_CHMOD => sys_chmod(args[0] as *const u8, args[1]),
+        SYS_CHOWN => sys_chown(args[0] as *const u8, args[1], args[2]),
+        SYS_LCHOWN => sys_lchown(args[0] as *const u8, args[1], args[2]),
         SYS_POLL => sys_poll(args[0] as *mut PollFd, args[1], args[2]),
         SYS_DUP2 => sys_dup2(args[0], args[1]),
         SYS_FORK => sys_fork(tf),
@@ -371,18 +399,10 @@ fn mips_syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> Option<SysRe
                 Err(err) => Err(err),
             }
         }
-        SYS_GETPGID => {
-            warn!("sys_getpgid is unimplemented");
-            Ok(0)
-        }
-        SYS_SETPGID => {
-            warn!("sys_setpgid is unimplemented");
-            Ok(0)
-        }
-        SYS_FCNTL64 => {
-            warn!("sys_fcntl64 is unimplemented");
-            Ok(0)
-        }
+        SYS_GETPGRP => sys_getpgrp(),
+        SYS_FCNTL64 => sys_fcntl(args[0], args[1], args[2]),
+        SYS_EPOLL_CREATE => sys_epoll_create(args[0]),
+        SYS_EPOLL_WAIT => sys_epoll_wait(args[0], args[1] as *mut EpollEvent, args[2], args[3]),
         SYS_SET_THREAD_AREA => {
             info!("set_thread_area: tls: 0x{:x}", args[0]);
             extern "C" {
@@ -424,29 +444,24 @@ fn x86_64_syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> Option<Sys
             Ok(0)
         }
         SYS_FORK => sys_fork(tf),
-        // use fork for vfork
-        SYS_VFORK => sys_fork(tf),
+        SYS_VFORK => sys_vfork(tf),
         SYS_RENAME => sys_rename(args[0] as *const u8, args[1] as *const u8),
         SYS_MKDIR => sys_mkdir(args[0] as *const u8, args[1]),
         SYS_RMDIR => sys_rmdir(args[0] as *const u8),
         SYS_LINK => sys_link(args[0] as *const u8, args[1] as *const u8),
         SYS_UNLINK => sys_unlink(args[0] as *const u8),
+        SYS_SYMLINK => sys_symlink(args[0] as *const u8, args[1] as *const u8),
         SYS_READLINK => sys_readlink(args[0] as *const u8, args[1] as *mut u8, args[2]),
         // 90
-        SYS_CHMOD => {
-            warn!("sys_chmod is unimplemented");
-            Ok(0)
-        }
-        SYS_CHOWN => {
-            warn!("sys_chown is unimplemented");
-            Ok(0)
-        }
+        SYS_CHMOD => sys_chmod(args[0] as *const u8, args[1]),
+        SYS_CHOWN => sys_chown(args[0] as *const u8, args[1], args[2]),
+        SYS_LCHOWN => sys_lchown(args[0] as *const u8, args[1], args[2]),
+        SYS_GETPGRP => sys_getpgrp(),
+        SYS_MKNOD => sys_mknod(args[0] as *const u8, args[1], args[2]),
         SYS_ARCH_PRCTL => sys_arch_prctl(args[0] as i32, args[1], tf),
         SYS_TIME => sys_time(args[0] as *mut u64),
-        SYS_EPOLL_CREATE => {
-            warn!("sys_epoll_create is unimplemented");
-            Err(SysError::ENOSYS)
-        }
+        SYS_EPOLL_CREATE => sys_epoll_create(args[0]),
+        SYS_EPOLL_WAIT => sys_epoll_wait(args[0], args[1] as *mut EpollEvent, args[2], args[3]),
         _ => {
             return None;
         }
@@ -500,14 +515,22 @@ pub enum SysError {
     ENOLCK = 37,
     ENOSYS = 38,
     ENOTEMPTY = 39,
+    ELOOP = 40,
     ENOTSOCK = 80,
+    EMSGSIZE = 90,
+    EPROTOTYPE = 91,
     ENOPROTOOPT = 92,
+    EOPNOTSUPP = 95,
     EPFNOSUPPORT = 96,
     EAFNOSUPPORT = 97,
+    EADDRINUSE = 98,
     ENOBUFS = 105,
     EISCONN = 106,
     ENOTCONN = 107,
+    ETIMEDOUT = 110,
     ECONNREFUSED = 111,
+    EALREADY = 114,
+    EINPROGRESS = 115,
 }
 
 #[allow(non_snake_case)]
@@ -557,14 +580,22 @@ impl fmt::Display for SysError {
                 ENOLCK => "No record locks available",
                 ENOSYS => "Function not implemented",
                 ENOTEMPTY => "Directory not empty",
+                ELOOP => "Too many levels of symbolic links",
                 ENOTSOCK => "Socket operation on non-socket",
+                EMSGSIZE => "Message too long",
+                EPROTOTYPE => "Protocol wrong type for socket",
                 ENOPROTOOPT => "Protocol not available",
+                EOPNOTSUPP => "Operation not supported on transport endpoint",
                 EPFNOSUPPORT => "Protocol family not supported",
                 EAFNOSUPPORT => "Address family not supported by protocol",
+                EADDRINUSE => "Address already in use",
                 ENOBUFS => "No buffer space available",
                 EISCONN => "Transport endpoint is already connected",
                 ENOTCONN => "Transport endpoint is not connected",
+                ETIMEDOUT => "Connection timed out",
                 ECONNREFUSED => "Connection refused",
+                EALREADY => "Operation already in progress",
+                EINPROGRESS => "Operation now in progress",
                 _ => "Unknown error",
             },
         )
@@ -579,16 +610,19 @@ impl From<VMError> for SysError {
 
 const SPIN_WAIT_TIMES: usize = 100;
 
-pub fn spin_and_wait<T>(condvars: &[&Condvar], mut action: impl FnMut() -> Option<T>) -> T {
+pub fn spin_and_wait<T>(condvars: &[Arc<Condvar>], mut action: impl FnMut() -> Option<T>) -> T {
     for _i in 0..SPIN_WAIT_TIMES {
         if let Some(result) = action() {
             return result;
         }
     }
     loop {
+        // join the queues before trying, so that the notification is not missed
+        let mut waiter = WaitAny::new();
+        waiter.extend(condvars.iter().cloned());
         if let Some(result) = action() {
             return result;
         }
-        Condvar::wait_any(&condvars);
+        waiter.wait(0);
     }
 }
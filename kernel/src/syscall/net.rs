@@ -1,21 +1,23 @@
 //! Syscalls for networking
 
-use super::fs::IoVecs;
+use super::fs::{wait_for_file, wait_for_readiness, IoVecs};
 use super::*;
-use crate::drivers::SOCKET_ACTIVITY;
 use crate::fs::FileLike;
 use crate::net::{
-    Endpoint, LinkLevelEndpoint, NetlinkEndpoint, NetlinkSocketState, PacketSocketState,
-    RawSocketState, Socket, TcpSocketState, UdpSocketState, SOCKETS,
+    Ancillary, Endpoint, LinkLevelEndpoint, NetlinkEndpoint, NetlinkSocketState, PacketSocketState,
+    RawSocketState, Socket, SocketStatus, TcpSocketState, UCred, UdpSocketState, UnixEndpoint,
+    UnixSocketState, SOCKETS,
 };
-use crate::sync::{MutexGuard, SpinNoIrq, SpinNoIrqLock as Mutex};
+use crate::sync::{MutexGuard, SpinNoIrq, SpinNoIrqLock as Mutex, WaitAny};
 use alloc::boxed::Box;
 use core::cmp::min;
 use core::mem::size_of;
 use smoltcp::wire::*;
+use spin::RwLock;
 
 pub fn sys_socket(domain: usize, socket_type: usize, protocol: usize) -> SysResult {
     let domain = AddressFamily::from(domain as u16);
+    let socket_type_flags = socket_type;
     let socket_type = SocketType::from(socket_type as u8 & SOCK_TYPE_MASK);
     info!(
         "socket: domain: {:?}, socket_type: {:?}, protocol: {}",
@@ -23,12 +25,18 @@ pub fn sys_socket(domain: usize, socket_type: usize, protocol: usize) -> SysResu
     );
     let mut proc = process();
     let socket: Box<dyn Socket> = match domain {
-        AddressFamily::Internet | AddressFamily::Unix => match socket_type {
+        AddressFamily::Internet => match socket_type {
             SocketType::Stream => Box::new(TcpSocketState::new()),
             SocketType::Datagram => Box::new(UdpSocketState::new()),
             SocketType::Raw => Box::new(RawSocketState::new(protocol as u8)),
             _ => return Err(SysError::EINVAL),
         },
+        AddressFamily::Unix => match socket_type {
+            SocketType::Stream | SocketType::Datagram | SocketType::SeqPacket => {
+                Box::new(UnixSocketState::new(socket_type))
+            }
+            _ => return Err(SysError::EINVAL),
+        },
         AddressFamily::Packet => match socket_type {
             SocketType::Raw => Box::new(PacketSocketState::new()),
             _ => return Err(SysError::EINVAL),
@@ -39,11 +47,44 @@ pub fn sys_socket(domain: usize, socket_type: usize, protocol: usize) -> SysResu
         },
         _ => return Err(SysError::EAFNOSUPPORT),
     };
-    let fd = proc.get_free_fd();
-    proc.files.insert(fd, FileLike::Socket(socket));
+    let fd = proc.add_socket(socket, socket_type_flags);
     Ok(fd)
 }
 
+pub fn sys_socketpair(
+    domain: usize,
+    socket_type: usize,
+    protocol: usize,
+    sv: *mut u32,
+) -> SysResult {
+    let domain = AddressFamily::from(domain as u16);
+    let socket_type_flags = socket_type;
+    let socket_type = SocketType::from(socket_type as u8 & SOCK_TYPE_MASK);
+    info!(
+        "socketpair: domain: {:?}, socket_type: {:?}, protocol: {}, sv: {:?}",
+        domain, socket_type, protocol, sv
+    );
+    let mut proc = process();
+    proc.vm.check_write_array(sv, 2)?;
+    match domain {
+        AddressFamily::Unix => {}
+        AddressFamily::Unknown(_) => return Err(SysError::EAFNOSUPPORT),
+        _ => return Err(SysError::EOPNOTSUPP),
+    }
+    match socket_type {
+        SocketType::Stream | SocketType::Datagram | SocketType::SeqPacket => {}
+        _ => return Err(SysError::EINVAL),
+    }
+    let (socket0, socket1) = UnixSocketState::new_pair(socket_type);
+    let fd0 = proc.add_socket(Box::new(socket0), socket_type_flags);
+    let fd1 = proc.add_socket(Box::new(socket1), socket_type_flags);
+    unsafe {
+        *sv = fd0 as u32;
+        *sv.add(1) = fd1 as u32;
+    }
+    Ok(0)
+}
+
 pub fn sys_setsockopt(
     fd: usize,
     level: usize,
@@ -58,6 +99,18 @@ pub fn sys_setsockopt(
     let mut proc = process();
     proc.vm.check_read_array(optval, optlen)?;
     let data = unsafe { slice::from_raw_parts(optval, optlen) };
+    if level == SOL_SOCKET && (optname == SO_RCVTIMEO || optname == SO_SNDTIMEO) {
+        if optlen < size_of::<TimeVal>() {
+            return Err(SysError::EINVAL);
+        }
+        let timeout = unsafe { *(optval as *const TimeVal) }.to_msec() as usize;
+        let mut status = proc.get_socket_status(fd)?.write();
+        match optname {
+            SO_RCVTIMEO => status.recv_timeout = timeout,
+            _ => status.send_timeout = timeout,
+        }
+        return Ok(0);
+    }
     let socket = proc.get_socket(fd)?;
     socket.setsockopt(level, optname, data)
 }
@@ -73,10 +126,38 @@ pub fn sys_getsockopt(
         "getsockopt: fd: {}, level: {}, optname: {} optval: {:?} optlen: {:?}",
         fd, level, optname, optval, optlen
     );
-    let proc = process();
+    let mut proc = process();
     proc.vm.check_write_ptr(optlen)?;
     match level {
         SOL_SOCKET => match optname {
+            SO_ERROR => {
+                proc.vm.check_write_array(optval, 4)?;
+                let (_, write, error) = proc.get_socket(fd)?.poll();
+                // a connection which is not established has been refused
+                let code = if error && !write {
+                    SysError::ECONNREFUSED as u32
+                } else {
+                    0
+                };
+                unsafe {
+                    *(optval as *mut u32) = code;
+                    *optlen = 4;
+                }
+                Ok(0)
+            }
+            SO_RCVTIMEO | SO_SNDTIMEO => {
+                proc.vm.check_write_array(optval, size_of::<TimeVal>())?;
+                let status = proc.get_socket_status(fd)?.read();
+                let timeout = match optname {
+                    SO_RCVTIMEO => status.recv_timeout,
+                    _ => status.send_timeout,
+                };
+                unsafe {
+                    *(optval as *mut TimeVal) = TimeVal::from_msec(timeout as u64);
+                    *optlen = size_of::<TimeVal>() as u32;
+                }
+                Ok(0)
+            }
             SO_SNDBUF => {
                 proc.vm.check_write_array(optval, 4)?;
                 unsafe {
@@ -111,22 +192,59 @@ pub fn sys_connect(fd: usize, addr: *const SockAddr, addr_len: usize) -> SysResu
 
     let mut proc = process();
     let endpoint = sockaddr_to_endpoint(&mut proc, addr, addr_len)?;
-    let socket = proc.get_socket(fd)?;
-    socket.connect(endpoint)?;
-    Ok(0)
+    let nonblock = proc.get_file_like(fd)?.nonblock();
+    let mut waiter = None;
+    loop {
+        let socket = proc.get_socket(fd)?;
+        match socket.connect(endpoint.clone()) {
+            // the backlog of the listening local domain socket is full
+            Err(SysError::EAGAIN) if !nonblock => {}
+            Err(SysError::EINPROGRESS) => break,
+            result => return result.map(|_| 0),
+        }
+        // join the queues and connect again, so that the notification is not missed
+        match waiter.take() {
+            None => {
+                let mut new_waiter = WaitAny::new();
+                new_waiter.extend(socket.wait_queues());
+                waiter = Some(new_waiter);
+            }
+            Some(waiter) => {
+                drop(proc);
+                wait_for_readiness(waiter, None)?;
+                proc = process();
+            }
+        }
+    }
+    drop(proc);
+    if nonblock {
+        return Err(SysError::EINPROGRESS);
+    }
+
+    // connected once it becomes writable
+    match wait_for_file(fd, true, false) {
+        Err(SysError::EAGAIN) => return Err(SysError::ETIMEDOUT),
+        result => result?,
+    }
+    let (_, write, _) = process().get_socket(fd)?.poll();
+    if write {
+        Ok(0)
+    } else {
+        Err(SysError::ECONNREFUSED)
+    }
 }
 
 pub fn sys_sendto(
     fd: usize,
     base: *const u8,
     len: usize,
-    _flags: usize,
+    flags: usize,
     addr: *const SockAddr,
     addr_len: usize,
 ) -> SysResult {
     info!(
-        "sys_sendto: fd: {} base: {:?} len: {} addr: {:?} addr_len: {}",
-        fd, base, len, addr, addr_len
+        "sys_sendto: fd: {} base: {:?} len: {} flags: {} addr: {:?} addr_len: {}",
+        fd, base, len, flags, addr, addr_len
     );
 
     let mut proc = process();
@@ -140,8 +258,10 @@ pub fn sys_sendto(
         info!("sys_sendto: sending to endpoint {:?}", endpoint);
         Some(endpoint)
     };
-    let socket = proc.get_socket(fd)?;
-    socket.write(&slice, endpoint)
+    drop(proc);
+    send_all(fd, len, flags & MSG_DONTWAIT != 0, |socket, sent| {
+        socket.write(&slice[sent..], endpoint.clone())
+    })
 }
 
 pub fn sys_recvfrom(
@@ -157,6 +277,7 @@ pub fn sys_recvfrom(
         fd, base, len, flags, addr, addr_len
     );
 
+    wait_for_file(fd, false, flags & MSG_DONTWAIT != 0)?;
     let mut proc = process();
     proc.vm.check_write_array(base, len)?;
 
@@ -174,16 +295,94 @@ pub fn sys_recvfrom(
     result
 }
 
+pub fn sys_sendmsg(fd: usize, msg: *const MsgHdr, flags: usize) -> SysResult {
+    info!("sendmsg: fd: {}, msg: {:?}, flags: {}", fd, msg, flags);
+    let mut proc = process();
+    proc.vm.check_read_ptr(msg)?;
+    let hdr = unsafe { &*msg };
+    let iovs = IoVecs::check_and_new(hdr.msg_iov, hdr.msg_iovlen, &proc.vm, false)?;
+
+    let buf = iovs.read_all_to_vec();
+    let endpoint = if hdr.msg_name.is_null() {
+        None
+    } else {
+        let len = hdr.msg_namelen as usize;
+        Some(sockaddr_to_endpoint(&mut proc, hdr.msg_name, len)?)
+    };
+    let mut ancillary = read_control(&mut proc, hdr.msg_control, hdr.msg_controllen)?;
+    drop(proc);
+    send_all(fd, buf.len(), flags & MSG_DONTWAIT != 0, |socket, sent| {
+        let len = socket.send_msg(&buf[sent..], endpoint.clone(), ancillary.clone())?;
+        // the ancillary data is sent once, with the first part
+        ancillary = Ancillary::default();
+        Ok(len)
+    })
+}
+
+/// Send `len` bytes on the socket `fd` by `send`, which is called with the bytes sent so far
+/// and sends what fits of the rest without waiting. It is called with the process unlocked,
+/// which a local domain socket locks for the credentials of the sender.
+/// Wait until all of them are sent unless the socket is non-blocking or `dont_wait`.
+pub fn send_all(
+    fd: usize,
+    len: usize,
+    dont_wait: bool,
+    mut send: impl FnMut(&dyn Socket, usize) -> SysResult,
+) -> SysResult {
+    let start = crate::trap::uptime_msec();
+    let mut sent = 0;
+    loop {
+        let result = wait_for_file(fd, true, dont_wait).and_then(|_| {
+            let (socket, status) = match process().get_file_like(fd)? {
+                FileLike::Socket(socket, status) => (socket.clone(), status.clone()),
+                _ => return Err(SysError::ENOTSOCK),
+            };
+            let (nonblock, deadline) = {
+                let status = status.read();
+                let deadline = match status.send_timeout {
+                    0 => None,
+                    timeout => Some(start + timeout),
+                };
+                (dont_wait || status.nonblock, deadline)
+            };
+            loop {
+                // join the queues before sending, so that the notification is not missed
+                let mut waiter = WaitAny::new();
+                waiter.extend(socket.wait_queues());
+                // e.g. a datagram does not fit though the socket is writable
+                match send(&*socket, sent) {
+                    Err(SysError::EAGAIN) if !nonblock => {}
+                    result => return result.map(|len| (len, nonblock)),
+                }
+                if !wait_for_readiness(waiter, deadline)? {
+                    return Err(SysError::EAGAIN);
+                }
+            }
+        });
+        match result {
+            Ok((part, nonblock)) => {
+                sent += part;
+                if sent == len || part == 0 || nonblock {
+                    return Ok(sent);
+                }
+            }
+            // report the sent part, like an interrupted write
+            Err(err) => return if sent > 0 { Ok(sent) } else { Err(err) },
+        }
+    }
+}
+
 pub fn sys_recvmsg(fd: usize, msg: *mut MsgHdr, flags: usize) -> SysResult {
     info!("recvmsg: fd: {}, msg: {:?}, flags: {}", fd, msg, flags);
+    wait_for_file(fd, false, flags & MSG_DONTWAIT != 0)?;
     let mut proc = process();
-    proc.vm.check_read_ptr(msg)?;
+    proc.vm.check_write_ptr(msg)?;
     let hdr = unsafe { &mut *msg };
     let mut iovs = IoVecs::check_and_new(hdr.msg_iov, hdr.msg_iovlen, &proc.vm, true)?;
 
     let mut buf = iovs.new_buf(true);
     let socket = proc.get_socket(fd)?;
-    let (result, endpoint) = socket.read(&mut buf);
+    let (result, endpoint, ancillary) = socket.recv_msg(&mut buf);
 
     if let Ok(len) = result {
         // copy data to user
@@ -192,10 +391,127 @@ pub fn sys_recvmsg(fd: usize, msg: *mut MsgHdr, flags: usize) -> SysResult {
         unsafe {
             sockaddr_in.write_to(&mut proc, hdr.msg_name, &mut hdr.msg_namelen as *mut u32)?;
         }
+        let cloexec = flags & MSG_CMSG_CLOEXEC != 0;
+        write_control(&mut proc, hdr, ancillary, cloexec)?;
     }
     result
 }
 
+/// Round up to the alignment of control messages
+fn cmsg_align(len: usize) -> usize {
+    (len + size_of::<usize>() - 1) & !(size_of::<usize>() - 1)
+}
+
+/// Parse the control messages of `sendmsg`
+fn read_control(proc: &mut Process, control: usize, len: usize) -> Result<Ancillary, SysError> {
+    let mut ancillary = Ancillary::default();
+    if control == 0 {
+        return Ok(ancillary);
+    }
+    proc.vm.check_read_array(control as *const u8, len)?;
+    let header_len = size_of::<CMsgHdr>();
+    let mut offset = 0;
+    while offset + header_len <= len {
+        let header = unsafe { &*((control + offset) as *const CMsgHdr) };
+        // `cmsg_len` is a `socklen_t` followed by padding in musl
+        let cmsg_len = header.cmsg_len as u32 as usize;
+        if cmsg_len < header_len || offset + cmsg_len > len {
+            return Err(SysError::EINVAL);
+        }
+        if header.cmsg_level as usize != SOL_SOCKET {
+            return Err(SysError::EINVAL);
+        }
+        let data_addr = (control + offset + header_len) as *const u8;
+        let data = unsafe { slice::from_raw_parts(data_addr, cmsg_len - header_len) };
+        match header.cmsg_type {
+            SCM_RIGHTS => {
+                for fd in data.chunks_exact(size_of::<u32>()) {
+                    let fd = u32::from_ne_bytes([fd[0], fd[1], fd[2], fd[3]]) as usize;
+                    let file = proc.get_file_like(fd)?.clone();
+                    ancillary.rights.push(file);
+                }
+            }
+            SCM_CREDENTIALS => {
+                if data.len() < size_of::<UCred>() {
+                    return Err(SysError::EINVAL);
+                }
+                let cred = unsafe { *(data.as_ptr() as *const UCred) };
+                // the process can only send its own credentials, unless it is root
+                let own = cred.pid as usize == proc.pid.get()
+                    && proc.cred.user.is_one_of(cred.uid as usize)
+                    && proc.cred.group.is_one_of(cred.gid as usize);
+                if !own && !proc.cred.is_root() {
+                    return Err(SysError::EPERM);
+                }
+                ancillary.cred = Some(cred);
+            }
+            _ => return Err(SysError::EINVAL),
+        }
+        offset += cmsg_align(cmsg_len);
+    }
+    Ok(ancillary)
+}
+
+/// Write the control messages of `recvmsg`, installing the received files.
+/// Files that do not fit into the control buffer are closed.
+fn write_control(
+    proc: &mut Process,
+    hdr: &mut MsgHdr,
+    ancillary: Ancillary,
+    cloexec: bool,
+) -> SysResult {
+    let control = hdr.msg_control;
+    let max_len = if control == 0 { 0 } else { hdr.msg_controllen };
+    if max_len > 0 {
+        proc.vm.check_write_array(control as *mut u8, max_len)?;
+    }
+    let header_len = size_of::<CMsgHdr>();
+    let mut written = 0;
+    let mut truncated = false;
+
+    if !ancillary.rights.is_empty() {
+        let space = max_len.saturating_sub(header_len) / size_of::<u32>();
+        let count = min(space, ancillary.rights.len());
+        truncated |= count < ancillary.rights.len();
+        let mut data = Vec::new();
+        for file in ancillary.rights.into_iter().take(count) {
+            let fd = proc.get_free_fd();
+            proc.files.insert(fd, file);
+            proc.set_cloexec(fd, cloexec);
+            data.extend_from_slice(&(fd as u32).to_ne_bytes());
+        }
+        if count > 0 {
+            written += unsafe { write_cmsg(control, SCM_RIGHTS, &data) };
+        }
+    }
+    if let Some(cred) = ancillary.cred {
+        if written + header_len + size_of::<UCred>() <= max_len {
+            let data = unsafe {
+                slice::from_raw_parts(&cred as *const UCred as *const u8, size_of::<UCred>())
+            };
+            written += unsafe { write_cmsg(control + written, SCM_CREDENTIALS, data) };
+        } else {
+            truncated = true;
+        }
+    }
+    hdr.msg_controllen = min(written, max_len);
+    hdr.msg_flags = if truncated { MSG_CTRUNC } else { 0 };
+    Ok(0)
+}
+
+/// Write a control message of `SOL_SOCKET` at `addr`, return its aligned length
+unsafe fn write_cmsg(addr: usize, cmsg_type: i32, data: &[u8]) -> usize {
+    let header_len = size_of::<CMsgHdr>();
+    *(addr as *mut CMsgHdr) = CMsgHdr {
+        cmsg_len: header_len + data.len(),
+        cmsg_level: SOL_SOCKET as i32,
+        cmsg_type,
+    };
+    let target = slice::from_raw_parts_mut((addr + header_len) as *mut u8, data.len());
+    target.copy_from_slice(data);
+    cmsg_align(header_len + data.len())
+}
+
 pub fn sys_bind(fd: usize, addr: *const SockAddr, addr_len: usize) -> SysResult {
     info!("sys_bind: fd: {} addr: {:?} len: {}", fd, addr, addr_len);
     let mut proc = process();
@@ -226,10 +542,19 @@ pub fn sys_shutdown(fd: usize, how: usize) -> SysResult {
 }
 
 pub fn sys_accept(fd: usize, addr: *mut SockAddr, addr_len: *mut u32) -> SysResult {
+    sys_accept4(fd, addr, addr_len, 0)
+}
+
+pub fn sys_accept4(fd: usize, addr: *mut SockAddr, addr_len: *mut u32, flags: usize) -> SysResult {
     info!(
-        "sys_accept: fd: {} addr: {:?} addr_len: {:?}",
-        fd, addr, addr_len
+        "sys_accept4: fd: {} addr: {:?} addr_len: {:?} flags: {:#x}",
+        fd, addr, addr_len, flags
     );
+    if flags & !(SOCK_CLOEXEC | SOCK_NONBLOCK) != 0 {
+        return Err(SysError::EINVAL);
+    }
+    // a connection to accept is readable
+    wait_for_file(fd, false, false)?;
     // smoltcp tcp sockets do not support backlog
     // open multiple sockets for each connection
     let mut proc = process();
@@ -237,8 +562,7 @@ pub fn sys_accept(fd: usize, addr: *mut SockAddr, addr_len: *mut u32) -> SysResu
     let socket = proc.get_socket(fd)?;
     let (new_socket, remote_endpoint) = socket.accept()?;
 
-    let new_fd = proc.get_free_fd();
-    proc.files.insert(new_fd, FileLike::Socket(new_socket));
+    let new_fd = proc.add_socket(new_socket, flags);
 
     if !addr.is_null() {
         let sockaddr_in = SockAddr::from(remote_endpoint);
@@ -296,10 +620,24 @@ pub fn sys_getpeername(fd: usize, addr: *mut SockAddr, addr_len: *mut u32) -> Sy
 impl Process {
     fn get_socket(&mut self, fd: usize) -> Result<&mut Box<dyn Socket>, SysError> {
         match self.get_file_like(fd)? {
-            FileLike::Socket(socket) => Ok(socket),
+            FileLike::Socket(socket, _) => Ok(socket),
+            _ => Err(SysError::EBADF),
+        }
+    }
+    fn get_socket_status(&mut self, fd: usize) -> Result<&RwLock<SocketStatus>, SysError> {
+        match self.get_file_like(fd)? {
+            FileLike::Socket(_, status) => Ok(status),
             _ => Err(SysError::EBADF),
         }
     }
+    /// Add `socket` as a new file descriptor, with `SOCK_NONBLOCK` and `SOCK_CLOEXEC` of `flags`
+    fn add_socket(&mut self, socket: Box<dyn Socket>, flags: usize) -> usize {
+        let fd = self.get_free_fd();
+        let file_like = FileLike::new_socket(socket, flags & SOCK_NONBLOCK != 0);
+        self.files.insert(fd, file_like);
+        self.set_cloexec(fd, flags & SOCK_CLOEXEC != 0);
+        fd
+    }
 }
 
 #[repr(C)]
@@ -383,6 +721,22 @@ impl From<Endpoint> for SockAddr {
                     sll_addr: [0; 8],
                 },
             }
+        } else if let Endpoint::Unix(unix) = endpoint {
+            let mut sun_path = [0; 108];
+            // a path ends with a zero byte and an abstract name starts with one
+            let (start, name, max_len) = match &unix {
+                UnixEndpoint::Unnamed => (0, &[][..], 0),
+                UnixEndpoint::Path(path) => (0, path.as_bytes(), sun_path.len() - 1),
+                UnixEndpoint::Abstract(name) => (1, name.as_slice(), sun_path.len() - 1),
+            };
+            let len = min(name.len(), max_len);
+            sun_path[start..start + len].copy_from_slice(&name[..len]);
+            SockAddr {
+                addr_un: SockAddrUn {
+                    sun_family: AddressFamily::Unix.into(),
+                    sun_path,
+                },
+            }
         } else if let Endpoint::Netlink(netlink) = endpoint {
             SockAddr {
                 addr_nl: SockAddrNl {
@@ -421,7 +775,21 @@ fn sockaddr_to_endpoint(
                 ));
                 Ok(Endpoint::Ip((addr, port).into()))
             }
-            AddressFamily::Unix => Err(SysError::EINVAL),
+            AddressFamily::Unix => {
+                let len = min(len, size_of::<SockAddrUn>());
+                let path = &(*addr).addr_un.sun_path[..len - size_of::<u16>()];
+                if path.is_empty() {
+                    return Err(SysError::EINVAL);
+                }
+                if path[0] == 0 {
+                    let name = path[1..].to_vec();
+                    return Ok(Endpoint::Unix(UnixEndpoint::Abstract(name)));
+                }
+                let path_len = path.iter().position(|&c| c == 0).unwrap_or(path.len());
+                let path = str::from_utf8(&path[..path_len]).map_err(|_| SysError::EINVAL)?;
+                let path = proc.absolute_path(AT_FDCWD, path)?;
+                Ok(Endpoint::Unix(UnixEndpoint::Path(path)))
+            }
             AddressFamily::Packet => {
                 if len < size_of::<SockAddrLl>() {
                     return Err(SysError::EINVAL);
@@ -464,7 +832,15 @@ impl SockAddr {
             AddressFamily::Internet => size_of::<SockAddrIn>(),
             AddressFamily::Packet => size_of::<SockAddrLl>(),
             AddressFamily::Netlink => size_of::<SockAddrNl>(),
-            AddressFamily::Unix => return Err(SysError::EINVAL),
+            AddressFamily::Unix => {
+                let path = &self.addr_un.sun_path;
+                // an abstract name starts with a zero byte and a path ends with one
+                match path.iter().rposition(|&c| c != 0) {
+                    None => size_of::<u16>(),
+                    Some(last) if path[0] == 0 => size_of::<u16>() + last + 1,
+                    Some(last) => size_of::<u16>() + last + 2,
+                }
+            }
             _ => return Err(SysError::EINVAL),
         };
 
@@ -492,6 +868,14 @@ pub struct MsgHdr {
     msg_flags: usize,
 }
 
+/// `struct cmsghdr` of Linux
+#[repr(C)]
+struct CMsgHdr {
+    cmsg_len: usize,
+    cmsg_level: i32,
+    cmsg_type: i32,
+}
+
 enum_with_unknown! {
     /// Address families
     pub doc enum AddressFamily(u16) {
@@ -509,6 +893,11 @@ enum_with_unknown! {
 }
 
 const SOCK_TYPE_MASK: u8 = 0xf;
+#[cfg(not(target_arch = "mips"))]
+const SOCK_NONBLOCK: usize = 0x800;
+#[cfg(target_arch = "mips")]
+const SOCK_NONBLOCK: usize = 0x80;
+const SOCK_CLOEXEC: usize = 0x80000;
 
 enum_with_unknown! {
     /// Socket types
@@ -519,6 +908,8 @@ enum_with_unknown! {
         Datagram = 2,
         /// Raw
         Raw = 3,
+        /// Sequenced packet
+        SeqPacket = 5,
     }
 }
 
@@ -526,10 +917,42 @@ const IPPROTO_IP: usize = 0;
 const IPPROTO_ICMP: usize = 1;
 const IPPROTO_TCP: usize = 6;
 
+#[cfg(not(target_arch = "mips"))]
 const SOL_SOCKET: usize = 1;
+#[cfg(not(target_arch = "mips"))]
 const SO_SNDBUF: usize = 7;
+#[cfg(not(target_arch = "mips"))]
 const SO_RCVBUF: usize = 8;
+#[cfg(not(target_arch = "mips"))]
+const SO_ERROR: usize = 4;
+#[cfg(not(target_arch = "mips"))]
 const SO_LINGER: usize = 13;
+#[cfg(not(target_arch = "mips"))]
+const SO_RCVTIMEO: usize = 20;
+#[cfg(not(target_arch = "mips"))]
+const SO_SNDTIMEO: usize = 21;
+
+#[cfg(target_arch = "mips")]
+const SOL_SOCKET: usize = 0xffff;
+#[cfg(target_arch = "mips")]
+const SO_SNDBUF: usize = 0x1001;
+#[cfg(target_arch = "mips")]
+const SO_RCVBUF: usize = 0x1002;
+#[cfg(target_arch = "mips")]
+const SO_ERROR: usize = 0x1007;
+#[cfg(target_arch = "mips")]
+const SO_LINGER: usize = 0x80;
+#[cfg(target_arch = "mips")]
+const SO_RCVTIMEO: usize = 0x1006;
+#[cfg(target_arch = "mips")]
+const SO_SNDTIMEO: usize = 0x1005;
+
+const SCM_RIGHTS: i32 = 1;
+const SCM_CREDENTIALS: i32 = 2;
+
+const MSG_CTRUNC: usize = 8;
+const MSG_DONTWAIT: usize = 0x40;
+const MSG_CMSG_CLOEXEC: usize = 0x40000000;
 
 const TCP_CONGESTION: usize = 13;
 
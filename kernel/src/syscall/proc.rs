@@ -2,20 +2,45 @@
 
 use super::*;
 use crate::fs::INodeExt;
+use crate::memory::MemorySet;
+use crate::process::cred::{Ids, X_OK};
+use crate::process::signal::*;
+use crate::sync::SpinNoIrqLock as Mutex;
+use core::sync::atomic::Ordering;
 
 /// Fork the current process. Return the child's PID.
 pub fn sys_fork(tf: &TrapFrame) -> SysResult {
-    let new_thread = current_thread().fork(tf);
+    let new_thread = current_thread().fork(tf, None);
     let pid = processor().manager().add(new_thread);
     info!("fork: {} -> {}", thread::current().id(), pid);
     Ok(pid)
 }
 
-/// Create a new thread in the current process.
-/// The new thread's stack pointer will be set to `newsp`,
-/// and thread pointer will be set to `newtls`.
-/// The child tid will be stored at both `parent_tid` and `child_tid`.
-/// This is partially implemented for musl only.
+/// Create a child process which borrows the address space of the current process,
+/// and suspend the current thread until the child execs or exits. Return the child's PID.
+pub fn sys_vfork(tf: &TrapFrame) -> SysResult {
+    let flags = CloneFlags::VM | CloneFlags::VFORK;
+    sys_clone(
+        flags.bits() | SIGCHLD,
+        0,
+        core::ptr::null_mut(),
+        core::ptr::null_mut(),
+        0,
+        tf,
+    )
+}
+
+/// Create a new process, or a new thread in the current process with `CLONE_THREAD`.
+/// The low byte of `flags` is the signal sent to the parent when the child process exits.
+/// The child runs on the user stack `newsp` unless it is 0,
+/// and its thread pointer is set to `newtls` with `CLONE_SETTLS`.
+/// Its tid is stored to `parent_tid` with `CLONE_PARENT_SETTID`, and to `child_tid`
+/// with `CLONE_CHILD_SETTID`, where it is cleared on exit with `CLONE_CHILD_CLEARTID`.
+/// With `CLONE_VFORK`, the current thread is suspended until the child execs or exits,
+/// and the child borrows the address space meanwhile with `CLONE_VM`.
+/// It gets a copy instead if the current process has other threads, which keep using it.
+///
+/// Return the tid of the child, which is its pid if it is a process.
 pub fn sys_clone(
     flags: usize,
     newsp: usize,
@@ -24,94 +49,232 @@ pub fn sys_clone(
     newtls: usize,
     tf: &TrapFrame,
 ) -> SysResult {
-    let clone_flags = CloneFlags::from_bits_truncate(flags);
+    let exit_signal = flags & CloneFlags::CSIGNAL.bits();
+    let clone_flags = CloneFlags::from_bits(flags & !exit_signal).ok_or(SysError::EINVAL)?;
     info!(
         "clone: flags: {:?} == {:#x}, newsp: {:#x}, parent_tid: {:?}, child_tid: {:?}, newtls: {:#x}",
         clone_flags, flags, newsp, parent_tid, child_tid, newtls
     );
-    if flags == 0x4111 || flags == 0x11 {
-        warn!("sys_clone is calling sys_fork instead, ignoring other args");
-        return sys_fork(tf);
-    }
-    if (flags != 0x7d0f00) && (flags != 0x5d0f00) {
-        //0x5d0f00 is the args from gcc of alpine linux
-        //warn!("sys_clone only support musl pthread_create");
-        panic!(
-            "sys_clone only support sys_fork OR musl pthread_create without flags{:x}",
-            flags
-        );
-        //return Err(SysError::ENOSYS);
-    }
-    {
+    check_clone_flags(clone_flags, exit_signal)?;
+    let is_thread = clone_flags.contains(CloneFlags::THREAD);
+    let (grandparent, lend_vm) = {
         let proc = process();
-        proc.vm.check_write_ptr(parent_tid)?;
-        proc.vm.check_write_ptr(child_tid)?;
+        if clone_flags.contains(CloneFlags::PARENT_SETTID) {
+            proc.vm.check_write_ptr(parent_tid)?;
+        }
+        // init can not have siblings
+        if clone_flags.contains(CloneFlags::PARENT) && !is_thread && proc.pid.is_init() {
+            return Err(SysError::EINVAL);
+        }
+        // the address space can not be lent while other threads are using it
+        let lend_vm = clone_flags.contains(CloneFlags::VM) && proc.threads.len() == 1;
+        (proc.parent.clone(), lend_vm)
+    };
+
+    let mut child_tf = tf.clone();
+    if newsp != 0 {
+        child_tf.set_sp(newsp);
+    }
+    let tls = if clone_flags.contains(CloneFlags::SETTLS) {
+        Some(newtls)
+    } else {
+        None
+    };
+    let clear_child_tid = if clone_flags.contains(CloneFlags::CHILD_CLEARTID) {
+        child_tid as usize
+    } else {
+        0
+    };
+    let vfork_done = if clone_flags.contains(CloneFlags::VFORK) {
+        Some(Arc::new(Condvar::new()))
+    } else {
+        None
+    };
+    let mut new_thread = if is_thread {
+        current_thread().clone(&child_tf, tls, clear_child_tid)
+    } else {
+        let mut new_thread = if lend_vm {
+            current_thread().vfork(&child_tf, tls)
+        } else {
+            current_thread().fork(&child_tf, tls)
+        };
+        new_thread.clear_child_tid = clear_child_tid;
+        let mut new_proc = new_thread.proc.lock();
+        new_proc.exit_signal = exit_signal;
+        new_proc.vfork_done = vfork_done.clone();
+        if clone_flags.contains(CloneFlags::PARENT) {
+            new_proc.parent = grandparent;
+        }
+        drop(new_proc);
+        new_thread
+    };
+    if clone_flags.contains(CloneFlags::CHILD_SETTID) {
+        new_thread.set_child_tid = child_tid as usize;
     }
-    let new_thread = current_thread().clone(tf, newsp, newtls, child_tid as usize);
-    // FIXME: parent pid
+    let child = new_thread.proc.clone();
     let tid = processor().manager().add(new_thread);
     info!("clone: {} -> {}", thread::current().id(), tid);
-    unsafe {
-        parent_tid.write(tid as u32);
-        child_tid.write(tid as u32);
+    if let Some(done) = vfork_done {
+        wait_vfork_done(&child, &done);
+    }
+    // written after the address space lent to a vfork child is given back
+    if clone_flags.contains(CloneFlags::PARENT_SETTID) {
+        unsafe { parent_tid.write(tid as u32) };
     }
     Ok(tid)
 }
 
-/// Wait for the process exit.
-/// Return the PID. Store exit code to `wstatus` if it's not null.
-pub fn sys_wait4(pid: isize, wstatus: *mut i32) -> SysResult {
-    info!("wait4: pid: {}, code: {:?}", pid, wstatus);
-    if !wstatus.is_null() {
-        process().vm.check_write_ptr(wstatus)?;
+/// Check that `flags` is a combination which is supported.
+///
+/// A thread shares everything with its process, while a process has a copy of everything.
+/// `CLONE_VM` is allowed for a process only with `CLONE_VFORK`,
+/// when it borrows the address space while its parent is suspended.
+fn check_clone_flags(flags: CloneFlags, exit_signal: usize) -> Result<(), SysError> {
+    let unsupported = CloneFlags::PIDFD
+        | CloneFlags::PTRACE
+        | CloneFlags::NEWNS
+        | CloneFlags::NEWCGROUP
+        | CloneFlags::NEWUTS
+        | CloneFlags::NEWIPC
+        | CloneFlags::NEWUSER
+        | CloneFlags::NEWPID
+        | CloneFlags::NEWNET
+        | CloneFlags::IO;
+    if flags.intersects(unsupported) || exit_signal > NSIG {
+        return Err(SysError::EINVAL);
     }
-    #[derive(Debug)]
-    enum WaitFor {
-        AnyChild,
-        Pid(usize),
+    // the same as Linux
+    if flags.contains(CloneFlags::THREAD) && !flags.contains(CloneFlags::SIGHAND)
+        || flags.contains(CloneFlags::SIGHAND) && !flags.contains(CloneFlags::VM)
+    {
+        return Err(SysError::EINVAL);
     }
-    let target = match pid {
-        -1 | 0 => WaitFor::AnyChild,
-        p if p > 0 => WaitFor::Pid(p as usize),
-        _ => unimplemented!(),
+    let supported = if flags.contains(CloneFlags::THREAD) {
+        flags.contains(CloneFlags::VM | CloneFlags::FS | CloneFlags::FILES)
+            && !flags.contains(CloneFlags::VFORK)
+    } else {
+        !flags.intersects(CloneFlags::FS | CloneFlags::FILES | CloneFlags::SIGHAND)
+            && (!flags.contains(CloneFlags::VM) || flags.contains(CloneFlags::VFORK))
+    };
+    if !supported {
+        return Err(SysError::EINVAL);
+    }
+    Ok(())
+}
+
+/// Suspend the current thread until its vfork `child` execs or exits
+fn wait_vfork_done(child: &Arc<Mutex<Process>>, done: &Condvar) {
+    loop {
+        // join the queue before checking, so that the notification is not missed
+        let queue = done.add_to_wait_queue();
+        if child.lock().vfork_done.is_none() {
+            return;
+        }
+        thread::park_action(move || drop(queue));
+    }
+}
+
+/// Give back the address space `proc` has borrowed, and resume the parent suspended
+/// by its vfork, when it has exited
+fn vfork_release(proc: &Arc<Mutex<Process>>) {
+    let lent = {
+        let mut proc = proc.lock();
+        proc.vm_lender
+            .take()
+            .map(|lender| (lender, core::mem::replace(&mut proc.vm, MemorySet::new())))
+    };
+    if let Some((lender, vm)) = lent {
+        lender.lock().vm = vm;
+    }
+    // the parent resumes only once its address space is given back
+    let done = proc.lock().vfork_done.take();
+    if let Some(done) = done {
+        done.notify_all();
+    }
+}
+
+/// The children to wait for
+#[derive(Debug, Clone, Copy)]
+enum WaitFor {
+    AnyChild,
+    Pid(usize),
+    Pgid(usize),
+}
+
+/// A child which `wait_child` has found to have changed
+struct WaitResult {
+    pid: usize,
+    /// Real user id
+    uid: usize,
+    status: WaitStatus,
+    /// Ticks it has run, including its children it has waited for
+    ticks: usize,
+}
+
+/// Wait for a child in `target` to exit, stop or continue, as selected by `options`.
+/// Return `None` with `NOHANG` if none has.
+/// An exited child is reaped, and a stop or continuation is reported once, unless `NOWAIT`.
+fn wait_child(target: WaitFor, options: WaitOptions) -> Result<Option<WaitResult>, SysError> {
+    let matches = |pid: usize, pgid: usize| match target {
+        WaitFor::AnyChild => true,
+        WaitFor::Pid(target) => pid == target,
+        WaitFor::Pgid(target) => pgid == target,
     };
     loop {
         let mut proc = process();
-        // check child_exit_code
-        let find = match target {
-            WaitFor::AnyChild => proc
-                .child_exit_code
-                .iter()
-                .next()
-                .map(|(&pid, &code)| (pid, code)),
-            WaitFor::Pid(pid) => proc.child_exit_code.get(&pid).map(|&code| (pid, code)),
-        };
-        // if found, return
-        if let Some((pid, exit_code)) = find {
-            proc.child_exit_code.remove(&pid);
-            if !wstatus.is_null() {
-                unsafe {
-                    wstatus.write(exit_code as i32);
-                }
+        let zombie = proc
+            .zombies
+            .iter()
+            .find(|(&pid, zombie)| matches(pid, zombie.pgid))
+            .map(|(&pid, zombie)| (pid, zombie.clone()));
+        let mut has_child = zombie.is_some();
+        if let (Some((pid, zombie)), true) = (zombie, options.contains(WaitOptions::EXITED)) {
+            if !options.contains(WaitOptions::NOWAIT) {
+                proc.zombies.remove(&pid);
+                proc.children_ticks += zombie.ticks;
             }
-            return Ok(pid);
+            return Ok(Some(WaitResult {
+                pid,
+                uid: zombie.uid,
+                status: zombie.status,
+                ticks: zombie.ticks,
+            }));
         }
-        // if not, check pid
         let children: Vec<_> = proc
             .children
             .iter()
             .filter_map(|weak| weak.upgrade())
             .collect();
-        let invalid = match target {
-            WaitFor::AnyChild => children.len() == 0,
-            WaitFor::Pid(pid) => children
-                .iter()
-                .find(|p| p.lock().pid.get() == pid)
-                .is_none(),
-        };
-        if invalid {
+        for child in children.iter() {
+            let mut child = child.lock();
+            if !matches(child.pid.get(), child.pgid) {
+                continue;
+            }
+            has_child = true;
+            let report = match child.state_change {
+                Some(WaitStatus::Stopped(_)) => options.contains(WaitOptions::UNTRACED),
+                Some(WaitStatus::Continued) => options.contains(WaitOptions::CONTINUED),
+                _ => false,
+            };
+            if report {
+                let status = child.state_change.unwrap();
+                if !options.contains(WaitOptions::NOWAIT) {
+                    child.state_change = None;
+                }
+                return Ok(Some(WaitResult {
+                    pid: child.pid.get(),
+                    uid: child.cred.user.real,
+                    status,
+                    ticks: child.ticks.load(Ordering::Relaxed) + child.children_ticks,
+                }));
+            }
+        }
+        if !has_child {
             return Err(SysError::ECHILD);
         }
+        if options.contains(WaitOptions::NOHANG) {
+            return Ok(None);
+        }
         info!(
             "wait: thread {} -> {:?}, sleep",
             thread::current().id(),
@@ -119,10 +282,105 @@ pub fn sys_wait4(pid: isize, wstatus: *mut i32) -> SysResult {
         );
         let condvar = proc.child_exit.clone();
         drop(proc); // must release lock of current process
+        if has_signal_to_handle() {
+            return Err(SysError::EINTR);
+        }
         condvar._wait();
     }
 }
 
+/// Wait for a child to exit, or to stop or continue as selected by `options`.
+///
+/// `pid` > 0: the child `pid`;
+/// `pid` == -1: any child;
+/// `pid` == 0: any child in the process group of the current process;
+/// `pid` < -1: any child in the process group `-pid`.
+///
+/// Return its pid, or 0 with `WNOHANG` if none has changed.
+/// Its status is stored to `wstatus`, and its resource usage to `rusage`, if they are not null.
+pub fn sys_wait4(pid: isize, wstatus: *mut i32, options: usize, rusage: *mut RUsage) -> SysResult {
+    info!(
+        "wait4: pid: {}, wstatus: {:?}, options: {:#x}, rusage: {:?}",
+        pid, wstatus, options, rusage
+    );
+    let options = WaitOptions::from_bits(options).ok_or(SysError::EINVAL)?;
+    if options.intersects(WaitOptions::EXITED | WaitOptions::NOWAIT) {
+        return Err(SysError::EINVAL);
+    }
+    let target = match pid {
+        -1 => WaitFor::AnyChild,
+        0 => WaitFor::Pgid(process().pgid),
+        p if p > 0 => WaitFor::Pid(p as usize),
+        p => WaitFor::Pgid(-p as usize),
+    };
+    let child = match wait_child(target, options | WaitOptions::EXITED)? {
+        Some(child) => child,
+        None => return Ok(0),
+    };
+    let proc = process();
+    if !wstatus.is_null() {
+        proc.vm.check_write_ptr(wstatus)?;
+        unsafe { wstatus.write(child.status.encode()) };
+    }
+    if !rusage.is_null() {
+        proc.vm.check_write_ptr(rusage)?;
+        unsafe { rusage.write(RUsage::from_ticks(child.ticks)) };
+    }
+    Ok(child.pid)
+}
+
+/// `idtype` of `waitid`
+const P_ALL: usize = 0;
+const P_PID: usize = 1;
+const P_PGID: usize = 2;
+
+/// Wait for a child selected by `idtype` and `id` to change as selected by `options`,
+/// which must include at least one of `WEXITED`, `WSTOPPED` and `WCONTINUED`.
+/// The child is described in `infop`, whose `si_pid` is 0 with `WNOHANG` if none has changed.
+pub fn sys_waitid(
+    idtype: usize,
+    id: usize,
+    infop: *mut SigInfo,
+    options: usize,
+    rusage: *mut RUsage,
+) -> SysResult {
+    info!(
+        "waitid: idtype: {}, id: {}, infop: {:?}, options: {:#x}, rusage: {:?}",
+        idtype, id, infop, options, rusage
+    );
+    let options = WaitOptions::from_bits(options).ok_or(SysError::EINVAL)?;
+    if !options.intersects(WaitOptions::EXITED | WaitOptions::UNTRACED | WaitOptions::CONTINUED) {
+        return Err(SysError::EINVAL);
+    }
+    let target = match idtype {
+        P_ALL => WaitFor::AnyChild,
+        P_PID => WaitFor::Pid(id),
+        // the process group of the current process if `id` is 0
+        P_PGID if id == 0 => WaitFor::Pgid(process().pgid),
+        P_PGID => WaitFor::Pgid(id),
+        _ => return Err(SysError::EINVAL),
+    };
+    let child = wait_child(target, options)?;
+    let proc = process();
+    if !infop.is_null() {
+        proc.vm.check_write_ptr(infop)?;
+        unsafe {
+            match child {
+                Some(ref child) => infop.write(SigInfo::child(child.pid, child.uid, child.status)),
+                None => {
+                    (*infop).signo = 0;
+                    (*infop).pid = 0;
+                }
+            }
+        }
+    }
+    if let (Some(child), false) = (child, rusage.is_null()) {
+        proc.vm.check_write_ptr(rusage)?;
+        unsafe { rusage.write(RUsage::from_ticks(child.ticks)) };
+    }
+    Ok(0)
+}
+
 pub fn sys_exec(
     name: *const u8,
     argv: *const *const u8,
@@ -130,7 +388,7 @@ pub fn sys_exec(
     tf: &mut TrapFrame,
 ) -> SysResult {
     info!("exec: name: {:?}, argv: {:?}, envp: {:?}", name, argv, envp);
-    let proc = process();
+    let mut proc = process();
     let exec_name = if name.is_null() {
         String::from("")
     } else {
@@ -178,16 +436,49 @@ pub fn sys_exec(
     //let path = args[0].as_str();
     let exec_path = exec_name.as_str();
     let inode = proc.lookup_inode(exec_path)?;
+    let metadata = inode.metadata()?;
+    if metadata.type_ != FileType::File {
+        return Err(SysError::EACCES);
+    }
+    proc.check_access(&inode, X_OK)?;
     let buf = inode.read_as_vec()?;
 
+    // the other threads can not run the new image, so they are killed as Linux's de_thread does
+    let tid = thread::current().id();
+    let others: Vec<_> = proc.threads.iter().cloned().filter(|&t| t != tid).collect();
+    for other in others {
+        processor().manager().exit(other, 0);
+        proc.thread_sig_pending.remove(&other);
+    }
+    proc.threads = vec![tid];
+
     // Make new Thread
     let mut thread = Thread::new_user(buf.as_slice(), exec_path, args, envs);
-    thread.proc.lock().clone_for_exec(&proc);
+    // the parent suspended by vfork is resumed once it has exec-ed,
+    // with the address space it has lent
+    let vm_lender = proc.vm_lender.take();
+    {
+        let mut new_proc = thread.proc.lock();
+        new_proc.clone_for_exec(&proc);
+        new_proc.cred.exec(&metadata);
+        // the process stays the same one for its parent and its children,
+        // only the contents are replaced
+        ::core::mem::swap(&mut *proc, &mut *new_proc);
+        proc.vfork_done = new_proc.vfork_done.take();
+    }
+    thread.sig_mask = current_thread().sig_mask;
+    thread.ticks = proc.ticks.clone();
+    thread.switched_at = current_thread().switched_at;
 
-    // Activate new page table
+    // Activate new page table, then the old one can be freed
     unsafe {
-        thread.proc.lock().vm.activate();
+        proc.vm.activate();
     }
+    let lent_vm = vm_lender.map(|lender| {
+        let vm = core::mem::replace(&mut thread.proc.lock().vm, MemorySet::new());
+        (lender, vm)
+    });
+    thread.proc = current_thread().proc.clone();
 
     // Modify the TrapFrame
     *tf = unsafe { thread.context.get_init_tf() };
@@ -196,6 +487,15 @@ pub fn sys_exec(
     ::core::mem::swap(&mut current_thread().kstack, &mut thread.kstack);
     ::core::mem::swap(current_thread(), &mut *thread);
 
+    drop(proc);
+    if let Some((lender, vm)) = lent_vm {
+        lender.lock().vm = vm;
+    }
+    // the parent resumes only once its address space is given back
+    let vfork_done = process().vfork_done.take();
+    if let Some(done) = vfork_done {
+        done.notify_all();
+    }
     Ok(0)
 }
 
@@ -204,40 +504,170 @@ pub fn sys_yield() -> SysResult {
     Ok(0)
 }
 
-/// Kill the process
-pub fn sys_kill(pid: usize, sig: usize) -> SysResult {
+/// Send a signal to processes.
+///
+/// `pid` > 0: the process `pid`;
+/// `pid` == 0: the process group of the current process;
+/// `pid` == -1: all processes except init and the current one;
+/// `pid` < -1: the process group `-pid`.
+///
+/// Only the processes it is permitted to signal are signalled.
+pub fn sys_kill(pid: isize, sig: usize) -> SysResult {
     info!(
         "kill: {} killed: {} with sig {}",
         thread::current().id(),
         pid,
         sig
     );
-    let current_pid = process().pid.get().clone();
-    if current_pid == pid {
-        // killing myself
-        sys_exit_group(sig);
-    } else {
-        if let Some(proc_arc) = PROCESSES.read().get(&pid).and_then(|weak| weak.upgrade()) {
-            let proc = proc_arc.lock();
-            // quit all threads
-            for tid in proc.threads.iter() {
-                processor().manager().exit(*tid, sig);
-            }
-            // notify parent and fill exit code
-            // avoid deadlock
-            let proc_parent = proc.parent.clone();
-            let pid = proc.pid.get();
-            drop(proc);
-            if let Some(parent) = proc_parent {
-                let mut parent = parent.lock();
-                parent.child_exit_code.insert(pid, sig);
-                parent.child_exit.notify_one();
-            }
-            Ok(0)
+    if sig > NSIG {
+        return Err(SysError::EINVAL);
+    }
+    let current = current_thread().proc.clone();
+    let cred = current.lock().cred.clone();
+    let targets: Vec<Arc<Mutex<Process>>> = match pid {
+        0 => {
+            let pgid = current.lock().pgid;
+            process_group(pgid)
+        }
+        -1 => PROCESSES
+            .read()
+            .values()
+            .filter_map(|weak| weak.upgrade())
+            .filter(|proc| !Arc::ptr_eq(proc, &current) && !proc.lock().pid.is_init())
+            .collect(),
+        _ if pid < -1 => process_group(pid.wrapping_abs() as usize),
+        _ => {
+            let proc = PROCESSES
+                .read()
+                .get(&(pid as usize))
+                .and_then(|weak| weak.upgrade());
+            vec![proc.ok_or(SysError::ESRCH)?]
+        }
+    };
+    if targets.is_empty() {
+        return Err(SysError::ESRCH);
+    }
+    let targets: Vec<_> = targets
+        .into_iter()
+        .filter(|proc| cred.can_signal(&proc.lock().cred))
+        .collect();
+    if targets.is_empty() {
+        return Err(SysError::EPERM);
+    }
+    if sig == 0 {
+        return Ok(0);
+    }
+    // kill the current process at last
+    let (this, others): (Vec<_>, Vec<_>) = targets
+        .into_iter()
+        .partition(|proc| Arc::ptr_eq(proc, &current));
+    for proc in others.iter().chain(this.iter()) {
+        if sig == SIGKILL {
+            kill_process(proc, sig);
         } else {
-            Err(SysError::EINVAL)
+            send_signal(proc, sig, None);
         }
     }
+    Ok(0)
+}
+
+/// Terminate the process immediately, even if it is blocking in a syscall.
+fn kill_process(proc_arc: &Arc<Mutex<Process>>, sig: usize) {
+    if Arc::ptr_eq(proc_arc, &current_thread().proc) {
+        // killing myself
+        exit_process(WaitStatus::Signaled(sig));
+    }
+    let status = WaitStatus::Signaled(sig);
+    let proc = proc_arc.lock();
+    // quit all threads
+    for tid in proc.threads.iter() {
+        processor().manager().exit(*tid, status.encode() as usize);
+    }
+    drop(proc);
+    process_exited(proc_arc, status);
+}
+
+/// The process has exited with `status`, after all its threads.
+/// It becomes a zombie of its parent, and its children are adopted by init.
+fn process_exited(proc_arc: &Arc<Mutex<Process>>, status: WaitStatus) {
+    let mut proc = proc_arc.lock();
+    let zombie = Zombie {
+        pgid: proc.pgid,
+        uid: proc.cred.user.real,
+        status,
+        ticks: proc.ticks.load(Ordering::Relaxed) + proc.children_ticks,
+    };
+    let pid = proc.pid.get();
+    let parent = proc.parent.clone();
+    let exit_signal = proc.exit_signal;
+    let orphans: Vec<_> = proc
+        .children
+        .drain(..)
+        .filter_map(|weak| weak.upgrade())
+        .collect();
+    // its own zombies are reaped with it
+    proc.zombies.clear();
+    drop(proc);
+    vfork_release(proc_arc);
+    adopt_orphans(proc_arc, orphans);
+    if let Some(parent) = parent {
+        notify_parent_exit(&parent, proc_arc, pid, zombie, exit_signal);
+    }
+}
+
+/// The orphans of the exiting process are adopted by init, which waits for them.
+/// If init itself exits, they are left without a parent to be reaped as they exit.
+fn adopt_orphans(exiting: &Arc<Mutex<Process>>, orphans: Vec<Arc<Mutex<Process>>>) {
+    if orphans.is_empty() {
+        return;
+    }
+    let init = PROCESSES
+        .read()
+        .get(&0)
+        .and_then(|weak| weak.upgrade())
+        .filter(|init| !Arc::ptr_eq(init, exiting));
+    for orphan in orphans.iter() {
+        orphan.lock().parent = init.clone();
+    }
+    if let Some(init) = init {
+        let mut init = init.lock();
+        init.children.extend(orphans.iter().map(Arc::downgrade));
+        // some of them may be stopped
+        init.child_exit.notify_all();
+    }
+}
+
+/// Leave the zombie of `child` to its `parent`, then wake up the parent waiting for it,
+/// and send it `exit_signal` unless it is 0.
+/// It is reaped at once if the parent ignores `SIGCHLD`.
+fn notify_parent_exit(
+    parent: &Arc<Mutex<Process>>,
+    child: &Arc<Mutex<Process>>,
+    pid: usize,
+    zombie: Zombie,
+    exit_signal: usize,
+) {
+    {
+        let mut parent = parent.lock();
+        let index = parent.children.iter().position(|weak| {
+            weak.upgrade()
+                .map_or(false, |proc| Arc::ptr_eq(&proc, child))
+        });
+        // it is not a child any more once its exit is told
+        let index = match index {
+            Some(index) => index,
+            None => return,
+        };
+        parent.children.remove(index);
+        let action = parent.sig_actions[SIGCHLD];
+        if action.handler != SIG_IGN && !action.flags.contains(SignalActionFlags::NOCLDWAIT) {
+            parent.zombies.insert(pid, zombie);
+        }
+        parent.child_exit.notify_all();
+    }
+    if exit_signal != 0 {
+        send_signal(parent, exit_signal, None);
+    }
 }
 
 /// Get the current process id
@@ -262,32 +692,250 @@ pub fn sys_getppid() -> SysResult {
     }
 }
 
+/// The process `pid`, or the current one if `pid` is 0
+fn process_or_current(pid: usize) -> Result<Arc<Mutex<Process>>, SysError> {
+    if pid == 0 {
+        return Ok(current_thread().proc.clone());
+    }
+    let proc = PROCESSES.read().get(&pid).and_then(|weak| weak.upgrade());
+    proc.ok_or(SysError::ESRCH)
+}
+
+/// Set the process group of process `pid` (0 for the current one) to `pgid` (0 for `pid`).
+///
+/// The process must be the current one or a child of it in the same session,
+/// and not a session leader. `pgid` must be `pid` or a group in the session.
+pub fn sys_setpgid(pid: isize, pgid: isize) -> SysResult {
+    info!("setpgid: pid: {}, pgid: {}", pid, pgid);
+    if pid < 0 || pgid < 0 {
+        return Err(SysError::EINVAL);
+    }
+    let current = current_thread().proc.clone();
+    let (current_pid, sid) = {
+        let proc = current.lock();
+        (proc.pid.get(), proc.sid)
+    };
+    let pid = if pid == 0 { current_pid } else { pid as usize };
+    let pgid = if pgid == 0 { pid } else { pgid as usize };
+    let target = if pid == current_pid {
+        current
+    } else {
+        let proc = current.lock();
+        let child = proc
+            .children
+            .iter()
+            .filter_map(|weak| weak.upgrade())
+            .find(|child| child.lock().pid.get() == pid);
+        child.ok_or(SysError::ESRCH)?
+    };
+    if pgid != pid
+        && !process_group(pgid)
+            .iter()
+            .any(|proc| proc.lock().sid == sid)
+    {
+        return Err(SysError::EPERM);
+    }
+    let mut target = target.lock();
+    if target.sid != sid || target.sid == pid {
+        return Err(SysError::EPERM);
+    }
+    target.pgid = pgid;
+    Ok(0)
+}
+
+pub fn sys_getpgid(pid: usize) -> SysResult {
+    info!("getpgid: pid: {}", pid);
+    let pgid = process_or_current(pid)?.lock().pgid;
+    Ok(pgid)
+}
+
+pub fn sys_getpgrp() -> SysResult {
+    sys_getpgid(0)
+}
+
+/// Make the current process the leader of a new session and a new process group,
+/// without a controlling terminal. It must not be a process group leader.
+pub fn sys_setsid() -> SysResult {
+    info!("setsid");
+    let pid = process().pid.get();
+    if !process_group(pid).is_empty() {
+        return Err(SysError::EPERM);
+    }
+    let mut proc = process();
+    proc.pgid = pid;
+    proc.sid = pid;
+    proc.ctty = None;
+    Ok(pid)
+}
+
+pub fn sys_getsid(pid: usize) -> SysResult {
+    info!("getsid: pid: {}", pid);
+    let sid = process_or_current(pid)?.lock().sid;
+    Ok(sid)
+}
+
+/// Maximum number of supplementary groups
+const NGROUPS_MAX: usize = 65536;
+
+/// An id argument of `setreuid` and alike, where -1 keeps the id
+fn optional_id(id: usize) -> Option<usize> {
+    // ids are 32 bits
+    match id as u32 {
+        core::u32::MAX => None,
+        id => Some(id as usize),
+    }
+}
+
+pub fn sys_getuid() -> SysResult {
+    Ok(process().cred.user.real)
+}
+
+pub fn sys_geteuid() -> SysResult {
+    Ok(process().cred.user.effective)
+}
+
+pub fn sys_getgid() -> SysResult {
+    Ok(process().cred.group.real)
+}
+
+pub fn sys_getegid() -> SysResult {
+    Ok(process().cred.group.effective)
+}
+
+pub fn sys_setuid(uid: usize) -> SysResult {
+    info!("setuid: uid: {}", uid);
+    let mut proc = process();
+    let privileged = proc.cred.is_root();
+    proc.cred.user.set(uid, privileged)?;
+    Ok(0)
+}
+
+pub fn sys_setgid(gid: usize) -> SysResult {
+    info!("setgid: gid: {}", gid);
+    let mut proc = process();
+    let privileged = proc.cred.is_root();
+    proc.cred.group.set(gid, privileged)?;
+    Ok(0)
+}
+
+pub fn sys_setreuid(ruid: usize, euid: usize) -> SysResult {
+    info!("setreuid: ruid: {}, euid: {}", ruid as isize, euid as isize);
+    let mut proc = process();
+    let privileged = proc.cred.is_root();
+    proc.cred
+        .user
+        .set_re(optional_id(ruid), optional_id(euid), privileged)?;
+    Ok(0)
+}
+
+pub fn sys_setregid(rgid: usize, egid: usize) -> SysResult {
+    info!("setregid: rgid: {}, egid: {}", rgid as isize, egid as isize);
+    let mut proc = process();
+    let privileged = proc.cred.is_root();
+    proc.cred
+        .group
+        .set_re(optional_id(rgid), optional_id(egid), privileged)?;
+    Ok(0)
+}
+
+pub fn sys_setresuid(ruid: usize, euid: usize, suid: usize) -> SysResult {
+    info!(
+        "setresuid: ruid: {}, euid: {}, suid: {}",
+        ruid as isize, euid as isize, suid as isize
+    );
+    let mut proc = process();
+    let privileged = proc.cred.is_root();
+    let (ruid, euid, suid) = (optional_id(ruid), optional_id(euid), optional_id(suid));
+    proc.cred.user.set_res(ruid, euid, suid, privileged)?;
+    Ok(0)
+}
+
+pub fn sys_setresgid(rgid: usize, egid: usize, sgid: usize) -> SysResult {
+    info!(
+        "setresgid: rgid: {}, egid: {}, sgid: {}",
+        rgid as isize, egid as isize, sgid as isize
+    );
+    let mut proc = process();
+    let privileged = proc.cred.is_root();
+    let (rgid, egid, sgid) = (optional_id(rgid), optional_id(egid), optional_id(sgid));
+    proc.cred.group.set_res(rgid, egid, sgid, privileged)?;
+    Ok(0)
+}
+
+/// Write the real, effective and saved `ids` to user space
+fn write_ids(ids: Ids, real: *mut u32, effective: *mut u32, saved: *mut u32) -> SysResult {
+    let proc = process();
+    proc.vm.check_write_ptr(real)?;
+    proc.vm.check_write_ptr(effective)?;
+    proc.vm.check_write_ptr(saved)?;
+    unsafe {
+        real.write(ids.real as u32);
+        effective.write(ids.effective as u32);
+        saved.write(ids.saved as u32);
+    }
+    Ok(0)
+}
+
+pub fn sys_getresuid(ruid: *mut u32, euid: *mut u32, suid: *mut u32) -> SysResult {
+    let ids = process().cred.user;
+    write_ids(ids, ruid, euid, suid)
+}
+
+pub fn sys_getresgid(rgid: *mut u32, egid: *mut u32, sgid: *mut u32) -> SysResult {
+    let ids = process().cred.group;
+    write_ids(ids, rgid, egid, sgid)
+}
+
+/// Get the supplementary groups, or only their number if `size` is 0
+pub fn sys_getgroups(size: usize, list: *mut u32) -> SysResult {
+    info!("getgroups: size: {}, list: {:?}", size, list);
+    let proc = process();
+    let groups = &proc.cred.groups;
+    if size == 0 {
+        return Ok(groups.len());
+    }
+    if size < groups.len() {
+        return Err(SysError::EINVAL);
+    }
+    proc.vm.check_write_array(list, groups.len())?;
+    let list = unsafe { slice::from_raw_parts_mut(list, groups.len()) };
+    for (x, &gid) in list.iter_mut().zip(groups.iter()) {
+        *x = gid as u32;
+    }
+    Ok(groups.len())
+}
+
+pub fn sys_setgroups(size: usize, list: *const u32) -> SysResult {
+    info!("setgroups: size: {}, list: {:?}", size, list);
+    let mut proc = process();
+    if !proc.cred.is_root() {
+        return Err(SysError::EPERM);
+    }
+    if size > NGROUPS_MAX {
+        return Err(SysError::EINVAL);
+    }
+    proc.vm.check_read_array(list, size)?;
+    let list = unsafe { slice::from_raw_parts(list, size) };
+    proc.cred.groups = list.iter().map(|&gid| gid as usize).collect();
+    Ok(0)
+}
+
 /// Exit the current thread
 pub fn sys_exit(exit_code: usize) -> ! {
     let tid = thread::current().id();
     info!("exit: {}, code: {}", tid, exit_code);
     let mut proc = process();
     proc.threads.retain(|&id| id != tid);
+    proc.thread_sig_pending.remove(&tid);
 
-    // for last thread,
-    // notify parent and fill exit code
-    // avoid deadlock
     let exit = proc.threads.len() == 0;
-    let proc_parent = proc.parent.clone();
-    let pid = proc.pid.get();
     drop(proc);
-    if exit {
-        if let Some(parent) = proc_parent {
-            let mut parent = parent.lock();
-            parent.child_exit_code.insert(pid, exit_code);
-            parent.child_exit.notify_one();
-        }
-    }
 
     // perform futex wake 1
     // ref: http://man7.org/linux/man-pages/man2/set_tid_address.2.html
     // FIXME: do it in all possible ways a thread can exit
     //        it has memory access so we can't move it to Thread::drop?
+    // it is done before the address space may be given back by `vfork_release`
     let clear_child_tid = current_thread().clear_child_tid;
     if clear_child_tid != 0 {
         unsafe {
@@ -297,6 +945,11 @@ pub fn sys_exit(exit_code: usize) -> ! {
         queue.notify_one();
     }
 
+    // the process exits with its last thread
+    if exit {
+        process_exited(&current_thread().proc, WaitStatus::Exited(exit_code));
+    }
+
     processor().manager().exit(tid, exit_code as usize);
     processor().yield_now();
     unreachable!();
@@ -304,24 +957,20 @@ pub fn sys_exit(exit_code: usize) -> ! {
 
 /// Exit the current thread group (i.e. process)
 pub fn sys_exit_group(exit_code: usize) -> ! {
+    exit_process(WaitStatus::Exited(exit_code))
+}
+
+/// Terminate the current process with `status`, which is `Signaled` if it is killed
+pub fn exit_process(status: WaitStatus) -> ! {
     let proc = process();
-    info!("exit_group: {}, code: {}", proc.pid, exit_code);
+    info!("exit_group: {}, status: {:?}", proc.pid, status);
 
     // quit all threads
     for tid in proc.threads.iter() {
-        processor().manager().exit(*tid, exit_code);
+        processor().manager().exit(*tid, status.encode() as usize);
     }
-
-    // notify parent and fill exit code
-    // avoid deadlock
-    let proc_parent = proc.parent.clone();
-    let pid = proc.pid.get();
     drop(proc);
-    if let Some(parent) = proc_parent {
-        let mut parent = parent.lock();
-        parent.child_exit_code.insert(pid, exit_code);
-        parent.child_exit.notify_one();
-    }
+    process_exited(&current_thread().proc, status);
 
     processor().yield_now();
     unreachable!();
@@ -333,6 +982,9 @@ pub fn sys_nanosleep(req: *const TimeSpec) -> SysResult {
     info!("nanosleep: time: {:#?}", time);
     // TODO: handle spurious wakeup
     thread::sleep(time.to_duration());
+    if has_signal_to_handle() {
+        return Err(SysError::EINTR);
+    }
     Ok(0)
 }
 
@@ -342,29 +994,52 @@ pub fn sys_set_priority(priority: usize) -> SysResult {
     Ok(0)
 }
 
+bitflags! {
+    pub struct WaitOptions: usize {
+        /// Return at once if no child has changed
+        const NOHANG = 1;
+        /// Report the children which stop, i.e. `WSTOPPED`
+        const UNTRACED = 2;
+        /// Report the children which exit, which `wait4` always does
+        const EXITED = 4;
+        /// Report the children which continue
+        const CONTINUED = 8;
+        /// Leave the child to be waited for again
+        const NOWAIT = 0x1000000;
+        /// The options for threads, which make no difference here
+        const NOTHREAD = 0x20000000;
+        const ALL = 0x40000000;
+        const CLONE = 0x80000000;
+    }
+}
+
 bitflags! {
     pub struct CloneFlags: usize {
+        /// The signal sent to the parent when the child exits
         const CSIGNAL = 0x000000ff;
-        const VM = 0x0000100;
-        const FS = 0x0000200;
-        const FILES = 0x0000400;
-        const SIGHAND = 0x0000800;
-        const PTRACE = 0x0002000;
-        const VFORK = 0x0004000;
-        const PARENT = 0x0008000;
-        const SYSVSEM = 0x0008000;
-        const SETTLS = 0x0008000;
-        const PARENT_SETTID = 0x0010000;
-        const CHILD_CLEARTID = 0x0020000;
-        const DETACHED = 0x0040000;
-        const UNTRACED = 0x0080000;
-        const CHILD_SETTID = 0x0100000;
-        const NEWCGROUP = 0x0200000;
-        const NEWUTS = 0x0400000;
-        const NEWIPC = 0x0800000;
-        const NEWUSER = 0x1000000;
-        const NEWPID = 0x2000000;
-        const NEWNET = 0x4000000;
-        const IO = 0x8000000;
+        const VM = 0x00000100;
+        const FS = 0x00000200;
+        const FILES = 0x00000400;
+        const SIGHAND = 0x00000800;
+        const PIDFD = 0x00001000;
+        const PTRACE = 0x00002000;
+        const VFORK = 0x00004000;
+        const PARENT = 0x00008000;
+        const THREAD = 0x00010000;
+        const NEWNS = 0x00020000;
+        const SYSVSEM = 0x00040000;
+        const SETTLS = 0x00080000;
+        const PARENT_SETTID = 0x00100000;
+        const CHILD_CLEARTID = 0x00200000;
+        const DETACHED = 0x00400000;
+        const UNTRACED = 0x00800000;
+        const CHILD_SETTID = 0x01000000;
+        const NEWCGROUP = 0x02000000;
+        const NEWUTS = 0x04000000;
+        const NEWIPC = 0x08000000;
+        const NEWUSER = 0x10000000;
+        const NEWPID = 0x20000000;
+        const NEWNET = 0x40000000;
+        const IO = 0x80000000;
     }
 }
@@ -0,0 +1,270 @@
+//! Syscalls for signals
+
+use super::*;
+use crate::process::signal::*;
+
+/// `struct sigaction` in the kernel ABI
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SigAction {
+    handler: usize,
+    flags: usize,
+    restorer: usize,
+    mask: Sigset,
+}
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+impl SigAction {
+    fn to_action(&self) -> SignalAction {
+        SignalAction {
+            handler: self.handler,
+            flags: SignalActionFlags::from_bits_truncate(self.flags),
+            restorer: self.restorer,
+            mask: self.mask,
+        }
+    }
+    fn from_action(action: &SignalAction) -> Self {
+        SigAction {
+            handler: action.handler,
+            flags: action.flags.bits(),
+            restorer: action.restorer,
+            mask: action.mask,
+        }
+    }
+}
+
+/// `struct sigaction` in the kernel ABI
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SigAction {
+    handler: usize,
+    flags: usize,
+    mask: Sigset,
+}
+
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+impl SigAction {
+    fn to_action(&self) -> SignalAction {
+        SignalAction {
+            handler: self.handler,
+            flags: SignalActionFlags::from_bits_truncate(self.flags),
+            restorer: 0,
+            mask: self.mask,
+        }
+    }
+    fn from_action(action: &SignalAction) -> Self {
+        SigAction {
+            handler: action.handler,
+            flags: action.flags.bits(),
+            mask: action.mask,
+        }
+    }
+}
+
+/// `struct sigaction` in the kernel ABI
+#[cfg(target_arch = "mips")]
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SigAction {
+    flags: u32,
+    handler: usize,
+    mask: [u32; 4],
+}
+
+#[cfg(target_arch = "mips")]
+impl SigAction {
+    fn to_action(&self) -> SignalAction {
+        SignalAction {
+            handler: self.handler,
+            flags: SignalActionFlags::from_bits_truncate(self.flags as usize),
+            restorer: 0,
+            mask: Sigset(self.mask[0] as u64 | (self.mask[1] as u64) << 32),
+        }
+    }
+    fn from_action(action: &SignalAction) -> Self {
+        SigAction {
+            flags: action.flags.bits() as u32,
+            handler: action.handler,
+            mask: [action.mask.0 as u32, (action.mask.0 >> 32) as u32, 0, 0],
+        }
+    }
+}
+
+/// Size of `sigset_t` in the kernel ABI
+#[cfg(not(target_arch = "mips"))]
+const SIGSET_SIZE: usize = 8;
+#[cfg(target_arch = "mips")]
+const SIGSET_SIZE: usize = 16;
+
+#[cfg(not(target_arch = "mips"))]
+const SIG_BLOCK: usize = 0;
+#[cfg(not(target_arch = "mips"))]
+const SIG_UNBLOCK: usize = 1;
+#[cfg(not(target_arch = "mips"))]
+const SIG_SETMASK: usize = 2;
+
+#[cfg(target_arch = "mips")]
+const SIG_BLOCK: usize = 1;
+#[cfg(target_arch = "mips")]
+const SIG_UNBLOCK: usize = 2;
+#[cfg(target_arch = "mips")]
+const SIG_SETMASK: usize = 3;
+
+/// Replace the signal mask of the current thread with `set`, if it is not null,
+/// during the wait of a syscall like `epoll_pwait`. Return the mask to restore.
+pub fn replace_sig_mask(set: *const Sigset, sigsetsize: usize) -> Result<Option<Sigset>, SysError> {
+    if set.is_null() {
+        return Ok(None);
+    }
+    if sigsetsize != SIGSET_SIZE {
+        return Err(SysError::EINVAL);
+    }
+    process()
+        .vm
+        .check_read_array(set as *const u8, SIGSET_SIZE)?;
+    let set = unsafe { set.read_unaligned() };
+    let thread = current_thread();
+    let old_mask = thread.sig_mask;
+    thread.sig_mask = set.without_unblockable();
+    Ok(Some(old_mask))
+}
+
+/// Restore the mask replaced by `replace_sig_mask` after the wait returned `result`.
+/// A signal interrupting the wait is handled with the replacing mask, then it is restored.
+pub fn restore_sig_mask(old_mask: Option<Sigset>, result: &SysResult) {
+    let old_mask = match old_mask {
+        Some(mask) => mask,
+        None => return,
+    };
+    let thread = current_thread();
+    match result {
+        Err(SysError::EINTR) => thread.saved_sig_mask = Some(old_mask),
+        _ => thread.sig_mask = old_mask,
+    }
+}
+
+pub fn sys_rt_sigaction(
+    signum: usize,
+    act: *const SigAction,
+    oldact: *mut SigAction,
+    sigsetsize: usize,
+) -> SysResult {
+    info!(
+        "rt_sigaction: signum: {}, act: {:?}, oldact: {:?}",
+        signum, act, oldact
+    );
+    if signum == 0 || signum > NSIG || sigsetsize != SIGSET_SIZE {
+        return Err(SysError::EINVAL);
+    }
+    let mut proc = process();
+    if !oldact.is_null() {
+        proc.vm.check_write_ptr(oldact)?;
+        unsafe {
+            oldact.write(SigAction::from_action(&proc.sig_actions[signum]));
+        }
+    }
+    if !act.is_null() {
+        proc.vm.check_read_ptr(act)?;
+        if signum == SIGKILL || signum == SIGSTOP {
+            return Err(SysError::EINVAL);
+        }
+        let mut action = unsafe { act.read() }.to_action();
+        action.mask = action.mask.without_unblockable();
+        info!("rt_sigaction: {:x?}", action);
+        proc.sig_actions[signum] = action;
+        if action.handler == SIG_IGN {
+            // pending signals are discarded if set to be ignored
+            proc.sig_pending.remove(signum);
+            for set in proc.thread_sig_pending.values_mut() {
+                set.remove(signum);
+            }
+        }
+    }
+    Ok(0)
+}
+
+pub fn sys_rt_sigprocmask(
+    how: usize,
+    set: *const Sigset,
+    oldset: *mut Sigset,
+    sigsetsize: usize,
+) -> SysResult {
+    info!(
+        "rt_sigprocmask: how: {}, set: {:?}, oldset: {:?}",
+        how, set, oldset
+    );
+    if sigsetsize != SIGSET_SIZE {
+        return Err(SysError::EINVAL);
+    }
+    let thread = current_thread();
+    {
+        let proc = process();
+        if !set.is_null() {
+            proc.vm.check_read_array(set as *const u8, SIGSET_SIZE)?;
+        }
+        if !oldset.is_null() {
+            proc.vm.check_write_array(oldset as *mut u8, SIGSET_SIZE)?;
+        }
+    }
+    let old_mask = thread.sig_mask;
+    if !set.is_null() {
+        let set = unsafe { set.read_unaligned() };
+        let mut mask = old_mask;
+        match how {
+            SIG_BLOCK => mask.add_set(set),
+            SIG_UNBLOCK => mask.remove_set(set),
+            SIG_SETMASK => mask = set,
+            _ => return Err(SysError::EINVAL),
+        }
+        thread.sig_mask = mask.without_unblockable();
+    }
+    if !oldset.is_null() {
+        unsafe {
+            (oldset as *mut u8).write_bytes(0, SIGSET_SIZE);
+            oldset.write_unaligned(old_mask);
+        }
+    }
+    Ok(0)
+}
+
+/// Return from a signal handler
+pub fn sys_rt_sigreturn(tf: &mut TrapFrame) -> SysResult {
+    info!("rt_sigreturn");
+    if !restore_signal_context(tf) {
+        warn!("rt_sigreturn: bad signal frame");
+        exit_process(WaitStatus::Signaled(SIGSEGV));
+    }
+    // the return value is dropped, see `syscall` in arch
+    Ok(0)
+}
+
+/// Send a signal to a thread
+pub fn sys_tkill(tid: usize, sig: usize) -> SysResult {
+    info!("tkill: tid: {}, sig: {}", tid, sig);
+    if sig > NSIG {
+        return Err(SysError::EINVAL);
+    }
+    let proc = process_of_thread(tid).ok_or(SysError::ESRCH)?;
+    if sig != 0 {
+        send_signal(&proc, sig, Some(tid));
+    }
+    Ok(0)
+}
+
+/// Send a signal to a thread in thread group `tgid`
+pub fn sys_tgkill(tgid: usize, tid: usize, sig: usize) -> SysResult {
+    info!("tgkill: tgid: {}, tid: {}, sig: {}", tgid, tid, sig);
+    if sig > NSIG {
+        return Err(SysError::EINVAL);
+    }
+    let proc = process_of_thread(tid).ok_or(SysError::ESRCH)?;
+    if proc.lock().pid.get() != tgid {
+        return Err(SysError::ESRCH);
+    }
+    if sig != 0 {
+        send_signal(&proc, sig, Some(tid));
+    }
+    Ok(0)
+}
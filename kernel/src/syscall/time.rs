@@ -2,8 +2,10 @@
 
 use super::*;
 use crate::consts::USEC_PER_TICK;
+use core::sync::atomic::Ordering;
 use core::time::Duration;
 use lazy_static::lazy_static;
+use rcore_fs::vfs::Timespec;
 
 /// should be initialized together
 lazy_static! {
@@ -42,6 +44,13 @@ impl TimeVal {
         (self.sec as u64) * MSEC_PER_SEC + (self.usec as u64) / USEC_PER_MSEC
     }
 
+    pub fn from_msec(msec: u64) -> Self {
+        TimeVal {
+            sec: (msec / MSEC_PER_SEC) as usize,
+            usec: (msec % MSEC_PER_SEC * USEC_PER_MSEC) as usize,
+        }
+    }
+
     pub fn get_epoch() -> Self {
         let usec = get_epoch_usec();
         TimeVal {
@@ -76,6 +85,15 @@ impl TimeSpec {
     }
 }
 
+impl From<TimeSpec> for Timespec {
+    fn from(time: TimeSpec) -> Self {
+        Timespec {
+            sec: time.sec as i64,
+            nsec: time.nsec as i32,
+        }
+    }
+}
+
 pub fn sys_gettimeofday(tv: *mut TimeVal, tz: *const u8) -> SysResult {
     info!("gettimeofday: tv: {:?}, tz: {:?}", tv, tz);
     if tz as usize != 0 {
@@ -117,32 +135,47 @@ pub fn sys_time(time: *mut u64) -> SysResult {
     Ok(sec as usize)
 }
 
-// ignore other fields for now
+/// Resource usage, of which only the CPU time is counted.
+/// It is all counted as user time.
 #[repr(C)]
 pub struct RUsage {
     utime: TimeVal,
     stime: TimeVal,
+    /// The other fields, which are all zero
+    _rest: [usize; 14],
+}
+
+impl RUsage {
+    /// The usage of having run `ticks` timer ticks
+    pub fn from_ticks(ticks: usize) -> Self {
+        let usec = ticks as u64 * USEC_PER_TICK as u64;
+        RUsage {
+            utime: TimeVal {
+                sec: (usec / USEC_PER_SEC) as usize,
+                usec: (usec % USEC_PER_SEC) as usize,
+            },
+            stime: TimeVal { sec: 0, usec: 0 },
+            _rest: [0; 14],
+        }
+    }
 }
 
+/// `who` of `getrusage`
+const RUSAGE_SELF: isize = 0;
+const RUSAGE_CHILDREN: isize = -1;
+const RUSAGE_THREAD: isize = 1;
+
 pub fn sys_getrusage(who: usize, rusage: *mut RUsage) -> SysResult {
-    info!("getrusage: who: {}, rusage: {:?}", who, rusage);
+    info!("getrusage: who: {}, rusage: {:?}", who as isize, rusage);
     let proc = process();
     proc.vm.check_write_ptr(rusage)?;
 
-    let tick_base = *TICK_BASE;
-    let tick = unsafe { crate::trap::TICK as u64 };
-
-    let usec = (tick - tick_base) * USEC_PER_TICK as u64;
-    let new_rusage = RUsage {
-        utime: TimeVal {
-            sec: (usec / USEC_PER_SEC) as usize,
-            usec: (usec % USEC_PER_SEC) as usize,
-        },
-        stime: TimeVal {
-            sec: (usec / USEC_PER_SEC) as usize,
-            usec: (usec % USEC_PER_SEC) as usize,
-        },
+    let ticks = match who as isize {
+        // threads are not counted apart
+        RUSAGE_SELF | RUSAGE_THREAD => proc.ticks.load(Ordering::Relaxed),
+        RUSAGE_CHILDREN => proc.children_ticks,
+        _ => return Err(SysError::EINVAL),
     };
-    unsafe { *rusage = new_rusage };
+    unsafe { *rusage = RUsage::from_ticks(ticks) };
     Ok(0)
 }
@@ -6,13 +6,14 @@ use log::*;
 pub static mut TICK: usize = 0;
 
 pub fn uptime_msec() -> usize {
-    unsafe { crate::trap::TICK / crate::consts::USEC_PER_TICK / 1000 }
+    unsafe { crate::trap::TICK * crate::consts::USEC_PER_TICK / 1000 }
 }
 
 pub fn timer() {
     if cpu::id() == 0 {
         unsafe {
             TICK += 1;
+            crate::sync::expire_timeouts(TICK);
         }
     }
     processor().tick();
@@ -28,11 +29,10 @@ pub fn error(tf: &TrapFrame) -> ! {
     unreachable!();
 }
 
+/// Input typed on the console
 pub fn serial(c: char) {
-    if c == '\r' {
-        // in linux, we use '\n' instead
-        crate::fs::STDIN.push('\n');
-    } else {
-        crate::fs::STDIN.push(c);
+    let mut buf = [0; 4];
+    for &byte in c.encode_utf8(&mut buf).as_bytes() {
+        crate::fs::CONSOLE.push(byte);
     }
 }